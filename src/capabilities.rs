@@ -0,0 +1,147 @@
+//! Describes what this build of the library can read and write.
+//!
+//! Tools orchestrating long jobs can check these before starting one, for
+//! example that the RDB version of a dump is supported.
+
+use serde::Serialize;
+
+use crate::constants::{encoding_type, op_code, version};
+use crate::module;
+use crate::types::Type;
+
+/// The output formats of the formatters in `formatter`, by the names the
+/// `rdb` binary accepts.
+pub const FORMATS: &[&str] = &[
+    "json",
+    "jsonl",
+    "plain",
+    "nil",
+    "protocol",
+    "digest",
+    "folded",
+    "dot",
+    "compression",
+    "stats",
+    "prometheus",
+    "table",
+    "template",
+    #[cfg(feature = "parquet")]
+    "parquet",
+];
+
+/// A value encoding or opcode, by the number stored in dumps.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Code {
+    pub code: u8,
+    pub name: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Capabilities {
+    /// The version of the library.
+    pub version: &'static str,
+    pub min_rdb_version: u32,
+    pub max_rdb_version: u32,
+    pub types: Vec<&'static str>,
+    pub encodings: Vec<Code>,
+    pub opcodes: Vec<Code>,
+    /// The modules whose values are decoded instead of skipped.
+    pub modules: Vec<String>,
+    pub formats: Vec<&'static str>,
+    /// The Cargo features the library was built with.
+    pub features: Vec<&'static str>,
+}
+
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "parquet")]
+    "parquet",
+];
+
+impl Capabilities {
+    pub fn new() -> Capabilities {
+        let types = [
+            Type::String,
+            Type::List,
+            Type::Set,
+            Type::SortedSet,
+            Type::Hash,
+            Type::Stream,
+            Type::Module,
+        ];
+        let encodings = [
+            (encoding_type::STRING, "string"),
+            (encoding_type::LIST, "list"),
+            (encoding_type::SET, "set"),
+            (encoding_type::ZSET, "zset"),
+            (encoding_type::HASH, "hash"),
+            (encoding_type::ZSET_2, "zset_2"),
+            (encoding_type::MODULE, "module"),
+            (encoding_type::MODULE_2, "module_2"),
+            (encoding_type::HASH_ZIPMAP, "hash_zipmap"),
+            (encoding_type::LIST_ZIPLIST, "list_ziplist"),
+            (encoding_type::SET_INTSET, "set_intset"),
+            (encoding_type::ZSET_ZIPLIST, "zset_ziplist"),
+            (encoding_type::HASH_ZIPLIST, "hash_ziplist"),
+            (encoding_type::LIST_QUICKLIST, "list_quicklist"),
+            (encoding_type::STREAM_LISTPACKS, "stream_listpacks"),
+            (encoding_type::HASH_LISTPACK, "hash_listpack"),
+            (encoding_type::ZSET_LISTPACK, "zset_listpack"),
+            (encoding_type::LIST_QUICKLIST_2, "list_quicklist_2"),
+            (encoding_type::STREAM_LISTPACKS_2, "stream_listpacks_2"),
+            (encoding_type::SET_LISTPACK, "set_listpack"),
+            (encoding_type::STREAM_LISTPACKS_3, "stream_listpacks_3"),
+            (encoding_type::HASH_METADATA_PRE_GA, "hash_metadata_pre_ga"),
+            (
+                encoding_type::HASH_LISTPACK_EX_PRE_GA,
+                "hash_listpack_ex_pre_ga",
+            ),
+            (encoding_type::HASH_METADATA, "hash_metadata"),
+            (encoding_type::HASH_LISTPACK_EX, "hash_listpack_ex"),
+        ];
+        let opcodes = [
+            (op_code::SLOT_INFO, "slot_info"),
+            (op_code::FUNCTION2, "function2"),
+            (op_code::MODULE_AUX, "module_aux"),
+            (op_code::IDLE, "idle"),
+            (op_code::FREQ, "freq"),
+            (op_code::AUX, "aux"),
+            (op_code::RESIZEDB, "resizedb"),
+            (op_code::EXPIRETIME_MS, "expiretime_ms"),
+            (op_code::EXPIRETIME, "expiretime"),
+            (op_code::SELECTDB, "selectdb"),
+            (op_code::EOF, "eof"),
+        ];
+
+        let mut modules: Vec<_> = module::builtin_decoders().into_keys().collect();
+        modules.sort();
+
+        Capabilities {
+            version: env!("CARGO_PKG_VERSION"),
+            min_rdb_version: version::SUPPORTED_MINIMUM,
+            max_rdb_version: version::SUPPORTED_MAXIMUM,
+            types: types.iter().map(|typ| typ.name()).collect(),
+            encodings: encodings
+                .iter()
+                .map(|&(code, name)| Code { code, name })
+                .collect(),
+            opcodes: opcodes
+                .iter()
+                .map(|&(code, name)| Code { code, name })
+                .collect(),
+            modules,
+            formats: FORMATS.to_vec(),
+            features: FEATURES.to_vec(),
+        }
+    }
+
+    /// Whether dumps of the given RDB version can be read.
+    pub fn supports_rdb_version(&self, version: u32) -> bool {
+        (self.min_rdb_version..=self.max_rdb_version).contains(&version)
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        Capabilities::new()
+    }
+}
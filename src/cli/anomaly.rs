@@ -0,0 +1,345 @@
+//! Flags key patterns that commonly point to problems in a dataset.
+//!
+//! Three kinds of anomalies are reported:
+//!
+//! * Unbounded patterns: keys whose names contain UUIDs, timestamps or
+//!   hashes, of which there are many. These usually grow without limit,
+//!   for example when a key is created per request and never deleted.
+//! * Missing TTLs: keys without an expiry under a prefix that suggests a
+//!   cache, such as `cache` or `session`.
+//! * Large keys: single keys holding a large share of the memory, estimated
+//!   like the `memory` module does.
+//!
+//! Patterns are found by splitting a key at the separator and replacing
+//! each segment that looks like an identifier with a placeholder, so
+//! `session:8c6f0c5e-3d4b-4a8e-9f1e-2b7d9c0a1e55` becomes `session:{uuid}`.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::memory::Memory;
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, StreamId, ValueLength};
+use rdb::RdbResult;
+
+/// The prefixes treated as caches unless others are set.
+pub const CACHE_PREFIXES: &[&str] = &["cache", "session", "sessions", "sess", "tmp", "temp"];
+
+/// A suspicious key pattern.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// Many keys whose names contain UUIDs, timestamps or hashes.
+    UnboundedPattern { pattern: String, keys: u64 },
+    /// Keys without an expiry under a cache-like prefix.
+    MissingTtl {
+        prefix: String,
+        keys: u64,
+        example: Vec<u8>,
+    },
+    /// A key holding a large share of the estimated memory.
+    LargeKey {
+        key: Vec<u8>,
+        allocated: u64,
+        share: f64,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::UnboundedPattern { pattern, keys } => {
+                write!(f, "unbounded pattern {:?}: {} keys", pattern, keys)
+            }
+            Anomaly::MissingTtl {
+                prefix,
+                keys,
+                example,
+            } => write!(
+                f,
+                "no expiry under cache prefix {:?}: {} keys, e.g. {:?}",
+                prefix,
+                keys,
+                String::from_utf8_lossy(example)
+            ),
+            Anomaly::LargeKey {
+                key,
+                allocated,
+                share,
+            } => write!(
+                f,
+                "large key {:?}: {} bytes, {:.1}% of memory",
+                String::from_utf8_lossy(key),
+                allocated,
+                share * 100.0
+            ),
+        }
+    }
+}
+
+/// Returns the placeholder for a segment of a key name that looks like an
+/// identifier, and whether identifiers of its kind are unbounded.
+fn placeholder(segment: &[u8]) -> Option<(&'static str, bool)> {
+    let is_hex = |b: &[u8]| !b.is_empty() && b.iter().all(u8::is_ascii_hexdigit);
+
+    if segment.len() == 36 {
+        let groups: Vec<_> = segment.split(|b| *b == b'-').collect();
+        let lengths: Vec<_> = groups.iter().map(|g| g.len()).collect();
+        if lengths == [8, 4, 4, 4, 12] && groups.iter().all(|g| is_hex(g)) {
+            return Some(("{uuid}", true));
+        }
+    }
+    if !segment.is_empty() && segment.iter().all(u8::is_ascii_digit) {
+        // Unix timestamps in seconds or milliseconds.
+        return match segment.len() {
+            10 | 13 => Some(("{timestamp}", true)),
+            _ => Some(("{id}", false)),
+        };
+    }
+    if segment.len() >= 16 && is_hex(segment) {
+        return Some(("{hash}", true));
+    }
+    None
+}
+
+/// A formatter collecting the anomalies of all keys.
+pub struct Anomalies {
+    separator: u8,
+    min_keys: u64,
+    max_share: f64,
+    cache_prefixes: Vec<Vec<u8>>,
+    memory: Memory,
+    patterns: HashMap<String, u64>,
+    missing_ttl: HashMap<Vec<u8>, (u64, Vec<u8>)>,
+}
+
+impl Anomalies {
+    pub fn new() -> Anomalies {
+        let mut anomalies = Anomalies {
+            separator: b':',
+            min_keys: 10_000,
+            max_share: 0.0,
+            cache_prefixes: CACHE_PREFIXES
+                .iter()
+                .map(|prefix| prefix.as_bytes().to_vec())
+                .collect(),
+            memory: Memory::new(),
+            patterns: HashMap::new(),
+            missing_ttl: HashMap::new(),
+        };
+        anomalies.set_max_share(0.1);
+        anomalies
+    }
+
+    /// Sets the byte the segments of a key end at. Defaults to `:`.
+    pub fn set_separator(&mut self, separator: u8) {
+        self.separator = separator;
+        self.memory.set_separator(separator);
+    }
+
+    /// Sets how many keys an unbounded pattern must have to be reported.
+    /// Defaults to 10000.
+    pub fn set_min_keys(&mut self, min_keys: u64) {
+        self.min_keys = min_keys;
+    }
+
+    /// Sets the share of the memory, between 0 and 1, above which a key is
+    /// reported as large. Defaults to 0.1.
+    pub fn set_max_share(&mut self, max_share: f64) {
+        self.max_share = max_share;
+        // At most this many keys can hold more than the share each.
+        let count = (1.0 / max_share).ceil().clamp(1.0, 1000.0);
+        self.memory.track_largest(count as usize);
+    }
+
+    /// Sets the first segments of keys that are expected to expire.
+    /// Defaults to `CACHE_PREFIXES`.
+    pub fn set_cache_prefixes(&mut self, prefixes: Vec<Vec<u8>>) {
+        self.cache_prefixes = prefixes;
+    }
+
+    /// All anomalies, unbounded patterns first, then missing TTLs, then
+    /// large keys. Each kind is ordered by its number of keys or size.
+    pub fn report(&self) -> Vec<Anomaly> {
+        let mut patterns: Vec<_> = self
+            .patterns
+            .iter()
+            .filter(|(_, keys)| **keys >= self.min_keys)
+            .collect();
+        patterns.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+
+        let mut missing_ttl: Vec<_> = self.missing_ttl.iter().collect();
+        missing_ttl.sort_by(|a, b| (b.1).0.cmp(&(a.1).0).then_with(|| a.0.cmp(b.0)));
+
+        let total = self.memory.allocated();
+        let large = self
+            .memory
+            .largest_keys()
+            .into_iter()
+            .map(|(key, allocated)| (key, allocated, allocated as f64 / total.max(1) as f64))
+            .filter(|(_, _, share)| *share > self.max_share);
+
+        let mut report = Vec::new();
+        for (pattern, keys) in patterns {
+            report.push(Anomaly::UnboundedPattern {
+                pattern: pattern.clone(),
+                keys: *keys,
+            });
+        }
+        for (prefix, (keys, example)) in missing_ttl {
+            report.push(Anomaly::MissingTtl {
+                prefix: String::from_utf8_lossy(prefix).into_owned(),
+                keys: *keys,
+                example: example.clone(),
+            });
+        }
+        for (key, allocated, share) in large {
+            report.push(Anomaly::LargeKey {
+                key,
+                allocated,
+                share,
+            });
+        }
+        report
+    }
+
+    fn start_key(&mut self, key: &[u8], expiry: Option<u64>) {
+        let prefix = key.split(|b| *b == self.separator).next().unwrap_or(key);
+        if expiry.is_none() && self.cache_prefixes.iter().any(|p| p == prefix) {
+            let missing = self
+                .missing_ttl
+                .entry(prefix.to_vec())
+                .or_insert_with(|| (0, key.to_vec()));
+            missing.0 += 1;
+        }
+
+        let mut unbounded = false;
+        let mut pattern = Vec::with_capacity(key.len());
+        for (i, segment) in key.split(|b| *b == self.separator).enumerate() {
+            if i > 0 {
+                pattern.push(self.separator);
+            }
+            match placeholder(segment) {
+                Some((placeholder, is_unbounded)) => {
+                    pattern.extend_from_slice(placeholder.as_bytes());
+                    unbounded |= is_unbounded;
+                }
+                None => pattern.extend_from_slice(segment),
+            }
+        }
+        if unbounded {
+            let pattern = String::from_utf8_lossy(&pattern).into_owned();
+            *self.patterns.entry(pattern).or_default() += 1;
+        }
+    }
+}
+
+impl Default for Anomalies {
+    fn default() -> Anomalies {
+        Anomalies::new()
+    }
+}
+
+impl Formatter for Anomalies {
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.memory.aux_field(key, value)
+    }
+
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.memory.end_rdb()
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.set(key, value, expiry)
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.start_key(key, None);
+        self.memory.module(key, module_name)
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.value_length(key, length, expiry)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.start_hash(key, length, expiry, info)
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.memory.hash_element(key, field, value)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.start_set(key, cardinality, expiry, info)
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.memory.set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.start_list(key, length, expiry, info)
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.memory.list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.start_sorted_set(key, length, expiry, info)
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.memory.sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.memory.start_stream(key, length, expiry, info)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.memory.stream_entry(key, id, fields)
+    }
+}
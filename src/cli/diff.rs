@@ -0,0 +1,457 @@
+//! Comparison of the data in two dumps.
+//!
+//! Both dumps are decoded into one normalized record per key, sorted by
+//! database and key with an `ExternalSorter`, and then walked in lockstep.
+//! Elements of sets, hashes and sorted sets are sorted first, so their
+//! encoding doesn't matter, only their content.
+
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fmt;
+use std::io::{Cursor, Read};
+
+use super::read_exact;
+use rdb::extsort::{ExternalSorter, Record, Sorted, DEFAULT_BUDGET};
+use rdb::filter::Filter;
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, StreamId, Type};
+use rdb::RdbResult;
+
+/// A difference between the left and the right dump.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The key only exists in the left dump.
+    Removed { db: u32, key: Vec<u8> },
+    /// The key only exists in the right dump.
+    Added { db: u32, key: Vec<u8> },
+    /// The key exists in both dumps, but its value, type or expiry differ.
+    Changed {
+        db: u32,
+        key: Vec<u8>,
+        reason: String,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difference::Removed { db, key } => {
+                write!(f, "- db={} {}", db, String::from_utf8_lossy(key))
+            }
+            Difference::Added { db, key } => {
+                write!(f, "+ db={} {}", db, String::from_utf8_lossy(key))
+            }
+            Difference::Changed { db, key, reason } => write!(
+                f,
+                "~ db={} {}: {}",
+                db,
+                String::from_utf8_lossy(key),
+                reason
+            ),
+        }
+    }
+}
+
+fn type_code(typ: Type) -> u8 {
+    match typ {
+        Type::String => 0,
+        Type::List => 1,
+        Type::Set => 2,
+        Type::SortedSet => 3,
+        Type::Hash => 4,
+        Type::Stream => 5,
+        Type::Module => 6,
+    }
+}
+
+fn type_name(code: u8) -> &'static str {
+    match code {
+        0 => "string",
+        1 => "list",
+        2 => "set",
+        3 => "sortedset",
+        4 => "hash",
+        5 => "stream",
+        _ => "module",
+    }
+}
+
+/// The normalized value of a key.
+///
+/// Strings, lists and sets only use the first half of each item, hashes
+/// store field and value and sorted sets the member and the score's bits.
+/// Streams store the entry ID, big endian so it sorts, and the entry's
+/// fields and values.
+struct Entry {
+    typ: u8,
+    expiry: Option<u64>,
+    items: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Entry {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![self.typ];
+        match self.expiry {
+            Some(expiry) => {
+                out.push(1);
+                out.write_u64::<LittleEndian>(expiry).unwrap();
+            }
+            None => out.push(0),
+        }
+        out.write_u64::<LittleEndian>(self.items.len() as u64)
+            .unwrap();
+        for (first, second) in &self.items {
+            for part in &[first, second] {
+                out.write_u64::<LittleEndian>(part.len() as u64).unwrap();
+                out.extend_from_slice(part);
+            }
+        }
+        out
+    }
+
+    fn decode(data: &[u8]) -> RdbResult<Entry> {
+        let mut input = Cursor::new(data);
+        let typ = input.read_u8()?;
+        let expiry = match input.read_u8()? {
+            0 => None,
+            _ => Some(input.read_u64::<LittleEndian>()?),
+        };
+
+        let count = input.read_u64::<LittleEndian>()?;
+        let mut items = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let length = input.read_u64::<LittleEndian>()?;
+            let first = read_exact(&mut input, length as usize)?;
+            let length = input.read_u64::<LittleEndian>()?;
+            let second = read_exact(&mut input, length as usize)?;
+            items.push((first, second));
+        }
+
+        Ok(Entry { typ, expiry, items })
+    }
+}
+
+/// A formatter feeding normalized entries into an `ExternalSorter`.
+struct Snapshot {
+    sorter: ExternalSorter,
+    db: u32,
+    current: Option<Entry>,
+}
+
+impl Snapshot {
+    fn start(&mut self, typ: Type, expiry: Option<u64>) {
+        self.current = Some(Entry {
+            typ: type_code(typ),
+            expiry,
+            items: vec![],
+        });
+    }
+
+    fn add(&mut self, first: &[u8], second: &[u8]) {
+        if let Some(ref mut entry) = self.current {
+            entry.items.push((first.to_vec(), second.to_vec()));
+        }
+    }
+
+    fn end(&mut self, key: &[u8]) -> RdbResult<()> {
+        let mut entry = match self.current.take() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        if entry.typ != type_code(Type::List) && entry.typ != type_code(Type::Stream) {
+            entry.items.sort();
+        }
+
+        let mut record_key = Vec::with_capacity(4 + key.len());
+        record_key.write_u32::<BigEndian>(self.db)?;
+        record_key.extend_from_slice(key);
+        self.sorter.push(record_key, entry.encode())
+    }
+}
+
+impl Formatter for Snapshot {
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.db = db_index;
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start(Type::String, expiry);
+        self.add(value, &[]);
+        self.end(key)
+    }
+
+    fn start_hash(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start(Type::Hash, expiry);
+        Ok(())
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end(key)
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field, value);
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        _key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start(Type::Set, expiry);
+        Ok(())
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end(key)
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member, &[]);
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start(Type::List, expiry);
+        Ok(())
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end(key)
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value, &[]);
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start(Type::SortedSet, expiry);
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end(key)
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member, &score.to_bits().to_le_bytes());
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start(Type::Stream, expiry);
+        Ok(())
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end(key)
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let mut first = Vec::with_capacity(16);
+        first.write_u64::<BigEndian>(id.ms)?;
+        first.write_u64::<BigEndian>(id.seq)?;
+
+        let mut second = vec![];
+        for (field, value) in fields {
+            for part in &[field, value] {
+                second.write_u64::<LittleEndian>(part.len() as u64)?;
+                second.extend_from_slice(part);
+            }
+        }
+        self.add(&first, &second);
+        Ok(())
+    }
+}
+
+fn score(bits: &[u8]) -> Option<f64> {
+    let mut bytes = [0; 8];
+    if bits.len() != bytes.len() {
+        return None;
+    }
+    bytes.copy_from_slice(bits);
+    Some(f64::from_bits(u64::from_le_bytes(bytes)))
+}
+
+fn split_record(record: Record) -> (u32, Vec<u8>, Vec<u8>) {
+    let (mut db_and_key, value) = record;
+    let key = db_and_key.split_off(4);
+    let db = u32::from_be_bytes([db_and_key[0], db_and_key[1], db_and_key[2], db_and_key[3]]);
+    (db, key, value)
+}
+
+/// Compares two dumps key by key.
+pub struct Diff {
+    score_epsilon: f64,
+    ttl_tolerance: u64,
+    budget: usize,
+}
+
+impl Diff {
+    pub fn new() -> Diff {
+        Diff {
+            score_epsilon: 0.0,
+            ttl_tolerance: 0,
+            budget: DEFAULT_BUDGET,
+        }
+    }
+
+    /// Treats sorted set scores as equal if they differ by at most `epsilon`.
+    pub fn set_score_epsilon(&mut self, epsilon: f64) {
+        self.score_epsilon = epsilon;
+    }
+
+    /// Treats expiries as equal if they differ by at most `tolerance` milliseconds.
+    pub fn set_ttl_tolerance(&mut self, tolerance: u64) {
+        self.ttl_tolerance = tolerance;
+    }
+
+    /// Sets the memory both dumps may use before spilling to disk.
+    pub fn set_memory_budget(&mut self, budget: usize) {
+        self.budget = budget;
+    }
+
+    fn snapshot<R: Read, L: Filter>(&self, input: R, filter: L) -> RdbResult<Sorted> {
+        let mut snapshot = Snapshot {
+            sorter: ExternalSorter::new(self.budget / 2),
+            db: 0,
+            current: None,
+        };
+        rdb::parse(input, &mut snapshot, filter)?;
+        snapshot.sorter.finish()
+    }
+
+    fn compare_entries(&self, left: &[u8], right: &[u8]) -> RdbResult<Option<String>> {
+        if left == right {
+            return Ok(None);
+        }
+
+        let left = Entry::decode(left)?;
+        let right = Entry::decode(right)?;
+
+        if left.typ != right.typ {
+            return Ok(Some(format!(
+                "type differs: {} vs {}",
+                type_name(left.typ),
+                type_name(right.typ)
+            )));
+        }
+
+        match (left.expiry, right.expiry) {
+            (None, None) => {}
+            (Some(a), Some(b)) if a.max(b) - a.min(b) <= self.ttl_tolerance => {}
+            (a, b) => {
+                let show = |expiry: Option<u64>| expiry.map_or("none".into(), |e| e.to_string());
+                return Ok(Some(format!("expiry differs: {} vs {}", show(a), show(b))));
+            }
+        }
+
+        if left.items.len() != right.items.len() {
+            return Ok(Some(format!(
+                "length differs: {} vs {}",
+                left.items.len(),
+                right.items.len()
+            )));
+        }
+
+        let is_sorted_set = left.typ == type_code(Type::SortedSet);
+        for (a, b) in left.items.iter().zip(right.items.iter()) {
+            if a.0 != b.0 {
+                return Ok(Some("value differs".into()));
+            }
+            if a.1 == b.1 {
+                continue;
+            }
+
+            match (is_sorted_set, score(&a.1), score(&b.1)) {
+                (true, Some(x), Some(y)) if (x - y).abs() <= self.score_epsilon => {}
+                (true, Some(x), Some(y)) => {
+                    return Ok(Some(format!(
+                        "score of {} differs: {} vs {}",
+                        String::from_utf8_lossy(&a.0),
+                        x,
+                        y
+                    )))
+                }
+                _ => return Ok(Some("value differs".into())),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Compares the keys of `left` and `right` that pass the filter and
+    /// calls `report` for every difference, ordered by database and key.
+    pub fn compare<A, B, L, C>(&self, left: A, right: B, filter: L, mut report: C) -> RdbResult<()>
+    where
+        A: Read,
+        B: Read,
+        L: Filter,
+        C: FnMut(Difference) -> RdbResult<()>,
+    {
+        let mut left = self.snapshot(left, &filter)?.map(|r| r.map(split_record));
+        let mut right = self.snapshot(right, &filter)?.map(|r| r.map(split_record));
+
+        let mut a = left.next().transpose()?;
+        let mut b = right.next().transpose()?;
+        loop {
+            let ordering = match (&a, &b) {
+                (None, None) => return Ok(()),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some(x), Some(y)) => (x.0, &x.1).cmp(&(y.0, &y.1)),
+            };
+
+            match ordering {
+                std::cmp::Ordering::Less => {
+                    let (db, key, _) = a.take().unwrap();
+                    report(Difference::Removed { db, key })?;
+                    a = left.next().transpose()?;
+                }
+                std::cmp::Ordering::Greater => {
+                    let (db, key, _) = b.take().unwrap();
+                    report(Difference::Added { db, key })?;
+                    b = right.next().transpose()?;
+                }
+                std::cmp::Ordering::Equal => {
+                    let (db, key, x) = a.take().unwrap();
+                    let (_, _, y) = b.take().unwrap();
+                    if let Some(reason) = self.compare_entries(&x, &y)? {
+                        report(Difference::Changed { db, key, reason })?;
+                    }
+                    a = left.next().transpose()?;
+                    b = right.next().transpose()?;
+                }
+            }
+        }
+    }
+}
+
+impl Default for Diff {
+    fn default() -> Diff {
+        Diff::new()
+    }
+}
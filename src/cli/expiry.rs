@@ -0,0 +1,255 @@
+//! Finds the time windows in which unusually many keys expire at once.
+//!
+//! Keys expiring together keep Redis busy in its active expiry cycle, which
+//! shows up as latency spikes. Expiries are grouped into windows of a fixed
+//! length, and a window is a storm if its keys or bytes exceed the mean of
+//! all windows in which any key expires by a factor. Bytes are counted like
+//! the `bytes` weight of the folded format.
+//!
+//! For each window the prefixes, i.e. the first segments of the keys, that
+//! contribute the most are reported.
+
+use std::collections::{BTreeMap, HashMap};
+
+use rdb::formatter::folded::Weight;
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, StreamId, ValueLength};
+use rdb::RdbResult;
+
+/// The keys expiring in one window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpiryWindow {
+    /// Unix timestamp in milliseconds of the start of the window.
+    pub start: u64,
+    pub keys: u64,
+    pub bytes: u64,
+    /// Whether the window holds unusually many keys or bytes.
+    pub storm: bool,
+    /// The prefixes contributing the most keys or bytes, with their weight.
+    pub prefixes: Vec<(String, u64)>,
+}
+
+#[derive(Default)]
+struct Window {
+    keys: u64,
+    bytes: u64,
+    prefixes: HashMap<Vec<u8>, (u64, u64)>,
+}
+
+impl Window {
+    fn weight(&self, weight: Weight) -> u64 {
+        match weight {
+            Weight::Bytes => self.bytes,
+            Weight::Count => self.keys,
+        }
+    }
+}
+
+/// A formatter collecting the expiries of all keys into windows.
+pub struct Expiries {
+    window: u64,
+    separator: u8,
+    weight: Weight,
+    factor: f64,
+    top: usize,
+    windows: BTreeMap<u64, Window>,
+    /// The window and prefix of the current key, if it expires.
+    current: Option<(u64, Vec<u8>)>,
+}
+
+impl Expiries {
+    /// Creates a formatter grouping expiries into windows of `window`
+    /// milliseconds.
+    pub fn new(window: u64) -> Expiries {
+        Expiries {
+            window: window.max(1),
+            separator: b':',
+            weight: Weight::Count,
+            factor: 10.0,
+            top: 3,
+            windows: BTreeMap::new(),
+            current: None,
+        }
+    }
+
+    /// Sets the byte the prefix of a key ends at. Defaults to `:`.
+    pub fn set_separator(&mut self, separator: u8) {
+        self.separator = separator;
+    }
+
+    /// Sets whether storms are detected by keys, the default, or bytes.
+    pub fn set_weight(&mut self, weight: Weight) {
+        self.weight = weight;
+    }
+
+    /// Sets how many times the mean a window must hold to be a storm.
+    /// Defaults to 10.
+    pub fn set_factor(&mut self, factor: f64) {
+        self.factor = factor;
+    }
+
+    /// Sets how many prefixes are reported per window. Defaults to 3.
+    pub fn set_top(&mut self, top: usize) {
+        self.top = top;
+    }
+
+    /// All windows in which keys expire, in time order.
+    pub fn report(&self) -> Vec<ExpiryWindow> {
+        let total: u64 = self.windows.values().map(|w| w.weight(self.weight)).sum();
+        let mean = total as f64 / self.windows.len().max(1) as f64;
+
+        self.windows
+            .iter()
+            .map(|(start, window)| {
+                let mut prefixes: Vec<_> = window
+                    .prefixes
+                    .iter()
+                    .map(|(prefix, (keys, bytes))| {
+                        let weight = match self.weight {
+                            Weight::Bytes => *bytes,
+                            Weight::Count => *keys,
+                        };
+                        (String::from_utf8_lossy(prefix).into_owned(), weight)
+                    })
+                    .collect();
+                prefixes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                prefixes.truncate(self.top);
+
+                ExpiryWindow {
+                    start: *start,
+                    keys: window.keys,
+                    bytes: window.bytes,
+                    storm: window.weight(self.weight) as f64 > mean * self.factor,
+                    prefixes,
+                }
+            })
+            .collect()
+    }
+
+    fn start_key(&mut self, key: &[u8], expiry: Option<u64>) {
+        self.current = expiry.map(|expiry| {
+            let prefix = key.split(|b| *b == self.separator).next().unwrap_or(key);
+            (expiry - expiry % self.window, prefix.to_vec())
+        });
+        if let Some((start, prefix)) = &self.current {
+            let window = self.windows.entry(*start).or_default();
+            window.keys += 1;
+            window.prefixes.entry(prefix.clone()).or_default().0 += 1;
+        }
+        self.add(key.len());
+    }
+
+    fn add(&mut self, bytes: usize) {
+        if let Some((start, prefix)) = &self.current {
+            if let Some(window) = self.windows.get_mut(start) {
+                window.bytes += bytes as u64;
+                if let Some(prefix) = window.prefixes.get_mut(prefix) {
+                    prefix.1 += bytes as u64;
+                }
+            }
+        }
+    }
+}
+
+impl Formatter for Expiries {
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.add(length.bytes as usize);
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field.len() + value.len());
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member.len());
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member.len() + std::mem::size_of::<f64>());
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let fields: usize = fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum();
+        self.add(std::mem::size_of::<StreamId>() + fields);
+        Ok(())
+    }
+}
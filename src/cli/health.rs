@@ -0,0 +1,117 @@
+//! Checks a dump for the problems `--fail-on` can turn into a failure.
+//!
+//! `inspect` parses a dump in strict mode and reports its warnings, whether
+//! it can be parsed at all and whether it holds any keys. Each problem falls
+//! under a `Condition` with its own exit status, so scripts can tell them
+//! apart without reading the messages.
+
+use std::io::Read;
+
+use rdb::filter::Simple;
+use rdb::formatter::Nil;
+use rdb::RdbParser;
+
+/// A condition of a dump that `--fail-on` can turn into a failure.
+///
+/// Ordered by severity, the exit status is that of the most severe
+/// condition found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Condition {
+    /// The dump can't be parsed.
+    Corrupt,
+    /// The dump parses, but strict mode found inconsistencies.
+    Warnings,
+    /// The dump holds no keys.
+    Empty,
+    /// Tolerant mode accepted quirks of a managed service's dump, see
+    /// `RdbParser::set_tolerant`.
+    Quirks,
+}
+
+impl Condition {
+    pub fn from_name(name: &str) -> Option<Condition> {
+        match name {
+            "corrupt" => Some(Condition::Corrupt),
+            "warnings" => Some(Condition::Warnings),
+            "empty" => Some(Condition::Empty),
+            "quirks" => Some(Condition::Quirks),
+            _ => None,
+        }
+    }
+
+    /// The exit status of a run failing on this condition.
+    pub fn status(self) -> i32 {
+        match self {
+            Condition::Warnings => 3,
+            Condition::Corrupt => 4,
+            Condition::Empty => 5,
+            Condition::Quirks => 7,
+        }
+    }
+}
+
+/// Parses a comma-separated list of condition names, e.g.
+/// `"warnings,corrupt"`.
+pub fn conditions(names: &str) -> Result<Vec<Condition>, String> {
+    names
+        .split(',')
+        .map(|name| {
+            Condition::from_name(name.trim())
+                .ok_or_else(|| format!("Unknown condition: {}", name.trim()))
+        })
+        .collect()
+}
+
+/// A problem found in a dump and the condition it falls under.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub condition: Condition,
+    pub message: String,
+}
+
+/// Parses a dump in strict mode and returns the problems found.
+///
+/// Every key counts against `Condition::Empty`, including module values
+/// skipped in lenient mode. In tolerant mode the quirks of managed
+/// services' dumps fall under `Condition::Quirks` instead of
+/// `Condition::Warnings`.
+pub fn inspect<R: Read>(input: R, lenient: bool, tolerant: bool) -> Vec<Finding> {
+    let mut parser = RdbParser::new(input, Nil::new(), Simple::new());
+    parser.set_strict(true);
+    parser.set_lenient(lenient);
+    parser.set_tolerant(tolerant);
+    let res = parser.parse();
+
+    let mut findings: Vec<_> = parser
+        .warnings()
+        .iter()
+        .map(|warning| Finding {
+            condition: Condition::Warnings,
+            message: warning.to_string(),
+        })
+        .collect();
+    findings.extend(parser.ignored().iter().map(|quirk| Finding {
+        condition: Condition::Quirks,
+        message: format!("ignored {}", quirk),
+    }));
+
+    let keys: u64 = parser
+        .summary()
+        .databases
+        .iter()
+        .map(|size| u64::from(size.keys))
+        .sum();
+    match res {
+        Err(err) => findings.push(Finding {
+            condition: Condition::Corrupt,
+            message: format!("parsing failed: {}", err),
+        }),
+        Ok(()) if keys == 0 => findings.push(Finding {
+            condition: Condition::Empty,
+            message: "no keys".to_owned(),
+        }),
+        Ok(()) => {}
+    }
+
+    findings
+}
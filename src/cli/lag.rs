@@ -0,0 +1,148 @@
+//! Reports the backlog of each consumer group of the streams in a dump.
+//!
+//! For every group this counts the entries still in the stream that were not
+//! delivered to it yet, the entries delivered but not acknowledged, and the
+//! lag as `XINFO GROUPS` reports it: the entries added to the stream since
+//! the last one the group read, including deleted ones. Redis can only tell
+//! the lag from its counters if no entry after the group's last delivered ID
+//! was deleted, otherwise it is unknown, as it is for dumps before stream
+//! encoding v2 (Redis 7.0).
+
+use std::fmt;
+
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry};
+use rdb::RdbResult;
+
+/// The backlog of a consumer group.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupLag {
+    pub db: u32,
+    pub key: Vec<u8>,
+    pub group: Vec<u8>,
+    /// The entries in the stream after the group's last delivered ID.
+    pub undelivered: u64,
+    /// The entries delivered to the group's consumers but not acknowledged.
+    pub pending: u64,
+    pub lag: Option<u64>,
+}
+
+impl fmt::Display for GroupLag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "db={} {:?} group {:?}: {} undelivered, {} pending, lag ",
+            self.db,
+            String::from_utf8_lossy(&self.key),
+            String::from_utf8_lossy(&self.group),
+            self.undelivered,
+            self.pending
+        )?;
+        match self.lag {
+            Some(lag) => write!(f, "{}", lag),
+            None => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A formatter collecting the backlog of every consumer group.
+#[derive(Default)]
+pub struct Lag {
+    db: u32,
+    /// The IDs of the current stream's entries, in ascending order.
+    ids: Vec<StreamId>,
+    metadata: Option<StreamMetadata>,
+    groups: Vec<GroupLag>,
+}
+
+impl Lag {
+    pub fn new() -> Lag {
+        Lag::default()
+    }
+
+    /// The backlog of every group, in the order of the dump.
+    pub fn report(&self) -> &[GroupLag] {
+        &self.groups
+    }
+
+    fn lag(&self, group: &StreamGroup) -> Option<u64> {
+        let metadata = self.metadata.as_ref()?;
+        if group.last_id >= metadata.last_id {
+            return Some(0);
+        }
+
+        // Deleted entries after the last delivered ID were never read, but
+        // are counted as added.
+        let deleted = metadata.max_deleted_entry_id.unwrap_or_default();
+        if deleted != StreamId::default() && deleted > group.last_id {
+            return None;
+        }
+
+        Some(metadata.entries_added?.saturating_sub(group.entries_read?))
+    }
+}
+
+impl Formatter for Lag {
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.db = db_number;
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.ids.clear();
+        self.metadata = None;
+        Ok(())
+    }
+
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        _fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.ids.push(id);
+        Ok(())
+    }
+
+    fn stream_metadata(&mut self, _key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.metadata = Some(metadata.clone());
+        Ok(())
+    }
+
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        let delivered = self.ids.partition_point(|id| *id <= group.last_id);
+        let lag = GroupLag {
+            db: self.db,
+            key: key.to_vec(),
+            group: group.name.clone(),
+            undelivered: (self.ids.len() - delivered) as u64,
+            pending: 0,
+            lag: self.lag(group),
+        };
+        self.groups.push(lag);
+        Ok(())
+    }
+
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        _entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        let lag = self
+            .groups
+            .iter_mut()
+            .rev()
+            .find(|lag| lag.key == key && lag.group == group);
+        if let Some(lag) = lag {
+            lag.pending += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! Orders backups by the replication history they belong to.
+//!
+//! Backups written by the same server, or by its replicas, share a
+//! `repl-id` and can be ordered by `repl-offset`: a higher offset has seen
+//! more of the replication stream. Backups of different histories cannot
+//! be compared this way, so they are grouped and the groups are ordered by
+//! the creation time of their most recent backup.
+
+use std::collections::BTreeMap;
+
+use rdb::Header;
+
+/// The backups sharing one replication ID.
+#[derive(Debug)]
+pub struct History<T> {
+    /// `repl-id` of the backups, `None` for those written without one.
+    pub repl_id: Option<String>,
+    /// The backups, ordered by replication offset, then creation time, then
+    /// source. The most recent one comes last.
+    pub backups: Vec<(T, Header)>,
+}
+
+/// Groups `backups` by replication ID and orders them within each group.
+/// The history written to most recently comes last.
+///
+/// `T` identifies a backup, usually by its path, and breaks ties between
+/// backups with the same offset and creation time.
+pub fn histories<T: Ord>(backups: impl IntoIterator<Item = (T, Header)>) -> Vec<History<T>> {
+    let mut grouped: BTreeMap<Option<String>, Vec<(T, Header)>> = BTreeMap::new();
+    for (source, header) in backups {
+        grouped
+            .entry(header.repl_id.clone())
+            .or_default()
+            .push((source, header));
+    }
+
+    let mut histories: Vec<_> = grouped
+        .into_iter()
+        .map(|(repl_id, mut backups)| {
+            backups.sort_by(|(a, ha), (b, hb)| {
+                (ha.repl_offset, ha.ctime, a).cmp(&(hb.repl_offset, hb.ctime, b))
+            });
+            History { repl_id, backups }
+        })
+        .collect();
+    histories.sort_by_key(|history| history.backups.iter().filter_map(|(_, h)| h.ctime).max());
+    histories
+}
@@ -0,0 +1,345 @@
+//! Checks a dump against a manifest of expected keys.
+//!
+//! A manifest is a YAML document listing key patterns together with the
+//! number of keys expected to match them:
+//!
+//! ```yaml
+//! databases: [0]
+//! total:
+//!   min: 1000
+//! keys:
+//!   - pattern: "^user:[0-9]+$"
+//!     type: hash
+//!     count: 1200
+//!     tolerance: 5%
+//!   - pattern: "^session:"
+//!     min: 1
+//!     max: 500
+//! ```
+//!
+//! `count` may be combined with a `tolerance`, either an absolute number of
+//! keys or a percentage. If a rule names a `type`, keys matching the pattern
+//! with a different type are reported as well.
+
+use regex::bytes::Regex;
+use serde::Deserialize;
+use std::fmt;
+use std::io::Read;
+
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, FilterInfo, Type, ValueLength};
+use rdb::{RdbError, RdbResult};
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Tolerance {
+    Absolute(u64),
+    Relative(String),
+}
+
+/// The accepted range of a key count.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    count: Option<u64>,
+    tolerance: Option<Tolerance>,
+    min: Option<u64>,
+    max: Option<u64>,
+}
+
+impl Bounds {
+    fn range(&self) -> RdbResult<(u64, u64)> {
+        let (mut min, mut max) = (0, u64::MAX);
+
+        if let Some(count) = self.count {
+            let tolerance = match self.tolerance {
+                None => 0,
+                Some(Tolerance::Absolute(tolerance)) => tolerance,
+                Some(Tolerance::Relative(ref percent)) => {
+                    let percent: f64 = percent
+                        .trim()
+                        .strip_suffix('%')
+                        .and_then(|percent| percent.trim().parse().ok())
+                        .ok_or_else(|| {
+                            RdbError::Other(format!("Invalid tolerance: {:?}", percent))
+                        })?;
+                    (count as f64 * percent / 100.0).round() as u64
+                }
+            };
+            min = count.saturating_sub(tolerance);
+            max = count.saturating_add(tolerance);
+        }
+
+        if let Some(lower) = self.min {
+            min = min.max(lower);
+        }
+        if let Some(upper) = self.max {
+            max = max.min(upper);
+        }
+
+        Ok((min, max))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawRule {
+    pattern: String,
+    #[serde(rename = "type")]
+    typ: Option<String>,
+    #[serde(flatten)]
+    bounds: Bounds,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawManifest {
+    #[serde(default)]
+    databases: Vec<u32>,
+    total: Option<Bounds>,
+    #[serde(default)]
+    keys: Vec<RawRule>,
+}
+
+struct Rule {
+    pattern: Regex,
+    typ: Option<Type>,
+    min: u64,
+    max: u64,
+}
+
+/// The expectations of a manifest, ready to be checked.
+pub struct Manifest {
+    databases: Vec<u32>,
+    total: (u64, u64),
+    rules: Vec<Rule>,
+}
+
+impl Manifest {
+    /// Reads a manifest from a YAML document.
+    pub fn from_reader<R: Read>(input: R) -> RdbResult<Manifest> {
+        let raw: RawManifest = serde_yaml::from_reader(input)
+            .map_err(|err| RdbError::Other(format!("Invalid manifest: {}", err)))?;
+
+        let total = match raw.total {
+            Some(bounds) => bounds.range()?,
+            None => (0, u64::MAX),
+        };
+
+        let mut rules = Vec::with_capacity(raw.keys.len());
+        for rule in raw.keys {
+            let pattern = Regex::new(&rule.pattern).map_err(|err| {
+                RdbError::Other(format!("Invalid pattern {:?}: {}", rule.pattern, err))
+            })?;
+            let typ = match rule.typ {
+                None => None,
+                Some(name) => Some(
+                    Type::from_name(&name)
+                        .ok_or_else(|| RdbError::Other(format!("Unknown type: {}", name)))?,
+                ),
+            };
+            let (min, max) = rule.bounds.range()?;
+
+            rules.push(Rule {
+                pattern,
+                typ,
+                min,
+                max,
+            });
+        }
+
+        Ok(Manifest {
+            databases: raw.databases,
+            total,
+            rules,
+        })
+    }
+
+    /// Creates a formatter that counts the keys of a dump for this manifest.
+    pub fn checker(&self) -> Checker<'_> {
+        Checker {
+            manifest: self,
+            current_db: 0,
+            total: 0,
+            matches: vec![0; self.rules.len()],
+            wrong_type: vec![0; self.rules.len()],
+        }
+    }
+}
+
+/// A single expectation the dump did not meet.
+#[derive(Debug, Clone)]
+pub struct Violation {
+    /// The pattern of the rule, or `None` for the total key count.
+    pub pattern: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.pattern {
+            Some(ref pattern) => write!(f, "keys matching {:?}: {}", pattern, self.message),
+            None => write!(f, "total keys: {}", self.message),
+        }
+    }
+}
+
+fn count_violation(count: u64, (min, max): (u64, u64)) -> Option<String> {
+    if count < min || count > max {
+        let expected = if max == u64::MAX {
+            format!("at least {}", min)
+        } else if min == max {
+            format!("{}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        Some(format!("found {}, expected {}", count, expected))
+    } else {
+        None
+    }
+}
+
+/// A formatter counting the keys that match each rule of a `Manifest`.
+pub struct Checker<'a> {
+    manifest: &'a Manifest,
+    current_db: u32,
+    total: u64,
+    matches: Vec<u64>,
+    wrong_type: Vec<u64>,
+}
+
+impl<'a> Checker<'a> {
+    fn count(&mut self, key: &[u8], typ: Type) -> RdbResult<()> {
+        let manifest = self.manifest;
+        if !manifest.databases.is_empty() && !manifest.databases.contains(&self.current_db) {
+            return Ok(());
+        }
+
+        self.total += 1;
+        for (i, rule) in manifest.rules.iter().enumerate() {
+            if !rule.pattern.is_match(key) {
+                continue;
+            }
+
+            match rule.typ {
+                Some(expected) if expected != typ => self.wrong_type[i] += 1,
+                _ => self.matches[i] += 1,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compares the counted keys with the manifest.
+    pub fn violations(&self) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        if let Some(message) = count_violation(self.total, self.manifest.total) {
+            violations.push(Violation {
+                pattern: None,
+                message,
+            });
+        }
+
+        for (i, rule) in self.manifest.rules.iter().enumerate() {
+            let pattern = Some(rule.pattern.as_str().to_owned());
+            if let Some(message) = count_violation(self.matches[i], (rule.min, rule.max)) {
+                violations.push(Violation {
+                    pattern: pattern.clone(),
+                    message,
+                });
+            }
+            if self.wrong_type[i] > 0 {
+                violations.push(Violation {
+                    pattern,
+                    message: format!("{} keys are not of the expected type", self.wrong_type[i]),
+                });
+            }
+        }
+
+        violations
+    }
+}
+
+impl<'a> Formatter for Checker<'a> {
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.current_db = db_index;
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], _value: &[u8], _expiry: Option<u64>) -> RdbResult<()> {
+        self.count(key, Type::String)
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        _expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.count(key, length.typ)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.count(key, Type::Hash)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.count(key, Type::Set)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.count(key, Type::List)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.count(key, Type::SortedSet)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.count(key, Type::Stream)
+    }
+
+    fn module(&mut self, key: &[u8], _module_name: &str) -> RdbResult<()> {
+        self.count(key, Type::Module)
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        _filter: &FilterInfo,
+        _expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.count(key, Type::Module)
+    }
+}
@@ -0,0 +1,672 @@
+//! Estimates the memory a Redis server needs for the keys of a dump.
+//!
+//! Redis allocates keys, values and the elements of collections separately,
+//! and jemalloc rounds every allocation up to the next of its size classes.
+//! The difference between the requested and the allocated bytes is the
+//! internal fragmentation, which makes raw value sizes underestimate the
+//! RSS of a server, especially for many small elements.
+//!
+//! By default, the estimate models the allocations of Redis 7 on a 64-bit
+//! system using jemalloc, adapting the pointer size and the layout of SDS
+//! strings to the `redis-bits` and `redis-ver` fields of the dump. A
+//! `MemoryProfile` sets them explicitly, e.g. for other allocators:
+//!
+//! * every key takes a dict entry, its name as an SDS string and an object
+//!   for its value, and one more dict entry if it expires
+//! * strings are stored as integers in the object, embedded into it up to
+//!   44 bytes, or as a separate SDS string
+//! * listpacks, ziplists and intsets are a single allocation of their
+//!   encoded size, long lists are split into listpacks of 8 KB
+//! * hash tables take a dict, a bucket array of the next power of two, and
+//!   an entry and SDS strings per element; sorted sets add a skiplist node
+//! * streams are split into listpacks of 4 KB
+//!
+//! The main dictionaries of the databases and values of modules are not
+//! included, and lists are always modelled as quicklists, which replaced
+//! linked lists in Redis 3.2.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, StreamId, Type, ValueLength};
+use rdb::RdbResult;
+
+/// The size listpacks of lists are limited to.
+const QUICKLIST_FILL: u64 = 8192;
+/// The number of levels of the node heading a skiplist.
+const SKIPLIST_MAX_LEVEL: u64 = 32;
+/// The size listpacks of streams are limited to.
+const STREAM_NODE_MAX: u64 = 4096;
+/// The header and end marker of a listpack.
+const LISTPACK: u64 = 7;
+/// The encoding and back length of a small listpack entry.
+const LISTPACK_ENTRY: u64 = 2;
+
+/// Rounds `size` up to jemalloc's next size class.
+///
+/// Classes are 8 bytes, multiples of 16 up to 128 bytes, and then four per
+/// doubling, e.g. 160, 192, 224 and 256.
+pub fn size_class(size: u64) -> u64 {
+    if size <= 8 {
+        return 8;
+    }
+    if size <= 128 {
+        return size.div_ceil(16) * 16;
+    }
+
+    let spacing = size.next_power_of_two() / 8;
+    size.div_ceil(spacing) * spacing
+}
+
+/// How SDS strings store their length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SdsHeaders {
+    /// Before Redis 3.2: the length and free space as 32-bit integers.
+    Legacy,
+    /// From Redis 3.2 on: 1 to 17 bytes, depending on the length.
+    Typed,
+}
+
+/// The allocator Redis was built with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Allocator {
+    /// Redis's default on Linux, rounding up to its size classes.
+    Jemalloc,
+    /// The malloc of glibc, which adds a size header and aligns to two
+    /// pointers.
+    Libc,
+}
+
+/// The parameters of the memory model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryProfile {
+    /// 8 bytes on 64-bit systems, 4 on 32-bit ones.
+    pub pointer_size: u64,
+    pub sds_headers: SdsHeaders,
+    /// The average number of elements per bucket of hash tables. Redis
+    /// sizes them for 1 on load.
+    pub load_factor: f64,
+    pub allocator: Allocator,
+}
+
+impl MemoryProfile {
+    /// The profile of a 64-bit build of the given Redis version with
+    /// jemalloc, e.g. `3.0.7` or `7.2`, or `None` for versions before 3.
+    pub fn redis(version: &str) -> Option<MemoryProfile> {
+        let mut parts = version.split('.').map(|part| part.parse::<u32>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+        if major < 3 {
+            return None;
+        }
+
+        let sds_headers = if (major, minor) < (3, 2) {
+            SdsHeaders::Legacy
+        } else {
+            SdsHeaders::Typed
+        };
+        Some(MemoryProfile {
+            sds_headers,
+            ..MemoryProfile::default()
+        })
+    }
+
+    /// The bytes the allocator hands out for a request of `size` bytes.
+    pub fn allocation(&self, size: u64) -> u64 {
+        match self.allocator {
+            Allocator::Jemalloc => size_class(size),
+            Allocator::Libc => {
+                let alignment = 2 * self.pointer_size;
+                ((size + self.pointer_size).div_ceil(alignment) * alignment)
+                    .max(4 * self.pointer_size)
+            }
+        }
+    }
+
+    /// The size of an SDS string of `len` bytes, including its header and
+    /// terminating null byte.
+    fn sds(&self, len: u64) -> u64 {
+        let header = match (self.sds_headers, len) {
+            (SdsHeaders::Legacy, _) => 8,
+            (SdsHeaders::Typed, 0..=31) => 1,
+            (SdsHeaders::Typed, 32..=255) => 3,
+            (SdsHeaders::Typed, 256..=65535) => 5,
+            (SdsHeaders::Typed, 65536..=0xFFFF_FFFF) => 9,
+            (SdsHeaders::Typed, _) => 17,
+        };
+        header + len + 1
+    }
+
+    /// The longest string embedded into its object, which fills an
+    /// allocation of 64 bytes.
+    fn embstr_max(&self) -> u64 {
+        match self.sds_headers {
+            SdsHeaders::Legacy => 39,
+            SdsHeaders::Typed => 44,
+        }
+    }
+
+    fn robj(&self) -> u64 {
+        8 + self.pointer_size
+    }
+
+    fn dict(&self) -> u64 {
+        7 * self.pointer_size
+    }
+
+    fn dict_entry(&self) -> u64 {
+        3 * self.pointer_size
+    }
+
+    /// The bucket array of a hash table holding `elements`.
+    fn buckets(&self, elements: u64) -> u64 {
+        let buckets = (elements as f64 / self.load_factor.max(f64::MIN_POSITIVE)).ceil() as u64;
+        self.pointer_size * buckets.max(1).next_power_of_two()
+    }
+
+    fn quicklist(&self) -> u64 {
+        5 * self.pointer_size
+    }
+
+    fn quicklist_node(&self) -> u64 {
+        4 * self.pointer_size
+    }
+
+    fn zset(&self) -> u64 {
+        2 * self.pointer_size
+    }
+
+    fn skiplist(&self) -> u64 {
+        4 * self.pointer_size
+    }
+
+    /// A skiplist node with `levels` levels: its member, score, backward
+    /// pointer, and forward pointer and span per level.
+    fn skiplist_node(&self, levels: u64) -> u64 {
+        2 * self.pointer_size + 8 + levels * (self.pointer_size + 8)
+    }
+
+    fn stream(&self) -> u64 {
+        8 * self.pointer_size
+    }
+
+    fn rax_node(&self) -> u64 {
+        6 * self.pointer_size
+    }
+}
+
+impl Default for MemoryProfile {
+    fn default() -> MemoryProfile {
+        MemoryProfile {
+            pointer_size: 8,
+            sds_headers: SdsHeaders::Typed,
+            load_factor: 1.0,
+            allocator: Allocator::Jemalloc,
+        }
+    }
+}
+
+fn is_integer(value: &[u8]) -> bool {
+    value.len() <= 20
+        && std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .is_some_and(|number| number.to_string().as_bytes() == value)
+}
+
+/// Requested and allocated bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Bytes {
+    requested: u64,
+    allocated: u64,
+}
+
+/// The estimated memory of the keys of one type and prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryUsage {
+    pub typ: Type,
+    pub prefix: String,
+    pub keys: u64,
+    /// The bytes Redis asks the allocator for.
+    pub requested: u64,
+    /// The bytes the allocator hands out, rounded up to its size classes.
+    pub allocated: u64,
+}
+
+impl MemoryUsage {
+    /// The share of the allocated bytes lost to rounding.
+    pub fn fragmentation(&self) -> f64 {
+        if self.allocated == 0 {
+            return 0.0;
+        }
+        (self.allocated - self.requested) as f64 / self.allocated as f64
+    }
+}
+
+/// The allocations of one size class of the allocator.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeClass {
+    pub size: u64,
+    pub allocations: u64,
+    pub requested: u64,
+}
+
+/// How the elements of the current key are stored.
+enum Elements {
+    /// In a single allocation made at the start of the key.
+    Compact,
+    /// In a hash table, each in its own SDS strings.
+    Hashtable,
+    /// In a skiplist and a hash table.
+    Skiplist,
+    /// In listpacks of at most this many bytes, filled up to `node`.
+    Listpacks { max: u64, node: u64, overhead: u64 },
+}
+
+/// A formatter estimating the memory of all keys by type and prefix.
+pub struct Memory {
+    separator: u8,
+    profile: MemoryProfile,
+    /// Whether the profile follows the `redis-ver` and `redis-bits` fields.
+    detect: bool,
+    usage: BTreeMap<(Type, Vec<u8>), (u64, Bytes)>,
+    classes: BTreeMap<u64, (u64, u64)>,
+    current: Option<(Type, Vec<u8>)>,
+    bytes: Bytes,
+    elements: Elements,
+    /// How many of the largest keys are kept, and the name of the current
+    /// key if any are.
+    largest: usize,
+    key: Vec<u8>,
+    keys: BinaryHeap<Reverse<(u64, Vec<u8>)>>,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory {
+            separator: b':',
+            profile: MemoryProfile::default(),
+            detect: true,
+            usage: BTreeMap::new(),
+            classes: BTreeMap::new(),
+            current: None,
+            bytes: Bytes::default(),
+            elements: Elements::Compact,
+            largest: 0,
+            key: Vec::new(),
+            keys: BinaryHeap::new(),
+        }
+    }
+
+    /// Sets the byte the prefix of a key ends at. Defaults to `:`.
+    pub fn set_separator(&mut self, separator: u8) {
+        self.separator = separator;
+    }
+
+    /// Sets the parameters of the model, instead of detecting the pointer
+    /// size and layout of SDS strings from the dump.
+    pub fn set_profile(&mut self, profile: MemoryProfile) {
+        self.profile = profile;
+        self.detect = false;
+    }
+
+    pub fn set_allocator(&mut self, allocator: Allocator) {
+        self.profile.allocator = allocator;
+    }
+
+    pub fn set_load_factor(&mut self, load_factor: f64) {
+        self.profile.load_factor = load_factor;
+    }
+
+    /// Keeps the names of the `count` keys allocating the most memory.
+    /// Defaults to none.
+    pub fn track_largest(&mut self, count: usize) {
+        self.largest = count;
+    }
+
+    /// The keys kept by `track_largest` with their allocated bytes, largest
+    /// first.
+    pub fn largest_keys(&self) -> Vec<(Vec<u8>, u64)> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|Reverse((allocated, key))| (key.clone(), *allocated))
+            .collect();
+        keys.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        keys
+    }
+
+    /// The bytes allocated for all keys.
+    pub fn allocated(&self) -> u64 {
+        self.usage.values().map(|(_, bytes)| bytes.allocated).sum()
+    }
+
+    /// The memory of the keys of each type and prefix, largest first.
+    pub fn report(&self) -> Vec<MemoryUsage> {
+        let mut report: Vec<_> = self
+            .usage
+            .iter()
+            .map(|((typ, prefix), (keys, bytes))| MemoryUsage {
+                typ: *typ,
+                prefix: String::from_utf8_lossy(prefix).into_owned(),
+                keys: *keys,
+                requested: bytes.requested,
+                allocated: bytes.allocated,
+            })
+            .collect();
+        report.sort_by_key(|usage| Reverse(usage.allocated));
+        report
+    }
+
+    /// The allocations of each size class, smallest first.
+    pub fn size_classes(&self) -> Vec<SizeClass> {
+        self.classes
+            .iter()
+            .map(|(size, (allocations, requested))| SizeClass {
+                size: *size,
+                allocations: *allocations,
+                requested: *requested,
+            })
+            .collect()
+    }
+
+    fn alloc(&mut self, size: u64) {
+        if size == 0 {
+            return;
+        }
+        let class = self.profile.allocation(size);
+        self.bytes.requested += size;
+        self.bytes.allocated += class;
+        let stats = self.classes.entry(class).or_default();
+        stats.0 += 1;
+        stats.1 += size;
+    }
+
+    fn start_key(&mut self, key: &[u8], typ: Type, expiry: Option<u64>) {
+        self.end_key();
+
+        let prefix = key.split(|b| *b == self.separator).next().unwrap_or(key);
+        self.current = Some((typ, prefix.to_vec()));
+        if self.largest > 0 {
+            self.key = key.to_vec();
+        }
+        self.elements = Elements::Compact;
+
+        self.alloc(self.profile.dict_entry());
+        self.alloc(self.profile.sds(key.len() as u64));
+        if expiry.is_some() {
+            self.alloc(self.profile.dict_entry());
+        }
+    }
+
+    /// Adds the bytes of the current key to its type and prefix.
+    fn end_key(&mut self) {
+        if let Elements::Listpacks { node, .. } = self.elements {
+            if node > 0 {
+                self.alloc(LISTPACK + node);
+            }
+        }
+        self.elements = Elements::Compact;
+
+        if let Some(current) = self.current.take() {
+            let bytes = std::mem::take(&mut self.bytes);
+            if self.largest > 0 {
+                let key = std::mem::take(&mut self.key);
+                self.keys.push(Reverse((bytes.allocated, key)));
+                if self.keys.len() > self.largest {
+                    self.keys.pop();
+                }
+            }
+            let usage = self.usage.entry(current).or_default();
+            usage.0 += 1;
+            usage.1.requested += bytes.requested;
+            usage.1.allocated += bytes.allocated;
+        }
+    }
+
+    fn start_collection(&mut self, typ: Type, length: u32, info: EncodingType) {
+        self.alloc(self.profile.robj());
+        match info {
+            EncodingType::Intset(raw_length)
+            | EncodingType::Ziplist(raw_length)
+            | EncodingType::Zipmap(raw_length)
+            | EncodingType::Listpack(raw_length) => {
+                self.alloc(raw_length);
+                self.elements = Elements::Compact;
+            }
+            EncodingType::LinkedList if typ == Type::List => {
+                self.alloc(self.profile.quicklist());
+                self.elements = Elements::Listpacks {
+                    max: QUICKLIST_FILL,
+                    node: 0,
+                    overhead: self.profile.quicklist_node(),
+                };
+            }
+            EncodingType::Hashtable | EncodingType::Skiplist | EncodingType::LinkedList => {
+                self.alloc(self.profile.dict());
+                self.alloc(self.profile.buckets(length as u64));
+                self.elements = Elements::Hashtable;
+            }
+            EncodingType::Quicklist(_) | EncodingType::String | EncodingType::StreamListpacks => {
+                self.alloc(self.profile.quicklist());
+                self.elements = Elements::Listpacks {
+                    max: QUICKLIST_FILL,
+                    node: 0,
+                    overhead: self.profile.quicklist_node(),
+                };
+            }
+        }
+    }
+
+    /// Adds an entry of `len` bytes to the current listpack, starting a new
+    /// one once it is full.
+    fn add_to_listpack(&mut self, len: u64) {
+        let (max, node, overhead) = match self.elements {
+            Elements::Listpacks {
+                max,
+                node,
+                overhead,
+            } => (max, node, overhead),
+            _ => return,
+        };
+
+        let node = if node > 0 && node + len > max {
+            self.alloc(LISTPACK + node);
+            0
+        } else {
+            node
+        };
+        if node == 0 {
+            self.alloc(overhead);
+        }
+        self.elements = Elements::Listpacks {
+            max,
+            node: node + len,
+            overhead,
+        };
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new()
+    }
+}
+
+impl Formatter for Memory {
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        if !self.detect {
+            return Ok(());
+        }
+
+        match key {
+            b"redis-ver" => {
+                let profile = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(MemoryProfile::redis);
+                if let Some(profile) = profile {
+                    self.profile.sds_headers = profile.sds_headers;
+                }
+            }
+            b"redis-bits" => match value {
+                b"32" => self.profile.pointer_size = 4,
+                b"64" => self.profile.pointer_size = 8,
+                _ => {}
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.end_key();
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key, Type::String, expiry);
+        let len = value.len() as u64;
+        let robj = self.profile.robj();
+        if is_integer(value) {
+            self.alloc(robj);
+        } else if len <= self.profile.embstr_max() {
+            self.alloc(robj + self.profile.sds(len));
+        } else {
+            self.alloc(robj);
+            self.alloc(self.profile.sds(len));
+        }
+        Ok(())
+    }
+
+    fn module(&mut self, key: &[u8], _module_name: &str) -> RdbResult<()> {
+        self.start_key(key, Type::Module, None);
+        self.alloc(self.profile.robj());
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, length.typ, expiry);
+        self.alloc(self.profile.robj());
+        self.alloc(length.bytes);
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Hash, expiry);
+        self.start_collection(Type::Hash, length, info);
+        Ok(())
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        if let Elements::Hashtable = self.elements {
+            self.alloc(self.profile.dict_entry());
+            self.alloc(self.profile.sds(field.len() as u64));
+            self.alloc(self.profile.sds(value.len() as u64));
+        }
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Set, expiry);
+        self.start_collection(Type::Set, cardinality, info);
+        Ok(())
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        if let Elements::Hashtable = self.elements {
+            self.alloc(self.profile.dict_entry());
+            self.alloc(self.profile.sds(member.len() as u64));
+        }
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::List, expiry);
+        self.start_collection(Type::List, length, info);
+        Ok(())
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add_to_listpack(value.len() as u64 + LISTPACK_ENTRY);
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::SortedSet, expiry);
+        self.start_collection(Type::SortedSet, length, info);
+        if let Elements::Hashtable = self.elements {
+            self.alloc(self.profile.zset());
+            self.alloc(self.profile.skiplist());
+            self.alloc(self.profile.skiplist_node(SKIPLIST_MAX_LEVEL));
+            self.elements = Elements::Skiplist;
+        }
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        if let Elements::Skiplist = self.elements {
+            self.alloc(self.profile.dict_entry());
+            self.alloc(self.profile.skiplist_node(1));
+            self.alloc(self.profile.sds(member.len() as u64));
+        }
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Stream, expiry);
+        self.alloc(self.profile.robj());
+        self.alloc(self.profile.stream());
+        self.elements = Elements::Listpacks {
+            max: STREAM_NODE_MAX,
+            node: 0,
+            overhead: self.profile.rax_node(),
+        };
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        // The flags and ID of the entry, then its fields and values.
+        let fields: u64 = fields
+            .iter()
+            .map(|(field, value)| (field.len() + value.len()) as u64 + 2 * LISTPACK_ENTRY)
+            .sum();
+        self.add_to_listpack(4 * LISTPACK_ENTRY + fields);
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+//! The analyses and subcommands of the `rdb` binary, built on the library
+//! but not part of its API.
+
+use std::io::Read;
+
+use rdb::RdbResult;
+
+pub mod anomaly;
+pub mod diff;
+pub mod expiry;
+pub mod health;
+pub mod lag;
+pub mod lineage;
+pub mod manifest;
+pub mod memory;
+pub mod quota;
+pub mod restore;
+pub mod split;
+
+/// Reads exactly `len` bytes, growing the buffer as the data arrives, so a
+/// corrupt length fails at the end of the input instead of allocating it
+/// all at once.
+fn read_exact<T: Read>(reader: &mut T, len: usize) -> RdbResult<Vec<u8>> {
+    let mut buf = vec![];
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+
+    Ok(buf)
+}
@@ -0,0 +1,305 @@
+//! Reports the usage of each tenant of a shared instance against its quota.
+//!
+//! Tenants are identified by key prefix. The limits file is a YAML document
+//! mapping prefixes to the number of keys and bytes they may use:
+//!
+//! ```yaml
+//! "tenant:1:":
+//!   keys: 10000
+//!   bytes: 64MiB
+//! "tenant:2:":
+//!   bytes: 500KB
+//! ```
+//!
+//! Bytes are counted like the `bytes` weight of the folded format: the key
+//! names plus all fields, members and values, without Redis's per-object
+//! overhead. Sizes take the suffixes `KB`, `MB`, `GB` (powers of 1000) and
+//! `KiB`, `MiB`, `GiB` (powers of 1024). A key counts towards the longest
+//! prefix it starts with, keys without one are not counted.
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Read;
+
+use rdb::formatter::Formatter;
+use rdb::value::{EncodingType, StreamId, ValueLength};
+use rdb::{RdbError, RdbResult};
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(u64),
+    WithUnit(String),
+}
+
+impl Size {
+    fn bytes(&self) -> RdbResult<u64> {
+        let size = match self {
+            Size::Bytes(bytes) => return Ok(*bytes),
+            Size::WithUnit(size) => size.trim(),
+        };
+
+        let units = [
+            ("KiB", 1 << 10),
+            ("MiB", 1 << 20),
+            ("GiB", 1 << 30),
+            ("KB", 1_000),
+            ("MB", 1_000_000),
+            ("GB", 1_000_000_000),
+            ("B", 1),
+        ];
+        let (number, factor) = units
+            .iter()
+            .find_map(|(unit, factor)| Some((size.strip_suffix(unit)?, *factor)))
+            .unwrap_or((size, 1));
+        let number: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| RdbError::Other(format!("Invalid size: {:?}", size)))?;
+        if number < 0.0 {
+            return Err(RdbError::Other(format!("Invalid size: {:?}", size)));
+        }
+
+        Ok((number * factor as f64).round() as u64)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawQuota {
+    keys: Option<u64>,
+    bytes: Option<Size>,
+}
+
+struct Quota {
+    prefix: Vec<u8>,
+    keys: Option<u64>,
+    bytes: Option<u64>,
+}
+
+/// The quotas of all tenants, ready to be checked.
+pub struct Limits {
+    /// Sorted by descending prefix length, so the first match is the
+    /// longest.
+    quotas: Vec<Quota>,
+}
+
+impl Limits {
+    /// Reads the limits from a YAML document.
+    pub fn from_reader<R: Read>(input: R) -> RdbResult<Limits> {
+        let raw: BTreeMap<String, RawQuota> = serde_yaml::from_reader(input)
+            .map_err(|err| RdbError::Other(format!("Invalid limits: {}", err)))?;
+
+        let mut quotas = Vec::with_capacity(raw.len());
+        for (prefix, quota) in raw {
+            let bytes = match quota.bytes {
+                Some(size) => Some(size.bytes()?),
+                None => None,
+            };
+            quotas.push(Quota {
+                prefix: prefix.into_bytes(),
+                keys: quota.keys,
+                bytes,
+            });
+        }
+        quotas.sort_by_key(|quota| std::cmp::Reverse(quota.prefix.len()));
+
+        Ok(Limits { quotas })
+    }
+
+    /// Creates a formatter that measures the usage of each tenant.
+    pub fn usage(&self) -> Usage<'_> {
+        Usage {
+            limits: self,
+            keys: vec![0; self.quotas.len()],
+            bytes: vec![0; self.quotas.len()],
+            current: None,
+        }
+    }
+}
+
+/// The usage of a tenant compared to its quota.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TenantUsage {
+    pub prefix: String,
+    pub keys: u64,
+    pub bytes: u64,
+    pub max_keys: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl TenantUsage {
+    /// Whether the tenant uses more keys or bytes than allowed.
+    pub fn exceeded(&self) -> bool {
+        self.max_keys.is_some_and(|max| self.keys > max)
+            || self.max_bytes.is_some_and(|max| self.bytes > max)
+    }
+}
+
+impl fmt::Display for TenantUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit = |max: Option<u64>| max.map_or("-".to_owned(), |max| max.to_string());
+        write!(
+            f,
+            "{:?}: keys {}/{}, bytes {}/{}{}",
+            self.prefix,
+            self.keys,
+            limit(self.max_keys),
+            self.bytes,
+            limit(self.max_bytes),
+            if self.exceeded() { ", over quota" } else { "" }
+        )
+    }
+}
+
+/// A formatter adding up the keys and bytes of each tenant of `Limits`.
+pub struct Usage<'a> {
+    limits: &'a Limits,
+    keys: Vec<u64>,
+    bytes: Vec<u64>,
+    /// The tenant of the current key, if it has one.
+    current: Option<usize>,
+}
+
+impl<'a> Usage<'a> {
+    /// The usage of every tenant, sorted by prefix.
+    pub fn report(&self) -> Vec<TenantUsage> {
+        let mut report: Vec<_> = self
+            .limits
+            .quotas
+            .iter()
+            .enumerate()
+            .map(|(i, quota)| TenantUsage {
+                prefix: String::from_utf8_lossy(&quota.prefix).into_owned(),
+                keys: self.keys[i],
+                bytes: self.bytes[i],
+                max_keys: quota.keys,
+                max_bytes: quota.bytes,
+            })
+            .collect();
+        report.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+        report
+    }
+
+    fn start_key(&mut self, key: &[u8]) {
+        self.current = self
+            .limits
+            .quotas
+            .iter()
+            .position(|quota| key.starts_with(&quota.prefix));
+        if let Some(i) = self.current {
+            self.keys[i] += 1;
+        }
+        self.add(key.len());
+    }
+
+    fn add(&mut self, bytes: usize) {
+        if let Some(i) = self.current {
+            self.bytes[i] += bytes as u64;
+        }
+    }
+}
+
+impl<'a> Formatter for Usage<'a> {
+    fn set(&mut self, key: &[u8], value: &[u8], _expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key);
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        _expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        self.add(length.bytes as usize);
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field.len() + value.len());
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member.len());
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member.len() + std::mem::size_of::<f64>());
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let fields: usize = fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum();
+        self.add(std::mem::size_of::<StreamId>() + fields);
+        Ok(())
+    }
+}
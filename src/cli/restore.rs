@@ -0,0 +1,265 @@
+//! Restoring dumps into a running Redis server.
+//!
+//! `Restore` connects to a server and hands out a writer, e.g. for the
+//! `Protocol` formatter, whose commands are sent to the server as they are
+//! written. The replies are read on a separate thread, so commands are
+//! pipelined instead of waiting for each reply:
+//!
+//! ```rust,no_run
+//! use crate::cli::restore::Restore;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! let restore = Restore::connect("redis://127.0.0.1:6379").unwrap();
+//! let file = BufReader::new(File::open("dump.rdb").unwrap());
+//! let protocol = rdb::formatter::Protocol::with_writer(restore.writer().unwrap());
+//! rdb::parse(file, protocol, rdb::filter::Simple::new()).unwrap();
+//! let summary = restore.finish().unwrap();
+//! println!("{} replies, {} errors", summary.replies, summary.errors.len());
+//! ```
+
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::net::TcpStream;
+use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::read_exact;
+use rdb::{RdbError, RdbResult};
+
+const DEFAULT_PORT: u16 = 6379;
+
+#[inline]
+fn other_error(desc: impl Into<String>) -> RdbError {
+    RdbError::Other(desc.into())
+}
+
+/// Where to restore to, parsed from `redis://[[user]:password@]host[:port]`,
+/// with IPv6 hosts in brackets, e.g. `redis://[::1]:6379`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Target {
+    pub fn parse(url: &str) -> RdbResult<Target> {
+        let rest = url
+            .strip_prefix("redis://")
+            .ok_or_else(|| other_error(format!("Not a redis:// URL: {}", url)))?;
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+        if rest.contains('/') {
+            return Err(other_error(
+                "The target URL can't select a database, dumps select their own",
+            ));
+        }
+
+        let (credentials, address) = match rest.rfind('@') {
+            Some(at) => (Some(&rest[..at]), &rest[at + 1..]),
+            None => (None, rest),
+        };
+        let (user, password) = match credentials {
+            Some(credentials) => match credentials.find(':') {
+                Some(colon) if colon > 0 => (
+                    Some(credentials[..colon].to_owned()),
+                    Some(credentials[colon + 1..].to_owned()),
+                ),
+                Some(_) => (None, Some(credentials[1..].to_owned())),
+                None => (None, Some(credentials.to_owned())),
+            },
+            None => (None, None),
+        };
+
+        // IPv6 addresses are written in brackets, as they contain colons.
+        let (host, port) = match address.strip_prefix('[') {
+            Some(bracketed) => {
+                let end = bracketed
+                    .find(']')
+                    .ok_or_else(|| other_error(format!("Unclosed bracket in {}", url)))?;
+                (&bracketed[..end], &bracketed[end + 1..])
+            }
+            None => match address.find(':') {
+                Some(colon) => (&address[..colon], &address[colon..]),
+                None => (address, ""),
+            },
+        };
+        let port = match port {
+            "" => DEFAULT_PORT,
+            port => port
+                .strip_prefix(':')
+                .and_then(|port| port.parse().ok())
+                .ok_or_else(|| other_error(format!("Invalid port in {}", url)))?,
+        };
+        if host.is_empty() {
+            return Err(other_error(format!("Missing host in {}", url)));
+        }
+
+        Ok(Target {
+            host: host.to_owned(),
+            port,
+            user,
+            password,
+        })
+    }
+}
+
+/// What the server replied to the restored commands.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// The number of replies, including errors.
+    pub replies: u64,
+    /// The error replies, in the order they were received.
+    pub errors: Vec<String>,
+}
+
+/// A connection to a server that dumps are restored into.
+pub struct Restore {
+    stream: TcpStream,
+    replies: JoinHandle<RdbResult<Summary>>,
+    marker: Vec<u8>,
+}
+
+impl Restore {
+    /// Connects to the server at `url`, authenticating if it has a password.
+    pub fn connect(url: &str) -> RdbResult<Restore> {
+        let target = Target::parse(url)?;
+        let mut stream = TcpStream::connect((&target.host[..], target.port))?;
+
+        if let Some(ref password) = target.password {
+            let mut args = vec!["AUTH".as_bytes()];
+            if let Some(ref user) = target.user {
+                args.push(user.as_bytes());
+            }
+            args.push(password.as_bytes());
+            write_command(&mut stream, &args)?;
+
+            let mut reader = BufReader::new(stream.try_clone()?);
+            if let Reply::Error(err) = read_reply(&mut reader)? {
+                return Err(other_error(format!("Authentication failed: {}", err)));
+            }
+        }
+
+        // Echoed back last, so the reader knows all replies were received.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        let marker = format!("rdb-restore-{}-{}", process::id(), nanos).into_bytes();
+
+        let reader = BufReader::new(stream.try_clone()?);
+        let expected = marker.clone();
+        let replies = thread::spawn(move || read_replies(reader, &expected));
+
+        Ok(Restore {
+            stream,
+            replies,
+            marker,
+        })
+    }
+
+    /// A writer sending everything written to it to the server.
+    ///
+    /// It must be flushed, or dropped, before `finish` is called.
+    pub fn writer(&self) -> RdbResult<BufWriter<TcpStream>> {
+        Ok(BufWriter::new(self.stream.try_clone()?))
+    }
+
+    /// Sends a single command.
+    pub fn command(&mut self, args: &[&[u8]]) -> RdbResult<()> {
+        write_command(&mut self.stream, args)
+    }
+
+    /// Waits until the server replied to everything sent before.
+    pub fn finish(mut self) -> RdbResult<Summary> {
+        let marker = self.marker.clone();
+        write_command(&mut self.stream, &[b"ECHO", &marker])?;
+        self.stream.flush()?;
+
+        self.replies
+            .join()
+            .map_err(|_| other_error("Reading the replies failed"))?
+    }
+}
+
+fn write_command<W: Write>(out: &mut W, args: &[&[u8]]) -> RdbResult<()> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend_from_slice(arg);
+        command.extend_from_slice(b"\r\n");
+    }
+    out.write_all(&command)?;
+
+    Ok(())
+}
+
+enum Reply {
+    Error(String),
+    Bulk(Vec<u8>),
+    /// An array, such as the replies of a transaction, with the errors it
+    /// holds.
+    Array(Vec<String>),
+    Other,
+}
+
+fn read_line<R: BufRead>(input: &mut R) -> RdbResult<String> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Err(other_error("Connection closed by the server"));
+    }
+    Ok(line.trim_end_matches("\r\n").to_owned())
+}
+
+fn parse_length(line: &str) -> RdbResult<i64> {
+    line[1..]
+        .parse()
+        .map_err(|_| other_error(format!("Invalid reply from the server: {}", line)))
+}
+
+/// Reads one reply, keeping only the errors of arrays.
+fn read_reply<R: BufRead>(input: &mut R) -> RdbResult<Reply> {
+    let line = read_line(input)?;
+    match line.as_bytes().first() {
+        Some(b'-') => Ok(Reply::Error(line[1..].to_owned())),
+        Some(b'+') | Some(b':') => Ok(Reply::Other),
+        Some(b'$') => {
+            let length = parse_length(&line)?;
+            if length < 0 {
+                return Ok(Reply::Other);
+            }
+            let mut value = read_exact(input, length as usize + 2)?;
+            value.truncate(length as usize);
+            Ok(Reply::Bulk(value))
+        }
+        Some(b'*') => {
+            let mut errors = vec![];
+            for _ in 0..parse_length(&line)?.max(0) {
+                match read_reply(input)? {
+                    Reply::Error(err) => errors.push(err),
+                    Reply::Array(nested) => errors.extend(nested),
+                    _ => {}
+                }
+            }
+            Ok(Reply::Array(errors))
+        }
+        _ => Err(other_error(format!(
+            "Invalid reply from the server: {}",
+            line
+        ))),
+    }
+}
+
+fn read_replies<R: BufRead>(mut input: R, marker: &[u8]) -> RdbResult<Summary> {
+    let mut summary = Summary::default();
+    loop {
+        match read_reply(&mut input)? {
+            Reply::Bulk(ref value) if value == marker => return Ok(summary),
+            Reply::Error(err) => summary.errors.push(err),
+            Reply::Array(errors) => summary.errors.extend(errors),
+            _ => {}
+        }
+        summary.replies += 1;
+    }
+}
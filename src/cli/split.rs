@@ -0,0 +1,466 @@
+//! Splitting a dump into one output per key prefix, e.g. per tenant of a
+//! shared instance, or per database.
+//!
+//! `Split` routes the callbacks of each key to the formatter of the output
+//! its name maps to. Output names come from a template like
+//! `out/tenant-{1}.json`, where `{N}` is replaced with capture group `N` of a
+//! pattern matched against the key, `{0}` with the whole match and `{db}`
+//! with the number of the key's database:
+//!
+//! ```rust,no_run
+//! use regex::bytes::Regex;
+//! use crate::cli::split::Split;
+//! use std::fs::File;
+//! use std::io::{BufReader, BufWriter};
+//!
+//! let pattern = Regex::new(r"^tenant:(\d+):").unwrap();
+//! let split = Split::new(pattern, "tenant-{1}.json", |name: &str| {
+//!     Ok(rdb::formatter::JSON::with_writer(BufWriter::new(File::create(name)?)))
+//! });
+//! let file = BufReader::new(File::open("dump.rdb").unwrap());
+//! rdb::parse(file, split, rdb::filter::Simple::new()).unwrap();
+//! ```
+//!
+//! Keys not matching the pattern are left out, so an empty pattern with a
+//! template like `out/db{db}.json` writes every database to its own file.
+//! All outputs stay open until the end of the dump.
+
+use regex::bytes::{Captures, Regex};
+use std::collections::HashMap;
+
+use rdb::formatter::Formatter;
+use rdb::value::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, StreamConsumer, StreamGroup,
+    StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
+use rdb::RdbResult;
+
+/// Creates the formatter of an output, given its name.
+type Open<F> = Box<dyn FnMut(&str) -> RdbResult<F>>;
+
+/// An output and the database it is in, if any.
+struct Output<F> {
+    formatter: F,
+    db: Option<u32>,
+}
+
+/// A formatter passing each key on to the formatter of its output.
+pub struct Split<F: Formatter> {
+    pattern: Regex,
+    template: String,
+    open: Open<F>,
+    outputs: HashMap<String, Output<F>>,
+    current: Option<String>,
+    db: u32,
+}
+
+/// Replaces `{N}` in `template` with capture group `N` and `{db}` with `db`.
+///
+/// Path separators and names made of dots only are replaced in the
+/// captured text, so keys can't choose where their output is written.
+fn expand(template: &str, captures: &Captures, db: u32) -> String {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let placeholder = rest[start + 1..]
+            .find('}')
+            .map(|end| (end, &rest[start + 1..start + 1 + end]));
+        if let Some((end, "db")) = placeholder {
+            name.push_str(&rest[..start]);
+            name.push_str(&db.to_string());
+            rest = &rest[start + end + 2..];
+            continue;
+        }
+        let group =
+            placeholder.and_then(|(end, placeholder)| Some((end, placeholder.parse().ok()?)));
+        match group {
+            Some((end, group)) => {
+                name.push_str(&rest[..start]);
+                let text = captures
+                    .get(group)
+                    .map(|m| String::from_utf8_lossy(m.as_bytes()).into_owned())
+                    .unwrap_or_default();
+                if text.chars().all(|c| c == '.') {
+                    name.push_str(&"_".repeat(text.len()));
+                } else {
+                    name.extend(text.chars().map(|c| match c {
+                        '/' | '\\' | '\0' => '_',
+                        c => c,
+                    }));
+                }
+                rest = &rest[start + end + 2..];
+            }
+            None => {
+                name.push_str(&rest[..=start]);
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    name.push_str(rest);
+    name
+}
+
+impl<F: Formatter> Split<F> {
+    /// Creates a split calling `open` with the name of each new output.
+    pub fn new<O>(pattern: Regex, template: &str, open: O) -> Split<F>
+    where
+        O: FnMut(&str) -> RdbResult<F> + 'static,
+    {
+        Split {
+            pattern,
+            template: template.to_owned(),
+            open: Box::new(open),
+            outputs: HashMap::new(),
+            current: None,
+            db: 0,
+        }
+    }
+
+    /// The names of the outputs opened so far.
+    pub fn outputs(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.outputs.keys().map(|name| &name[..]).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Picks the output of a key, opening it on first use, and makes sure it
+    /// is in the current database.
+    fn start_key(&mut self, key: &[u8]) -> RdbResult<Option<&mut F>> {
+        let name = match self.pattern.captures(key) {
+            Some(captures) => expand(&self.template, &captures, self.db),
+            None => {
+                self.current = None;
+                return Ok(None);
+            }
+        };
+
+        if !self.outputs.contains_key(&name) {
+            let mut formatter = (self.open)(&name)?;
+            formatter.start_rdb()?;
+            self.outputs.insert(
+                name.clone(),
+                Output {
+                    formatter,
+                    db: None,
+                },
+            );
+        }
+        let output = self.outputs.get_mut(&name).expect("output was just opened");
+        if output.db != Some(self.db) {
+            if let Some(db) = output.db {
+                output.formatter.end_database(db)?;
+            }
+            output.formatter.start_database(self.db)?;
+            output.db = Some(self.db);
+        }
+
+        self.current = Some(name);
+        Ok(Some(&mut output.formatter))
+    }
+
+    /// The output of the key started last, if it has one.
+    fn output(&mut self) -> Option<&mut F> {
+        let outputs = &mut self.outputs;
+        self.current
+            .as_ref()
+            .and_then(move |name| outputs.get_mut(name))
+            .map(|output| &mut output.formatter)
+    }
+}
+
+impl<F: Formatter> Formatter for Split<F> {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        for output in self.outputs.values_mut() {
+            if let Some(db) = output.db.take() {
+                output.formatter.end_database(db)?;
+            }
+            output.formatter.end_rdb()?;
+        }
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.db = db_index;
+        Ok(())
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        for output in self.outputs.values_mut() {
+            if output.db == Some(db_index) {
+                output.formatter.end_database(db_index)?;
+                output.db = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.set(key, value, expiry),
+            None => Ok(()),
+        }
+    }
+
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.compressed_set(key, compressed, real_length, expiry),
+            None => Ok(()),
+        }
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.module(key, module_name),
+            None => Ok(()),
+        }
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.probabilistic_filter(key, filter, expiry),
+            None => Ok(()),
+        }
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.hyperloglog(key, encoding, cardinality, expiry),
+            None => Ok(()),
+        }
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.bitmap(key, length, popcount, expiry),
+            None => Ok(()),
+        }
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.value_length(key, length, expiry),
+            None => Ok(()),
+        }
+    }
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.blob_stats(key, stats),
+            None => Ok(()),
+        }
+    }
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.key_offset(key, offset, size),
+            None => Ok(()),
+        }
+    }
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.key_usage(key, usage),
+            None => Ok(()),
+        }
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.start_hash(key, length, expiry, info),
+            None => Ok(()),
+        }
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.end_hash(key),
+            None => Ok(()),
+        }
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.hash_element(key, field, value),
+            None => Ok(()),
+        }
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.hash_element_ex(key, field, value, expiry),
+            None => Ok(()),
+        }
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.start_set(key, cardinality, expiry, info),
+            None => Ok(()),
+        }
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.end_set(key),
+            None => Ok(()),
+        }
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.set_element(key, member),
+            None => Ok(()),
+        }
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.start_list(key, length, expiry, info),
+            None => Ok(()),
+        }
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.end_list(key),
+            None => Ok(()),
+        }
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.list_element(key, value),
+            None => Ok(()),
+        }
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.start_sorted_set(key, length, expiry, info),
+            None => Ok(()),
+        }
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.end_sorted_set(key),
+            None => Ok(()),
+        }
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.sorted_set_element(key, score, member),
+            None => Ok(()),
+        }
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        match self.start_key(key)? {
+            Some(output) => output.start_stream(key, length, expiry, info),
+            None => Ok(()),
+        }
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.end_stream(key),
+            None => Ok(()),
+        }
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.stream_metadata(key, metadata),
+            None => Ok(()),
+        }
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.stream_entry(key, id, fields),
+            None => Ok(()),
+        }
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.stream_group(key, group),
+            None => Ok(()),
+        }
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.stream_consumer(key, group, consumer),
+            None => Ok(()),
+        }
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        match self.output() {
+            Some(output) => output.stream_pending_entry(key, group, entry),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! The encodings dumps store values in, for decoding them without a parser.
+//!
+//! `read_length`, `read_length_with_encoding`, `read_blob`, `verify_magic`
+//! and `verify_version` read from any `Read`, as the parser does. The
+//! `decode_*` functions take the bytes of a single item, e.g. a ziplist as
+//! `DEBUG ZIPLIST` or a dump stores it:
+//!
+//! ```rust
+//! use rdb::codec::{self, ZiplistEntry};
+//!
+//! # fn main() -> rdb::RdbResult<()> {
+//! assert_eq!((300, 2), codec::decode_length(&[0x41, 0x2C])?);
+//! assert_eq!((b"foo".to_vec(), 4), codec::decode_blob(b"\x03foo")?);
+//!
+//! let intset = [2, 0, 0, 0, 2, 0, 0, 0, 1, 0, 2, 0];
+//! assert_eq!(vec![1, 2], codec::decode_intset(&intset)?);
+//!
+//! let listpack = [12, 0, 0, 0, 2, 0, 0x81, b'a', 2, 7, 1, 0xFF];
+//! let entries = codec::decode_listpack(&listpack)?;
+//! assert!(matches!(&entries[..], [ZiplistEntry::String(_), ZiplistEntry::Number(7)]));
+//! # Ok(())
+//! # }
+//! ```
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read};
+
+use crate::helper::{check_remaining, read_bounded};
+use crate::listpack::Listpack;
+use crate::types::{HllEncoding, RdbError, RdbResult};
+use crate::ziplist::Ziplist;
+use crate::{geo, hyperloglog};
+
+pub use crate::geo::Coordinates;
+pub use crate::parser::{
+    read_blob, read_length, read_length_with_encoding, verify_magic, verify_version,
+};
+pub use crate::types::ZiplistEntry;
+
+/// Decodes a length at the start of `bytes`, returning it with the number
+/// of bytes it takes up.
+pub fn decode_length(bytes: &[u8]) -> RdbResult<(u64, usize)> {
+    let mut reader = Cursor::new(bytes);
+    let length = read_length(&mut reader)?;
+    Ok((length, reader.position() as usize))
+}
+
+/// Decodes a string at the start of `bytes`, decompressing or formatting it
+/// as needed, and returns it with the number of bytes it takes up.
+pub fn decode_blob(bytes: &[u8]) -> RdbResult<(Vec<u8>, usize)> {
+    let mut reader = Cursor::new(bytes);
+    let blob = read_blob(&mut reader)?;
+    Ok((blob, reader.position() as usize))
+}
+
+/// Decodes the entries of a ziplist, as lists, hashes and sorted sets were
+/// stored before Redis 7.0.
+pub fn decode_ziplist(bytes: &[u8]) -> RdbResult<Vec<ZiplistEntry>> {
+    let mut ziplist = Ziplist::new(bytes.to_vec())?;
+    let mut entries = vec![];
+    while let Some(entry) = ziplist.next_entry()? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Decodes the entries of a listpack, which replaces the ziplist from Redis
+/// 7.0 on.
+pub fn decode_listpack(bytes: &[u8]) -> RdbResult<Vec<ZiplistEntry>> {
+    let mut listpack = Listpack::new(bytes.to_vec())?;
+    let mut entries = vec![];
+    while let Some(entry) = listpack.next_entry()? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Decodes the integers of an intset, a set of integers only.
+pub fn decode_intset(bytes: &[u8]) -> RdbResult<Vec<i64>> {
+    let mut reader = Cursor::new(bytes);
+    let byte_size = reader.read_u32::<LittleEndian>()?;
+    let length = reader.read_u32::<LittleEndian>()?;
+
+    if byte_size != 2 && byte_size != 4 && byte_size != 8 {
+        return Err(RdbError::Other(format!(
+            "Unhandled byte size in intset: {}",
+            byte_size
+        )));
+    }
+    check_remaining(&reader, length as u64 * byte_size as u64, "intset")?;
+
+    (0..length)
+        .map(|_| {
+            Ok(match byte_size {
+                2 => reader.read_i16::<LittleEndian>()? as i64,
+                4 => reader.read_i32::<LittleEndian>()? as i64,
+                _ => reader.read_i64::<LittleEndian>()?,
+            })
+        })
+        .collect()
+}
+
+/// Decodes the fields and values of a zipmap, as hashes were stored before
+/// Redis 2.6.
+pub fn decode_zipmap(bytes: &[u8]) -> RdbResult<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut reader = Cursor::new(bytes);
+    // The number of entries, unless it is 254 or more.
+    reader.read_u8()?;
+
+    let mut entries = vec![];
+    loop {
+        let next_byte = reader.read_u8()?;
+        if next_byte == 0xFF {
+            break;
+        }
+
+        let field_length = read_zipmap_length(next_byte, &mut reader)?;
+        let field = read_bounded(&mut reader, field_length, "zipmap")?;
+        let next_byte = reader.read_u8()?;
+        let value_length = read_zipmap_length(next_byte, &mut reader)?;
+        // Unused bytes after the value, left by updates shrinking it.
+        let free = reader.read_u8()? as u64;
+        let value = read_bounded(&mut reader, value_length, "zipmap")?;
+        read_bounded(&mut reader, free, "zipmap")?;
+        entries.push((field, value));
+    }
+    Ok(entries)
+}
+
+/// Decodes the score of a member of a GEO set into the coordinates `GEOPOS`
+/// returns, or returns `None` if the score can't be a geohash.
+pub fn decode_geohash(score: f64) -> Option<Coordinates> {
+    geo::decode(score)
+}
+
+/// Returns the encoding and estimated cardinality of a HyperLogLog, or
+/// `None` if `value` isn't one.
+pub fn decode_hyperloglog(value: &[u8]) -> Option<(HllEncoding, u64)> {
+    hyperloglog::decode(value)
+}
+
+/// Reads the length of a zipmap field or value starting with `next_byte`.
+pub(crate) fn read_zipmap_length<R: Read>(next_byte: u8, zipmap: &mut R) -> RdbResult<u64> {
+    match next_byte {
+        253 => Ok(zipmap.read_u32::<LittleEndian>()? as u64),
+        254 | 255 => Err(RdbError::Other(format!(
+            "Invalid length value in zipmap: {}",
+            next_byte
+        ))),
+        _ => Ok(next_byte as u64),
+    }
+}
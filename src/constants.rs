@@ -1,16 +1,26 @@
 pub mod version {
     pub const SUPPORTED_MINIMUM: u32 = 1;
-    pub const SUPPORTED_MAXIMUM: u32 = 8;
+    /// Only raised once every encoding the version introduced is decoded:
+    /// version 10 brought the listpacks of types 16 to 19, version 11 those of
+    /// types 20 and 21, version 12 the hash field expiries of types 22 to 25.
+    pub const SUPPORTED_MAXIMUM: u32 = 12;
 }
 
 pub mod constant {
     pub const RDB_6BITLEN: u8 = 0;
     pub const RDB_14BITLEN: u8 = 1;
+    pub const RDB_32BITLEN: u8 = 0x80;
+    pub const RDB_64BITLEN: u8 = 0x81;
     pub const RDB_ENCVAL: u8 = 3;
-    pub const RDB_MAGIC: &'static str = "REDIS";
+    pub const RDB_MAGIC: &str = "REDIS";
 }
 
 pub mod op_code {
+    pub const SLOT_INFO: u8 = 244;
+    pub const FUNCTION2: u8 = 245;
+    pub const MODULE_AUX: u8 = 247;
+    pub const IDLE: u8 = 248;
+    pub const FREQ: u8 = 249;
     pub const AUX: u8 = 250;
     pub const RESIZEDB: u8 = 251;
     pub const EXPIRETIME_MS: u8 = 252;
@@ -26,17 +36,60 @@ pub mod encoding_type {
     pub const ZSET: u8 = 3;
     pub const HASH: u8 = 4;
     pub const ZSET_2: u8 = 5;
+    pub const MODULE: u8 = 6;
+    pub const MODULE_2: u8 = 7;
     pub const HASH_ZIPMAP: u8 = 9;
     pub const LIST_ZIPLIST: u8 = 10;
     pub const SET_INTSET: u8 = 11;
     pub const ZSET_ZIPLIST: u8 = 12;
     pub const HASH_ZIPLIST: u8 = 13;
     pub const LIST_QUICKLIST: u8 = 14;
+    pub const STREAM_LISTPACKS: u8 = 15;
+    pub const HASH_LISTPACK: u8 = 16;
+    pub const ZSET_LISTPACK: u8 = 17;
+    pub const LIST_QUICKLIST_2: u8 = 18;
+    pub const STREAM_LISTPACKS_2: u8 = 19;
+    pub const SET_LISTPACK: u8 = 20;
+    pub const STREAM_LISTPACKS_3: u8 = 21;
+    /// Hashes with field expiries, as written by the release candidates of
+    /// Redis 7.4, which store absolute expiries.
+    pub const HASH_METADATA_PRE_GA: u8 = 22;
+    pub const HASH_LISTPACK_EX_PRE_GA: u8 = 23;
+    /// Hashes with field expiries, which store the minimum expiry first.
+    pub const HASH_METADATA: u8 = 24;
+    pub const HASH_LISTPACK_EX: u8 = 25;
 }
 
 pub mod encoding {
-    pub const INT8: u32 = 0;
-    pub const INT16: u32 = 1;
-    pub const INT32: u32 = 2;
-    pub const LZF: u32 = 3;
+    pub const INT8: u64 = 0;
+    pub const INT16: u64 = 1;
+    pub const INT32: u64 = 2;
+    pub const LZF: u64 = 3;
+}
+
+pub mod module {
+    /// The characters module names are made of, 6 bits each.
+    pub const NAME_CHARSET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+    pub const OPCODE_EOF: u64 = 0;
+    pub const OPCODE_SINT: u64 = 1;
+    pub const OPCODE_UINT: u64 = 2;
+    pub const OPCODE_FLOAT: u64 = 3;
+    pub const OPCODE_DOUBLE: u64 = 4;
+    pub const OPCODE_STRING: u64 = 5;
+}
+
+pub mod quicklist {
+    /// The node holds a single element too large to pack.
+    pub const CONTAINER_PLAIN: u64 = 1;
+    /// The node holds a listpack.
+    pub const CONTAINER_PACKED: u64 = 2;
+}
+
+pub mod stream {
+    /// The entry was deleted and is only kept until its node is compacted.
+    pub const FLAG_DELETED: i64 = 1;
+    /// The entry has the same fields as the master entry of its node.
+    pub const FLAG_SAMEFIELDS: i64 = 2;
 }
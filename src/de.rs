@@ -0,0 +1,290 @@
+//! Deserializing dumps into your own types with serde, see `from_reader`.
+
+use std::fmt::Display;
+use std::io::Read;
+use std::str::{self, FromStr};
+
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+
+use crate::filter::{Filter, Simple};
+use crate::formatter::{KeyContext, ValueFormatter};
+use crate::types::{RdbError, RdbResult, StreamEntry, Value};
+
+/// A hash field with its value and expiry.
+type HashField = (Vec<u8>, Vec<u8>, Option<u64>);
+
+impl de::Error for RdbError {
+    fn custom<T: Display>(msg: T) -> RdbError {
+        RdbError::Other(msg.to_string())
+    }
+}
+
+/// Deserializes a whole dump read from `input`.
+///
+/// A dump deserializes as a map from each key to its value, with keys of
+/// all databases together:
+///
+/// * strings as strings, bytes, or numbers and booleans they spell out
+/// * lists and sets as sequences of their elements
+/// * sorted sets as maps from members to scores, or sequences of
+///   `(member, score)` pairs
+/// * hashes as maps from fields to values, or sequences of pairs
+/// * streams as maps from entry IDs, like `"1526919030474-55"`, to maps of
+///   their fields
+/// * module values as the name of their module
+///
+/// ```rust,no_run
+/// use serde::Deserialize;
+/// use std::collections::HashMap;
+/// use std::fs::File;
+/// use std::io::BufReader;
+///
+/// #[derive(Deserialize)]
+/// #[serde(untagged)]
+/// enum MyValue {
+///     Text(String),
+///     Items(Vec<String>),
+///     Fields(HashMap<String, String>),
+/// }
+///
+/// # fn main() -> rdb::RdbResult<()> {
+/// let file = BufReader::new(File::open("dump.rdb")?);
+/// let data: HashMap<String, MyValue> = rdb::from_reader(file)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The whole dump is read into memory first. Hash field expiries and keys
+/// the parser only measures, see `formatter::Materialize`, are left out.
+/// A single value from `parse_values` deserializes with `Deserializer`.
+pub fn from_reader<R: Read, T: DeserializeOwned>(input: R) -> RdbResult<T> {
+    from_reader_with_filter(input, Simple::new())
+}
+
+/// Like `from_reader`, but only with the keys matching `filter`.
+pub fn from_reader_with_filter<R: Read, T: DeserializeOwned, L: Filter>(
+    input: R,
+    filter: L,
+) -> RdbResult<T> {
+    let mut values = Collect(Vec::new());
+    crate::parse_values(input, &mut values, filter)?;
+    let entries = values
+        .0
+        .into_iter()
+        .map(|(key, value)| (Value::String(key), value));
+    T::deserialize(MapDeserializer::new(entries))
+}
+
+struct Collect(Vec<(Vec<u8>, Value)>);
+
+impl ValueFormatter for &mut Collect {
+    fn value(&mut self, context: &KeyContext, value: Value) -> RdbResult<()> {
+        self.0.push((context.key.clone(), value));
+        Ok(())
+    }
+}
+
+/// A `serde::Deserializer` for a single value.
+pub struct Deserializer(Value);
+
+impl Deserializer {
+    pub fn new(value: Value) -> Deserializer {
+        Deserializer(value)
+    }
+}
+
+impl<'de> IntoDeserializer<'de, RdbError> for Value {
+    type Deserializer = Deserializer;
+
+    fn into_deserializer(self) -> Deserializer {
+        Deserializer(self)
+    }
+}
+
+fn parse_string<T: FromStr>(bytes: &[u8]) -> RdbResult<T> {
+    str::from_utf8(bytes)
+        .ok()
+        .and_then(|string| string.parse().ok())
+        .ok_or_else(|| {
+            RdbError::Other(format!(
+                "Invalid {}: {}",
+                std::any::type_name::<T>(),
+                String::from_utf8_lossy(bytes)
+            ))
+        })
+}
+
+fn into_string(bytes: Vec<u8>) -> RdbResult<String> {
+    String::from_utf8(bytes).map_err(|err| {
+        RdbError::Other(format!(
+            "Invalid UTF-8: {}",
+            String::from_utf8_lossy(err.as_bytes())
+        ))
+    })
+}
+
+/// Deserializes a string value by parsing it, anything else as usual.
+macro_rules! parse_scalar {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+                match self.0 {
+                    Value::String(bytes) => visitor.$visit(parse_string(&bytes)?),
+                    value => Deserializer(value).deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+/// The members of a value deserializing as a map.
+enum Entries {
+    Scores(Vec<(Vec<u8>, f64)>),
+    Fields(Vec<HashField>),
+    Stream(Vec<StreamEntry>),
+}
+
+impl Entries {
+    /// Passes the entries to `visitor` as a map, or as a sequence of pairs.
+    fn visit<'de, V: Visitor<'de>>(self, visitor: V, as_seq: bool) -> RdbResult<V::Value> {
+        macro_rules! visit {
+            ($entries:expr) => {{
+                let map = MapDeserializer::new($entries);
+                if as_seq {
+                    de::Deserializer::deserialize_seq(map, visitor)
+                } else {
+                    de::Deserializer::deserialize_map(map, visitor)
+                }
+            }};
+        }
+
+        match self {
+            Entries::Scores(members) => visit!(members
+                .into_iter()
+                .map(|(member, score)| (Value::String(member), score))),
+            Entries::Fields(fields) => visit!(fields
+                .into_iter()
+                .map(|(field, value, _)| (Value::String(field), Value::String(value)))),
+            Entries::Stream(entries) => visit!(entries.into_iter().map(|(id, fields)| {
+                let id = format!("{}-{}", id.ms, id.seq).into_bytes();
+                let fields = fields
+                    .into_iter()
+                    .map(|(field, value)| (field, value, None))
+                    .collect();
+                (Value::String(id), Value::Hash(fields))
+            })),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = RdbError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        match self.0 {
+            Value::String(bytes) => match String::from_utf8(bytes) {
+                Ok(string) => visitor.visit_string(string),
+                Err(err) => visitor.visit_byte_buf(err.into_bytes()),
+            },
+            Value::List(elements) | Value::Set(elements) => {
+                SeqDeserializer::new(elements.into_iter().map(Value::String))
+                    .deserialize_any(visitor)
+            }
+            Value::SortedSet(members) => Entries::Scores(members).visit(visitor, false),
+            Value::Hash(fields) => Entries::Fields(fields).visit(visitor, false),
+            Value::Stream(entries) => Entries::Stream(entries).visit(visitor, false),
+            Value::Module(module_name) => visitor.visit_string(module_name),
+        }
+    }
+
+    parse_scalar! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        match self.0 {
+            Value::String(bytes) => match &bytes[..] {
+                b"1" => visitor.visit_bool(true),
+                b"0" => visitor.visit_bool(false),
+                _ => visitor.visit_bool(parse_string(&bytes)?),
+            },
+            value => Deserializer(value).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        match self.0 {
+            Value::String(bytes) => visitor.visit_byte_buf(bytes),
+            value => Deserializer(value).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> RdbResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        match self.0 {
+            Value::SortedSet(members) => Entries::Scores(members).visit(visitor, true),
+            Value::Hash(fields) => Entries::Fields(fields).visit(visitor, true),
+            Value::Stream(entries) => Entries::Stream(entries).visit(visitor, true),
+            value => Deserializer(value).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> RdbResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> RdbResult<V::Value> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> RdbResult<V::Value> {
+        match self.0 {
+            Value::String(bytes) => visitor.visit_enum(into_string(bytes)?.into_deserializer()),
+            value => Deserializer(value).deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> RdbResult<V::Value> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 str string unit unit_struct map struct identifier
+    }
+}
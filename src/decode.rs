@@ -0,0 +1,139 @@
+//! Decoding single values outside of a dump, and encoding them back.
+//!
+//! `DUMP` returns a value the way a dump stores it, after its type byte and
+//! followed by the RDB version and a CRC64 checksum. `parse_dump_payload`
+//! takes such a payload as it is, `decode_value` the type byte and the
+//! value with the footer stripped:
+//!
+//! ```rust
+//! use rdb::value::Value;
+//!
+//! # fn main() -> rdb::RdbResult<()> {
+//! let payload = b"\x00\x03bar\x09\x00\x36\x4c\x18\xac\xba\xe0\x9e\xa6";
+//! let value = rdb::parse_dump_payload(payload)?;
+//! assert_eq!(Value::String(b"bar".to_vec()), value);
+//!
+//! let value = rdb::decode_value(0, b"\x05hello")?;
+//! assert_eq!(Value::String(b"hello".to_vec()), value);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! `dump_payload` writes a value into a payload `RESTORE` loads, e.g. to
+//! move single keys from a dump to a server.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::constants::version;
+use crate::filter::Simple;
+use crate::formatter::payload::{self, crc64, Payload};
+use crate::formatter::{KeyContext, Materialize, ValueFormatter, WithContext};
+use crate::parser::RdbParser;
+use crate::types::{RdbError, RdbResult, Value};
+
+/// The RDB version and the CRC64 checksum ending a payload.
+const FOOTER_LENGTH: usize = 10;
+
+/// Decodes the value of type `value_type` stored in `value`, in any of the
+/// encodings a dump may hold it in.
+pub fn decode_value(value_type: u8, value: &[u8]) -> RdbResult<Value> {
+    let mut decoded = Decode(None);
+    let formatter = WithContext::new(Materialize::new(&mut decoded));
+    RdbParser::new(value, formatter, Simple::new()).parse_value(value_type)?;
+    decoded
+        .0
+        .ok_or_else(|| RdbError::Other("The payload holds no value".into()))
+}
+
+/// Decodes a payload as returned by `DUMP`, after checking the RDB version
+/// and the CRC64 checksum in its footer.
+///
+/// The version is the one of the server that wrote the payload, so values
+/// of servers newer than this crate are rejected.
+pub fn parse_dump_payload(payload: &[u8]) -> RdbResult<Value> {
+    if payload.len() < 1 + FOOTER_LENGTH {
+        return Err(RdbError::Other(format!(
+            "Payload of {} bytes is too short",
+            payload.len()
+        )));
+    }
+    let (body, footer) = payload.split_at(payload.len() - FOOTER_LENGTH);
+
+    let payload_version = u32::from(LittleEndian::read_u16(&footer[..2]));
+    let supported = version::SUPPORTED_MINIMUM..=version::SUPPORTED_MAXIMUM;
+    if !supported.contains(&payload_version) {
+        return Err(RdbError::Other(format!(
+            "Payload of unsupported RDB version {}, expected {} to {}",
+            payload_version,
+            version::SUPPORTED_MINIMUM,
+            version::SUPPORTED_MAXIMUM
+        )));
+    }
+    // Servers with `rdbchecksum no` store 0.
+    let stored = LittleEndian::read_u64(&footer[2..]);
+    let crc = crc64(&payload[..payload.len() - 8]);
+    if stored != 0 && stored != crc {
+        return Err(RdbError::Other(format!(
+            "Checksum mismatch: the payload stores {:016x}, but its contents have {:016x}",
+            stored, crc
+        )));
+    }
+
+    decode_value(body[0], &body[1..])
+}
+
+/// Encodes `value` into a payload as `DUMP` returns it and `RESTORE` loads
+/// it, in RDB version 9, which every server since Redis 5 accepts.
+///
+/// Hash field expiries can't be stored in that version and are left out.
+/// Streams, module values and empty values, which `RESTORE` rejects, fail.
+pub fn dump_payload(value: &Value) -> RdbResult<Vec<u8>> {
+    let payload = match value {
+        Value::String(value) => return Ok(payload::string(value)),
+        Value::List(elements) => {
+            let mut payload = Payload::list();
+            for element in elements {
+                payload.element(element);
+            }
+            payload
+        }
+        Value::Set(members) => {
+            let mut payload = Payload::set();
+            for member in members {
+                payload.element(member);
+            }
+            payload
+        }
+        Value::SortedSet(members) => {
+            let mut payload = Payload::sorted_set();
+            for (member, score) in members {
+                payload.member(member, *score);
+            }
+            payload
+        }
+        Value::Hash(fields) => {
+            let mut payload = Payload::hash();
+            for (field, value, _) in fields {
+                payload.field(field, value);
+            }
+            payload
+        }
+        Value::Stream(_) | Value::Module(_) => {
+            return Err(RdbError::Other(
+                "Only strings, lists, sets, sorted sets and hashes can be encoded".into(),
+            ))
+        }
+    };
+    payload
+        .finish()
+        .ok_or_else(|| RdbError::Other("Empty values can't be restored".into()))
+}
+
+struct Decode(Option<Value>);
+
+impl ValueFormatter for &mut Decode {
+    fn value(&mut self, _context: &KeyContext, value: Value) -> RdbResult<()> {
+        self.0 = Some(value);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! Dumps wrapped in an envelope, such as the encrypted exports of some
+//! managed platforms.
+//!
+//! A `KeyUnwrapper` turns the raw input into the plain dump before its magic is
+//! verified:
+//!
+//! ```rust,no_run
+//! use rdb::envelope::{AesGcm, KeyEncoding};
+//! use std::fs::File;
+//!
+//! let key = AesGcm::from_key_file("dump.key", KeyEncoding::Hex).unwrap();
+//! let file = File::open("dump.rdb.enc").unwrap();
+//! rdb::parse_unwrapped(file, key, rdb::formatter::JSON::new(), rdb::filter::Simple::new());
+//! ```
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, Nonce};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::types::{RdbError, RdbResult};
+
+/// Transforms the raw input before it is parsed.
+pub trait KeyUnwrapper {
+    fn unwrap_key(&self, input: Box<dyn Read>) -> RdbResult<Box<dyn Read>>;
+}
+
+impl<F> KeyUnwrapper for F
+where
+    F: Fn(Box<dyn Read>) -> RdbResult<Box<dyn Read>>,
+{
+    fn unwrap_key(&self, input: Box<dyn Read>) -> RdbResult<Box<dyn Read>> {
+        self(input)
+    }
+}
+
+/// How a key is stored in a key file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEncoding {
+    /// The bytes of the key as they are.
+    Raw,
+    /// Hex digits, surrounding whitespace is ignored.
+    Hex,
+}
+
+impl KeyEncoding {
+    pub fn from_name(name: &str) -> Option<KeyEncoding> {
+        match name {
+            "raw" => Some(KeyEncoding::Raw),
+            "hex" => Some(KeyEncoding::Hex),
+            _ => None,
+        }
+    }
+}
+
+/// Length of the nonce in front of the ciphertext.
+const NONCE_LEN: usize = 12;
+
+/// Decrypts dumps encrypted with AES-GCM.
+///
+/// The input is the 12 byte nonce followed by the ciphertext and the 16 byte
+/// authentication tag. As nothing can be trusted before the tag is checked,
+/// the whole dump is decrypted in memory.
+pub struct AesGcm {
+    key: Vec<u8>,
+}
+
+impl AesGcm {
+    /// Creates an unwrapper from a 128 or 256 bit key.
+    pub fn new(key: &[u8]) -> RdbResult<AesGcm> {
+        match key.len() {
+            16 | 32 => Ok(AesGcm { key: key.to_vec() }),
+            len => Err(RdbError::Other(format!(
+                "Invalid AES key length: {} bytes, expected 16 or 32",
+                len
+            ))),
+        }
+    }
+
+    /// Reads the key from a file stored with the given encoding.
+    pub fn from_key_file<P: AsRef<Path>>(path: P, encoding: KeyEncoding) -> RdbResult<AesGcm> {
+        let raw = fs::read(path)?;
+        let key = match encoding {
+            KeyEncoding::Raw => raw,
+            KeyEncoding::Hex => std::str::from_utf8(&raw)
+                .ok()
+                .and_then(|text| hex::decode(text.trim()).ok())
+                .ok_or_else(|| RdbError::Other("Invalid hex encoded key".into()))?,
+        };
+        AesGcm::new(&key)
+    }
+
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> RdbResult<Vec<u8>> {
+        let nonce = Nonce::from_slice(nonce);
+        let res = match self.key.len() {
+            16 => Aes128Gcm::new_from_slice(&self.key)
+                .expect("key length was checked")
+                .decrypt(nonce, ciphertext),
+            _ => Aes256Gcm::new_from_slice(&self.key)
+                .expect("key length was checked")
+                .decrypt(nonce, ciphertext),
+        };
+        res.map_err(|_| RdbError::Other("Decryption failed: wrong key or corrupt input".into()))
+    }
+}
+
+impl KeyUnwrapper for AesGcm {
+    fn unwrap_key(&self, mut input: Box<dyn Read>) -> RdbResult<Box<dyn Read>> {
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        if data.len() < NONCE_LEN {
+            return Err(RdbError::Other("Encrypted input is too short".into()));
+        }
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let plain = self.decrypt(nonce, ciphertext)?;
+        Ok(Box::new(Cursor::new(plain)))
+    }
+}
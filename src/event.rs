@@ -0,0 +1,460 @@
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::SyncSender;
+
+use crate::formatter::Formatter;
+use crate::types::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, RdbError, RdbResult,
+    StreamConsumer, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
+
+/// An owned copy of a single `Formatter` callback.
+///
+/// Serializable for formatters buffering callbacks on disk, like `Sorted`.
+#[derive(Serialize, Deserialize)]
+pub(crate) enum Event {
+    EndRdb,
+    Checksum(Vec<u8>),
+    StartDatabase(u32),
+    EndDatabase(u32),
+    ResizeDb(u32, u32),
+    SlotInfo(u32, u32, u32),
+    AuxField(Vec<u8>, Vec<u8>),
+    Function(Vec<u8>),
+    Set(Vec<u8>, Vec<u8>, Option<u64>),
+    CompressedSet(Vec<u8>, Vec<u8>, u64, Option<u64>),
+    Module(Vec<u8>, String),
+    UnknownType(u8),
+    ProbabilisticFilter(Vec<u8>, FilterInfo, Option<u64>),
+    Hyperloglog(Vec<u8>, HllEncoding, u64, Option<u64>),
+    Bitmap(Vec<u8>, u64, u64, Option<u64>),
+    ValueLength(Vec<u8>, ValueLength, Option<u64>),
+    BlobStats(Vec<u8>, BlobStats),
+    KeyOffset(Vec<u8>, u64, u64),
+    KeyUsage(Vec<u8>, KeyUsage),
+    StartHash(Vec<u8>, u32, Option<u64>, EncodingType),
+    EndHash(Vec<u8>),
+    HashElement(Vec<u8>, Vec<u8>, Vec<u8>),
+    HashElementEx(Vec<u8>, Vec<u8>, Vec<u8>, u64),
+    StartSet(Vec<u8>, u32, Option<u64>, EncodingType),
+    EndSet(Vec<u8>),
+    SetElement(Vec<u8>, Vec<u8>),
+    StartList(Vec<u8>, u32, Option<u64>, EncodingType),
+    EndList(Vec<u8>),
+    ListElement(Vec<u8>, Vec<u8>),
+    StartSortedSet(Vec<u8>, u32, Option<u64>, EncodingType),
+    EndSortedSet(Vec<u8>),
+    SortedSetElement(Vec<u8>, #[serde(with = "score_bits")] f64, Vec<u8>),
+    StartStream(Vec<u8>, u32, Option<u64>, EncodingType),
+    EndStream(Vec<u8>),
+    StreamMetadata(Vec<u8>, StreamMetadata),
+    StreamEntry(Vec<u8>, StreamId, Vec<(Vec<u8>, Vec<u8>)>),
+    StreamGroup(Vec<u8>, StreamGroup),
+    StreamConsumer(Vec<u8>, Vec<u8>, StreamConsumer),
+    StreamPendingEntry(Vec<u8>, Vec<u8>, StreamPendingEntry),
+}
+
+/// Scores as their bits, as serde_json can't store NaN and infinities.
+mod score_bits {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(score: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(score.to_bits())
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        u64::deserialize(deserializer).map(f64::from_bits)
+    }
+}
+
+impl Event {
+    /// Calls the matching callback on `formatter`.
+    pub(crate) fn replay<F: Formatter>(self, formatter: &mut F) -> RdbResult<()> {
+        match self {
+            Event::EndRdb => formatter.end_rdb(),
+            Event::Checksum(checksum) => formatter.checksum(&checksum),
+            Event::StartDatabase(db) => formatter.start_database(db),
+            Event::EndDatabase(db) => formatter.end_database(db),
+            Event::ResizeDb(db_size, expires_size) => formatter.resizedb(db_size, expires_size),
+            Event::SlotInfo(slot, slot_size, expires_slot_size) => {
+                formatter.slot_info(slot, slot_size, expires_slot_size)
+            }
+            Event::AuxField(key, value) => formatter.aux_field(&key, &value),
+            Event::Function(library_code) => formatter.function(&library_code),
+            Event::Set(key, value, expiry) => formatter.set(&key, &value, expiry),
+            Event::CompressedSet(key, compressed, real_length, expiry) => {
+                formatter.compressed_set(&key, &compressed, real_length, expiry)
+            }
+            Event::Module(key, module_name) => formatter.module(&key, &module_name),
+            Event::UnknownType(code) => formatter.unknown_type(code),
+            Event::ProbabilisticFilter(key, filter, expiry) => {
+                formatter.probabilistic_filter(&key, &filter, expiry)
+            }
+            Event::Hyperloglog(key, encoding, cardinality, expiry) => {
+                formatter.hyperloglog(&key, encoding, cardinality, expiry)
+            }
+            Event::Bitmap(key, length, popcount, expiry) => {
+                formatter.bitmap(&key, length, popcount, expiry)
+            }
+            Event::ValueLength(key, length, expiry) => {
+                formatter.value_length(&key, &length, expiry)
+            }
+            Event::BlobStats(key, stats) => formatter.blob_stats(&key, &stats),
+            Event::KeyOffset(key, offset, size) => formatter.key_offset(&key, offset, size),
+            Event::KeyUsage(key, usage) => formatter.key_usage(&key, usage),
+            Event::StartHash(key, length, expiry, info) => {
+                formatter.start_hash(&key, length, expiry, info)
+            }
+            Event::EndHash(key) => formatter.end_hash(&key),
+            Event::HashElement(key, field, value) => formatter.hash_element(&key, &field, &value),
+            Event::HashElementEx(key, field, value, expiry) => {
+                formatter.hash_element_ex(&key, &field, &value, expiry)
+            }
+            Event::StartSet(key, cardinality, expiry, info) => {
+                formatter.start_set(&key, cardinality, expiry, info)
+            }
+            Event::EndSet(key) => formatter.end_set(&key),
+            Event::SetElement(key, member) => formatter.set_element(&key, &member),
+            Event::StartList(key, length, expiry, info) => {
+                formatter.start_list(&key, length, expiry, info)
+            }
+            Event::EndList(key) => formatter.end_list(&key),
+            Event::ListElement(key, value) => formatter.list_element(&key, &value),
+            Event::StartSortedSet(key, length, expiry, info) => {
+                formatter.start_sorted_set(&key, length, expiry, info)
+            }
+            Event::EndSortedSet(key) => formatter.end_sorted_set(&key),
+            Event::SortedSetElement(key, score, member) => {
+                formatter.sorted_set_element(&key, score, &member)
+            }
+            Event::StartStream(key, length, expiry, info) => {
+                formatter.start_stream(&key, length, expiry, info)
+            }
+            Event::EndStream(key) => formatter.end_stream(&key),
+            Event::StreamMetadata(key, metadata) => formatter.stream_metadata(&key, &metadata),
+            Event::StreamEntry(key, id, fields) => formatter.stream_entry(&key, id, &fields),
+            Event::StreamGroup(key, group) => formatter.stream_group(&key, &group),
+            Event::StreamConsumer(key, group, consumer) => {
+                formatter.stream_consumer(&key, &group, &consumer)
+            }
+            Event::StreamPendingEntry(key, group, entry) => {
+                formatter.stream_pending_entry(&key, &group, &entry)
+            }
+        }
+    }
+}
+
+/// Number of events buffered before a batch is handed to the channel.
+const BATCH_SIZE: usize = 1024;
+
+/// A formatter that records every callback as an `Event` and sends them in
+/// batches through a channel, or keeps them until they are taken.
+pub(crate) struct Recorder {
+    events: Vec<Event>,
+    sender: Option<SyncSender<RdbResult<Vec<Event>>>>,
+    batch_size: usize,
+}
+
+impl Recorder {
+    pub(crate) fn new(sender: SyncSender<RdbResult<Vec<Event>>>) -> Recorder {
+        Recorder {
+            events: Vec::with_capacity(BATCH_SIZE),
+            sender: Some(sender),
+            batch_size: BATCH_SIZE,
+        }
+    }
+
+    /// Creates a recorder that sends every event as soon as it happens.
+    pub(crate) fn unbatched(sender: SyncSender<RdbResult<Vec<Event>>>) -> Recorder {
+        Recorder {
+            events: Vec::with_capacity(1),
+            sender: Some(sender),
+            batch_size: 1,
+        }
+    }
+
+    /// Creates a recorder that keeps all events until `take` is called.
+    pub(crate) fn buffered() -> Recorder {
+        Recorder {
+            events: vec![],
+            sender: None,
+            batch_size: BATCH_SIZE,
+        }
+    }
+
+    /// Returns the events recorded so far.
+    pub(crate) fn take(&mut self) -> Vec<Event> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Sends all buffered events.
+    pub(crate) fn flush(&mut self) -> RdbResult<()> {
+        let sender = match self.sender {
+            Some(ref sender) if !self.events.is_empty() => sender,
+            _ => return Ok(()),
+        };
+
+        let events = std::mem::replace(&mut self.events, Vec::with_capacity(self.batch_size));
+        sender
+            .send(Ok(events))
+            .map_err(|_| RdbError::Other("Receiver of recorded events hung up".into()))
+    }
+
+    fn record(&mut self, event: Event) -> RdbResult<()> {
+        self.events.push(event);
+        if self.events.len() >= self.batch_size {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Formatter for Recorder {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.record(Event::EndRdb)
+    }
+    fn checksum(&mut self, checksum: &[u8]) -> RdbResult<()> {
+        self.record(Event::Checksum(checksum.to_vec()))
+    }
+
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.record(Event::StartDatabase(db_index))
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.record(Event::EndDatabase(db_index))
+    }
+
+    fn resizedb(&mut self, db_size: u32, expires_size: u32) -> RdbResult<()> {
+        self.record(Event::ResizeDb(db_size, expires_size))
+    }
+    fn slot_info(&mut self, slot: u32, slot_size: u32, expires_slot_size: u32) -> RdbResult<()> {
+        self.record(Event::SlotInfo(slot, slot_size, expires_slot_size))
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.record(Event::AuxField(key.to_vec(), value.to_vec()))
+    }
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        self.record(Event::Function(library_code.to_vec()))
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.record(Event::Set(key.to_vec(), value.to_vec(), expiry))
+    }
+
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.record(Event::CompressedSet(
+            key.to_vec(),
+            compressed.to_vec(),
+            real_length,
+            expiry,
+        ))
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.record(Event::Module(key.to_vec(), module_name.to_owned()))
+    }
+
+    fn unknown_type(&mut self, code: u8) -> RdbResult<()> {
+        self.record(Event::UnknownType(code))
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.record(Event::ProbabilisticFilter(
+            key.to_vec(),
+            filter.clone(),
+            expiry,
+        ))
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.record(Event::Hyperloglog(
+            key.to_vec(),
+            encoding,
+            cardinality,
+            expiry,
+        ))
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.record(Event::Bitmap(key.to_vec(), length, popcount, expiry))
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.record(Event::ValueLength(key.to_vec(), *length, expiry))
+    }
+
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        self.record(Event::BlobStats(key.to_vec(), *stats))
+    }
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        self.record(Event::KeyOffset(key.to_vec(), offset, size))
+    }
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        self.record(Event::KeyUsage(key.to_vec(), usage))
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.record(Event::StartHash(key.to_vec(), length, expiry, info))
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.record(Event::EndHash(key.to_vec()))
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.record(Event::HashElement(
+            key.to_vec(),
+            field.to_vec(),
+            value.to_vec(),
+        ))
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.record(Event::HashElementEx(
+            key.to_vec(),
+            field.to_vec(),
+            value.to_vec(),
+            expiry,
+        ))
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.record(Event::StartSet(key.to_vec(), cardinality, expiry, info))
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.record(Event::EndSet(key.to_vec()))
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.record(Event::SetElement(key.to_vec(), member.to_vec()))
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.record(Event::StartList(key.to_vec(), length, expiry, info))
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.record(Event::EndList(key.to_vec()))
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.record(Event::ListElement(key.to_vec(), value.to_vec()))
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.record(Event::StartSortedSet(key.to_vec(), length, expiry, info))
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.record(Event::EndSortedSet(key.to_vec()))
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.record(Event::SortedSetElement(
+            key.to_vec(),
+            score,
+            member.to_vec(),
+        ))
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.record(Event::StartStream(key.to_vec(), length, expiry, info))
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.record(Event::EndStream(key.to_vec()))
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.record(Event::StreamMetadata(key.to_vec(), metadata.clone()))
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.record(Event::StreamEntry(key.to_vec(), id, fields.to_vec()))
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.record(Event::StreamGroup(key.to_vec(), group.clone()))
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.record(Event::StreamConsumer(
+            key.to_vec(),
+            group.to_vec(),
+            consumer.clone(),
+        ))
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.record(Event::StreamPendingEntry(
+            key.to_vec(),
+            group.to_vec(),
+            entry.clone(),
+        ))
+    }
+}
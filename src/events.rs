@@ -0,0 +1,397 @@
+//! Parsing into an iterator of events.
+//!
+//! `events::parse` runs the parser on a background thread and hands out
+//! every callback as an owned `RdbEvent`, so a dump can be read with `for`,
+//! iterator adapters and `?` instead of a `Formatter`:
+//!
+//! ```rust,no_run
+//! use rdb::events::RdbEvent;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! # fn main() -> rdb::RdbResult<()> {
+//! let file = BufReader::new(File::open("dump.rdb")?);
+//! for event in rdb::events::parse(file, rdb::filter::Simple::new()) {
+//!     if let RdbEvent::KeyString { key, value, .. } = event? {
+//!         println!("{:?} = {:?}", key, value);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Events are sent over a bounded channel in batches, so the parser only
+//! runs a little ahead of the consumer. Dropping the iterator stops it.
+
+use std::io::Read;
+use std::panic;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::{self, JoinHandle};
+use std::vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::event::{Event, Recorder};
+use crate::filter::Filter;
+use crate::parser::RdbParser;
+use crate::types::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, RdbError, RdbResult,
+    StreamConsumer, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
+
+/// Number of batches the parser may get ahead of the consumer.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// A single callback of the parser, with owned data.
+///
+/// Element events carry the key they belong to and always come between
+/// the matching start and end event.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RdbEvent {
+    SelectDb {
+        db: u32,
+    },
+    EndDb {
+        db: u32,
+    },
+    ResizeDb {
+        db_size: u32,
+        expires_size: u32,
+    },
+    SlotInfo {
+        slot: u32,
+        slot_size: u32,
+        expires_slot_size: u32,
+    },
+    AuxField {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Function {
+        library_code: Vec<u8>,
+    },
+    UnknownType {
+        code: u8,
+    },
+
+    KeyString {
+        key: Vec<u8>,
+        value: Vec<u8>,
+        expiry: Option<u64>,
+    },
+    Module {
+        key: Vec<u8>,
+        module_name: String,
+    },
+    ProbabilisticFilter {
+        key: Vec<u8>,
+        filter: FilterInfo,
+        expiry: Option<u64>,
+    },
+    Hyperloglog {
+        key: Vec<u8>,
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    },
+    Bitmap {
+        key: Vec<u8>,
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    },
+    ValueLength {
+        key: Vec<u8>,
+        length: ValueLength,
+        expiry: Option<u64>,
+    },
+    BlobStats {
+        key: Vec<u8>,
+        stats: BlobStats,
+    },
+    /// Where the key is stored, see `Formatter::key_offset`.
+    KeyOffset {
+        key: Vec<u8>,
+        offset: u64,
+        size: u64,
+    },
+    KeyUsage {
+        key: Vec<u8>,
+        usage: KeyUsage,
+    },
+
+    HashStart {
+        key: Vec<u8>,
+        length: u32,
+        expiry: Option<u64>,
+        encoding: EncodingType,
+    },
+    HashField {
+        key: Vec<u8>,
+        field: Vec<u8>,
+        value: Vec<u8>,
+        expiry: Option<u64>,
+    },
+    HashEnd {
+        key: Vec<u8>,
+    },
+    SetStart {
+        key: Vec<u8>,
+        cardinality: u32,
+        expiry: Option<u64>,
+        encoding: EncodingType,
+    },
+    SetMember {
+        key: Vec<u8>,
+        member: Vec<u8>,
+    },
+    SetEnd {
+        key: Vec<u8>,
+    },
+    ListStart {
+        key: Vec<u8>,
+        length: u32,
+        expiry: Option<u64>,
+        encoding: EncodingType,
+    },
+    ListElement {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    ListEnd {
+        key: Vec<u8>,
+    },
+    SortedSetStart {
+        key: Vec<u8>,
+        length: u32,
+        expiry: Option<u64>,
+        encoding: EncodingType,
+    },
+    SortedSetMember {
+        key: Vec<u8>,
+        score: f64,
+        member: Vec<u8>,
+    },
+    SortedSetEnd {
+        key: Vec<u8>,
+    },
+    StreamStart {
+        key: Vec<u8>,
+        length: u32,
+        expiry: Option<u64>,
+        encoding: EncodingType,
+    },
+    StreamMetadata {
+        key: Vec<u8>,
+        metadata: StreamMetadata,
+    },
+    StreamEntry {
+        key: Vec<u8>,
+        id: StreamId,
+        fields: Vec<(Vec<u8>, Vec<u8>)>,
+    },
+    StreamGroup {
+        key: Vec<u8>,
+        group: StreamGroup,
+    },
+    StreamConsumer {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        consumer: StreamConsumer,
+    },
+    StreamPendingEntry {
+        key: Vec<u8>,
+        group: Vec<u8>,
+        entry: StreamPendingEntry,
+    },
+    StreamEnd {
+        key: Vec<u8>,
+    },
+
+    /// The end of the dump. Only the checksum may follow.
+    Eof,
+    Checksum {
+        checksum: Vec<u8>,
+    },
+}
+
+impl RdbEvent {
+    pub(crate) fn from_event(event: Event) -> RdbResult<RdbEvent> {
+        Ok(match event {
+            Event::EndRdb => RdbEvent::Eof,
+            Event::Checksum(checksum) => RdbEvent::Checksum { checksum },
+            Event::StartDatabase(db) => RdbEvent::SelectDb { db },
+            Event::EndDatabase(db) => RdbEvent::EndDb { db },
+            Event::ResizeDb(db_size, expires_size) => RdbEvent::ResizeDb {
+                db_size,
+                expires_size,
+            },
+            Event::SlotInfo(slot, slot_size, expires_slot_size) => RdbEvent::SlotInfo {
+                slot,
+                slot_size,
+                expires_slot_size,
+            },
+            Event::AuxField(key, value) => RdbEvent::AuxField { key, value },
+            Event::Function(library_code) => RdbEvent::Function { library_code },
+            Event::UnknownType(code) => RdbEvent::UnknownType { code },
+            Event::Set(key, value, expiry) => RdbEvent::KeyString { key, value, expiry },
+            Event::CompressedSet(key, compressed, real_length, expiry) => {
+                let value = lzf::decompress(&compressed, real_length as usize)
+                    .map_err(|err| RdbError::Other(format!("Invalid LZF payload: {:?}", err)))?;
+                RdbEvent::KeyString { key, value, expiry }
+            }
+            Event::Module(key, module_name) => RdbEvent::Module { key, module_name },
+            Event::ProbabilisticFilter(key, filter, expiry) => RdbEvent::ProbabilisticFilter {
+                key,
+                filter,
+                expiry,
+            },
+            Event::Hyperloglog(key, encoding, cardinality, expiry) => RdbEvent::Hyperloglog {
+                key,
+                encoding,
+                cardinality,
+                expiry,
+            },
+            Event::Bitmap(key, length, popcount, expiry) => RdbEvent::Bitmap {
+                key,
+                length,
+                popcount,
+                expiry,
+            },
+            Event::ValueLength(key, length, expiry) => RdbEvent::ValueLength {
+                key,
+                length,
+                expiry,
+            },
+            Event::BlobStats(key, stats) => RdbEvent::BlobStats { key, stats },
+            Event::KeyOffset(key, offset, size) => RdbEvent::KeyOffset { key, offset, size },
+            Event::KeyUsage(key, usage) => RdbEvent::KeyUsage { key, usage },
+            Event::StartHash(key, length, expiry, encoding) => RdbEvent::HashStart {
+                key,
+                length,
+                expiry,
+                encoding,
+            },
+            Event::EndHash(key) => RdbEvent::HashEnd { key },
+            Event::HashElement(key, field, value) => RdbEvent::HashField {
+                key,
+                field,
+                value,
+                expiry: None,
+            },
+            Event::HashElementEx(key, field, value, expiry) => RdbEvent::HashField {
+                key,
+                field,
+                value,
+                expiry: Some(expiry),
+            },
+            Event::StartSet(key, cardinality, expiry, encoding) => RdbEvent::SetStart {
+                key,
+                cardinality,
+                expiry,
+                encoding,
+            },
+            Event::EndSet(key) => RdbEvent::SetEnd { key },
+            Event::SetElement(key, member) => RdbEvent::SetMember { key, member },
+            Event::StartList(key, length, expiry, encoding) => RdbEvent::ListStart {
+                key,
+                length,
+                expiry,
+                encoding,
+            },
+            Event::EndList(key) => RdbEvent::ListEnd { key },
+            Event::ListElement(key, value) => RdbEvent::ListElement { key, value },
+            Event::StartSortedSet(key, length, expiry, encoding) => RdbEvent::SortedSetStart {
+                key,
+                length,
+                expiry,
+                encoding,
+            },
+            Event::EndSortedSet(key) => RdbEvent::SortedSetEnd { key },
+            Event::SortedSetElement(key, score, member) => {
+                RdbEvent::SortedSetMember { key, score, member }
+            }
+            Event::StartStream(key, length, expiry, encoding) => RdbEvent::StreamStart {
+                key,
+                length,
+                expiry,
+                encoding,
+            },
+            Event::EndStream(key) => RdbEvent::StreamEnd { key },
+            Event::StreamMetadata(key, metadata) => RdbEvent::StreamMetadata { key, metadata },
+            Event::StreamEntry(key, id, fields) => RdbEvent::StreamEntry { key, id, fields },
+            Event::StreamGroup(key, group) => RdbEvent::StreamGroup { key, group },
+            Event::StreamConsumer(key, group, consumer) => RdbEvent::StreamConsumer {
+                key,
+                group,
+                consumer,
+            },
+            Event::StreamPendingEntry(key, group, entry) => {
+                RdbEvent::StreamPendingEntry { key, group, entry }
+            }
+        })
+    }
+}
+
+/// An iterator over the events of a dump, see `parse`.
+///
+/// A parse error is yielded as the last item.
+pub struct Events {
+    receiver: Receiver<RdbResult<Vec<Event>>>,
+    batch: vec::IntoIter<Event>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Iterator for Events {
+    type Item = RdbResult<RdbEvent>;
+
+    fn next(&mut self) -> Option<RdbResult<RdbEvent>> {
+        loop {
+            if let Some(event) = self.batch.next() {
+                return Some(RdbEvent::from_event(event));
+            }
+
+            match self.receiver.recv() {
+                Ok(Ok(batch)) => self.batch = batch.into_iter(),
+                Ok(Err(err)) => return Some(Err(err)),
+                Err(_) => {
+                    // The worker is done. Pass on a panic instead of
+                    // ending quietly with a truncated dump.
+                    if let Some(worker) = self.worker.take() {
+                        if let Err(panic) = worker.join() {
+                            panic::resume_unwind(panic);
+                        }
+                    }
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Parses `input` on a background thread into an iterator of events.
+///
+/// The events follow the `Formatter` callbacks, in the same order.
+pub fn parse<R, L>(input: R, filter: L) -> Events
+where
+    R: Read + Send + 'static,
+    L: Filter + Send + 'static,
+{
+    let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+    let worker = thread::spawn(move || {
+        let mut recorder = Recorder::new(sender.clone());
+        let parsed = RdbParser::new(input, &mut recorder, filter).parse();
+        // Hand out the events before a parse error as well.
+        let result = recorder.flush().and(parsed);
+        if let Err(err) = result {
+            // Nobody is listening anymore if the send fails.
+            let _ = sender.send(Err(err));
+        }
+    });
+
+    Events {
+        receiver,
+        batch: Vec::new().into_iter(),
+        worker: Some(worker),
+    }
+}
@@ -0,0 +1,168 @@
+//! Sorting of key/value records that don't fit into memory.
+//!
+//! Records are buffered until the memory budget is used up. The buffer is
+//! then sorted and written to a temporary file as a run. Once all records
+//! were pushed, the runs are merged back into a single sorted stream.
+//! Temporary files are removed when the sorter or the stream are dropped.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::PathBuf;
+
+use crate::helper::read_exact;
+use crate::types::RdbResult;
+
+/// Memory budget used unless configured otherwise: 256 MiB.
+pub const DEFAULT_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Bookkeeping cost of a single buffered record, in addition to its data.
+const RECORD_OVERHEAD: usize = 2 * mem::size_of::<Vec<u8>>();
+
+pub type Record = (Vec<u8>, Vec<u8>);
+
+/// Sorts records by key, then by value.
+pub struct ExternalSorter {
+    budget: usize,
+    temp_dir: Option<PathBuf>,
+    buffer: Vec<Record>,
+    buffered: usize,
+    runs: Vec<File>,
+}
+
+impl ExternalSorter {
+    /// Creates a sorter keeping at most about `budget` bytes of records in memory.
+    pub fn new(budget: usize) -> ExternalSorter {
+        ExternalSorter {
+            budget,
+            temp_dir: None,
+            buffer: vec![],
+            buffered: 0,
+            runs: vec![],
+        }
+    }
+
+    /// Writes runs to `dir` instead of the system's temporary directory.
+    pub fn set_temp_dir<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.temp_dir = Some(dir.into());
+    }
+
+    /// The number of runs spilled to disk so far.
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn push(&mut self, key: Vec<u8>, value: Vec<u8>) -> RdbResult<()> {
+        self.buffered += key.len() + value.len() + RECORD_OVERHEAD;
+        self.buffer.push((key, value));
+
+        if self.buffered >= self.budget {
+            self.spill()?;
+        }
+
+        Ok(())
+    }
+
+    fn spill(&mut self) -> RdbResult<()> {
+        self.buffer.sort_unstable();
+
+        let file = match self.temp_dir {
+            Some(ref dir) => tempfile::tempfile_in(dir)?,
+            None => tempfile::tempfile()?,
+        };
+        let mut out = BufWriter::new(file);
+        for (key, value) in self.buffer.drain(..) {
+            write_field(&mut out, &key)?;
+            write_field(&mut out, &value)?;
+        }
+        let mut file = out.into_inner().map_err(|err| err.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+
+        self.runs.push(file);
+        self.buffered = 0;
+
+        Ok(())
+    }
+
+    /// Returns all pushed records in sorted order.
+    pub fn finish(mut self) -> RdbResult<Sorted> {
+        if self.runs.is_empty() {
+            self.buffer.sort_unstable();
+            return Ok(Sorted {
+                source: Source::Memory(mem::take(&mut self.buffer).into_iter()),
+            });
+        }
+
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+
+        let mut runs: Vec<BufReader<File>> = self.runs.drain(..).map(BufReader::new).collect();
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(record) = read_record(run)? {
+                heap.push(Reverse((record, i)));
+            }
+        }
+
+        Ok(Sorted {
+            source: Source::Merge { runs, heap },
+        })
+    }
+}
+
+fn write_field<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    out.write_u64::<LittleEndian>(data.len() as u64)?;
+    out.write_all(data)
+}
+
+fn read_record(run: &mut BufReader<File>) -> RdbResult<Option<Record>> {
+    let key_length = match run.read_u64::<LittleEndian>() {
+        Ok(length) => length,
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let key = read_exact(run, key_length as usize)?;
+    let value_length = run.read_u64::<LittleEndian>()?;
+    let value = read_exact(run, value_length as usize)?;
+
+    Ok(Some((key, value)))
+}
+
+enum Source {
+    Memory(std::vec::IntoIter<Record>),
+    Merge {
+        runs: Vec<BufReader<File>>,
+        heap: BinaryHeap<Reverse<(Record, usize)>>,
+    },
+}
+
+/// The records of an `ExternalSorter` in sorted order.
+pub struct Sorted {
+    source: Source,
+}
+
+impl Iterator for Sorted {
+    type Item = RdbResult<Record>;
+
+    fn next(&mut self) -> Option<RdbResult<Record>> {
+        match &mut self.source {
+            Source::Memory(records) => records.next().map(Ok),
+            Source::Merge { runs, heap } => {
+                let Reverse((record, i)) = heap.pop()?;
+                match read_record(&mut runs[i]) {
+                    Ok(Some(next)) => heap.push(Reverse((next, i))),
+                    Ok(None) => {}
+                    Err(err) => {
+                        heap.clear();
+                        return Some(Err(err));
+                    }
+                }
+                Some(Ok(record))
+            }
+        }
+    }
+}
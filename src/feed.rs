@@ -0,0 +1,212 @@
+//! Parsing a dump from chunks of bytes as they arrive.
+//!
+//! `FeedParser` is pushed the bytes of a dump, e.g. as they come in over a
+//! replication connection, and returns the events of everything complete
+//! so far. Nothing blocks waiting for input that hasn't been fed yet:
+//!
+//! ```rust,no_run
+//! use rdb::feed::FeedParser;
+//! use std::io::Read;
+//! use std::net::TcpStream;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut stream = TcpStream::connect("127.0.0.1:7000")?;
+//! let mut parser = FeedParser::new(rdb::filter::Simple::new());
+//! let mut chunk = [0; 16 * 1024];
+//! loop {
+//!     let length = stream.read(&mut chunk)?;
+//!     if length == 0 {
+//!         break;
+//!     }
+//!     for event in parser.feed(&chunk[..length])? {
+//!         println!("{:?}", event);
+//!     }
+//! }
+//! for event in parser.finish()? {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The parser runs on a thread of its own, which is handed each chunk and
+//! reports back once it has read all of it, so its state carries over from
+//! one chunk to the next, even in the middle of a value. `feed` blocks
+//! until the thread has parsed the chunk, and dropping the parser ends the
+//! input and waits for the thread to stop.
+
+use std::io::{self, Read};
+use std::panic;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::event::{Event, Recorder};
+use crate::events::RdbEvent;
+use crate::filter::Filter;
+use crate::parser::RdbParser;
+use crate::types::{RdbError, RdbResult};
+
+/// Number of events the parser may get ahead of the caller.
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// The input of the parser thread, read from the fed chunks.
+///
+/// Once a chunk is used up, an empty batch of events tells the caller that
+/// everything before it was parsed. The end of input is reached when the
+/// sending side hangs up.
+struct Chunks {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+    events: SyncSender<RdbResult<Vec<Event>>>,
+    started: bool,
+    done: bool,
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            if self.started && self.events.send(Ok(Vec::new())).is_err() {
+                self.done = true;
+                return Ok(0);
+            }
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                    self.started = true;
+                }
+                Err(_) => {
+                    self.done = true;
+                    return Ok(0);
+                }
+            }
+        }
+
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// A parser that is pushed its input instead of reading it.
+pub struct FeedParser {
+    chunks: Option<SyncSender<Vec<u8>>>,
+    events: Receiver<RdbResult<Vec<Event>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl FeedParser {
+    pub fn new<L: Filter + Send + 'static>(filter: L) -> FeedParser {
+        let (chunks, receiver) = sync_channel(1);
+        let (events, events_receiver) = sync_channel(CHANNEL_CAPACITY);
+        let worker = thread::spawn(move || {
+            let input = Chunks {
+                receiver,
+                chunk: Vec::new(),
+                position: 0,
+                events: events.clone(),
+                started: false,
+                done: false,
+            };
+            let mut recorder = Recorder::unbatched(events.clone());
+            let parsed = RdbParser::new(input, &mut recorder, filter).parse();
+            let result = recorder.flush().and(parsed);
+            if let Err(err) = result {
+                // Nobody is listening anymore if the send fails.
+                let _ = events.send(Err(err));
+            }
+        });
+
+        FeedParser {
+            chunks: Some(chunks),
+            events: events_receiver,
+            worker: Some(worker),
+        }
+    }
+
+    /// Parses the next `bytes` of the dump and returns the events of all
+    /// input complete so far.
+    ///
+    /// Blocks until the parser thread has read all of `bytes`.
+    ///
+    /// After an error, or once the dump has ended, no more input is
+    /// accepted.
+    pub fn feed(&mut self, bytes: &[u8]) -> RdbResult<Vec<RdbEvent>> {
+        if bytes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let chunks = self
+            .chunks
+            .as_ref()
+            .ok_or_else(|| RdbError::Other("Parser was fed after it stopped".into()))?;
+        if chunks.send(bytes.to_vec()).is_err() {
+            // The dump ended before these bytes, or parsing failed.
+            self.chunks = None;
+            return Err(RdbError::Other("Parser was fed after it stopped".into()));
+        }
+
+        self.receive(false)
+    }
+
+    /// Ends the input and returns the remaining events, like the checksum.
+    ///
+    /// Fails if the dump is incomplete.
+    pub fn finish(mut self) -> RdbResult<Vec<RdbEvent>> {
+        self.chunks = None;
+        self.receive(true)
+    }
+
+    /// Collects events until the parser has used up the last chunk, or
+    /// until it is done if `to_end` is set.
+    fn receive(&mut self, to_end: bool) -> RdbResult<Vec<RdbEvent>> {
+        let mut events = Vec::new();
+        loop {
+            match self.events.recv() {
+                Ok(Ok(batch)) if batch.is_empty() => {
+                    if !to_end {
+                        break;
+                    }
+                }
+                Ok(Ok(batch)) => {
+                    for event in batch {
+                        events.push(RdbEvent::from_event(event)?);
+                    }
+                }
+                Ok(Err(err)) => {
+                    self.chunks = None;
+                    return Err(err);
+                }
+                Err(_) => {
+                    // Pass on a panic of the parser thread.
+                    if let Some(worker) = self.worker.take() {
+                        if let Err(panic) = worker.join() {
+                            panic::resume_unwind(panic);
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+impl Drop for FeedParser {
+    /// Ends the input of a parser dropped in the middle of the dump and
+    /// waits for its thread, which then fails on the incomplete dump.
+    fn drop(&mut self) {
+        self.chunks = None;
+        // Unblocks the thread if it waits for room for more events.
+        while self.events.recv().is_ok() {}
+        if let Some(worker) = self.worker.take() {
+            // A panic is only passed on while the parser is used.
+            let _ = worker.join();
+        }
+    }
+}
@@ -11,6 +11,27 @@ pub trait Filter {
     fn matches_key(&self, _key: &[u8]) -> bool {
         true
     }
+    /// Called for the keys of dumps of cluster nodes, from Redis 7.2 on,
+    /// which tell the hash slot of their keys. Keys of other dumps are
+    /// not checked.
+    fn matches_slot(&self, _slot: u32) -> bool {
+        true
+    }
+}
+
+impl<T: Filter + ?Sized> Filter for &T {
+    fn matches_db(&self, db: u32) -> bool {
+        (**self).matches_db(db)
+    }
+    fn matches_type(&self, enc_type: u8) -> bool {
+        (**self).matches_type(enc_type)
+    }
+    fn matches_key(&self, key: &[u8]) -> bool {
+        (**self).matches_key(key)
+    }
+    fn matches_slot(&self, slot: u32) -> bool {
+        (**self).matches_slot(slot)
+    }
 }
 
 #[derive(Default)]
@@ -18,6 +39,7 @@ pub struct Simple {
     databases: Vec<u32>,
     types: Vec<Type>,
     keys: Option<Regex>,
+    slots: Vec<u32>,
 }
 
 impl Simple {
@@ -36,6 +58,10 @@ impl Simple {
     pub fn add_keys(&mut self, re: Regex) {
         self.keys = Some(re);
     }
+
+    pub fn add_slot(&mut self, slot: u32) {
+        self.slots.push(slot);
+    }
 }
 
 impl Filter for Simple {
@@ -43,7 +69,7 @@ impl Filter for Simple {
         if self.databases.is_empty() {
             true
         } else {
-            self.databases.iter().any(|&x| x == db)
+            self.databases.contains(&db)
         }
     }
 
@@ -53,7 +79,7 @@ impl Filter for Simple {
         }
 
         let typ = Type::from_encoding(enc_type);
-        self.types.iter().any(|x| *x == typ)
+        self.types.contains(&typ)
     }
 
     fn matches_key(&self, key: &[u8]) -> bool {
@@ -62,4 +88,8 @@ impl Filter for Simple {
             Some(re) => re.is_match(key),
         }
     }
+
+    fn matches_slot(&self, slot: u32) -> bool {
+        self.slots.is_empty() || self.slots.contains(&slot)
+    }
 }
@@ -0,0 +1,104 @@
+use super::write_str;
+use crate::formatter::Formatter;
+use crate::types::{BlobStats, RdbResult};
+use std::io;
+use std::io::Write;
+
+/// Reports how much LZF compression saves, for every key stored with
+/// compressed blobs and for the whole dump.
+///
+/// Redis only compresses blobs longer than 20 bytes, and only keeps the
+/// compressed form if it is smaller, so most small values have no
+/// compressed blobs at all.
+pub struct Compression {
+    out: Box<dyn Write + 'static>,
+    dbnum: u32,
+    total: BlobStats,
+    line_ending: &'static str,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Compression {
+        Compression {
+            out: Box::new(out),
+            dbnum: 0,
+            total: BlobStats::default(),
+            line_ending: "\n",
+        }
+    }
+
+    /// Ends lines with CRLF instead of LF, as Windows tools expect. Line
+    /// feeds inside keys are written unchanged.
+    pub fn set_crlf(&mut self, crlf: bool) {
+        self.line_ending = if crlf { "\r\n" } else { "\n" };
+    }
+
+    /// The blobs of all values seen so far.
+    pub fn total(&self) -> &BlobStats {
+        &self.total
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+fn describe(stats: &BlobStats) -> String {
+    format!(
+        "{}/{} blobs compressed, {} -> {} bytes ({:.2}x)",
+        stats.compressed_blobs,
+        stats.blobs,
+        stats.real_bytes,
+        stats.stored_bytes,
+        stats.ratio()
+    )
+}
+
+impl Formatter for Compression {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        let total = describe(&self.total);
+        let saved = self
+            .total
+            .real_bytes
+            .saturating_sub(self.total.stored_bytes);
+        write_str(
+            &mut self.out,
+            &format!(
+                "total: {}, {} bytes saved{}",
+                total, saved, self.line_ending
+            ),
+        )?;
+        self.out.flush()?;
+
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
+
+        Ok(())
+    }
+
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        self.total.merge(stats);
+        if stats.compressed_blobs == 0 {
+            return Ok(());
+        }
+
+        write_str(&mut self.out, &format!("db={} ", self.dbnum))?;
+        self.out.write_all(key)?;
+        write_str(
+            &mut self.out,
+            &format!(": {}{}", describe(stats), self.line_ending),
+        )?;
+
+        Ok(())
+    }
+}
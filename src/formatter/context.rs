@@ -0,0 +1,413 @@
+//! Callbacks that carry the database, expiry and encoding of their key.
+//!
+//! The element callbacks of `Formatter`, like `hash_element`, only get the
+//! key, so formatters needing the database or expiry of an element keep
+//! track of it themselves. A `ContextFormatter` gets all of that with every
+//! callback instead, and `WithContext` turns it into a `Formatter`:
+//!
+//! ```rust,no_run
+//! use rdb::formatter::{ContextFormatter, KeyContext, WithContext};
+//! use rdb::RdbResult;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! struct Fields;
+//!
+//! impl ContextFormatter for Fields {
+//!     fn hash_element(
+//!         &mut self,
+//!         context: &KeyContext,
+//!         field: &[u8],
+//!         _value: &[u8],
+//!         _expiry: Option<u64>,
+//!     ) -> RdbResult<()> {
+//!         println!("{} {:?} {:?}", context.db, context.key, field);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let file = BufReader::new(File::open("dump.rdb").unwrap());
+//! rdb::parse(file, WithContext::new(Fields), rdb::filter::Simple::new()).unwrap();
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use crate::formatter::Formatter;
+use crate::types::{
+    EncodingType, FilterInfo, HllEncoding, RdbResult, StreamConsumer, StreamGroup, StreamId,
+    StreamMetadata, StreamPendingEntry, Type, ValueLength,
+};
+
+/// The key a callback belongs to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyContext {
+    pub db: u32,
+    pub key: Vec<u8>,
+    pub typ: Type,
+    /// The expiry of the key as a Unix timestamp in milliseconds.
+    pub expiry: Option<u64>,
+    /// How the value is stored, for lists, sets, sorted sets, hashes and
+    /// streams.
+    pub encoding: Option<EncodingType>,
+}
+
+/// A formatter getting the context of the key with each of its callbacks.
+///
+/// Every key starts with `start_key` and ends with `end_key`, with the
+/// callbacks of its value in between.
+#[allow(unused_variables)]
+pub trait ContextFormatter {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        Ok(())
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        Ok(())
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// `length` is the number of elements, 1 for strings and values the
+    /// parser doesn't list.
+    fn start_key(&mut self, context: &KeyContext, length: u32) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_key(&mut self, context: &KeyContext) -> RdbResult<()> {
+        Ok(())
+    }
+
+    fn string(&mut self, context: &KeyContext, value: &[u8]) -> RdbResult<()> {
+        Ok(())
+    }
+    fn module(&mut self, context: &KeyContext, module_name: &str) -> RdbResult<()> {
+        Ok(())
+    }
+    fn probabilistic_filter(&mut self, context: &KeyContext, filter: &FilterInfo) -> RdbResult<()> {
+        Ok(())
+    }
+    fn hyperloglog(
+        &mut self,
+        context: &KeyContext,
+        encoding: HllEncoding,
+        cardinality: u64,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn bitmap(&mut self, context: &KeyContext, length: u64, popcount: u64) -> RdbResult<()> {
+        Ok(())
+    }
+    fn value_length(&mut self, context: &KeyContext, length: &ValueLength) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// `expiry` is that of the field, see `Formatter::hash_element_ex`.
+    fn hash_element(
+        &mut self,
+        context: &KeyContext,
+        field: &[u8],
+        value: &[u8],
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn set_element(&mut self, context: &KeyContext, member: &[u8]) -> RdbResult<()> {
+        Ok(())
+    }
+    fn list_element(&mut self, context: &KeyContext, value: &[u8]) -> RdbResult<()> {
+        Ok(())
+    }
+    fn sorted_set_element(
+        &mut self,
+        context: &KeyContext,
+        score: f64,
+        member: &[u8],
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+
+    fn stream_metadata(
+        &mut self,
+        context: &KeyContext,
+        metadata: &StreamMetadata,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        context: &KeyContext,
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_group(&mut self, context: &KeyContext, group: &StreamGroup) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_consumer(
+        &mut self,
+        context: &KeyContext,
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_pending_entry(
+        &mut self,
+        context: &KeyContext,
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+}
+
+/// A formatter keeping track of the current key and passing it on to a
+/// `ContextFormatter` with every callback.
+pub struct WithContext<F> {
+    formatter: F,
+    context: KeyContext,
+}
+
+impl<F: ContextFormatter> WithContext<F> {
+    pub fn new(formatter: F) -> WithContext<F> {
+        WithContext {
+            formatter,
+            context: KeyContext {
+                db: 0,
+                key: vec![],
+                typ: Type::String,
+                expiry: None,
+                encoding: None,
+            },
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.formatter
+    }
+
+    fn start_key(
+        &mut self,
+        key: &[u8],
+        typ: Type,
+        expiry: Option<u64>,
+        encoding: Option<EncodingType>,
+        length: u32,
+    ) -> RdbResult<()> {
+        self.context.key.clear();
+        self.context.key.extend_from_slice(key);
+        self.context.typ = typ;
+        self.context.expiry = expiry;
+        self.context.encoding = encoding;
+        self.formatter.start_key(&self.context, length)
+    }
+
+    fn end_key(&mut self) -> RdbResult<()> {
+        self.formatter.end_key(&self.context)
+    }
+}
+
+impl<F: ContextFormatter> Formatter for WithContext<F> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.end_rdb()
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.context.db = db_index;
+        self.formatter.start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.formatter.end_database(db_index)
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.formatter.aux_field(key, value)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key, Type::String, expiry, None, 1)?;
+        self.formatter.string(&self.context, value)?;
+        self.end_key()
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.start_key(key, Type::Module, None, None, 1)?;
+        self.formatter.module(&self.context, module_name)?;
+        self.end_key()
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Module, expiry, None, 1)?;
+        self.formatter.probabilistic_filter(&self.context, filter)?;
+        self.end_key()
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::String, expiry, None, 1)?;
+        self.formatter
+            .hyperloglog(&self.context, encoding, cardinality)?;
+        self.end_key()
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::String, expiry, None, 1)?;
+        self.formatter.bitmap(&self.context, length, popcount)?;
+        self.end_key()
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, length.typ, expiry, None, 1)?;
+        self.formatter.value_length(&self.context, length)?;
+        self.end_key()
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Hash, expiry, Some(info), length)
+    }
+    fn end_hash(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key()
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.formatter
+            .hash_element(&self.context, field, value, None)
+    }
+    fn hash_element_ex(
+        &mut self,
+        _key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.formatter
+            .hash_element(&self.context, field, value, Some(expiry))
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Set, expiry, Some(info), cardinality)
+    }
+    fn end_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key()
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.formatter.set_element(&self.context, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::List, expiry, Some(info), length)
+    }
+    fn end_list(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key()
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.formatter.list_element(&self.context, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::SortedSet, expiry, Some(info), length)
+    }
+    fn end_sorted_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key()
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.formatter
+            .sorted_set_element(&self.context, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, Type::Stream, expiry, Some(info), length)
+    }
+    fn end_stream(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key()
+    }
+    fn stream_metadata(&mut self, _key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.formatter.stream_metadata(&self.context, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.formatter.stream_entry(&self.context, id, fields)
+    }
+    fn stream_group(&mut self, _key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.formatter.stream_group(&self.context, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        _key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.formatter
+            .stream_consumer(&self.context, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        _key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.formatter
+            .stream_pending_entry(&self.context, group, entry)
+    }
+}
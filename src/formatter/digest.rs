@@ -0,0 +1,414 @@
+use super::write_str;
+use crate::formatter::{Formatter, TimeFormat};
+use crate::types::{EncodingType, RdbResult, StreamId};
+use sha2::{Digest as _, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::str;
+
+/// Prints one line per key with its type, a SHA-256 digest of its value
+/// and its TTL, but never the value itself.
+///
+/// Digests of sets, hashes and sorted sets don't depend on the order of
+/// their elements, so dumps of the same data compare equal regardless of
+/// encoding. The TTL is relative to the `ctime` AUX field if the dump has
+/// one, otherwise the absolute expiry is printed, in milliseconds unless a
+/// time format is set.
+///
+/// A plain digest of a short or guessable value gives the value away, so
+/// digests that leave the company should be keyed with `set_salt`: each
+/// digest is then replaced by its HMAC-SHA256 under the salt, printed as
+/// `hmac-sha256=`. Only dumps digested with the same salt compare equal.
+///
+/// With `set_fingerprint`, the lines of the keys are summed up into a
+/// single fingerprint per database and one for the whole dump instead.
+pub struct Digest {
+    out: Output,
+    salt: Option<Vec<u8>>,
+    line_ending: &'static str,
+    time_format: Option<TimeFormat>,
+    dbnum: u32,
+    ctime: Option<u64>,
+    expiry: Option<u64>,
+    ordered: Sha256,
+    unordered: [u8; 32],
+    fingerprint: bool,
+    /// The number of keys and the sum of their digests, by database.
+    fingerprints: BTreeMap<u32, (u64, [u8; 32])>,
+}
+
+/// The digest of a single key.
+pub(crate) struct KeyDigest<'a> {
+    pub(crate) db: u32,
+    pub(crate) key: &'a [u8],
+    pub(crate) typ: &'static str,
+    pub(crate) sha256: &'a [u8],
+    pub(crate) expiry: Option<u64>,
+}
+
+type Callback = Box<dyn FnMut(KeyDigest) -> RdbResult<()>>;
+
+enum Output {
+    Lines(Box<dyn Write + 'static>),
+    Callback(Callback),
+}
+
+fn write_len(hasher: &mut Sha256, data: &[u8]) {
+    hasher.update((data.len() as u64).to_le_bytes());
+    hasher.update(data);
+}
+
+fn element_digest(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        write_len(&mut hasher, part);
+    }
+    hasher.finalize().into()
+}
+
+/// HMAC-SHA256 of `message` under `key`, as defined in RFC 2104.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(block.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// Adds `digest` to `sum` modulo 2^256, which is independent of the order
+/// the digests are added in.
+fn add_digest(sum: &mut [u8; 32], digest: &[u8; 32]) {
+    let mut carry = 0u16;
+    for (a, b) in sum.iter_mut().zip(digest.iter()) {
+        let total = *a as u16 + *b as u16 + carry;
+        *a = total as u8;
+        carry = total >> 8;
+    }
+}
+
+impl Digest {
+    pub fn new() -> Digest {
+        Digest::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Digest {
+        Digest::with_output(Output::Lines(Box::new(out)))
+    }
+
+    /// Creates a formatter handing each key's digest to `callback` instead
+    /// of printing it.
+    pub(crate) fn with_callback<C>(callback: C) -> Digest
+    where
+        C: FnMut(KeyDigest) -> RdbResult<()> + 'static,
+    {
+        Digest::with_output(Output::Callback(Box::new(callback)))
+    }
+
+    fn with_output(out: Output) -> Digest {
+        Digest {
+            out,
+            salt: None,
+            line_ending: "\n",
+            time_format: None,
+            dbnum: 0,
+            ctime: None,
+            expiry: None,
+            ordered: Sha256::new(),
+            unordered: [0; 32],
+            fingerprint: false,
+            fingerprints: BTreeMap::new(),
+        }
+    }
+
+    /// Keys all digests with `salt`, see the type's documentation.
+    pub fn set_salt(&mut self, salt: &[u8]) {
+        self.salt = Some(salt.to_vec());
+    }
+
+    /// Ends lines with CRLF instead of LF, as Windows tools expect. Line
+    /// feeds inside keys are written unchanged.
+    pub fn set_crlf(&mut self, crlf: bool) {
+        self.line_ending = if crlf { "\r\n" } else { "\n" };
+    }
+
+    /// Prints a line per database with its number of keys and a
+    /// fingerprint of them, and a last one for the whole dump, instead of
+    /// a line per key, e.g. `db=0 keys=2 sha256=...` and
+    /// `total keys=2 sha256=...`.
+    ///
+    /// A fingerprint is the sum of the digests of the key lines, so it
+    /// doesn't depend on the order of the keys either.
+    pub fn set_fingerprint(&mut self, fingerprint: bool) {
+        self.fingerprint = fingerprint;
+    }
+
+    /// Renders absolute expiries with `time_format`.
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.time_format = Some(time_format);
+    }
+
+    fn start_key(&mut self, expiry: Option<u64>) {
+        self.expiry = expiry;
+        self.ordered = Sha256::new();
+        self.unordered = [0; 32];
+    }
+
+    fn write_line(&mut self, key: &[u8], typ: &'static str, digest: &[u8]) -> RdbResult<()> {
+        let keyed;
+        let (digest, label) = match self.salt {
+            Some(ref salt) => {
+                keyed = hmac_sha256(salt, digest);
+                (&keyed[..], "hmac-sha256")
+            }
+            None => (digest, "sha256"),
+        };
+
+        let out = match self.out {
+            Output::Lines(ref mut out) => out,
+            Output::Callback(ref mut callback) => {
+                return callback(KeyDigest {
+                    db: self.dbnum,
+                    key,
+                    typ,
+                    sha256: digest,
+                    expiry: self.expiry,
+                })
+            }
+        };
+
+        let ttl = match (self.expiry, self.ctime) {
+            (None, _) => "ttl=-1".to_owned(),
+            (Some(expiry), Some(ctime)) => format!("ttl={}", expiry as i64 - ctime as i64),
+            (Some(expiry), None) => match self.time_format {
+                Some(ref time_format) => format!("expiry={}", time_format.render_millis(expiry)),
+                None => format!("expiry={}", expiry),
+            },
+        };
+
+        if self.fingerprint {
+            let line = element_digest(&[key, typ.as_bytes(), digest, ttl.as_bytes()]);
+            let (keys, sum) = self.fingerprints.entry(self.dbnum).or_insert((0, [0; 32]));
+            *keys += 1;
+            add_digest(sum, &line);
+            return Ok(());
+        }
+
+        write_str(out, &format!("db={} ", self.dbnum))?;
+        out.write_all(key)?;
+        write_str(
+            out,
+            &format!(" type={} {}={} ", typ, label, hex::encode(digest)),
+        )?;
+        write_str(out, &ttl)?;
+        write_str(out, self.line_ending)?;
+
+        Ok(())
+    }
+
+    /// Prints the fingerprints of the databases and of the whole dump.
+    fn write_fingerprints(&mut self) -> RdbResult<()> {
+        let out = match self.out {
+            Output::Lines(ref mut out) => out,
+            Output::Callback(_) => return Ok(()),
+        };
+        let label = match self.salt {
+            Some(_) => "hmac-sha256",
+            None => "sha256",
+        };
+
+        let (mut total_keys, mut total) = (0, [0; 32]);
+        for (db, (keys, sum)) in &self.fingerprints {
+            write_str(
+                out,
+                &format!("db={} keys={} {}={}", db, keys, label, hex::encode(sum)),
+            )?;
+            write_str(out, self.line_ending)?;
+            total_keys += keys;
+            add_digest(&mut total, sum);
+        }
+        write_str(
+            out,
+            &format!("total keys={} {}={}", total_keys, label, hex::encode(total)),
+        )?;
+        write_str(out, self.line_ending)?;
+
+        Ok(())
+    }
+
+    fn end_ordered(&mut self, key: &[u8], typ: &'static str) -> RdbResult<()> {
+        let digest = std::mem::replace(&mut self.ordered, Sha256::new()).finalize();
+        self.write_line(key, typ, &digest)
+    }
+
+    fn end_unordered(&mut self, key: &[u8], typ: &'static str) -> RdbResult<()> {
+        let digest = self.unordered;
+        self.write_line(key, typ, &digest)
+    }
+}
+
+impl Default for Digest {
+    fn default() -> Digest {
+        Digest::new()
+    }
+}
+
+impl Formatter for Digest {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        if self.fingerprint {
+            self.write_fingerprints()?;
+        }
+        if let Output::Lines(ref mut out) = self.out {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
+        Ok(())
+    }
+
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        if key == b"ctime" {
+            self.ctime = str::from_utf8(value)
+                .ok()
+                .and_then(|ctime| ctime.parse::<u64>().ok())
+                .map(|ctime| ctime * 1000);
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(expiry);
+        write_len(&mut self.ordered, value);
+        self.end_ordered(key, "string")
+    }
+
+    fn start_hash(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(expiry);
+        Ok(())
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_unordered(key, "hash")
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        add_digest(&mut self.unordered, &element_digest(&[field, value]));
+        Ok(())
+    }
+    fn hash_element_ex(
+        &mut self,
+        _key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        let expiry = expiry.to_le_bytes();
+        add_digest(
+            &mut self.unordered,
+            &element_digest(&[field, value, &expiry]),
+        );
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        _key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(expiry);
+        Ok(())
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_unordered(key, "set")
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        add_digest(&mut self.unordered, &element_digest(&[member]));
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(expiry);
+        Ok(())
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_ordered(key, "list")
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        write_len(&mut self.ordered, value);
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(expiry);
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_unordered(key, "sortedset")
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        let score = score.to_bits().to_le_bytes();
+        add_digest(&mut self.unordered, &element_digest(&[member, &score]));
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(expiry);
+        Ok(())
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_ordered(key, "stream")
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        write_len(&mut self.ordered, id.to_string().as_bytes());
+        self.ordered.update((fields.len() as u64).to_le_bytes());
+        for (field, value) in fields {
+            write_len(&mut self.ordered, field);
+            write_len(&mut self.ordered, value);
+        }
+        Ok(())
+    }
+}
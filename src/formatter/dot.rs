@@ -0,0 +1,175 @@
+use super::write_str;
+use crate::formatter::Formatter;
+use crate::types::{EncodingType, RdbResult, StreamId};
+use regex::bytes::Regex;
+use std::collections::{BTreeSet, HashSet};
+use std::io;
+use std::io::Write;
+
+/// Prints a Graphviz graph of the references between keys.
+///
+/// Every match of the reference pattern in a value, field or member is taken
+/// as the name of another key, or the first capture group if the pattern has
+/// one. Each distinct reference becomes an edge from the key holding it.
+/// Referenced keys that don't exist in the dump are drawn dashed.
+pub struct Dot {
+    out: Box<dyn Write + 'static>,
+    references: Regex,
+    keys: HashSet<Vec<u8>>,
+    edges: BTreeSet<(Vec<u8>, Vec<u8>)>,
+}
+
+fn quote(name: &[u8]) -> String {
+    let name = String::from_utf8_lossy(name);
+    let name = name
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+    format!("\"{}\"", name)
+}
+
+impl Dot {
+    pub fn new(references: Regex) -> Dot {
+        Dot::with_writer(references, io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(references: Regex, out: W) -> Dot {
+        let out = Box::new(out);
+        Dot {
+            out,
+            references,
+            keys: HashSet::new(),
+            edges: BTreeSet::new(),
+        }
+    }
+
+    fn scan(&mut self, key: &[u8], value: &[u8]) {
+        for captures in self.references.captures_iter(value) {
+            let target = captures.get(1).or_else(|| captures.get(0));
+            if let Some(target) = target {
+                self.edges
+                    .insert((key.to_vec(), target.as_bytes().to_vec()));
+            }
+        }
+    }
+}
+
+impl Formatter for Dot {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        write_str(&mut self.out, "digraph keys {\n")?;
+
+        let mut missing = BTreeSet::new();
+        for (from, to) in &self.edges {
+            write_str(
+                &mut self.out,
+                &format!("  {} -> {};\n", quote(from), quote(to)),
+            )?;
+            if !self.keys.contains(to) {
+                missing.insert(to);
+            }
+        }
+        for key in missing {
+            write_str(
+                &mut self.out,
+                &format!("  {} [style=dashed];\n", quote(key)),
+            )?;
+        }
+
+        write_str(&mut self.out, "}\n")?;
+        self.out.flush()?;
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], _expiry: Option<u64>) -> RdbResult<()> {
+        self.keys.insert(key.to_vec());
+        self.scan(key, value);
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.keys.insert(key.to_vec());
+        Ok(())
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.scan(key, field);
+        self.scan(key, value);
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.keys.insert(key.to_vec());
+        Ok(())
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.scan(key, member);
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.keys.insert(key.to_vec());
+        Ok(())
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.scan(key, value);
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.keys.insert(key.to_vec());
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        self.scan(key, member);
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.keys.insert(key.to_vec());
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        for (field, value) in fields {
+            self.scan(key, field);
+            self.scan(key, value);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use super::write_str;
+use crate::formatter::Formatter;
+use crate::types::{EncodingType, RdbResult, StreamId, ValueLength};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+/// What the numbers of the folded stacks measure.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Weight {
+    /// Approximate size of the data: the bytes of the key and of all fields,
+    /// members and values. Redis's per-object overhead is not included.
+    Bytes,
+    /// Number of keys.
+    Count,
+}
+
+/// Aggregates the keyspace by prefix and prints it in the folded-stack
+/// format understood by flamegraph and treemap tools.
+///
+/// Keys are split at the separator, so `user:42:name` becomes the stack
+/// `user;42;name`. With a depth limit, all keys sharing the first `depth`
+/// segments are aggregated into one stack. Keys of all databases are
+/// aggregated together.
+pub struct Folded {
+    out: Box<dyn Write + 'static>,
+    separator: u8,
+    depth: usize,
+    weight: Weight,
+    stacks: BTreeMap<String, u64>,
+    key_size: u64,
+}
+
+impl Folded {
+    pub fn new() -> Folded {
+        Folded::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Folded {
+        let out = Box::new(out);
+        Folded {
+            out,
+            separator: b':',
+            depth: 0,
+            weight: Weight::Bytes,
+            stacks: BTreeMap::new(),
+            key_size: 0,
+        }
+    }
+
+    /// Sets the byte keys are split at. Defaults to `:`.
+    pub fn set_separator(&mut self, separator: u8) {
+        self.separator = separator;
+    }
+
+    /// Limits the stacks to `depth` frames. 0, the default, means no limit.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    pub fn set_weight(&mut self, weight: Weight) {
+        self.weight = weight;
+    }
+
+    fn stack(&self, key: &[u8]) -> String {
+        let depth = if self.depth == 0 {
+            usize::MAX
+        } else {
+            self.depth
+        };
+
+        key.split(|&b| b == self.separator)
+            .take(depth)
+            .map(|frame| String::from_utf8_lossy(frame).replace([';', '\n'], "_"))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+
+    fn start_key(&mut self, key: &[u8]) {
+        self.key_size = key.len() as u64;
+    }
+
+    fn add(&mut self, bytes: usize) {
+        self.key_size += bytes as u64;
+    }
+
+    fn end_key(&mut self, key: &[u8]) {
+        let weight = match self.weight {
+            Weight::Bytes => self.key_size,
+            Weight::Count => 1,
+        };
+        let stack = self.stack(key);
+        *self.stacks.entry(stack).or_insert(0) += weight;
+    }
+}
+
+impl Default for Folded {
+    fn default() -> Folded {
+        Folded::new()
+    }
+}
+
+impl Formatter for Folded {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        for (stack, weight) in &self.stacks {
+            write_str(&mut self.out, &format!("{} {}\n", stack, weight))?;
+        }
+        self.out.flush()?;
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], _expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key);
+        self.add(value.len());
+        self.end_key(key);
+
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        _expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        self.add(length.bytes as usize);
+        self.end_key(key);
+
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key);
+        Ok(())
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field.len() + value.len());
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key);
+        Ok(())
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member.len());
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key);
+        Ok(())
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key);
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member.len() + std::mem::size_of::<f64>());
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        _expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key);
+        Ok(())
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key);
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let fields: usize = fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum();
+        self.add(std::mem::size_of::<StreamId>() + fields);
+        Ok(())
+    }
+}
@@ -1,30 +1,92 @@
+use super::stream_json::{self, Groups};
 use super::write_str;
-use crate::formatter::Formatter;
-use crate::types::{EncodingType, RdbResult};
+use crate::formatter::{Buffered, FlushPolicy, Formatter, TimeFormat};
+use crate::geo;
+use crate::types::{
+    EncodingType, RdbResult, StreamConsumer, StreamGroup, StreamId, StreamMetadata,
+    StreamPendingEntry, Type,
+};
+use regex::bytes::Regex;
 use std::io;
 use std::io::Write;
 
 pub struct JSON {
-    out: Box<dyn Write + 'static>,
+    out: Buffered,
     is_first_db: bool,
     has_databases: bool,
     is_first_key_in_db: bool,
     elements_in_key: u32,
     element_index: u32,
+    stream_entries_open: bool,
+    stream_groups: Groups,
+    geo_keys: Option<Regex>,
+    is_geo: bool,
+    metadata: bool,
+    time_format: Option<TimeFormat>,
+    dbnum: u32,
 }
 
 impl JSON {
     pub fn new() -> JSON {
-        let out = Box::new(io::stdout());
+        JSON::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> JSON {
         JSON {
-            out,
+            out: Buffered::new(out),
             is_first_db: true,
             has_databases: false,
             is_first_key_in_db: true,
             elements_in_key: 0,
             element_index: 0,
+            stream_entries_open: false,
+            stream_groups: Groups::default(),
+            geo_keys: None,
+            is_geo: false,
+            metadata: false,
+            time_format: None,
+            dbnum: 0,
         }
     }
+
+    /// Sets when the output is flushed. Defaults to every 64 KiB and at
+    /// the end of the dump.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.out.set_policy(policy);
+    }
+
+    /// Renders the times of stream consumers and pending entries as strings
+    /// instead of milliseconds, and adds the expiry as a string next to
+    /// `expiry_ms` in the metadata.
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.stream_groups.set_time_format(time_format.clone());
+        self.time_format = Some(time_format);
+    }
+
+    /// Takes the sorted sets whose names match `keys` as GEO sets and
+    /// writes the coordinates of their members instead of the scores, e.g.
+    /// `"Palermo":{"longitude":13.361389338970184,"latitude":38.1155563954963}`.
+    ///
+    /// Scores that can't be geohashes are written as usual.
+    pub fn set_geo_keys(&mut self, keys: Regex) {
+        self.geo_keys = Some(keys);
+    }
+
+    /// Nests every value in an object carrying its type, database, expiry
+    /// and encoding, e.g. `"k":{"type":"list","db":0,"expiry_ms":null,
+    /// "encoding":"quicklist","value":["a"]}`.
+    ///
+    /// The expiry is in milliseconds, `null` for keys without one.
+    pub fn set_metadata(&mut self, metadata: bool) {
+        self.metadata = metadata;
+    }
+}
+
+impl Default for JSON {
+    fn default() -> JSON {
+        JSON::new()
+    }
 }
 
 fn encode_to_ascii(value: &[u8]) -> String {
@@ -45,7 +107,46 @@ impl JSON {
         Ok(())
     }
 
-    fn end_key(&mut self) {}
+    /// Opens the object holding the metadata of the value, if enabled.
+    fn write_metadata(
+        &mut self,
+        typ: Type,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        if self.metadata {
+            let rendered = match (expiry, &self.time_format) {
+                (Some(expiry), Some(time_format)) => format!(
+                    ",\"expiry\":{}",
+                    serde_json::Value::from(time_format.render_millis(expiry))
+                ),
+                _ => String::new(),
+            };
+            let expiry = expiry.map_or_else(|| "null".to_owned(), |expiry| expiry.to_string());
+            write_str(
+                &mut self.out,
+                &format!(
+                    "{{\"type\":\"{}\",\"db\":{},\"expiry_ms\":{}{},\"encoding\":\"{}\",\"value\":",
+                    typ.name(),
+                    self.dbnum,
+                    expiry,
+                    rendered,
+                    info.name()
+                ),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn end_key(&mut self) -> RdbResult<()> {
+        if self.metadata {
+            write_str(&mut self.out, "}")?;
+        }
+        self.out.end_key()?;
+
+        Ok(())
+    }
 
     fn write_comma(&mut self) -> RdbResult<()> {
         if self.element_index > 0 {
@@ -67,6 +168,16 @@ impl JSON {
 
         Ok(())
     }
+
+    /// Opens the entries of the current stream, after its metadata.
+    fn open_stream_entries(&mut self) -> RdbResult<()> {
+        if !self.stream_entries_open {
+            write_str(&mut self.out, "\"entries\":{")?;
+            self.stream_entries_open = true;
+        }
+
+        Ok(())
+    }
 }
 
 impl Formatter for JSON {
@@ -79,11 +190,13 @@ impl Formatter for JSON {
             write_str(&mut self.out, "}")?;
         }
         write_str(&mut self.out, "]\n")?;
+        self.out.flush()?;
 
         Ok(())
     }
 
-    fn start_database(&mut self, _db_number: u32) -> RdbResult<()> {
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
         if !self.is_first_db {
             write_str(&mut self.out, "},")?;
         }
@@ -96,35 +209,33 @@ impl Formatter for JSON {
         Ok(())
     }
 
-    fn set(&mut self, key: &[u8], value: &[u8], _expiry: Option<u64>) -> RdbResult<()> {
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
         self.start_key(0)?;
         self.write_key(key)?;
         write_str(&mut self.out, ":")?;
+        self.write_metadata(Type::String, expiry, EncodingType::String)?;
         self.write_value(value)?;
-
-        Ok(())
+        self.end_key()
     }
 
     fn start_hash(
         &mut self,
         key: &[u8],
         length: u32,
-        _expiry: Option<u64>,
-        _info: EncodingType,
+        expiry: Option<u64>,
+        info: EncodingType,
     ) -> RdbResult<()> {
         self.start_key(length)?;
         self.write_key(key)?;
-        write_str(&mut self.out, ":{")?;
-        self.out.flush()?;
-
+        write_str(&mut self.out, ":")?;
+        self.write_metadata(Type::Hash, expiry, info)?;
+        write_str(&mut self.out, "{")?;
         Ok(())
     }
 
     fn end_hash(&mut self, _key: &[u8]) -> RdbResult<()> {
-        self.end_key();
         write_str(&mut self.out, "}")?;
-        self.out.flush()?;
-
+        self.end_key()?;
         Ok(())
     }
 
@@ -133,8 +244,6 @@ impl Formatter for JSON {
         self.write_key(field)?;
         write_str(&mut self.out, ":")?;
         self.write_value(value)?;
-        self.out.flush()?;
-
         Ok(())
     }
 
@@ -142,20 +251,20 @@ impl Formatter for JSON {
         &mut self,
         key: &[u8],
         cardinality: u32,
-        _expiry: Option<u64>,
-        _info: EncodingType,
+        expiry: Option<u64>,
+        info: EncodingType,
     ) -> RdbResult<()> {
         self.start_key(cardinality)?;
         self.write_key(key)?;
-        write_str(&mut self.out, ":[")?;
-        self.out.flush()?;
-
+        write_str(&mut self.out, ":")?;
+        self.write_metadata(Type::Set, expiry, info)?;
+        write_str(&mut self.out, "[")?;
         Ok(())
     }
 
     fn end_set(&mut self, _key: &[u8]) -> RdbResult<()> {
-        self.end_key();
         write_str(&mut self.out, "]")?;
+        self.end_key()?;
 
         Ok(())
     }
@@ -171,19 +280,21 @@ impl Formatter for JSON {
         &mut self,
         key: &[u8],
         length: u32,
-        _expiry: Option<u64>,
-        _info: EncodingType,
+        expiry: Option<u64>,
+        info: EncodingType,
     ) -> RdbResult<()> {
         self.start_key(length)?;
         self.write_key(key)?;
-        write_str(&mut self.out, ":[")?;
+        write_str(&mut self.out, ":")?;
+        self.write_metadata(Type::List, expiry, info)?;
+        write_str(&mut self.out, "[")?;
 
         Ok(())
     }
 
     fn end_list(&mut self, _key: &[u8]) -> RdbResult<()> {
-        self.end_key();
         write_str(&mut self.out, "]")?;
+        self.end_key()?;
 
         Ok(())
     }
@@ -199,19 +310,25 @@ impl Formatter for JSON {
         &mut self,
         key: &[u8],
         length: u32,
-        _expiry: Option<u64>,
-        _info: EncodingType,
+        expiry: Option<u64>,
+        info: EncodingType,
     ) -> RdbResult<()> {
         self.start_key(length)?;
         self.write_key(key)?;
-        write_str(&mut self.out, ":{")?;
+        write_str(&mut self.out, ":")?;
+        self.write_metadata(Type::SortedSet, expiry, info)?;
+        write_str(&mut self.out, "{")?;
+        self.is_geo = self
+            .geo_keys
+            .as_ref()
+            .is_some_and(|keys| keys.is_match(key));
 
         Ok(())
     }
 
     fn end_sorted_set(&mut self, _key: &[u8]) -> RdbResult<()> {
-        self.end_key();
         write_str(&mut self.out, "}")?;
+        self.end_key()?;
 
         Ok(())
     }
@@ -220,8 +337,108 @@ impl Formatter for JSON {
         self.write_comma()?;
         self.write_key(member)?;
         write_str(&mut self.out, ":")?;
-        self.write_value(score.to_string().as_bytes())?;
+        match geo::decode(score).filter(|_| self.is_geo) {
+            Some(position) => write_str(
+                &mut self.out,
+                &format!(
+                    "{{\"longitude\":{},\"latitude\":{}}}",
+                    position.longitude, position.latitude
+                ),
+            )?,
+            None => self.write_value(score.to_string().as_bytes())?,
+        }
+
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(length)?;
+        self.write_key(key)?;
+        write_str(&mut self.out, ":")?;
+        self.write_metadata(Type::Stream, expiry, info)?;
+        write_str(&mut self.out, "{")?;
+        self.stream_entries_open = false;
+
+        Ok(())
+    }
+
+    fn end_stream(&mut self, _key: &[u8]) -> RdbResult<()> {
+        // Entries are written as they come, groups are collected as their
+        // consumers and pending entries may arrive in any order.
+        self.open_stream_entries()?;
+        let groups = self.stream_groups.take_value();
+        write_str(&mut self.out, "},\"groups\":")?;
+        write_str(&mut self.out, &groups.to_string())?;
+        write_str(&mut self.out, "}")?;
+        self.end_key()?;
+
+        Ok(())
+    }
+
+    fn stream_metadata(&mut self, _key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        for (name, value) in stream_json::metadata(metadata) {
+            self.write_key(name.as_bytes())?;
+            write_str(&mut self.out, ":")?;
+            write_str(&mut self.out, &value.to_string())?;
+            write_str(&mut self.out, ",")?;
+        }
 
         Ok(())
     }
+
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.open_stream_entries()?;
+        self.write_comma()?;
+        self.write_key(id.to_string().as_bytes())?;
+        write_str(&mut self.out, ":[")?;
+        for (index, (field, value)) in fields.iter().enumerate() {
+            if index > 0 {
+                write_str(&mut self.out, ",")?;
+            }
+            write_str(&mut self.out, "[")?;
+            self.write_value(field)?;
+            write_str(&mut self.out, ",")?;
+            self.write_value(value)?;
+            write_str(&mut self.out, "]")?;
+        }
+        write_str(&mut self.out, "]")?;
+
+        Ok(())
+    }
+
+    fn stream_group(&mut self, _key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.stream_groups.group(group);
+        Ok(())
+    }
+
+    fn stream_consumer(
+        &mut self,
+        _key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.stream_groups.consumer(group, consumer);
+        Ok(())
+    }
+
+    fn stream_pending_entry(
+        &mut self,
+        _key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.stream_groups.pending_entry(group, entry);
+        Ok(())
+    }
 }
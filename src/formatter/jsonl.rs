@@ -0,0 +1,379 @@
+use super::stream_json::{self, Groups};
+use super::write_str;
+use crate::formatter::{Formatter, TimeFormat};
+use crate::types::{
+    EncodingType, HllEncoding, RdbResult, StreamConsumer, StreamGroup, StreamId, StreamMetadata,
+    StreamPendingEntry,
+};
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use std::io;
+use std::io::Write;
+use std::str;
+
+/// Prints one JSON object per key and line, e.g.
+/// `{"db":0,"key":"k","type":"hash","expiry":1700000000000,"value":{"f":"v"}}`.
+///
+/// Unlike `JSON`, every line stands on its own, so the output can be
+/// streamed into tools that process one record at a time. The expiry is
+/// left out for keys without one. It is in milliseconds unless a time
+/// format is set, which renders it and the times of streams as strings.
+/// If the dump has a `ctime` AUX field, keys with an expiry also get a
+/// `ttl`, the milliseconds they had left when the dump was written.
+///
+/// HyperLogLogs the parser recognizes are of type `hyperloglog`, with
+/// `{"cardinality":3,"encoding":"sparse"}` as value. Strings the parser
+/// treats as bitmaps are of type `bitmap`, with `{"length":2,"popcount":5}`.
+pub struct JsonLines {
+    out: Output,
+    dbnum: u32,
+    current: Option<Pending>,
+    stream_groups: Groups,
+    time_format: Option<TimeFormat>,
+    /// The `ctime` of the dump, in milliseconds.
+    ctime: Option<u64>,
+}
+
+enum Output {
+    Lines(Box<dyn Write + 'static>),
+    Callback(Box<dyn FnMut(Record) -> RdbResult<()>>),
+}
+
+/// A finished key, as handed to a callback.
+pub(crate) struct Record {
+    pub db: u32,
+    pub key: Vec<u8>,
+    pub typ: &'static str,
+    pub expiry: Option<u64>,
+    pub value: Value,
+}
+
+struct Pending {
+    typ: &'static str,
+    expiry: Option<u64>,
+    value: Value,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    db: u32,
+    key: &'a str,
+    #[serde(rename = "type")]
+    typ: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiry: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ttl: Option<i64>,
+    value: &'a Value,
+}
+
+fn string(value: &[u8]) -> Value {
+    Value::String(String::from_utf8_lossy(value).into_owned())
+}
+
+impl JsonLines {
+    pub fn new() -> JsonLines {
+        JsonLines::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> JsonLines {
+        JsonLines::with_output(Output::Lines(Box::new(out)))
+    }
+
+    /// Creates a formatter handing each key to `callback` instead of
+    /// printing it.
+    pub(crate) fn with_callback<C>(callback: C) -> JsonLines
+    where
+        C: FnMut(Record) -> RdbResult<()> + 'static,
+    {
+        JsonLines::with_output(Output::Callback(Box::new(callback)))
+    }
+
+    fn with_output(out: Output) -> JsonLines {
+        JsonLines {
+            out,
+            dbnum: 0,
+            current: None,
+            stream_groups: Groups::default(),
+            time_format: None,
+            ctime: None,
+        }
+    }
+
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.stream_groups.set_time_format(time_format.clone());
+        self.time_format = Some(time_format);
+    }
+
+    fn start_key(&mut self, typ: &'static str, expiry: Option<u64>, value: Value) {
+        self.current = Some(Pending { typ, expiry, value });
+    }
+
+    fn add(&mut self, name: Option<String>, value: Value) {
+        match self.current {
+            Some(Pending {
+                value: Value::Object(ref mut map),
+                ..
+            }) => {
+                map.insert(name.unwrap_or_default(), value);
+            }
+            Some(Pending {
+                value: Value::Array(ref mut items),
+                ..
+            }) => items.push(value),
+            _ => {}
+        }
+    }
+
+    /// The object of the current stream.
+    fn stream(&mut self) -> Option<&mut Map<String, Value>> {
+        match self.current {
+            Some(Pending {
+                value: Value::Object(ref mut map),
+                ..
+            }) => Some(map),
+            _ => None,
+        }
+    }
+
+    fn end_key(&mut self, key: &[u8]) -> RdbResult<()> {
+        let pending = match self.current.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        let time_format = self.time_format.as_ref();
+        match self.out {
+            Output::Lines(ref mut out) => {
+                let line = Line {
+                    db: self.dbnum,
+                    key: &String::from_utf8_lossy(key),
+                    typ: pending.typ,
+                    expiry: pending
+                        .expiry
+                        .map(|expiry| stream_json::timestamp(expiry, time_format)),
+                    ttl: pending
+                        .expiry
+                        .zip(self.ctime)
+                        .map(|(expiry, ctime)| expiry as i64 - ctime as i64),
+                    value: &pending.value,
+                };
+                write_str(out, &serde_json::to_string(&line).unwrap())?;
+                write_str(out, "\n")
+            }
+            Output::Callback(ref mut callback) => callback(Record {
+                db: self.dbnum,
+                key: key.to_vec(),
+                typ: pending.typ,
+                expiry: pending.expiry,
+                value: pending.value,
+            }),
+        }
+    }
+}
+
+impl Default for JsonLines {
+    fn default() -> JsonLines {
+        JsonLines::new()
+    }
+}
+
+impl Formatter for JsonLines {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        if let Output::Lines(ref mut out) = self.out {
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
+        Ok(())
+    }
+
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        if key == b"ctime" {
+            self.ctime = str::from_utf8(value)
+                .ok()
+                .and_then(|ctime| ctime.parse::<u64>().ok())
+                .map(|ctime| ctime * 1000);
+        }
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key("string", expiry, string(value));
+        self.end_key(key)
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let encoding = match encoding {
+            HllEncoding::Dense => "dense",
+            HllEncoding::Sparse => "sparse",
+        };
+        let mut value = Map::new();
+        value.insert("encoding".to_owned(), Value::from(encoding));
+        value.insert("cardinality".to_owned(), Value::from(cardinality));
+        self.start_key("hyperloglog", expiry, Value::Object(value));
+        self.end_key(key)
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let mut value = Map::new();
+        value.insert("length".to_owned(), Value::from(length));
+        value.insert("popcount".to_owned(), Value::from(popcount));
+        self.start_key("bitmap", expiry, Value::Object(value));
+        self.end_key(key)
+    }
+
+    fn start_hash(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("hash", expiry, Value::Object(Map::new()));
+        Ok(())
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        let field = String::from_utf8_lossy(field).into_owned();
+        self.add(Some(field), string(value));
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        _key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("set", expiry, Value::Array(vec![]));
+        Ok(())
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(None, string(member));
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("list", expiry, Value::Array(vec![]));
+        Ok(())
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(None, string(value));
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("sortedset", expiry, Value::Object(Map::new()));
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        // JSON has no infinity, keep those as strings like Redis prints them.
+        let score = Number::from_f64(score)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(score.to_string()));
+        let member = String::from_utf8_lossy(member).into_owned();
+        self.add(Some(member), score);
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        let mut stream = Map::new();
+        stream.insert("entries".into(), Value::Object(Map::new()));
+        self.start_key("stream", expiry, Value::Object(stream));
+        Ok(())
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        let groups = self.stream_groups.take_value();
+        if let Some(stream) = self.stream() {
+            stream.insert("groups".into(), groups);
+        }
+        self.end_key(key)
+    }
+    fn stream_metadata(&mut self, _key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        if let Some(stream) = self.stream() {
+            stream.extend(stream_json::metadata(metadata));
+        }
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let entries = self.stream().and_then(|stream| stream.get_mut("entries"));
+        if let Some(Value::Object(entries)) = entries {
+            entries.insert(id.to_string(), stream_json::fields(fields));
+        }
+        Ok(())
+    }
+    fn stream_group(&mut self, _key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.stream_groups.group(group);
+        Ok(())
+    }
+    fn stream_consumer(
+        &mut self,
+        _key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.stream_groups.consumer(group, consumer);
+        Ok(())
+    }
+    fn stream_pending_entry(
+        &mut self,
+        _key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.stream_groups.pending_entry(group, entry);
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+//! Whole values instead of streamed elements.
+//!
+//! Some outputs, like rows of a table, need a value with all of its
+//! elements at once. A `ValueFormatter` gets each value in one call,
+//! collected by `Materialize`, and `rdb::parse_values` parses a dump into
+//! one:
+//!
+//! ```rust,no_run
+//! use rdb::formatter::{KeyContext, ValueFormatter};
+//! use rdb::RdbResult;
+//! use rdb::value::Value;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! struct Lengths;
+//!
+//! impl ValueFormatter for Lengths {
+//!     fn value(&mut self, context: &KeyContext, value: Value) -> RdbResult<()> {
+//!         if let Value::List(elements) = value {
+//!             println!("{:?}: {}", context.key, elements.len());
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let file = BufReader::new(File::open("dump.rdb").unwrap());
+//! rdb::parse_values(file, Lengths, rdb::filter::Simple::new()).unwrap();
+//! ```
+
+use crate::formatter::{ContextFormatter, KeyContext};
+use crate::types::{RdbResult, StreamId, Type, Value};
+
+/// A formatter getting whole values.
+#[allow(unused_variables)]
+pub trait ValueFormatter {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        Ok(())
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called once per key, after all of its elements are read.
+    fn value(&mut self, context: &KeyContext, value: Value) -> RdbResult<()>;
+}
+
+/// A `ContextFormatter` collecting the elements of each key and passing
+/// the whole value on to a `ValueFormatter` once the key ends.
+///
+/// Each value is held in memory until then. Keys the parser only measures
+/// or summarizes, like HyperLogLogs, bitmaps, probabilistic filters and
+/// everything in lengths-only mode, have no value and are left out.
+pub struct Materialize<F> {
+    formatter: F,
+    value: Option<Value>,
+}
+
+impl<F: ValueFormatter> Materialize<F> {
+    pub fn new(formatter: F) -> Materialize<F> {
+        Materialize {
+            formatter,
+            value: None,
+        }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.formatter
+    }
+}
+
+impl<F: ValueFormatter> ContextFormatter for Materialize<F> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.end_rdb()
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.formatter.start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.formatter.end_database(db_index)
+    }
+
+    fn start_key(&mut self, context: &KeyContext, length: u32) -> RdbResult<()> {
+        // Lengths come from the dump, so they only hint at the capacity.
+        let length = (length as usize).min(1024);
+        self.value = match context.typ {
+            Type::List => Some(Value::List(Vec::with_capacity(length))),
+            Type::Set => Some(Value::Set(Vec::with_capacity(length))),
+            Type::SortedSet => Some(Value::SortedSet(Vec::with_capacity(length))),
+            Type::Hash => Some(Value::Hash(Vec::with_capacity(length))),
+            Type::Stream => Some(Value::Stream(Vec::with_capacity(length))),
+            Type::String | Type::Module => None,
+        };
+        Ok(())
+    }
+    fn end_key(&mut self, context: &KeyContext) -> RdbResult<()> {
+        match self.value.take() {
+            Some(value) => self.formatter.value(context, value),
+            None => Ok(()),
+        }
+    }
+
+    fn string(&mut self, _context: &KeyContext, value: &[u8]) -> RdbResult<()> {
+        self.value = Some(Value::String(value.to_vec()));
+        Ok(())
+    }
+    fn module(&mut self, _context: &KeyContext, module_name: &str) -> RdbResult<()> {
+        self.value = Some(Value::Module(module_name.to_owned()));
+        Ok(())
+    }
+
+    fn hash_element(
+        &mut self,
+        _context: &KeyContext,
+        field: &[u8],
+        value: &[u8],
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        if let Some(Value::Hash(fields)) = &mut self.value {
+            fields.push((field.to_vec(), value.to_vec(), expiry));
+        }
+        Ok(())
+    }
+    fn set_element(&mut self, _context: &KeyContext, member: &[u8]) -> RdbResult<()> {
+        if let Some(Value::Set(members)) = &mut self.value {
+            members.push(member.to_vec());
+        }
+        Ok(())
+    }
+    fn list_element(&mut self, _context: &KeyContext, value: &[u8]) -> RdbResult<()> {
+        if let Some(Value::List(elements)) = &mut self.value {
+            elements.push(value.to_vec());
+        }
+        Ok(())
+    }
+    fn sorted_set_element(
+        &mut self,
+        _context: &KeyContext,
+        score: f64,
+        member: &[u8],
+    ) -> RdbResult<()> {
+        if let Some(Value::SortedSet(members)) = &mut self.value {
+            members.push((member.to_vec(), score));
+        }
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _context: &KeyContext,
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        if let Some(Value::Stream(entries)) = &mut self.value {
+            entries.push((id, fields.to_vec()));
+        }
+        Ok(())
+    }
+}
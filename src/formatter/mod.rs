@@ -1,19 +1,195 @@
-use std::io::Write;
+use std::io::{self, BufWriter, Write};
 
+pub use self::compression::Compression;
+pub use self::context::{ContextFormatter, KeyContext, WithContext};
+pub use self::digest::Digest;
+pub use self::dot::Dot;
+pub use self::folded::Folded;
 pub use self::json::JSON;
+pub use self::jsonl::JsonLines;
+pub use self::materialize::{Materialize, ValueFormatter};
 pub use self::nil::Nil;
+#[cfg(feature = "parquet")]
+pub use self::parquet::Parquet;
 pub use self::plain::Plain;
+pub use self::prometheus::Prometheus;
 pub use self::protocol::Protocol;
+pub use self::sorted::Sorted;
+pub use self::statistics::Statistics;
+pub use self::table::Table;
+pub use self::tee::Tee;
+pub use self::template::Template;
+pub use self::time::{TimeFormat, Zone};
 
-use super::types::{EncodingType, RdbResult};
+use super::types::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, RdbError, RdbResult,
+    StreamConsumer, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
 
+pub mod compression;
+pub mod context;
+pub mod digest;
+pub mod dot;
+pub mod folded;
 pub mod json;
+pub mod jsonl;
+pub mod materialize;
 pub mod nil;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub(crate) mod payload;
 pub mod plain;
+pub mod prometheus;
 pub mod protocol;
+pub mod sorted;
+pub mod statistics;
+mod stream_json;
+pub mod table;
+pub mod tee;
+pub mod template;
+pub mod time;
+
+// The names of the formatters before 0.3. Re-exports can't be deprecated,
+// so the trait keeps its old name without a warning.
+#[doc(hidden)]
+pub use self::Formatter as RdbParseFormatter;
+#[doc(hidden)]
+#[deprecated(since = "0.3.0", note = "renamed to `JSON`")]
+pub type JSONFormatter = JSON;
+#[doc(hidden)]
+#[deprecated(since = "0.3.0", note = "renamed to `Plain`")]
+pub type PlainFormatter = Plain;
+#[doc(hidden)]
+#[deprecated(since = "0.3.0", note = "renamed to `Nil`")]
+pub type NilFormatter = Nil;
+#[doc(hidden)]
+#[deprecated(since = "0.3.0", note = "renamed to `Protocol`")]
+pub type ProtocolFormatter = Protocol;
+
+/// A writer that turns every line feed into a carriage return and line feed,
+/// as Windows tools expect. Existing `\r\n` pairs are left alone.
+///
+/// Only suitable for formats that escape line feeds inside values, like
+/// `JSON`. `Plain`, `Digest` and `Compression` write values unchanged and
+/// have `set_crlf` instead.
+pub struct Crlf<W: Write> {
+    out: W,
+    last_was_cr: bool,
+}
+
+impl<W: Write> Crlf<W> {
+    pub fn new(out: W) -> Crlf<W> {
+        Crlf {
+            out,
+            last_was_cr: false,
+        }
+    }
+}
+
+impl<W: Write> Write for Crlf<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for (i, &byte) in buf.iter().enumerate() {
+            let after_cr = if i == 0 {
+                self.last_was_cr
+            } else {
+                buf[i - 1] == b'\r'
+            };
+            if byte == b'\n' && !after_cr {
+                self.out.write_all(&buf[start..i])?;
+                self.out.write_all(b"\r")?;
+                start = i;
+            }
+        }
+        self.out.write_all(&buf[start..])?;
+
+        if let Some(&last) = buf.last() {
+            self.last_was_cr = last == b'\r';
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// When `JSON` and `Plain` flush their output, see their `set_flush_policy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlushPolicy {
+    /// After every key, so the output of a key can be read as soon as it
+    /// is parsed.
+    Key,
+    /// Whenever this many bytes were written since the last flush. The
+    /// default, with 64 KiB.
+    Bytes(usize),
+    /// Only once the dump ends, which is fastest on large dumps.
+    End,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> FlushPolicy {
+        FlushPolicy::Bytes(64 * 1024)
+    }
+}
+
+/// Buffered output, flushed as its `FlushPolicy` says.
+pub(crate) struct Buffered {
+    out: BufWriter<Box<dyn Write + 'static>>,
+    policy: FlushPolicy,
+    unflushed: usize,
+}
+
+impl Buffered {
+    pub(crate) fn new<W: Write + 'static>(out: W) -> Buffered {
+        Buffered {
+            out: BufWriter::new(Box::new(out)),
+            policy: FlushPolicy::default(),
+            unflushed: 0,
+        }
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: FlushPolicy) {
+        self.policy = policy;
+    }
+
+    /// Flushes if the policy says so after every key.
+    pub(crate) fn end_key(&mut self) -> io::Result<()> {
+        if self.policy == FlushPolicy::Key {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Buffered {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.out.write(buf)?;
+        self.unflushed += written;
+        if let FlushPolicy::Bytes(bytes) = self.policy {
+            if self.unflushed >= bytes {
+                self.flush()?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        self.out.flush()
+    }
+}
+
+impl Drop for Buffered {
+    /// Flushes what a formatter dropped before the end of the dump, e.g.
+    /// after an error, has written. Errors can't be reported anymore.
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
 
 pub fn write_str<W: Write>(out: &mut W, data: &str) -> RdbResult<()> {
-    out.write(data.as_bytes())?;
+    out.write_all(data.as_bytes())?;
 
     Ok(())
 }
@@ -40,14 +216,122 @@ pub trait Formatter {
     fn resizedb(&mut self, db_size: u32, expires_size: u32) -> RdbResult<()> {
         Ok(())
     }
+    /// Called by dumps of cluster nodes, from Redis 7.2 on, before the keys
+    /// of each hash slot with the number of keys and keys with an expiry in
+    /// it.
+    fn slot_info(&mut self, slot: u32, slot_size: u32, expires_slot_size: u32) -> RdbResult<()> {
+        Ok(())
+    }
     fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
         Ok(())
     }
+    /// Called for every library of Redis Functions with its source code,
+    /// starting with a line like `#!lua name=mylib`.
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        Ok(())
+    }
 
     fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
         Ok(())
     }
 
+    /// Called instead of `set` for strings stored LZF compressed if the
+    /// parser passes them through raw, see `RdbParser::set_raw_strings`.
+    ///
+    /// `compressed` is the payload as stored in the dump, e.g. to copy it
+    /// into another dump without recompressing it. By default it is
+    /// decompressed and passed to `set`.
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let value = lzf::decompress(compressed, real_length as usize)
+            .map_err(|err| RdbError::Other(format!("Invalid LZF payload: {:?}", err)))?;
+        self.set(key, &value, expiry)
+    }
+
+    /// Called for values written by a Redis module, e.g. RedisJSON, which
+    /// are skipped as they can't be decoded without the module.
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called for an opcode or value type this version doesn't know, right
+    /// before the parse fails with `RdbError::UnknownType`.
+    fn unknown_type(&mut self, code: u8) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called for the bloom and cuckoo filters of the RedisBloom module
+    /// with their parameters, as their contents can't be listed.
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called instead of `set` for strings holding a HyperLogLog with its
+    /// estimated cardinality, if the parser recognizes them, see
+    /// `RdbParser::set_hyperloglogs`.
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called instead of `set` for strings of keys the parser treats as
+    /// bitmaps, with their length in bytes and number of set bits, see
+    /// `RdbParser::set_bitmap_keys`.
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called for every key instead of the value callbacks if the parser
+    /// only measures lengths, see `RdbParser::set_lengths_only`.
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called after the callbacks of each value with the lengths of the
+    /// blobs it is stored in.
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called after `blob_stats` with where the key is stored: the offset
+    /// of its type byte in the dump and the number of bytes from there to
+    /// the end of its value.
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        Ok(())
+    }
+
+    /// Called before the callbacks of a value with how recently or often it
+    /// was used, if the dump stores it.
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        Ok(())
+    }
+
     fn start_hash(
         &mut self,
         key: &[u8],
@@ -63,6 +347,18 @@ pub trait Formatter {
     fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
         Ok(())
     }
+    /// A field of a hash that expires at the Unix timestamp `expiry` in
+    /// milliseconds, as Redis 7.4 stores with `HEXPIRE`. Formatters that
+    /// don't handle field expiries get the field as a plain element.
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.hash_element(key, field, value)
+    }
 
     fn start_set(
         &mut self,
@@ -111,4 +407,265 @@ pub trait Formatter {
     fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
         Ok(())
     }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        Ok(())
+    }
+}
+
+impl<F: Formatter + ?Sized> Formatter for &mut F {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        (**self).start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        (**self).end_rdb()
+    }
+    fn checksum(&mut self, checksum: &[u8]) -> RdbResult<()> {
+        (**self).checksum(checksum)
+    }
+
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        (**self).start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        (**self).end_database(db_index)
+    }
+
+    fn resizedb(&mut self, db_size: u32, expires_size: u32) -> RdbResult<()> {
+        (**self).resizedb(db_size, expires_size)
+    }
+    fn slot_info(&mut self, slot: u32, slot_size: u32, expires_slot_size: u32) -> RdbResult<()> {
+        (**self).slot_info(slot, slot_size, expires_slot_size)
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        (**self).aux_field(key, value)
+    }
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        (**self).function(library_code)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        (**self).set(key, value, expiry)
+    }
+
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        (**self).compressed_set(key, compressed, real_length, expiry)
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        (**self).module(key, module_name)
+    }
+
+    fn unknown_type(&mut self, code: u8) -> RdbResult<()> {
+        (**self).unknown_type(code)
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        (**self).probabilistic_filter(key, filter, expiry)
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        (**self).hyperloglog(key, encoding, cardinality, expiry)
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        (**self).bitmap(key, length, popcount, expiry)
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        (**self).value_length(key, length, expiry)
+    }
+
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        (**self).blob_stats(key, stats)
+    }
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        (**self).key_offset(key, offset, size)
+    }
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        (**self).key_usage(key, usage)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        (**self).start_hash(key, length, expiry, info)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        (**self).end_hash(key)
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        (**self).hash_element(key, field, value)
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        (**self).hash_element_ex(key, field, value, expiry)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        (**self).start_set(key, cardinality, expiry, info)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        (**self).end_set(key)
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        (**self).set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        (**self).start_list(key, length, expiry, info)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        (**self).end_list(key)
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        (**self).list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        (**self).start_sorted_set(key, length, expiry, info)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        (**self).end_sorted_set(key)
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        (**self).sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        (**self).start_stream(key, length, expiry, info)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        (**self).end_stream(key)
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        (**self).stream_metadata(key, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        (**self).stream_entry(key, id, fields)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        (**self).stream_group(key, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        (**self).stream_consumer(key, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        (**self).stream_pending_entry(key, group, entry)
+    }
 }
@@ -8,4 +8,10 @@ impl Nil {
     }
 }
 
+impl Default for Nil {
+    fn default() -> Nil {
+        Nil::new()
+    }
+}
+
 impl Formatter for Nil {}
@@ -0,0 +1,542 @@
+use super::jsonl::Record;
+use crate::formatter::{Formatter, JsonLines};
+use crate::types::{
+    EncodingType, RdbResult, StreamConsumer, StreamGroup, StreamId, StreamMetadata,
+    StreamPendingEntry,
+};
+use std::cell::RefCell;
+use std::io::Write;
+use std::mem;
+use std::rc::Rc;
+
+/// Writes one row per key to an [Apache Parquet](https://parquet.apache.org/)
+/// file, for analytics tools that read it directly.
+///
+/// The rows have the same columns as the objects of `JsonLines`: `db`,
+/// `key`, `type`, `expiry` and `value`. The key is a byte array holding the
+/// key as it is, without a UTF-8 annotation, as keys may be binary. The
+/// expiry is a timestamp in milliseconds and null for keys without one, the
+/// value is the JSON text `JsonLines` writes for it. Pages are uncompressed
+/// and plain encoded, and every `ROW_GROUP_ROWS` keys start a new row group,
+/// so only that many rows are held in memory. The footer is written by
+/// `end_rdb`, the file is unreadable without it.
+///
+/// The format is experimental: the writer is implemented here rather than
+/// by a Parquet library, and its output is only checked against the format
+/// specification, not yet against the files of other writers or readers.
+pub struct Parquet {
+    lines: JsonLines,
+    rows: Rc<RefCell<Vec<Record>>>,
+    out: Box<dyn Write + 'static>,
+    offset: u64,
+    row_groups: Vec<RowGroup>,
+}
+
+/// The number of rows per row group.
+pub const ROW_GROUP_ROWS: usize = 10_000;
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Physical types.
+const INT32: i32 = 1;
+const INT64: i32 = 2;
+const BYTE_ARRAY: i32 = 6;
+
+// Converted types.
+const UTF8: i32 = 0;
+const TIMESTAMP_MILLIS: i32 = 9;
+const UINT_32: i32 = 13;
+const JSON: i32 = 19;
+
+// Repetition types.
+const REQUIRED: i32 = 0;
+const OPTIONAL: i32 = 1;
+
+// Encodings.
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+
+const UNCOMPRESSED: i32 = 0;
+const DATA_PAGE: i32 = 0;
+
+struct Column {
+    name: &'static str,
+    typ: i32,
+    converted_type: Option<i32>,
+    repetition: i32,
+}
+
+const COLUMNS: [Column; 5] = [
+    Column {
+        name: "db",
+        typ: INT32,
+        converted_type: Some(UINT_32),
+        repetition: REQUIRED,
+    },
+    Column {
+        name: "key",
+        typ: BYTE_ARRAY,
+        converted_type: None,
+        repetition: REQUIRED,
+    },
+    Column {
+        name: "type",
+        typ: BYTE_ARRAY,
+        converted_type: Some(UTF8),
+        repetition: REQUIRED,
+    },
+    Column {
+        name: "expiry",
+        typ: INT64,
+        converted_type: Some(TIMESTAMP_MILLIS),
+        repetition: OPTIONAL,
+    },
+    Column {
+        name: "value",
+        typ: BYTE_ARRAY,
+        converted_type: Some(JSON),
+        repetition: REQUIRED,
+    },
+];
+
+/// Where a column chunk was written.
+struct ColumnChunk {
+    offset: u64,
+    size: u64,
+}
+
+struct RowGroup {
+    columns: Vec<ColumnChunk>,
+    rows: usize,
+}
+
+impl Parquet {
+    pub fn with_writer<W: Write + 'static>(out: W) -> Parquet {
+        let rows = Rc::new(RefCell::new(Vec::new()));
+        let sink = rows.clone();
+        let lines = JsonLines::with_callback(move |record| {
+            sink.borrow_mut().push(record);
+            Ok(())
+        });
+
+        Parquet {
+            lines,
+            rows,
+            out: Box::new(out),
+            offset: 0,
+            row_groups: vec![],
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> RdbResult<()> {
+        if self.offset == 0 {
+            self.offset = MAGIC.len() as u64;
+            self.out.write_all(MAGIC)?;
+        }
+        self.out.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the buffered rows as a row group once there are enough of them,
+    /// or any at all if `all` is set.
+    fn flush_rows(&mut self, all: bool) -> RdbResult<()> {
+        let count = self.rows.borrow().len();
+        if count == 0 || (!all && count < ROW_GROUP_ROWS) {
+            return Ok(());
+        }
+
+        let rows = mem::take(&mut *self.rows.borrow_mut());
+        let mut columns = Vec::with_capacity(COLUMNS.len());
+        for column in &COLUMNS {
+            let page = page(column, &rows);
+            let header = page_header(&page, rows.len());
+            self.write(&header)?;
+            let offset = self.offset - header.len() as u64;
+            self.write(&page)?;
+            columns.push(ColumnChunk {
+                offset,
+                size: (header.len() + page.len()) as u64,
+            });
+        }
+        self.row_groups.push(RowGroup {
+            columns,
+            rows: rows.len(),
+        });
+        Ok(())
+    }
+
+    fn end_key(&mut self) -> RdbResult<()> {
+        self.flush_rows(false)
+    }
+}
+
+/// The plain encoded values of `column`, preceded by the definition levels
+/// of an optional column.
+fn page(column: &Column, rows: &[Record]) -> Vec<u8> {
+    let mut page = vec![];
+    let byte_array = |page: &mut Vec<u8>, value: &[u8]| {
+        page.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        page.extend_from_slice(value);
+    };
+    match column.name {
+        "db" => {
+            for row in rows {
+                page.extend_from_slice(&(row.db as i32).to_le_bytes());
+            }
+        }
+        "key" => {
+            for row in rows {
+                byte_array(&mut page, &row.key);
+            }
+        }
+        "type" => {
+            for row in rows {
+                byte_array(&mut page, row.typ.as_bytes());
+            }
+        }
+        "expiry" => {
+            let levels = definition_levels(rows.iter().map(|row| row.expiry.is_some()));
+            page.extend_from_slice(&(levels.len() as u32).to_le_bytes());
+            page.extend_from_slice(&levels);
+            for expiry in rows.iter().filter_map(|row| row.expiry) {
+                page.extend_from_slice(&(expiry as i64).to_le_bytes());
+            }
+        }
+        _ => {
+            for row in rows {
+                byte_array(
+                    &mut page,
+                    serde_json::to_string(&row.value).unwrap().as_bytes(),
+                );
+            }
+        }
+    }
+    page
+}
+
+/// Encodes definition levels of bit width 1 as runs of the RLE hybrid
+/// encoding.
+fn definition_levels<I: Iterator<Item = bool>>(defined: I) -> Vec<u8> {
+    let mut levels = vec![];
+    let mut run: Option<(bool, u64)> = None;
+    let end_run = |levels: &mut Vec<u8>, (value, length): (bool, u64)| {
+        varint(levels, length << 1);
+        levels.push(value as u8);
+    };
+    for value in defined {
+        run = match run {
+            Some((current, length)) if current == value => Some((current, length + 1)),
+            Some(finished) => {
+                end_run(&mut levels, finished);
+                Some((value, 1))
+            }
+            None => Some((value, 1)),
+        };
+    }
+    if let Some(finished) = run {
+        end_run(&mut levels, finished);
+    }
+    levels
+}
+
+fn page_header(page: &[u8], rows: usize) -> Vec<u8> {
+    let mut header = Compact::default();
+    header.i32(1, DATA_PAGE);
+    header.i32(2, page.len() as i32);
+    header.i32(3, page.len() as i32);
+    header.begin(5);
+    header.i32(1, rows as i32);
+    header.i32(2, PLAIN);
+    header.i32(3, RLE);
+    header.i32(4, RLE);
+    header.end();
+    header.stop()
+}
+
+fn footer(row_groups: &[RowGroup]) -> Vec<u8> {
+    let mut meta = Compact::default();
+    meta.i32(1, 1);
+
+    meta.list(2, STRUCT, COLUMNS.len() + 1);
+    meta.element();
+    meta.binary(4, b"schema");
+    meta.i32(5, COLUMNS.len() as i32);
+    meta.end();
+    for column in &COLUMNS {
+        meta.element();
+        meta.i32(1, column.typ);
+        meta.i32(3, column.repetition);
+        meta.binary(4, column.name.as_bytes());
+        if let Some(converted_type) = column.converted_type {
+            meta.i32(6, converted_type);
+        }
+        meta.end();
+    }
+
+    let rows: usize = row_groups.iter().map(|group| group.rows).sum();
+    meta.i64(3, rows as i64);
+
+    meta.list(4, STRUCT, row_groups.len());
+    for group in row_groups {
+        meta.element();
+        meta.list(1, STRUCT, group.columns.len());
+        for (chunk, column) in group.columns.iter().zip(&COLUMNS) {
+            meta.element();
+            meta.i64(2, chunk.offset as i64);
+            meta.begin(3);
+            meta.i32(1, column.typ);
+            meta.list(2, I32, 2);
+            meta.int(PLAIN as i64);
+            meta.int(RLE as i64);
+            meta.list(3, BINARY, 1);
+            meta.bytes(column.name.as_bytes());
+            meta.i32(4, UNCOMPRESSED);
+            meta.i64(5, group.rows as i64);
+            meta.i64(6, chunk.size as i64);
+            meta.i64(7, chunk.size as i64);
+            meta.i64(9, chunk.offset as i64);
+            meta.end();
+            meta.end();
+        }
+        let size: u64 = group.columns.iter().map(|chunk| chunk.size).sum();
+        meta.i64(2, size as i64);
+        meta.i64(3, group.rows as i64);
+        meta.end();
+    }
+
+    meta.binary(
+        6,
+        concat!("rdb-rs version ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    meta.stop()
+}
+
+// Types of the Thrift compact protocol.
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Writes structs in the Thrift compact protocol, which Parquet uses for
+/// its page headers and footer.
+#[derive(Default)]
+struct Compact {
+    buf: Vec<u8>,
+    last_field: i16,
+    outer_fields: Vec<i16>,
+}
+
+impl Compact {
+    fn field(&mut self, id: i16, typ: u8) {
+        let delta = id - self.last_field;
+        if delta > 0 && delta <= 15 {
+            self.buf.push((delta as u8) << 4 | typ);
+        } else {
+            self.buf.push(typ);
+            self.int(i64::from(id));
+        }
+        self.last_field = id;
+    }
+
+    /// A zigzag encoded integer, without a field header.
+    fn int(&mut self, value: i64) {
+        varint(&mut self.buf, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    /// A byte array, without a field header.
+    fn bytes(&mut self, value: &[u8]) {
+        varint(&mut self.buf, value.len() as u64);
+        self.buf.extend_from_slice(value);
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.int(i64::from(value));
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        self.int(value);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.bytes(value);
+    }
+
+    fn list(&mut self, id: i16, elements: u8, size: usize) {
+        self.field(id, LIST);
+        if size < 15 {
+            self.buf.push((size as u8) << 4 | elements);
+        } else {
+            self.buf.push(0xf0 | elements);
+            varint(&mut self.buf, size as u64);
+        }
+    }
+
+    /// Starts a struct field.
+    fn begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.element();
+    }
+
+    /// Starts a struct in a list.
+    fn element(&mut self) {
+        self.outer_fields.push(self.last_field);
+        self.last_field = 0;
+    }
+
+    fn end(&mut self) {
+        self.buf.push(0);
+        self.last_field = self.outer_fields.pop().unwrap_or(0);
+    }
+
+    /// Ends the outermost struct and returns it.
+    fn stop(mut self) -> Vec<u8> {
+        self.buf.push(0);
+        self.buf
+    }
+}
+
+impl Formatter for Parquet {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.flush_rows(true)?;
+        let footer = footer(&self.row_groups);
+        self.write(&footer)?;
+        self.write(&(footer.len() as u32).to_le_bytes())?;
+        self.write(MAGIC)?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.lines.start_database(db_number)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.lines.set(key, value, expiry)?;
+        self.end_key()
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_hash(key, length, expiry, info)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_hash(key)?;
+        self.end_key()
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.lines.hash_element(key, field, value)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_set(key, cardinality, expiry, info)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_set(key)?;
+        self.end_key()
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.lines.set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_list(key, length, expiry, info)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_list(key)?;
+        self.end_key()
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.lines.list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_sorted_set(key, length, expiry, info)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_sorted_set(key)?;
+        self.end_key()
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.lines.sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_stream(key, length, expiry, info)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_stream(key)?;
+        self.end_key()
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.lines.stream_metadata(key, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.lines.stream_entry(key, id, fields)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.lines.stream_group(key, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.lines.stream_consumer(key, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.lines.stream_pending_entry(key, group, entry)
+    }
+}
@@ -0,0 +1,149 @@
+//! Serialization of values into the payloads of `DUMP` and `RESTORE`.
+//!
+//! A payload holds the value type, the value as stored in a dump, the RDB
+//! version it is written in and a CRC64 checksum of all of that. Values are
+//! written in the element by element encodings, which every server since
+//! Redis 5 loads and converts to its preferred encoding.
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+use crate::constants::encoding_type;
+
+/// The RDB version of the payloads, that of Redis 5. `RESTORE` rejects
+/// payloads of versions newer than the server's own.
+const VERSION: u16 = 9;
+
+/// The polynomial of CRC-64/Jones, as Redis checksums dumps, reflected.
+const POLYNOMIAL: u64 = 0x95ac_9329_ac4b_c9b5;
+
+const CRC_TABLE: [u64; 256] = crc_table();
+
+const fn crc_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[byte] = crc;
+        byte += 1;
+    }
+    table
+}
+
+/// The CRC64 checksum Redis appends to dumps and payloads.
+pub fn crc64(bytes: &[u8]) -> u64 {
+    crc64_update(0, bytes)
+}
+
+/// Continues the checksum `crc` of the bytes before with `bytes`.
+pub(crate) fn crc64_update(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ u64::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn write_length(out: &mut Vec<u8>, length: u64) {
+    if length < 1 << 6 {
+        out.push(length as u8);
+    } else if length < 1 << 14 {
+        out.push(0x40 | (length >> 8) as u8);
+        out.push(length as u8);
+    } else if length <= u64::from(u32::MAX) {
+        out.push(0x80);
+        out.write_u32::<BigEndian>(length as u32).unwrap();
+    } else {
+        out.push(0x81);
+        out.write_u64::<BigEndian>(length).unwrap();
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, value: &[u8]) {
+    write_length(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+/// Collects the elements of a hash, set, list or sorted set.
+pub struct Payload {
+    value_type: u8,
+    elements: u64,
+    body: Vec<u8>,
+}
+
+impl Payload {
+    pub fn hash() -> Payload {
+        Payload::new(encoding_type::HASH)
+    }
+
+    pub fn set() -> Payload {
+        Payload::new(encoding_type::SET)
+    }
+
+    pub fn list() -> Payload {
+        Payload::new(encoding_type::LIST)
+    }
+
+    pub fn sorted_set() -> Payload {
+        Payload::new(encoding_type::ZSET_2)
+    }
+
+    fn new(value_type: u8) -> Payload {
+        Payload {
+            value_type,
+            elements: 0,
+            body: vec![],
+        }
+    }
+
+    /// Adds a list element or set member.
+    pub fn element(&mut self, value: &[u8]) {
+        self.elements += 1;
+        write_string(&mut self.body, value);
+    }
+
+    pub fn field(&mut self, field: &[u8], value: &[u8]) {
+        self.elements += 1;
+        write_string(&mut self.body, field);
+        write_string(&mut self.body, value);
+    }
+
+    pub fn member(&mut self, member: &[u8], score: f64) {
+        self.elements += 1;
+        write_string(&mut self.body, member);
+        self.body.write_f64::<LittleEndian>(score).unwrap();
+    }
+
+    /// The payload, or `None` for an empty value, which `RESTORE` rejects.
+    pub fn finish(self) -> Option<Vec<u8>> {
+        if self.elements == 0 {
+            return None;
+        }
+
+        let mut out = vec![self.value_type];
+        write_length(&mut out, self.elements);
+        out.extend_from_slice(&self.body);
+        Some(seal(out))
+    }
+}
+
+/// The payload of a string.
+pub fn string(value: &[u8]) -> Vec<u8> {
+    let mut out = vec![encoding_type::STRING];
+    write_string(&mut out, value);
+    seal(out)
+}
+
+/// Appends the version and the checksum.
+fn seal(mut out: Vec<u8>) -> Vec<u8> {
+    out.write_u16::<LittleEndian>(VERSION).unwrap();
+    let crc = crc64(&out);
+    out.write_u64::<LittleEndian>(crc).unwrap();
+    out
+}
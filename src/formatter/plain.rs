@@ -1,37 +1,135 @@
 use super::write_str;
-use crate::formatter::Formatter;
-use crate::types::{EncodingType, RdbResult};
+use crate::formatter::{Buffered, FlushPolicy, Formatter, TimeFormat};
+use crate::types::{
+    EncodingType, KeyUsage, RdbResult, StreamConsumer, StreamGroup, StreamId, StreamPendingEntry,
+};
 use std::io;
 use std::io::Write;
 
+// ANSI colors of the parts of a line, see `Plain::set_color`. The type
+// markers are the ` . `, `{ }`, `[0]` and `<id>` telling the elements of
+// hashes, sets, lists and sorted sets, and streams apart.
+const DB: &str = "2";
+const KEY: &str = "1;36";
+const TYPE: &str = "35";
+const EXPIRY: &str = "33";
+
 pub struct Plain {
-    out: Box<dyn Write + 'static>,
+    out: Buffered,
     dbnum: u32,
     index: u32,
+    time_format: TimeFormat,
+    expiries: bool,
+    line_ending: &'static str,
+    color: bool,
 }
 
 impl Plain {
     pub fn new() -> Plain {
-        let out = Box::new(io::stdout());
+        Plain::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Plain {
         Plain {
-            out,
+            out: Buffered::new(out),
             dbnum: 0,
             index: 0,
+            time_format: TimeFormat::default(),
+            expiries: false,
+            line_ending: "\n",
+            color: false,
+        }
+    }
+
+    /// Sets how timestamps are rendered, and prints a line with the expiry
+    /// of each key that has one, which the default output leaves out.
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.time_format = time_format;
+        self.expiries = true;
+    }
+
+    /// Sets when the output is flushed. Defaults to every 64 KiB and at
+    /// the end of the dump.
+    pub fn set_flush_policy(&mut self, policy: FlushPolicy) {
+        self.out.set_policy(policy);
+    }
+
+    /// Colors the database, key, type markers and expiries of each line
+    /// with ANSI escape codes, for reading dumps in a terminal.
+    pub fn set_color(&mut self, color: bool) {
+        self.color = color;
+    }
+
+    /// Ends lines with CRLF instead of LF, as Windows tools expect. Line
+    /// feeds inside keys and values are written unchanged.
+    pub fn set_crlf(&mut self, crlf: bool) {
+        self.line_ending = if crlf { "\r\n" } else { "\n" };
+    }
+
+    /// Writes `text` in `color` if colors are enabled.
+    fn paint(&mut self, color: &str, text: &str) -> RdbResult<()> {
+        if self.color {
+            write_str(&mut self.out, &format!("\x1b[{}m{}\x1b[0m", color, text))
+        } else {
+            write_str(&mut self.out, text)
+        }
+    }
+
+    fn write_key(&mut self, key: &[u8]) -> RdbResult<()> {
+        if self.color {
+            write_str(&mut self.out, &format!("\x1b[{}m", KEY))?;
+            self.out.write_all(key)?;
+            write_str(&mut self.out, "\x1b[0m")
+        } else {
+            self.out.write_all(key)?;
+            Ok(())
         }
     }
 
     fn write_line_start(&mut self) -> RdbResult<()> {
-        write_str(&mut self.out, &format!("db={} ", self.dbnum))?;
+        self.paint(DB, &format!("db={}", self.dbnum))?;
+        write_str(&mut self.out, " ")?;
 
         Ok(())
     }
+
+    fn write_line_end(&mut self) -> RdbResult<()> {
+        write_str(&mut self.out, self.line_ending)
+    }
+
+    fn write_expiry(&mut self, key: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        if let Some(expiry) = expiry.filter(|_| self.expiries) {
+            self.write_line_start()?;
+            self.write_key(key)?;
+            let expires = format!(" expires {}", self.time_format.render_millis(expiry));
+            self.paint(EXPIRY, &expires)?;
+            self.write_line_end()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Plain {
+    fn default() -> Plain {
+        Plain::new()
+    }
 }
 
 impl Formatter for Plain {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.out.flush()?;
+
+        Ok(())
+    }
+
     fn checksum(&mut self, checksum: &[u8]) -> RdbResult<()> {
         write_str(&mut self.out, "checksum ")?;
-        write_str(&mut self.out, &hex::encode(&checksum))?;
-        write_str(&mut self.out, "\n")?;
+        write_str(&mut self.out, &hex::encode(checksum))?;
+        self.write_line_end()?;
+        // The checksum follows the end of the dump.
+        self.out.flush()?;
 
         Ok(())
     }
@@ -42,14 +140,15 @@ impl Formatter for Plain {
         Ok(())
     }
 
-    fn set(&mut self, key: &[u8], value: &[u8], _expiry: Option<u64>) -> RdbResult<()> {
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.write_expiry(key, expiry)?;
         self.write_line_start()?;
-        self.out.write_all(key)?;
+        self.write_key(key)?;
         write_str(&mut self.out, " -> ")?;
 
         self.out.write_all(value)?;
-        write_str(&mut self.out, "\n")?;
-        self.out.flush()?;
+        self.write_line_end()?;
+        self.out.end_key()?;
 
         Ok(())
     }
@@ -59,8 +158,36 @@ impl Formatter for Plain {
         self.out.write_all(key)?;
         write_str(&mut self.out, " -> ")?;
         self.out.write_all(value)?;
-        write_str(&mut self.out, "\n")?;
-        self.out.flush()?;
+        self.write_line_end()?;
+
+        Ok(())
+    }
+
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        self.write_line_start()?;
+        self.write_key(key)?;
+        match usage {
+            KeyUsage::Idle(idle) => write_str(&mut self.out, &format!(" idle {}s", idle))?,
+            KeyUsage::Frequency(frequency) => {
+                write_str(&mut self.out, &format!(" frequency {}", frequency))?
+            }
+        }
+        self.write_line_end()?;
+
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.write_expiry(key, expiry)
+    }
+    fn end_hash(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.out.end_key()?;
 
         Ok(())
     }
@@ -68,13 +195,46 @@ impl Formatter for Plain {
     fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
         self.write_line_start()?;
 
-        self.out.write_all(key)?;
-        write_str(&mut self.out, " . ")?;
+        self.write_key(key)?;
+        self.paint(TYPE, " . ")?;
         self.out.write_all(field)?;
         write_str(&mut self.out, " -> ")?;
         self.out.write_all(value)?;
-        write_str(&mut self.out, "\n")?;
-        self.out.flush()?;
+        self.write_line_end()?;
+
+        Ok(())
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.hash_element(key, field, value)?;
+
+        self.write_line_start()?;
+        self.write_key(key)?;
+        self.paint(TYPE, " . ")?;
+        self.out.write_all(field)?;
+        let expires = format!(" expires {}", self.time_format.render_millis(expiry));
+        self.paint(EXPIRY, &expires)?;
+        self.write_line_end()?;
+
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.write_expiry(key, expiry)
+    }
+    fn end_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.out.end_key()?;
 
         Ok(())
     }
@@ -82,36 +242,40 @@ impl Formatter for Plain {
     fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
         self.write_line_start()?;
 
-        self.out.write_all(key)?;
-        write_str(&mut self.out, " { ")?;
+        self.write_key(key)?;
+        self.paint(TYPE, " { ")?;
         self.out.write_all(member)?;
-        write_str(&mut self.out, " } ")?;
-        write_str(&mut self.out, "\n")?;
-        self.out.flush()?;
+        self.paint(TYPE, " } ")?;
+        self.write_line_end()?;
 
         Ok(())
     }
 
     fn start_list(
         &mut self,
-        _key: &[u8],
+        key: &[u8],
         _length: u32,
-        _expiry: Option<u64>,
+        expiry: Option<u64>,
         _info: EncodingType,
     ) -> RdbResult<()> {
         self.index = 0;
+        self.write_expiry(key, expiry)?;
+
+        Ok(())
+    }
+    fn end_list(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.out.end_key()?;
 
         Ok(())
     }
     fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
         self.write_line_start()?;
 
-        self.out.write_all(key)?;
-        write_str(&mut self.out, &format!("[{}]", self.index))?;
+        self.write_key(key)?;
+        self.paint(TYPE, &format!("[{}]", self.index))?;
         write_str(&mut self.out, " -> ")?;
         self.out.write_all(value)?;
-        write_str(&mut self.out, "\n")?;
-        self.out.flush()?;
+        self.write_line_end()?;
         self.index += 1;
 
         Ok(())
@@ -119,12 +283,18 @@ impl Formatter for Plain {
 
     fn start_sorted_set(
         &mut self,
-        _key: &[u8],
+        key: &[u8],
         _length: u32,
-        _expiry: Option<u64>,
+        expiry: Option<u64>,
         _info: EncodingType,
     ) -> RdbResult<()> {
         self.index = 0;
+        self.write_expiry(key, expiry)?;
+
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.out.end_key()?;
 
         Ok(())
     }
@@ -132,15 +302,113 @@ impl Formatter for Plain {
     fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
         self.write_line_start()?;
 
-        self.out.write_all(key)?;
-        write_str(&mut self.out, &format!("[{}]", self.index))?;
+        self.write_key(key)?;
+        self.paint(TYPE, &format!("[{}]", self.index))?;
         write_str(&mut self.out, " -> {")?;
         self.out.write_all(member)?;
         write_str(&mut self.out, &format!(", score={}", score))?;
-        write_str(&mut self.out, "}\n")?;
-        self.out.flush()?;
+        write_str(&mut self.out, "}")?;
+        self.write_line_end()?;
         self.index += 1;
 
         Ok(())
     }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.write_expiry(key, expiry)
+    }
+    fn end_stream(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.out.end_key()?;
+
+        Ok(())
+    }
+
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.write_line_start()?;
+
+        self.write_key(key)?;
+        self.paint(TYPE, &format!(" <{}>", id))?;
+        write_str(&mut self.out, " ->")?;
+        for (field, value) in fields {
+            write_str(&mut self.out, " ")?;
+            self.out.write_all(field)?;
+            write_str(&mut self.out, "=")?;
+            self.out.write_all(value)?;
+        }
+        self.write_line_end()?;
+
+        Ok(())
+    }
+
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.write_line_start()?;
+        self.write_key(key)?;
+        write_str(&mut self.out, " group ")?;
+        self.out.write_all(&group.name)?;
+        write_str(
+            &mut self.out,
+            &format!(" last-delivered <{}>", group.last_id),
+        )?;
+        if let Some(entries_read) = group.entries_read {
+            write_str(&mut self.out, &format!(" entries-read={}", entries_read))?;
+        }
+        self.write_line_end()?;
+
+        Ok(())
+    }
+
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.write_line_start()?;
+        self.write_key(key)?;
+        write_str(&mut self.out, " group ")?;
+        self.out.write_all(group)?;
+        write_str(&mut self.out, " consumer ")?;
+        self.out.write_all(&consumer.name)?;
+        let seen = self.time_format.render_millis(consumer.seen_time);
+        write_str(&mut self.out, &format!(" seen {}", seen))?;
+        self.write_line_end()?;
+
+        Ok(())
+    }
+
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.write_line_start()?;
+        self.write_key(key)?;
+        write_str(&mut self.out, " group ")?;
+        self.out.write_all(group)?;
+        write_str(&mut self.out, &format!(" pending <{}> -> ", entry.id))?;
+        self.out.write_all(&entry.consumer)?;
+        let delivered = self.time_format.render_millis(entry.delivery_time);
+        write_str(
+            &mut self.out,
+            &format!(
+                " deliveries={} delivered {}",
+                entry.delivery_count, delivered
+            ),
+        )?;
+        self.write_line_end()?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,284 @@
+use super::statistics::TypeStatistics;
+use super::write_str;
+use crate::formatter::Formatter;
+use crate::types::{EncodingType, FilterInfo, RdbResult, StreamId, Type, ValueLength};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+/// A metric's name, its help text and the statistic it reports.
+type Metric = (&'static str, &'static str, fn(&TypeStatistics) -> u64);
+
+/// The metrics written, in order.
+const METRICS: [Metric; 4] = [
+    ("rdb_keys", "Number of keys.", |s| s.keys),
+    (
+        "rdb_elements",
+        "Number of elements, field/value pairs or scored members of the keys.",
+        |s| s.elements,
+    ),
+    (
+        "rdb_bytes",
+        "Bytes of the keys and of all their fields, members and values.",
+        |s| s.bytes,
+    ),
+    ("rdb_expiring_keys", "Number of keys with an expiry.", |s| {
+        s.expiring
+    }),
+];
+
+/// Writes no output per key, only metrics in the Prometheus text exposition
+/// format once the dump ends, e.g. to push them to a Pushgateway.
+///
+/// Every metric is a gauge labelled with the database, the type and the
+/// prefix of the keys, which ends at the first separator, `:` by default.
+/// Keys without a separator are their own prefix. The numbers are those of
+/// `Statistics`.
+pub struct Prometheus {
+    out: Box<dyn Write + 'static>,
+    separator: u8,
+    dbnum: u32,
+    statistics: BTreeMap<(u32, Type, Vec<u8>), TypeStatistics>,
+    prefix: Vec<u8>,
+    current: TypeStatistics,
+}
+
+impl Prometheus {
+    pub fn new() -> Prometheus {
+        Prometheus::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Prometheus {
+        Prometheus {
+            out: Box::new(out),
+            separator: b':',
+            dbnum: 0,
+            statistics: BTreeMap::new(),
+            prefix: vec![],
+            current: TypeStatistics::default(),
+        }
+    }
+
+    /// Sets the byte the prefix of a key ends at. Defaults to `:`.
+    pub fn set_separator(&mut self, separator: u8) {
+        self.separator = separator;
+    }
+
+    fn start_key(&mut self, key: &[u8], expiry: Option<u64>) {
+        let prefix = key.split(|b| *b == self.separator).next().unwrap_or(key);
+        self.prefix = prefix.to_vec();
+        self.current = TypeStatistics {
+            keys: 1,
+            elements: 0,
+            bytes: key.len() as u64,
+            expiring: expiry.is_some() as u64,
+        };
+    }
+
+    fn add(&mut self, bytes: usize) {
+        self.current.elements += 1;
+        self.current.bytes += bytes as u64;
+    }
+
+    fn end_key(&mut self, typ: Type) {
+        let prefix = std::mem::take(&mut self.prefix);
+        self.statistics
+            .entry((self.dbnum, typ, prefix))
+            .or_default()
+            .merge(&self.current);
+    }
+}
+
+impl Default for Prometheus {
+    fn default() -> Prometheus {
+        Prometheus::new()
+    }
+}
+
+/// Escapes a label value as the exposition format requires.
+fn label(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Formatter for Prometheus {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        let mut metrics = String::new();
+        for (name, help, statistic) in &METRICS {
+            metrics.push_str(&format!(
+                "# HELP {} {}\n# TYPE {} gauge\n",
+                name, help, name
+            ));
+            for ((db, typ, prefix), statistics) in &self.statistics {
+                metrics.push_str(&format!(
+                    "{}{{db=\"{}\",type=\"{}\",prefix=\"{}\"}} {}\n",
+                    name,
+                    db,
+                    typ.name(),
+                    label(prefix),
+                    statistic(statistics)
+                ));
+            }
+        }
+        write_str(&mut self.out, &metrics)?;
+        self.out.flush()?;
+
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.add(value.len());
+        self.end_key(Type::String);
+
+        Ok(())
+    }
+
+    fn module(&mut self, key: &[u8], _module_name: &str) -> RdbResult<()> {
+        self.start_key(key, None);
+        self.add(0);
+        self.end_key(Type::Module);
+
+        Ok(())
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        _filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.add(0);
+        self.end_key(Type::Module);
+
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.current.elements = length.elements;
+        self.current.bytes += length.bytes;
+        self.end_key(length.typ);
+
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_hash(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::Hash);
+        Ok(())
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field.len() + value.len());
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::Set);
+        Ok(())
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member.len());
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_list(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::List);
+        Ok(())
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::SortedSet);
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member.len() + std::mem::size_of::<f64>());
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_stream(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::Stream);
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let fields: usize = fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum();
+        self.add(std::mem::size_of::<StreamId>() + fields);
+        Ok(())
+    }
+}
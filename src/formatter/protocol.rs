@@ -1,22 +1,164 @@
+use super::payload::{self, Payload};
 use super::write_str;
 use crate::formatter::Formatter;
-use crate::types::{EncodingType, RdbResult};
+use crate::types::{
+    EncodingType, RdbError, RdbResult, StreamConsumer, StreamGroup, StreamId, StreamMetadata,
+    StreamPendingEntry,
+};
+use std::collections::HashSet;
 use std::io;
 use std::io::Write;
 
+/// What to delete on the target before restoring, see `Protocol::set_flush`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flush {
+    /// All databases, with FLUSHALL before the first command.
+    All,
+    /// Each restored database, with FLUSHDB after selecting it.
+    Databases,
+}
+
 pub struct Protocol {
     out: Box<dyn Write + 'static>,
     last_expiry: Option<u64>,
+    stream_metadata: Option<StreamMetadata>,
+    /// Whether a command created the current stream yet.
+    stream_created: bool,
+    replace: bool,
+    /// The most XADD commands sent in one transaction, 0 for no transactions.
+    stream_batch: usize,
+    /// The XADD commands of the open transaction.
+    batched: usize,
+    /// The IDs of the current stream's entries, which its pending entries
+    /// must refer to.
+    stream_ids: HashSet<StreamId>,
+    dropped_pending_entries: u64,
+    dump_payloads: bool,
+    /// The elements of the current key, in payload mode.
+    payload: Option<Payload>,
+    /// The fields of the current hash with their own expiry, in payload
+    /// mode.
+    field_expiries: Vec<(Vec<u8>, u64)>,
+    /// The most elements sent in one command, 0 for no limit.
+    batch_elements: usize,
+    /// The most bytes of elements sent in one command, 0 for no limit.
+    batch_bytes: usize,
+    batch: Option<Batch>,
+    flush: Option<Flush>,
+    select: bool,
+    /// The database the commands go to, once one was started.
+    selected: Option<u32>,
+}
+
+/// Elements of the current key collected into a single command.
+struct Batch {
+    command: &'static [u8],
+    key: Vec<u8>,
+    args: Vec<Vec<u8>>,
+    elements: usize,
+    bytes: usize,
 }
 
 impl Protocol {
     pub fn new() -> Protocol {
-        let out = Box::new(io::stdout());
+        Protocol::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Protocol {
+        let out = Box::new(out);
         Protocol {
-            out: out,
+            out,
             last_expiry: None,
+            stream_metadata: None,
+            stream_created: false,
+            replace: false,
+            stream_batch: 0,
+            batched: 0,
+            stream_ids: HashSet::new(),
+            dropped_pending_entries: 0,
+            dump_payloads: false,
+            payload: None,
+            field_expiries: vec![],
+            batch_elements: 1,
+            batch_bytes: 0,
+            batch: None,
+            flush: None,
+            select: true,
+            selected: None,
         }
     }
+
+    /// Deletes every hash, set, list, sorted set and stream before restoring
+    /// it, so it replaces an existing key of that name instead of being
+    /// merged into it, and replaces existing function libraries.
+    ///
+    /// The deletion and the commands restoring the key are sent as one
+    /// MULTI/EXEC transaction, so clients never see the key half restored.
+    pub fn set_replace(&mut self, replace: bool) {
+        self.replace = replace;
+    }
+
+    /// Sends the XADD commands of each stream in MULTI/EXEC transactions
+    /// of up to `size` entries, so a large stream is restored in steps that
+    /// each apply fully or not at all. Ignored in replace mode, which sends
+    /// each key in a single transaction.
+    pub fn set_stream_batch(&mut self, size: usize) {
+        self.stream_batch = size;
+    }
+
+    /// Restores strings, hashes, sets, lists and sorted sets with a single
+    /// `RESTORE key ttl payload REPLACE ABSTTL` each instead of a command
+    /// per element, which servers load much faster.
+    ///
+    /// The payloads are those of `DUMP` in RDB version 9, as loaded by
+    /// Redis 5 and later. Streams are still restored with XADD.
+    pub fn set_dump_payloads(&mut self, dump_payloads: bool) {
+        self.dump_payloads = dump_payloads;
+    }
+
+    /// Sends up to `elements` elements of a hash, set, list or sorted set
+    /// in one variadic HSET, SADD, RPUSH or ZADD instead of one command
+    /// each, 0 for no limit. Replaying large collections takes far fewer
+    /// round trips this way.
+    pub fn set_batch_elements(&mut self, elements: usize) {
+        self.batch_elements = elements;
+    }
+
+    /// Ends a batch of elements, see `set_batch_elements`, before the
+    /// elements in it would exceed `bytes`, 0 for no limit. A single larger
+    /// element is sent on its own.
+    pub fn set_batch_bytes(&mut self, bytes: usize) {
+        self.batch_bytes = bytes;
+    }
+
+    /// Deletes the data of the target before restoring, so it ends up
+    /// holding exactly the restored keys.
+    pub fn set_flush(&mut self, flush: Flush) {
+        self.flush = Some(flush);
+    }
+
+    /// Leaves out the SELECT commands, so the keys go to the database the
+    /// client selected. Only dumps of a single database, e.g. filtered to
+    /// one, can be restored this way, the start of a second one fails.
+    ///
+    /// A SELECT of the database already selected is always left out.
+    pub fn set_select(&mut self, select: bool) {
+        self.select = select;
+    }
+
+    /// The number of pending entries left out as their message was deleted
+    /// from the stream. Redis can't restore them: XCLAIM ignores IDs of
+    /// messages that don't exist.
+    pub fn dropped_pending_entries(&self) -> u64 {
+        self.dropped_pending_entries
+    }
+}
+
+impl Default for Protocol {
+    fn default() -> Protocol {
+        Protocol::new()
+    }
 }
 
 impl Protocol {
@@ -35,10 +177,135 @@ impl Protocol {
         Ok(())
     }
 
+    /// Starts collecting the elements of a key in payload mode.
+    fn start_payload(&mut self, payload: fn() -> Payload) {
+        if self.dump_payloads {
+            self.payload = Some(payload());
+        }
+    }
+
+    /// Sends an element of a collection, with others if batching.
+    fn add_element(
+        &mut self,
+        command: &'static [u8],
+        key: &[u8],
+        element: &[&[u8]],
+    ) -> RdbResult<()> {
+        if self.batch_elements == 1 {
+            let mut args = vec![command, key];
+            args.extend_from_slice(element);
+            return self.emit(args);
+        }
+
+        let bytes = element.iter().map(|arg| arg.len()).sum::<usize>();
+        let full = self
+            .batch
+            .as_ref()
+            .is_some_and(|batch| self.batch_bytes > 0 && batch.bytes + bytes > self.batch_bytes);
+        if full {
+            self.end_batch_of_elements()?;
+        }
+        let batch = self.batch.get_or_insert_with(|| Batch {
+            command,
+            key: key.to_vec(),
+            args: vec![],
+            elements: 0,
+            bytes: 0,
+        });
+        batch.args.extend(element.iter().map(|arg| arg.to_vec()));
+        batch.elements += 1;
+        batch.bytes += bytes;
+        if batch.elements == self.batch_elements {
+            self.end_batch_of_elements()?;
+        }
+        Ok(())
+    }
+
+    /// Sends the batched elements, if any.
+    fn end_batch_of_elements(&mut self) -> RdbResult<()> {
+        if let Some(batch) = self.batch.take() {
+            let mut args = vec![batch.command, &batch.key[..]];
+            args.extend(batch.args.iter().map(|arg| &arg[..]));
+            self.emit(args)?;
+        }
+        Ok(())
+    }
+
+    /// Sends what was collected of the elements of a key: the payload, or
+    /// the last batch.
+    fn end_elements(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_batch_of_elements()?;
+        if let Some(payload) = self.payload.take() {
+            if let Some(payload) = payload.finish() {
+                self.restore(key, &payload)?;
+            }
+            for (field, expiry) in std::mem::take(&mut self.field_expiries) {
+                self.expire_field(key, &field, expiry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores `key` from `payload` with the pending expiry, replacing it.
+    fn restore(&mut self, key: &[u8], payload: &[u8]) -> RdbResult<()> {
+        match self.last_expiry.take() {
+            Some(expiry) => {
+                let expiry = expiry.to_string();
+                self.emit(vec![
+                    "RESTORE".as_bytes(),
+                    key,
+                    expiry.as_bytes(),
+                    payload,
+                    b"REPLACE",
+                    b"ABSTTL",
+                ])
+            }
+            None => self.emit(vec!["RESTORE".as_bytes(), key, b"0", payload, b"REPLACE"]),
+        }
+    }
+
+    fn expire_field(&mut self, key: &[u8], field: &[u8], expiry: u64) -> RdbResult<()> {
+        let expiry = expiry.to_string();
+        self.emit(vec![
+            "HPEXPIREAT".as_bytes(),
+            key,
+            expiry.as_bytes(),
+            b"FIELDS",
+            b"1",
+            field,
+        ])
+    }
+
     fn pre_expire(&mut self, expiry: Option<u64>) {
         self.last_expiry = expiry
     }
 
+    fn start_key(&mut self, key: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.pre_expire(expiry);
+        if self.replace {
+            self.emit(vec!["MULTI".as_bytes()])?;
+            self.emit(vec!["DEL".as_bytes(), key])?;
+        }
+        Ok(())
+    }
+
+    fn end_key(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.post_expire(key)?;
+        if self.replace {
+            self.emit(vec!["EXEC".as_bytes()])?;
+        }
+        Ok(())
+    }
+
+    /// Ends the open transaction of XADD commands, if any.
+    fn end_batch(&mut self) -> RdbResult<()> {
+        if self.batched > 0 {
+            self.emit(vec!["EXEC".as_bytes()])?;
+            self.batched = 0;
+        }
+        Ok(())
+    }
+
     fn post_expire(&mut self, key: &[u8]) -> RdbResult<()> {
         if let Some(expire) = self.last_expiry {
             let expire = expire.to_string();
@@ -52,22 +319,59 @@ impl Protocol {
 
 impl Formatter for Protocol {
     fn start_rdb(&mut self) -> RdbResult<()> {
+        if self.flush == Some(Flush::All) {
+            self.emit(vec!["FLUSHALL".as_bytes()])?;
+        }
         Ok(())
     }
 
     fn end_rdb(&mut self) -> RdbResult<()> {
+        self.out.flush()?;
         Ok(())
     }
 
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        if self.replace {
+            self.emit(vec![
+                "FUNCTION".as_bytes(),
+                b"LOAD",
+                b"REPLACE",
+                library_code,
+            ])
+        } else {
+            self.emit(vec!["FUNCTION".as_bytes(), b"LOAD", library_code])
+        }
+    }
+
     fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
-        let db = db_number.to_string();
-        self.emit(vec!["SELECT".as_bytes(), db.as_bytes()])?;
+        match self.selected {
+            Some(selected) if selected == db_number => return Ok(()),
+            Some(_) if !self.select => {
+                return Err(RdbError::Other(format!(
+                    "Database {} can't be restored without SELECT after another one",
+                    db_number
+                )))
+            }
+            _ => {}
+        }
+        self.selected = Some(db_number);
+
+        if self.select {
+            let db = db_number.to_string();
+            self.emit(vec!["SELECT".as_bytes(), db.as_bytes()])?;
+        }
+        if self.flush == Some(Flush::Databases) {
+            self.emit(vec!["FLUSHDB".as_bytes()])?;
+        }
 
         Ok(())
     }
 
     fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
         self.pre_expire(expiry);
+        if self.dump_payloads {
+            return self.restore(key, &payload::string(value));
+        }
         self.emit(vec!["SET".as_bytes(), key, value])?;
         self.post_expire(key)?;
         Ok(())
@@ -75,83 +379,277 @@ impl Formatter for Protocol {
 
     fn start_hash(
         &mut self,
-        _key: &[u8],
+        key: &[u8],
         _length: u32,
         expiry: Option<u64>,
         _info: EncodingType,
     ) -> RdbResult<()> {
-        self.pre_expire(expiry);
+        self.start_key(key, expiry)?;
+        self.start_payload(Payload::hash);
 
         Ok(())
     }
 
     fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
-        self.post_expire(key)?;
+        self.end_elements(key)?;
+        self.end_key(key)?;
 
         Ok(())
     }
     fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
-        self.emit(vec!["HSET".as_bytes(), key, field, value])?;
-        Ok(())
+        if let Some(ref mut payload) = self.payload {
+            payload.field(field, value);
+            return Ok(());
+        }
+        self.add_element(b"HSET", key, &[field, value])
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.hash_element(key, field, value)?;
+        // The payload can't carry the expiry, it follows the RESTORE.
+        if self.payload.is_some() {
+            self.field_expiries.push((field.to_vec(), expiry));
+            return Ok(());
+        }
+        // The field must exist before it can expire.
+        self.end_batch_of_elements()?;
+        self.expire_field(key, field, expiry)
     }
 
     fn start_set(
         &mut self,
-        _key: &[u8],
+        key: &[u8],
         _cardinality: u32,
         expiry: Option<u64>,
         _info: EncodingType,
     ) -> RdbResult<()> {
-        self.pre_expire(expiry);
+        self.start_key(key, expiry)?;
+        self.start_payload(Payload::set);
         Ok(())
     }
     fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
-        self.post_expire(key)?;
+        self.end_elements(key)?;
+        self.end_key(key)?;
         Ok(())
     }
     fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
-        self.emit(vec!["SADD".as_bytes(), key, member])?;
-        Ok(())
+        if let Some(ref mut payload) = self.payload {
+            payload.element(member);
+            return Ok(());
+        }
+        self.add_element(b"SADD", key, &[member])
     }
 
     fn start_list(
         &mut self,
-        _key: &[u8],
+        key: &[u8],
         _length: u32,
         expiry: Option<u64>,
         _info: EncodingType,
     ) -> RdbResult<()> {
-        self.pre_expire(expiry);
+        self.start_key(key, expiry)?;
+        self.start_payload(Payload::list);
         Ok(())
     }
     fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
-        self.post_expire(key)?;
+        self.end_elements(key)?;
+        self.end_key(key)?;
         Ok(())
     }
     fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
-        self.emit(vec!["RPUSH".as_bytes(), key, value])?;
-        Ok(())
+        if let Some(ref mut payload) = self.payload {
+            payload.element(value);
+            return Ok(());
+        }
+        self.add_element(b"RPUSH", key, &[value])
     }
 
     fn start_sorted_set(
         &mut self,
-        _key: &[u8],
+        key: &[u8],
         _length: u32,
         expiry: Option<u64>,
         _info: EncodingType,
     ) -> RdbResult<()> {
-        self.pre_expire(expiry);
+        self.start_key(key, expiry)?;
+        self.start_payload(Payload::sorted_set);
         Ok(())
     }
 
     fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
-        self.post_expire(key)?;
+        self.end_elements(key)?;
+        self.end_key(key)?;
         Ok(())
     }
 
     fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        if let Some(ref mut payload) = self.payload {
+            payload.member(member, score);
+            return Ok(());
+        }
         let score = score.to_string();
-        self.emit(vec!["ZADD".as_bytes(), key, score.as_bytes(), member])?;
+        self.add_element(b"ZADD", key, &[score.as_bytes(), member])
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry)?;
+        self.stream_created = false;
+        self.stream_ids.clear();
+        Ok(())
+    }
+
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_batch()?;
+        // Restores the IDs and counters that entries deleted since don't
+        // leave behind.
+        if let Some(metadata) = self.stream_metadata.take() {
+            if !self.stream_created {
+                // XSETID needs an existing stream, but there is no command
+                // to create an empty one besides XGROUP CREATE.
+                self.emit(vec![
+                    "XGROUP".as_bytes(),
+                    b"CREATE",
+                    key,
+                    b"rdb-mkstream",
+                    b"$",
+                    b"MKSTREAM",
+                ])?;
+                self.emit(vec!["XGROUP".as_bytes(), b"DESTROY", key, b"rdb-mkstream"])?;
+            }
+
+            let last_id = metadata.last_id.to_string();
+            let entries_added = metadata.entries_added.map(|added| added.to_string());
+            let max_deleted = metadata.max_deleted_entry_id.map(|id| id.to_string());
+
+            let mut args = vec!["XSETID".as_bytes(), key, last_id.as_bytes()];
+            if let Some(ref entries_added) = entries_added {
+                args.push(b"ENTRIESADDED");
+                args.push(entries_added.as_bytes());
+            }
+            if let Some(ref max_deleted) = max_deleted {
+                args.push(b"MAXDELETEDID");
+                args.push(max_deleted.as_bytes());
+            }
+            self.emit(args)?;
+        }
+        self.end_key(key)?;
+        Ok(())
+    }
+
+    fn stream_metadata(&mut self, _key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.stream_metadata = Some(metadata.clone());
+        Ok(())
+    }
+
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let batching = self.stream_batch > 0 && !self.replace;
+        if batching && self.batched == 0 {
+            self.emit(vec!["MULTI".as_bytes()])?;
+        }
+
+        self.stream_ids.insert(id);
+        let id = id.to_string();
+        let mut args = vec!["XADD".as_bytes(), key, id.as_bytes()];
+        for (field, value) in fields {
+            args.push(field);
+            args.push(value);
+        }
+        self.emit(args)?;
+        self.stream_created = true;
+
+        if batching {
+            self.batched += 1;
+            if self.batched == self.stream_batch {
+                self.end_batch()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.end_batch()?;
+        // MKSTREAM in case all entries were deleted and no XADD came before.
+        let last_id = group.last_id.to_string();
+        let entries_read = group.entries_read.map(|read| read.to_string());
+        let mut args = vec![
+            "XGROUP".as_bytes(),
+            b"CREATE",
+            key,
+            &group.name,
+            last_id.as_bytes(),
+            b"MKSTREAM",
+        ];
+        if let Some(ref entries_read) = entries_read {
+            args.push(b"ENTRIESREAD");
+            args.push(entries_read.as_bytes());
+        }
+        self.emit(args)?;
+        self.stream_created = true;
+        Ok(())
+    }
+
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.emit(vec![
+            "XGROUP".as_bytes(),
+            b"CREATECONSUMER",
+            key,
+            group,
+            &consumer.name,
+        ])?;
+        Ok(())
+    }
+
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        if !self.stream_ids.contains(&entry.id) {
+            self.dropped_pending_entries += 1;
+            return Ok(());
+        }
+
+        // FORCE creates the pending entry, JUSTID keeps the delivery count
+        // as given.
+        let id = entry.id.to_string();
+        let time = entry.delivery_time.to_string();
+        let count = entry.delivery_count.to_string();
+        self.emit(vec![
+            "XCLAIM".as_bytes(),
+            key,
+            group,
+            &entry.consumer,
+            b"0",
+            id.as_bytes(),
+            b"TIME",
+            time.as_bytes(),
+            b"RETRYCOUNT",
+            count.as_bytes(),
+            b"FORCE",
+            b"JUSTID",
+        ])?;
         Ok(())
     }
 }
@@ -0,0 +1,354 @@
+//! Canonical output: keys in byte order, and the members of sets, the
+//! fields of hashes and the elements of sorted sets in a fixed order, no
+//! matter how the server happened to lay them out in the dump.
+//!
+//! Dumps of the same data taken at different times then produce the same
+//! output, which lets standard text tools like `diff` compare them.
+
+use std::cmp::Ordering;
+use std::mem;
+
+use crate::event::{Event, Recorder};
+use crate::extsort::{ExternalSorter, DEFAULT_BUDGET};
+use crate::formatter::Formatter;
+use crate::types::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, RdbError, RdbResult,
+    StreamConsumer, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
+
+/// A formatter collecting the keys of a database and passing them on to
+/// `formatter` sorted once the database ends.
+///
+/// Set members and hash fields are sorted by their bytes, sorted set
+/// elements by score and then member, as `ZRANGE` returns them. Lists and
+/// streams keep their order, which is part of their value. Callbacks that
+/// don't belong to a key, like `aux_field` and `resizedb`, are passed on
+/// right away.
+///
+/// The keys are sorted with an `ExternalSorter`, which spills them to
+/// temporary files once they take up more than the memory budget. The
+/// elements of a single key are sorted in memory.
+pub struct Sorted<F> {
+    formatter: F,
+    /// The callbacks of the key being read.
+    key: Recorder,
+    current: Option<Vec<u8>>,
+    /// The keys of the database read so far, with their callbacks.
+    keys: ExternalSorter,
+    budget: usize,
+}
+
+impl<F: Formatter> Sorted<F> {
+    pub fn new(formatter: F) -> Sorted<F> {
+        Sorted {
+            formatter,
+            key: Recorder::buffered(),
+            current: None,
+            keys: ExternalSorter::new(DEFAULT_BUDGET),
+            budget: DEFAULT_BUDGET,
+        }
+    }
+
+    /// Keeps at most about `budget` bytes of keys in memory before spilling
+    /// them to disk. Defaults to 256 MiB.
+    pub fn set_memory_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.keys = ExternalSorter::new(budget);
+    }
+
+    pub fn into_inner(self) -> F {
+        self.formatter
+    }
+
+    /// Records the callbacks of `key`, ending the previous key.
+    fn key(&mut self, key: &[u8]) -> RdbResult<&mut Recorder> {
+        if self.current.as_deref() != Some(key) {
+            self.end_key()?;
+            self.current = Some(key.to_vec());
+        }
+        Ok(&mut self.key)
+    }
+
+    /// Hands the callbacks of the current key to the sorter.
+    fn end_key(&mut self) -> RdbResult<()> {
+        if let Some(key) = self.current.take() {
+            let events = serde_json::to_vec(&self.key.take())
+                .map_err(|err| RdbError::Other(format!("Failed to buffer a key: {}", err)))?;
+            self.keys.push(key, events)?;
+        }
+        Ok(())
+    }
+
+    /// Passes the keys read so far on in order.
+    fn flush(&mut self) -> RdbResult<()> {
+        self.end_key()?;
+        let keys = mem::replace(&mut self.keys, ExternalSorter::new(self.budget));
+        // A key stored twice keeps both of its values.
+        for record in keys.finish()? {
+            let (_, events) = record?;
+            let mut events: Vec<Event> = serde_json::from_slice(&events)
+                .map_err(|err| RdbError::Other(format!("Failed to read a key back: {}", err)))?;
+            sort_elements(&mut events);
+            for event in events {
+                event.replay(&mut self.formatter)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What set members, hash fields and sorted set elements are ordered by.
+fn order(event: &Event) -> Option<(f64, &[u8])> {
+    match event {
+        Event::SetElement(_, member) => Some((0.0, member)),
+        Event::HashElement(_, field, _) | Event::HashElementEx(_, field, ..) => Some((0.0, field)),
+        Event::SortedSetElement(_, score, member) => Some((*score, member)),
+        _ => None,
+    }
+}
+
+fn compare(a: &Event, b: &Event) -> Ordering {
+    match (order(a), order(b)) {
+        (Some((a_score, a)), Some((b_score, b))) => a_score.total_cmp(&b_score).then(a.cmp(b)),
+        _ => Ordering::Equal,
+    }
+}
+
+/// Sorts the elements of a key, which follow each other between the
+/// callbacks starting and ending the value.
+fn sort_elements(events: &mut [Event]) {
+    let start = match events.iter().position(|event| order(event).is_some()) {
+        Some(start) => start,
+        None => return,
+    };
+    let end = events[start..]
+        .iter()
+        .position(|event| order(event).is_none())
+        .map_or(events.len(), |length| start + length);
+    events[start..end].sort_by(compare);
+}
+
+impl<F: Formatter> Formatter for Sorted<F> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.flush()?;
+        self.formatter.end_rdb()
+    }
+    fn checksum(&mut self, checksum: &[u8]) -> RdbResult<()> {
+        self.formatter.checksum(checksum)
+    }
+
+    // The parser only ends the last database, a new one ends the previous.
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.flush()?;
+        self.formatter.start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.flush()?;
+        self.formatter.end_database(db_index)
+    }
+
+    fn resizedb(&mut self, db_size: u32, expires_size: u32) -> RdbResult<()> {
+        self.formatter.resizedb(db_size, expires_size)
+    }
+    fn slot_info(&mut self, slot: u32, slot_size: u32, expires_slot_size: u32) -> RdbResult<()> {
+        self.formatter.slot_info(slot, slot_size, expires_slot_size)
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.formatter.aux_field(key, value)
+    }
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        self.formatter.function(library_code)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.key(key)?.set(key, value, expiry)
+    }
+
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.key(key)?
+            .compressed_set(key, compressed, real_length, expiry)
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.key(key)?.module(key, module_name)
+    }
+
+    fn unknown_type(&mut self, code: u8) -> RdbResult<()> {
+        self.formatter.unknown_type(code)
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.key(key)?.probabilistic_filter(key, filter, expiry)
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.key(key)?
+            .hyperloglog(key, encoding, cardinality, expiry)
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.key(key)?.bitmap(key, length, popcount, expiry)
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.key(key)?.value_length(key, length, expiry)
+    }
+
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        self.key(key)?.blob_stats(key, stats)
+    }
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        self.key(key)?.key_offset(key, offset, size)
+    }
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        self.key(key)?.key_usage(key, usage)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key(key)?.start_hash(key, length, expiry, info)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key(key)?.end_hash(key)
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.key(key)?.hash_element(key, field, value)
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.key(key)?.hash_element_ex(key, field, value, expiry)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key(key)?.start_set(key, cardinality, expiry, info)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key(key)?.end_set(key)
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.key(key)?.set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key(key)?.start_list(key, length, expiry, info)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key(key)?.end_list(key)
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.key(key)?.list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key(key)?.start_sorted_set(key, length, expiry, info)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key(key)?.end_sorted_set(key)
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.key(key)?.sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key(key)?.start_stream(key, length, expiry, info)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key(key)?.end_stream(key)
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.key(key)?.stream_metadata(key, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.key(key)?.stream_entry(key, id, fields)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.key(key)?.stream_group(key, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.key(key)?.stream_consumer(key, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.key(key)?.stream_pending_entry(key, group, entry)
+    }
+}
@@ -0,0 +1,328 @@
+use super::write_str;
+use crate::formatter::Formatter;
+use crate::types::{EncodingType, FilterInfo, RdbResult, StreamId, Type, ValueLength};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+
+/// The keys of one type, in one database or in the whole dump.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct TypeStatistics {
+    pub keys: u64,
+    /// The number of elements, field/value pairs or scored members, 1 for
+    /// strings and module values.
+    pub elements: u64,
+    /// Approximate size of the data: the bytes of the keys and of all fields,
+    /// members and values, as `Folded` weighs them.
+    pub bytes: u64,
+    /// The number of keys with an expiry.
+    pub expiring: u64,
+}
+
+impl TypeStatistics {
+    pub(crate) fn merge(&mut self, other: &TypeStatistics) {
+        self.keys += other.keys;
+        self.elements += other.elements;
+        self.bytes += other.bytes;
+        self.expiring += other.expiring;
+    }
+
+    /// The share of keys with an expiry, from 0 to 1.
+    pub fn ttl_coverage(&self) -> f64 {
+        if self.keys == 0 {
+            0.0
+        } else {
+            self.expiring as f64 / self.keys as f64
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    databases: BTreeMap<u32, BTreeMap<&'static str, &'a TypeStatistics>>,
+    total: TypeStatistics,
+}
+
+/// Writes no output per key, only a summary of the keys of every type in
+/// every database once the dump ends: a table by default, or a JSON object.
+///
+/// Works with lengths-only parsing as well, which only reads the lengths of
+/// values.
+pub struct Statistics {
+    out: Box<dyn Write + 'static>,
+    json: bool,
+    dbnum: u32,
+    statistics: BTreeMap<(u32, Type), TypeStatistics>,
+    current: TypeStatistics,
+}
+
+impl Statistics {
+    pub fn new() -> Statistics {
+        Statistics::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Statistics {
+        Statistics {
+            out: Box::new(out),
+            json: false,
+            dbnum: 0,
+            statistics: BTreeMap::new(),
+            current: TypeStatistics::default(),
+        }
+    }
+
+    /// Writes the summary as a JSON object instead of a table.
+    pub fn set_json(&mut self, json: bool) {
+        self.json = json;
+    }
+
+    /// The keys of every type in every database seen so far.
+    pub fn statistics(&self) -> &BTreeMap<(u32, Type), TypeStatistics> {
+        &self.statistics
+    }
+
+    /// The keys of all types in all databases seen so far.
+    pub fn total(&self) -> TypeStatistics {
+        let mut total = TypeStatistics::default();
+        for statistics in self.statistics.values() {
+            total.merge(statistics);
+        }
+        total
+    }
+
+    fn start_key(&mut self, key: &[u8], expiry: Option<u64>) {
+        self.current = TypeStatistics {
+            keys: 1,
+            elements: 0,
+            bytes: key.len() as u64,
+            expiring: expiry.is_some() as u64,
+        };
+    }
+
+    fn add(&mut self, bytes: usize) {
+        self.current.elements += 1;
+        self.current.bytes += bytes as u64;
+    }
+
+    fn end_key(&mut self, typ: Type) {
+        self.statistics
+            .entry((self.dbnum, typ))
+            .or_default()
+            .merge(&self.current);
+    }
+
+    fn write_table(&mut self) -> RdbResult<()> {
+        let mut table = format!(
+            "{:<4} {:<10} {:>12} {:>14} {:>16} {:>12}\n",
+            "db", "type", "keys", "elements", "bytes", "ttl"
+        );
+        let mut rows: Vec<_> = self
+            .statistics
+            .iter()
+            .map(|(&(db, typ), statistics)| (db.to_string(), typ.name(), *statistics))
+            .collect();
+        rows.push(("all".into(), "all", self.total()));
+        for (db, typ, statistics) in rows {
+            table.push_str(&format!(
+                "{:<4} {:<10} {:>12} {:>14} {:>16} {:>11.1}%\n",
+                db,
+                typ,
+                statistics.keys,
+                statistics.elements,
+                statistics.bytes,
+                statistics.ttl_coverage() * 100.0
+            ));
+        }
+        write_str(&mut self.out, &table)
+    }
+
+    fn write_json(&mut self) -> RdbResult<()> {
+        let mut summary = Summary {
+            databases: BTreeMap::new(),
+            total: self.total(),
+        };
+        for ((db, typ), statistics) in &self.statistics {
+            summary
+                .databases
+                .entry(*db)
+                .or_default()
+                .insert(typ.name(), statistics);
+        }
+        serde_json::to_writer(&mut self.out, &summary).map_err(io::Error::from)?;
+        write_str(&mut self.out, "\n")
+    }
+}
+
+impl Default for Statistics {
+    fn default() -> Statistics {
+        Statistics::new()
+    }
+}
+
+impl Formatter for Statistics {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        if self.json {
+            self.write_json()?;
+        } else {
+            self.write_table()?;
+        }
+        self.out.flush()?;
+
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.add(value.len());
+        self.end_key(Type::String);
+
+        Ok(())
+    }
+
+    fn module(&mut self, key: &[u8], _module_name: &str) -> RdbResult<()> {
+        self.start_key(key, None);
+        self.add(0);
+        self.end_key(Type::Module);
+
+        Ok(())
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        _filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.add(0);
+        self.end_key(Type::Module);
+
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        self.current.elements = length.elements;
+        self.current.bytes += length.bytes;
+        self.end_key(length.typ);
+
+        Ok(())
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_hash(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::Hash);
+        Ok(())
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field.len() + value.len());
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::Set);
+        Ok(())
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member.len());
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_list(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::List);
+        Ok(())
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value.len());
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::SortedSet);
+        Ok(())
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], _score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member.len() + std::mem::size_of::<f64>());
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key(key, expiry);
+        Ok(())
+    }
+    fn end_stream(&mut self, _key: &[u8]) -> RdbResult<()> {
+        self.end_key(Type::Stream);
+        Ok(())
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        _id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let fields: usize = fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum();
+        self.add(std::mem::size_of::<StreamId>() + fields);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! The parts of the JSON representation of streams shared by `JSON` and
+//! `JsonLines`.
+//!
+//! A stream is an object with its IDs and counters, `entries` mapping the
+//! ID of each entry to its fields as `[field, value]` pairs in their order,
+//! as an entry may repeat a field, and `groups`:
+//!
+//! ```json
+//! {"last_id":"5-0","entries_added":2,"entries":{"1-0":[["f","v"]],"5-0":[["f","w"],["f","x"]]},
+//!  "groups":[{"name":"g","last_id":"1-0","entries_read":1,
+//!             "consumers":[{"name":"c","seen_time":1700000000000}],
+//!             "pending":[{"id":"1-0","consumer":"c","delivery_time":1700000000000,"delivery_count":1}]}]}
+//! ```
+//!
+//! Fields that are not stored by older encodings are left out. Times are in
+//! milliseconds, or rendered as strings once a time format is set.
+
+use serde_json::{Map, Value};
+
+use crate::formatter::TimeFormat;
+use crate::types::{StreamConsumer, StreamGroup, StreamMetadata, StreamPendingEntry};
+
+fn string(value: &[u8]) -> Value {
+    Value::String(String::from_utf8_lossy(value).into_owned())
+}
+
+/// A Unix timestamp in milliseconds, rendered if there is a time format.
+pub(crate) fn timestamp(millis: u64, time_format: Option<&TimeFormat>) -> Value {
+    match time_format {
+        Some(time_format) => time_format.render_millis(millis).into(),
+        None => millis.into(),
+    }
+}
+
+/// The IDs and counters of a stream.
+pub(crate) fn metadata(metadata: &StreamMetadata) -> Map<String, Value> {
+    let mut map = Map::new();
+    map.insert("last_id".into(), metadata.last_id.to_string().into());
+    if let Some(first_id) = metadata.first_id {
+        map.insert("first_id".into(), first_id.to_string().into());
+    }
+    if let Some(max_deleted) = metadata.max_deleted_entry_id {
+        map.insert(
+            "max_deleted_entry_id".into(),
+            max_deleted.to_string().into(),
+        );
+    }
+    if let Some(entries_added) = metadata.entries_added {
+        map.insert("entries_added".into(), entries_added.into());
+    }
+    map
+}
+
+/// The fields of an entry, as `[field, value]` pairs.
+pub(crate) fn fields(fields: &[(Vec<u8>, Vec<u8>)]) -> Value {
+    let fields = fields
+        .iter()
+        .map(|(field, value)| Value::Array(vec![string(field), string(value)]))
+        .collect();
+    Value::Array(fields)
+}
+
+/// The consumer groups of a stream, collected from their callbacks.
+#[derive(Default)]
+pub(crate) struct Groups {
+    groups: Vec<(Vec<u8>, Map<String, Value>)>,
+    time_format: Option<TimeFormat>,
+}
+
+impl Groups {
+    pub(crate) fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.time_format = Some(time_format);
+    }
+
+    pub(crate) fn group(&mut self, group: &StreamGroup) {
+        let mut map = Map::new();
+        map.insert("name".into(), string(&group.name));
+        map.insert("last_id".into(), group.last_id.to_string().into());
+        if let Some(entries_read) = group.entries_read {
+            map.insert("entries_read".into(), entries_read.into());
+        }
+        map.insert("consumers".into(), Value::Array(vec![]));
+        map.insert("pending".into(), Value::Array(vec![]));
+        self.groups.push((group.name.clone(), map));
+    }
+
+    pub(crate) fn consumer(&mut self, group: &[u8], consumer: &StreamConsumer) {
+        let mut map = Map::new();
+        map.insert("name".into(), string(&consumer.name));
+        let time_format = self.time_format.as_ref();
+        map.insert(
+            "seen_time".into(),
+            timestamp(consumer.seen_time, time_format),
+        );
+        if let Some(active_time) = consumer.active_time {
+            map.insert("active_time".into(), timestamp(active_time, time_format));
+        }
+        self.push(group, "consumers", map);
+    }
+
+    pub(crate) fn pending_entry(&mut self, group: &[u8], entry: &StreamPendingEntry) {
+        let mut map = Map::new();
+        map.insert("id".into(), entry.id.to_string().into());
+        map.insert("consumer".into(), string(&entry.consumer));
+        map.insert(
+            "delivery_time".into(),
+            timestamp(entry.delivery_time, self.time_format.as_ref()),
+        );
+        map.insert("delivery_count".into(), entry.delivery_count.into());
+        self.push(group, "pending", map);
+    }
+
+    fn push(&mut self, group: &[u8], list: &str, item: Map<String, Value>) {
+        let group = self.groups.iter_mut().rev().find(|(name, _)| name == group);
+        if let Some(Value::Array(items)) = group.and_then(|(_, map)| map.get_mut(list)) {
+            items.push(Value::Object(item));
+        }
+    }
+
+    /// The groups collected since the last call.
+    pub(crate) fn take_value(&mut self) -> Value {
+        Value::Array(
+            std::mem::take(&mut self.groups)
+                .into_iter()
+                .map(|(_, map)| Value::Object(map))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,305 @@
+use super::write_str;
+use crate::formatter::{Formatter, TimeFormat};
+use crate::types::{EncodingType, FilterInfo, RdbResult, StreamId};
+use std::io;
+use std::io::Write;
+use std::str;
+
+/// The width of the TTL column, that of an expiry in the default time format.
+const TTL_WIDTH: usize = 23;
+
+/// Prints a line per key in aligned columns: its database, type, name,
+/// size, TTL and the start of its value, for reading dumps in a terminal.
+///
+/// Names and values longer than their column are cut off with `…`, and
+/// control characters in them are escaped. The size is the bytes of all
+/// fields, members and values, with 8 bytes per score. The TTL is relative
+/// to the `ctime` AUX field if the dump has one, otherwise the expiry is
+/// printed.
+pub struct Table {
+    out: Box<dyn Write + 'static>,
+    width: usize,
+    time_format: TimeFormat,
+    dbnum: u32,
+    ctime: Option<u64>,
+    typ: &'static str,
+    expiry: Option<u64>,
+    size: u64,
+    value: String,
+}
+
+impl Table {
+    pub fn new() -> Table {
+        Table::with_writer(io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(out: W) -> Table {
+        Table {
+            out: Box::new(out),
+            width: 40,
+            time_format: TimeFormat::default(),
+            dbnum: 0,
+            ctime: None,
+            typ: "",
+            expiry: None,
+            size: 0,
+            value: String::new(),
+        }
+    }
+
+    /// Sets the number of characters names and values are cut off after.
+    /// Defaults to 40.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = width;
+    }
+
+    /// Sets how expiries are rendered.
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.time_format = time_format;
+    }
+
+    fn start_key(&mut self, typ: &'static str, expiry: Option<u64>) {
+        self.typ = typ;
+        self.expiry = expiry;
+        self.size = 0;
+        self.value.clear();
+    }
+
+    /// Adds an element to the value column, separated by a comma. Elements
+    /// beyond the column's width are only counted into the size.
+    fn add(&mut self, size: usize, element: impl FnOnce(usize) -> String) {
+        self.size += size as u64;
+        if self.value.chars().count() > self.width {
+            return;
+        }
+
+        if !self.value.is_empty() {
+            self.value.push_str(", ");
+        }
+        self.value.push_str(&element(self.width));
+    }
+
+    fn end_key(&mut self, key: &[u8]) -> RdbResult<()> {
+        let ttl = match (self.expiry, self.ctime) {
+            (None, _) => "-".to_owned(),
+            (Some(expiry), Some(ctime)) => format!("{}s", (expiry as i64 - ctime as i64) / 1000),
+            (Some(expiry), None) => self.time_format.render_millis(expiry),
+        };
+        let line = format!(
+            "{:<3} {:<9} {:<key_width$} {:>10} {:<ttl_width$} {}",
+            self.dbnum,
+            self.typ,
+            truncate(&escape(key, self.width), self.width),
+            self.size,
+            ttl,
+            truncate(&self.value, self.width),
+            key_width = self.width + 1,
+            ttl_width = TTL_WIDTH,
+        );
+        write_str(&mut self.out, line.trim_end())?;
+        write_str(&mut self.out, "\n")
+    }
+}
+
+impl Default for Table {
+    fn default() -> Table {
+        Table::new()
+    }
+}
+
+/// Makes the start of `bytes` printable, escaping control characters and
+/// invalid UTF-8. Only as many bytes as can fill `width` characters are
+/// read, so large values don't need to be copied.
+fn escape(bytes: &[u8], width: usize) -> String {
+    let bytes = &bytes[..bytes.len().min(4 * (width + 1))];
+    let mut escaped = String::with_capacity(bytes.len());
+    for c in String::from_utf8_lossy(bytes).chars() {
+        if c.is_control() {
+            escaped.extend(c.escape_default());
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Cuts `text` off after `width` characters, ending it in `…` if it is
+/// longer.
+fn truncate(text: &str, width: usize) -> String {
+    match text.char_indices().nth(width) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_owned(),
+    }
+}
+
+impl Formatter for Table {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        let header = format!(
+            "{:<3} {:<9} {:<key_width$} {:>10} {:<ttl_width$} {}\n",
+            "db",
+            "type",
+            "key",
+            "size",
+            "ttl",
+            "value",
+            key_width = self.width + 1,
+            ttl_width = TTL_WIDTH,
+        );
+        write_str(&mut self.out, &header)
+    }
+
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.dbnum = db_number;
+
+        Ok(())
+    }
+
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        if key == b"ctime" {
+            self.ctime = str::from_utf8(value)
+                .ok()
+                .and_then(|ctime| ctime.parse::<u64>().ok())
+                .map(|ctime| ctime * 1000);
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.start_key("string", expiry);
+        self.add(value.len(), |width| escape(value, width));
+        self.end_key(key)
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.start_key("module", None);
+        self.add(0, |_| format!("<{}>", module_name));
+        self.end_key(key)
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.start_key("module", expiry);
+        self.add(0, |_| format!("<{:?}>", filter.kind));
+        self.end_key(key)
+    }
+
+    fn start_hash(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("hash", expiry);
+        Ok(())
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn hash_element(&mut self, _key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(field.len() + value.len(), |width| {
+            format!("{}={}", escape(field, width), escape(value, width))
+        });
+        Ok(())
+    }
+
+    fn start_set(
+        &mut self,
+        _key: &[u8],
+        _cardinality: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("set", expiry);
+        Ok(())
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn set_element(&mut self, _key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.add(member.len(), |width| escape(member, width));
+        Ok(())
+    }
+
+    fn start_list(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("list", expiry);
+        Ok(())
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn list_element(&mut self, _key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.add(value.len(), |width| escape(value, width));
+        Ok(())
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("sortedset", expiry);
+        Ok(())
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn sorted_set_element(&mut self, _key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.add(member.len() + std::mem::size_of::<f64>(), |width| {
+            format!("{}={}", escape(member, width), score)
+        });
+        Ok(())
+    }
+
+    fn start_stream(
+        &mut self,
+        _key: &[u8],
+        _length: u32,
+        expiry: Option<u64>,
+        _info: EncodingType,
+    ) -> RdbResult<()> {
+        self.start_key("stream", expiry);
+        Ok(())
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.end_key(key)
+    }
+    fn stream_entry(
+        &mut self,
+        _key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let size: usize = fields
+            .iter()
+            .map(|(field, value)| field.len() + value.len())
+            .sum();
+        self.add(std::mem::size_of::<StreamId>() + size, |width| {
+            let fields: Vec<_> = fields
+                .iter()
+                .map(|(field, value)| format!("{}={}", escape(field, width), escape(value, width)))
+                .collect();
+            format!("{} {}", id, fields.join(" "))
+        });
+        Ok(())
+    }
+}
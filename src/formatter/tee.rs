@@ -0,0 +1,286 @@
+//! Passes every callback to two formatters, to write several outputs in
+//! a single pass over a dump.
+
+use crate::formatter::Formatter;
+use crate::types::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, RdbResult, StreamConsumer,
+    StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
+
+/// A formatter calling `first` and then `second` for every callback.
+///
+/// If `first` fails, `second` is not called.
+pub struct Tee<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Formatter, B: Formatter> Tee<A, B> {
+    pub fn new(first: A, second: B) -> Tee<A, B> {
+        Tee { first, second }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: Formatter, B: Formatter> Formatter for Tee<A, B> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        self.first.start_rdb()?;
+        self.second.start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.first.end_rdb()?;
+        self.second.end_rdb()
+    }
+    fn checksum(&mut self, checksum: &[u8]) -> RdbResult<()> {
+        self.first.checksum(checksum)?;
+        self.second.checksum(checksum)
+    }
+
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.first.start_database(db_index)?;
+        self.second.start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.first.end_database(db_index)?;
+        self.second.end_database(db_index)
+    }
+
+    fn resizedb(&mut self, db_size: u32, expires_size: u32) -> RdbResult<()> {
+        self.first.resizedb(db_size, expires_size)?;
+        self.second.resizedb(db_size, expires_size)
+    }
+    fn slot_info(&mut self, slot: u32, slot_size: u32, expires_slot_size: u32) -> RdbResult<()> {
+        self.first.slot_info(slot, slot_size, expires_slot_size)?;
+        self.second.slot_info(slot, slot_size, expires_slot_size)
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.first.aux_field(key, value)?;
+        self.second.aux_field(key, value)
+    }
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        self.first.function(library_code)?;
+        self.second.function(library_code)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.first.set(key, value, expiry)?;
+        self.second.set(key, value, expiry)
+    }
+
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.first
+            .compressed_set(key, compressed, real_length, expiry)?;
+        self.second
+            .compressed_set(key, compressed, real_length, expiry)
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.first.module(key, module_name)?;
+        self.second.module(key, module_name)
+    }
+
+    fn unknown_type(&mut self, code: u8) -> RdbResult<()> {
+        self.first.unknown_type(code)?;
+        self.second.unknown_type(code)
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.first.probabilistic_filter(key, filter, expiry)?;
+        self.second.probabilistic_filter(key, filter, expiry)
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.first.hyperloglog(key, encoding, cardinality, expiry)?;
+        self.second.hyperloglog(key, encoding, cardinality, expiry)
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.first.bitmap(key, length, popcount, expiry)?;
+        self.second.bitmap(key, length, popcount, expiry)
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.first.value_length(key, length, expiry)?;
+        self.second.value_length(key, length, expiry)
+    }
+
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        self.first.blob_stats(key, stats)?;
+        self.second.blob_stats(key, stats)
+    }
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        self.first.key_offset(key, offset, size)?;
+        self.second.key_offset(key, offset, size)
+    }
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        self.first.key_usage(key, usage)?;
+        self.second.key_usage(key, usage)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.first.start_hash(key, length, expiry, info)?;
+        self.second.start_hash(key, length, expiry, info)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.first.end_hash(key)?;
+        self.second.end_hash(key)
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.first.hash_element(key, field, value)?;
+        self.second.hash_element(key, field, value)
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.first.hash_element_ex(key, field, value, expiry)?;
+        self.second.hash_element_ex(key, field, value, expiry)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.first.start_set(key, cardinality, expiry, info)?;
+        self.second.start_set(key, cardinality, expiry, info)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.first.end_set(key)?;
+        self.second.end_set(key)
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.first.set_element(key, member)?;
+        self.second.set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.first.start_list(key, length, expiry, info)?;
+        self.second.start_list(key, length, expiry, info)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.first.end_list(key)?;
+        self.second.end_list(key)
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.first.list_element(key, value)?;
+        self.second.list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.first.start_sorted_set(key, length, expiry, info)?;
+        self.second.start_sorted_set(key, length, expiry, info)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.first.end_sorted_set(key)?;
+        self.second.end_sorted_set(key)
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.first.sorted_set_element(key, score, member)?;
+        self.second.sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.first.start_stream(key, length, expiry, info)?;
+        self.second.start_stream(key, length, expiry, info)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.first.end_stream(key)?;
+        self.second.end_stream(key)
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.first.stream_metadata(key, metadata)?;
+        self.second.stream_metadata(key, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.first.stream_entry(key, id, fields)?;
+        self.second.stream_entry(key, id, fields)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.first.stream_group(key, group)?;
+        self.second.stream_group(key, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.first.stream_consumer(key, group, consumer)?;
+        self.second.stream_consumer(key, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.first.stream_pending_entry(key, group, entry)?;
+        self.second.stream_pending_entry(key, group, entry)
+    }
+}
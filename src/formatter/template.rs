@@ -0,0 +1,291 @@
+use super::jsonl::Record;
+use super::write_str;
+use crate::formatter::{Formatter, JsonLines, TimeFormat};
+use crate::types::{
+    EncodingType, RdbError, RdbResult, StreamConsumer, StreamGroup, StreamId, StreamMetadata,
+    StreamPendingEntry,
+};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::rc::Rc;
+
+/// A piece of a template.
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Db,
+    Key,
+    Type,
+    Expiry,
+    Value,
+}
+
+/// Prints one line per key in a format given as a template, e.g.
+/// `{db}\t{key}\t{type}\t{value}`, for scripts expecting exactly that.
+///
+/// The placeholders are `{db}`, `{key}`, `{type}`, `{expiry}` and
+/// `{value}`, with the values of the same names `JsonLines` writes: strings
+/// as they are, other values as the JSON `JsonLines` writes for them. The
+/// expiry is in milliseconds unless a time format is set, and empty for keys
+/// without one. `\t`, `\n` and `\\` stand for a tab, a line feed and a
+/// backslash, `{{` and `}}` for braces. Every key's line ends with a line
+/// feed.
+pub struct Template {
+    lines: JsonLines,
+    records: Rc<RefCell<Vec<Record>>>,
+    parts: Vec<Part>,
+    time_format: Option<TimeFormat>,
+    out: Box<dyn Write + 'static>,
+}
+
+/// Splits `template` into text and placeholders.
+fn parse(template: &str) -> RdbResult<Vec<Part>> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let escaped = match (c, chars.peek()) {
+            ('\\', Some('t')) => '\t',
+            ('\\', Some('n')) => '\n',
+            ('\\', Some('\\')) => '\\',
+            ('{', Some('{')) => '{',
+            ('}', Some('}')) => '}',
+            ('{', _) => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => {
+                            return Err(RdbError::Other(format!(
+                                "Unclosed placeholder in template: {}",
+                                template
+                            )))
+                        }
+                    }
+                }
+                let part = match &name[..] {
+                    "db" => Part::Db,
+                    "key" => Part::Key,
+                    "type" => Part::Type,
+                    "expiry" => Part::Expiry,
+                    "value" => Part::Value,
+                    _ => {
+                        return Err(RdbError::Other(format!(
+                            "Unknown placeholder in template: {{{}}}",
+                            name
+                        )))
+                    }
+                };
+                if !text.is_empty() {
+                    parts.push(Part::Text(mem::take(&mut text)));
+                }
+                parts.push(part);
+                continue;
+            }
+            (c, _) => {
+                text.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        text.push(escaped);
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+
+    Ok(parts)
+}
+
+impl Template {
+    /// Fails if `template` has unknown or unclosed placeholders.
+    pub fn new(template: &str) -> RdbResult<Template> {
+        Template::with_writer(template, io::stdout())
+    }
+
+    /// Creates a formatter writing to `out` instead of stdout.
+    pub fn with_writer<W: Write + 'static>(template: &str, out: W) -> RdbResult<Template> {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let sink = records.clone();
+        let lines = JsonLines::with_callback(move |record| {
+            sink.borrow_mut().push(record);
+            Ok(())
+        });
+
+        Ok(Template {
+            lines,
+            records,
+            parts: parse(template)?,
+            time_format: None,
+            out: Box::new(out),
+        })
+    }
+
+    /// Renders expiries and the times of streams with `time_format`.
+    pub fn set_time_format(&mut self, time_format: TimeFormat) {
+        self.lines.set_time_format(time_format.clone());
+        self.time_format = Some(time_format);
+    }
+
+    fn end_key(&mut self) -> RdbResult<()> {
+        let records = mem::take(&mut *self.records.borrow_mut());
+        for record in records {
+            let mut line = String::new();
+            for part in &self.parts {
+                match part {
+                    Part::Text(text) => line.push_str(text),
+                    Part::Db => line.push_str(&record.db.to_string()),
+                    Part::Key => line.push_str(&String::from_utf8_lossy(&record.key)),
+                    Part::Type => line.push_str(record.typ),
+                    Part::Expiry => match (record.expiry, &self.time_format) {
+                        (None, _) => {}
+                        (Some(expiry), None) => line.push_str(&expiry.to_string()),
+                        (Some(expiry), Some(time_format)) => {
+                            line.push_str(&time_format.render_millis(expiry))
+                        }
+                    },
+                    Part::Value => match record.value {
+                        Value::String(ref value) => line.push_str(value),
+                        ref value => line.push_str(&value.to_string()),
+                    },
+                }
+            }
+            line.push('\n');
+            write_str(&mut self.out, &line)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Formatter for Template {
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn start_database(&mut self, db_number: u32) -> RdbResult<()> {
+        self.lines.start_database(db_number)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.lines.set(key, value, expiry)?;
+        self.end_key()
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_hash(key, length, expiry, info)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_hash(key)?;
+        self.end_key()
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.lines.hash_element(key, field, value)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_set(key, cardinality, expiry, info)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_set(key)?;
+        self.end_key()
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.lines.set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_list(key, length, expiry, info)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_list(key)?;
+        self.end_key()
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.lines.list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_sorted_set(key, length, expiry, info)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_sorted_set(key)?;
+        self.end_key()
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.lines.sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.lines.start_stream(key, length, expiry, info)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.lines.end_stream(key)?;
+        self.end_key()
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.lines.stream_metadata(key, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.lines.stream_entry(key, id, fields)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.lines.stream_group(key, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.lines.stream_consumer(key, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.lines.stream_pending_entry(key, group, entry)
+    }
+}
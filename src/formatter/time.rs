@@ -0,0 +1,108 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Duration, Local, TimeZone, Utc};
+
+use crate::types::{RdbError, RdbResult};
+
+/// The time zone timestamps are shown in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Utc,
+    Local,
+}
+
+/// How a `TimeFormat` renders a time.
+#[derive(Debug, Clone)]
+enum Style {
+    /// A strftime-like pattern.
+    Pattern(String),
+    /// The time left from the given time on.
+    Remaining(DateTime<Utc>),
+}
+
+/// How text formatters render timestamps such as expiries.
+#[derive(Debug, Clone)]
+pub struct TimeFormat {
+    zone: Zone,
+    style: Style,
+}
+
+/// Used unless a pattern is given, e.g. `2023-11-14 22:13:20 UTC`.
+pub const DEFAULT_PATTERN: &str = "%Y-%m-%d %H:%M:%S %Z";
+
+/// Timestamps as RFC 3339 and ISO 8601 have them, e.g.
+/// `2023-11-14T22:13:20.000+00:00`.
+pub const RFC3339_PATTERN: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+impl TimeFormat {
+    /// Creates a format from a strftime-like `pattern`, see `chrono::format::strftime`.
+    pub fn new(zone: Zone, pattern: &str) -> RdbResult<TimeFormat> {
+        if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+            return Err(RdbError::Other(format!("Invalid time format: {}", pattern)));
+        }
+
+        Ok(TimeFormat {
+            zone,
+            style: Style::Pattern(pattern.to_owned()),
+        })
+    }
+
+    /// Creates a format rendering the time left from `now` on instead of a
+    /// timestamp, e.g. `1d2h3m4s`, or `-5s` for times that have passed.
+    pub fn remaining(now: DateTime<Utc>) -> TimeFormat {
+        TimeFormat {
+            zone: Zone::Utc,
+            style: Style::Remaining(now),
+        }
+    }
+
+    pub fn render(&self, time: DateTime<Utc>) -> String {
+        match (&self.style, self.zone) {
+            (Style::Pattern(pattern), Zone::Utc) => time.format(pattern).to_string(),
+            (Style::Pattern(pattern), Zone::Local) => {
+                time.with_timezone(&Local).format(pattern).to_string()
+            }
+            (Style::Remaining(now), _) => duration(time.signed_duration_since(*now)),
+        }
+    }
+
+    /// Renders a Unix timestamp in milliseconds, like the expiries in a dump.
+    pub fn render_millis(&self, millis: u64) -> String {
+        match Utc.timestamp_millis_opt(millis as i64).single() {
+            Some(time) => self.render(time),
+            None => millis.to_string(),
+        }
+    }
+}
+
+impl Default for TimeFormat {
+    fn default() -> TimeFormat {
+        TimeFormat {
+            zone: Zone::Utc,
+            style: Style::Pattern(DEFAULT_PATTERN.to_owned()),
+        }
+    }
+}
+
+/// Renders a duration in days, hours, minutes and seconds, leaving out the
+/// parts that are 0.
+fn duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds();
+    let mut text = String::new();
+    if seconds < 0 {
+        text.push('-');
+    }
+    let seconds = seconds.unsigned_abs();
+    let parts = [
+        (seconds / 86400, 'd'),
+        (seconds / 3600 % 24, 'h'),
+        (seconds / 60 % 60, 'm'),
+        (seconds % 60, 's'),
+    ];
+    for (value, unit) in parts.iter().filter(|(value, _)| *value > 0) {
+        text.push_str(&format!("{}{}", value, unit));
+    }
+    if seconds == 0 {
+        text.push_str("0s");
+    }
+    text
+}
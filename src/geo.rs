@@ -0,0 +1,48 @@
+//! Decoding of the positions of GEO sets.
+//!
+//! `GEOADD` stores members in a sorted set whose scores are 52 bit
+//! geohashes, interleaving 26 bits each of longitude and latitude. `decode`
+//! turns a score back into the coordinates `GEOPOS` would return, e.g. for
+//! the `JSON` formatter's `set_geo_keys`.
+
+/// A position in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinates {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+/// The bits per coordinate.
+const STEP: u32 = 26;
+const LONGITUDE: (f64, f64) = (-180.0, 180.0);
+/// Latitudes are limited to those of the Web Mercator projection.
+const LATITUDE: (f64, f64) = (-85.05112878, 85.05112878);
+
+/// Decodes the score of a member of a GEO set, or returns `None` if the
+/// score can't be a geohash, as it is no integer below 2^52.
+pub fn decode(score: f64) -> Option<Coordinates> {
+    let limit = (1u64 << (2 * STEP)) as f64;
+    if !(0.0..limit).contains(&score) || score.fract() != 0.0 {
+        return None;
+    }
+
+    let hash = score as u64;
+    let (mut longitude, mut latitude) = (0, 0);
+    for bit in 0..STEP {
+        latitude |= ((hash >> (2 * bit)) & 1) << bit;
+        longitude |= ((hash >> (2 * bit + 1)) & 1) << bit;
+    }
+
+    Some(Coordinates {
+        longitude: center(longitude, LONGITUDE),
+        latitude: center(latitude, LATITUDE),
+    })
+}
+
+/// The center of the `cell`th of the 2^26 cells `range` is divided into.
+fn center(cell: u64, (min, max): (f64, f64)) -> f64 {
+    let cells = (1u64 << STEP) as f64;
+    let low = min + cell as f64 / cells * (max - min);
+    let high = min + (cell + 1) as f64 / cells * (max - min);
+    ((low + high) / 2.0).clamp(min, max)
+}
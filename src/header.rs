@@ -0,0 +1,144 @@
+//! Typed access to the metadata at the start of a RDB file.
+//!
+//! Since RDB version 7 Redis stores information about itself in AUX fields
+//! before the first database. `Header` interprets the fields it knows and
+//! keeps all others as raw byte pairs.
+
+use byteorder::ReadBytesExt;
+use chrono::{DateTime, TimeZone, Utc};
+use std::fmt;
+use std::io::Read;
+use std::str;
+
+use crate::constants::op_code;
+use crate::parser::{read_blob, read_version, verify_magic};
+use crate::types::RdbResult;
+
+/// A `major.minor.patch` version number, as used for `redis-ver`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl Version {
+    /// Parses versions like `7.2.4`. Missing minor or patch numbers count as 0.
+    pub fn parse(version: &str) -> Option<Version> {
+        let mut parts = version.trim().splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+        let patch = parts.next().map_or(Some(0), |part| part.parse().ok())?;
+
+        Some(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Header {
+    /// Version of the RDB format.
+    pub rdb_version: u32,
+    /// `redis-ver`: Version of the Redis server that wrote the file.
+    pub redis_version: Option<Version>,
+    /// `redis-bits`: Architecture of the server, 32 or 64 bit.
+    pub redis_bits: Option<u32>,
+    /// `ctime`: Creation time of the file.
+    pub ctime: Option<DateTime<Utc>>,
+    /// `used-mem`: Memory used by the server when the file was written, in bytes.
+    pub used_mem: Option<u64>,
+    /// `repl-stream-db`: Database selected in the replication stream.
+    pub repl_stream_db: Option<u32>,
+    /// `repl-id`: Replication ID of the server.
+    pub repl_id: Option<String>,
+    /// `repl-offset`: Replication offset at the time the file was written.
+    pub repl_offset: Option<u64>,
+    /// `aof-preamble` or `aof-base`: Whether the file is part of an AOF.
+    pub aof_base: Option<bool>,
+    /// All AUX fields that are unknown or could not be interpreted.
+    pub other: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+fn parse<T: str::FromStr>(value: &[u8]) -> Option<T> {
+    str::from_utf8(value).ok()?.parse().ok()
+}
+
+impl Header {
+    pub fn new(rdb_version: u32) -> Header {
+        Header {
+            rdb_version,
+            ..Header::default()
+        }
+    }
+
+    /// Interprets a single AUX field.
+    pub fn add_aux_field(&mut self, key: &[u8], value: &[u8]) {
+        let known = match key {
+            b"redis-ver" => str::from_utf8(value)
+                .ok()
+                .and_then(Version::parse)
+                .map(|version| self.redis_version = Some(version)),
+            b"redis-bits" => parse(value).map(|bits| self.redis_bits = Some(bits)),
+            b"ctime" => parse(value)
+                .and_then(|ctime| Utc.timestamp_opt(ctime, 0).single())
+                .map(|ctime| self.ctime = Some(ctime)),
+            b"used-mem" => parse(value).map(|used_mem| self.used_mem = Some(used_mem)),
+            b"repl-stream-db" => parse(value).map(|db| self.repl_stream_db = Some(db)),
+            b"repl-id" => str::from_utf8(value)
+                .ok()
+                .map(|repl_id| self.repl_id = Some(repl_id.to_owned())),
+            b"repl-offset" => parse(value).map(|offset| self.repl_offset = Some(offset)),
+            b"aof-preamble" | b"aof-base" => {
+                parse::<u8>(value).map(|aof_base| self.aof_base = Some(aof_base != 0))
+            }
+            _ => None,
+        };
+
+        if known.is_none() {
+            self.other.push((key.to_vec(), value.to_vec()));
+        }
+    }
+}
+
+/// Whether Redis itself writes the AUX field `key`, as opposed to fields
+/// added by forks or managed services.
+pub fn is_redis_aux_field(key: &[u8]) -> bool {
+    matches!(
+        key,
+        b"redis-ver"
+            | b"redis-bits"
+            | b"ctime"
+            | b"used-mem"
+            | b"repl-stream-db"
+            | b"repl-id"
+            | b"repl-offset"
+            | b"aof-preamble"
+            | b"aof-base"
+            | b"lua"
+    )
+}
+
+/// Reads the magic string, the version and all AUX fields in front of the first database.
+///
+/// Stops reading at the first opcode that is not an AUX field.
+pub fn read_header<R: Read>(mut input: R) -> RdbResult<Header> {
+    verify_magic(&mut input)?;
+    let mut header = Header::new(read_version(&mut input)?);
+
+    while input.read_u8()? == op_code::AUX {
+        let key = read_blob(&mut input)?;
+        let value = read_blob(&mut input)?;
+        header.add_aux_field(&key, &value);
+    }
+
+    Ok(header)
+}
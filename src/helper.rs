@@ -1,5 +1,5 @@
-use crate::types::RdbResult;
-use std::io::Read;
+use crate::types::{RdbError, RdbResult};
+use std::io::{Cursor, Read};
 
 pub fn int_to_vec(number: i32) -> Vec<u8> {
     let number = number.to_string();
@@ -10,9 +10,53 @@ pub fn int_to_vec(number: i32) -> Vec<u8> {
     result
 }
 
+/// The most `read_exact` allocates up front. Longer reads grow the buffer
+/// as the data arrives, so a corrupt length fails at the end of the input
+/// instead of allocating it all at once.
+const PREALLOCATE_LIMIT: usize = 1 << 20;
+
 pub fn read_exact<T: Read>(reader: &mut T, len: usize) -> RdbResult<Vec<u8>> {
-    let mut buf = vec![0; len];
-    reader.read_exact(&mut buf)?;
+    if len <= PREALLOCATE_LIMIT {
+        let mut buf = vec![0; len];
+        reader.read_exact(&mut buf)?;
+        return Ok(buf);
+    }
+
+    let mut buf = Vec::with_capacity(PREALLOCATE_LIMIT);
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
 
     Ok(buf)
 }
+
+/// Fails with `RdbError::Overrun` if fewer than `len` bytes are left in
+/// `blob`, so malformed lengths can't make us allocate or loop for more.
+pub fn check_remaining<T: AsRef<[u8]>>(
+    blob: &Cursor<T>,
+    len: u64,
+    container: &'static str,
+) -> RdbResult<()> {
+    let available = (blob.get_ref().as_ref().len() as u64).saturating_sub(blob.position());
+    if len > available {
+        return Err(RdbError::Overrun {
+            container,
+            length: len,
+            available,
+        });
+    }
+
+    Ok(())
+}
+
+/// Like `read_exact`, but checks `len` against the bytes left in `blob`
+/// first.
+pub fn read_bounded<T: AsRef<[u8]>>(
+    blob: &mut Cursor<T>,
+    len: u64,
+    container: &'static str,
+) -> RdbResult<Vec<u8>> {
+    check_remaining(blob, len, container)?;
+    read_exact(blob, len as usize)
+}
@@ -0,0 +1,146 @@
+//! Recognition of HyperLogLogs stored in strings.
+//!
+//! `PFADD` keeps a HyperLogLog in a string starting with the magic `HYLL`,
+//! followed by the encoding, three unused bytes and a cached cardinality.
+//! The 16384 registers of 6 bits follow either as they are, in the dense
+//! encoding, or run-length encoded, in the sparse one. `decode` estimates
+//! the cardinality as `PFCOUNT` does.
+
+use crate::types::HllEncoding;
+
+const MAGIC: &[u8] = b"HYLL";
+const HEADER_SIZE: usize = 16;
+/// The number of bits of a hash selecting the register.
+const P: u32 = 14;
+const REGISTERS: usize = 1 << P;
+const REGISTER_BITS: usize = 6;
+const DENSE_SIZE: usize = HEADER_SIZE + REGISTERS * REGISTER_BITS / 8;
+/// The bits of a hash left to count leading zeros in.
+const Q: usize = 64 - P as usize;
+
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+
+/// Returns the encoding and estimated cardinality of a HyperLogLog, or
+/// `None` if `value` isn't one.
+///
+/// The cardinality cached in the header is used if it is valid.
+pub fn decode(value: &[u8]) -> Option<(HllEncoding, u64)> {
+    if value.len() < HEADER_SIZE || &value[..4] != MAGIC {
+        return None;
+    }
+
+    let encoding = match value[4] {
+        DENSE if value.len() == DENSE_SIZE => HllEncoding::Dense,
+        SPARSE => HllEncoding::Sparse,
+        _ => return None,
+    };
+    let registers = &value[HEADER_SIZE..];
+    let histogram = match encoding {
+        HllEncoding::Dense => dense_histogram(registers),
+        HllEncoding::Sparse => sparse_histogram(registers)?,
+    };
+
+    // The most significant bit marks the cache as stale.
+    let mut cached = [0; 8];
+    cached.copy_from_slice(&value[8..16]);
+    let cardinality = match u64::from_le_bytes(cached) {
+        cached if cached >> 63 == 0 => cached,
+        _ => estimate(&histogram),
+    };
+    Some((encoding, cardinality))
+}
+
+/// Counts the registers by value.
+fn dense_histogram(registers: &[u8]) -> [u32; 64] {
+    let mut histogram = [0; 64];
+    for index in 0..REGISTERS {
+        let bit = index * REGISTER_BITS;
+        let (byte, shift) = (bit / 8, bit % 8);
+        let low = registers[byte] as u16 >> shift;
+        let high = registers
+            .get(byte + 1)
+            .map_or(0, |&b| (b as u16) << (8 - shift));
+        histogram[((low | high) & 63) as usize] += 1;
+    }
+    histogram
+}
+
+/// Counts the registers by value, or returns `None` if the runs don't
+/// cover all registers exactly.
+fn sparse_histogram(registers: &[u8]) -> Option<[u32; 64]> {
+    let mut histogram = [0; 64];
+    let mut index = 0;
+    let mut bytes = registers.iter();
+    while let Some(&op) = bytes.next() {
+        let (value, run) = match op >> 6 {
+            // ZERO: up to 64 registers set to 0.
+            0b00 => (0, (op & 0x3f) as usize + 1),
+            // XZERO: up to 16384 registers set to 0.
+            0b01 => (
+                0,
+                (((op & 0x3f) as usize) << 8 | *bytes.next()? as usize) + 1,
+            ),
+            // VAL: up to 4 registers set to a value of up to 32.
+            _ => (((op >> 2) & 0x1f) as usize + 1, (op & 0x03) as usize + 1),
+        };
+        index += run;
+        if index > REGISTERS {
+            return None;
+        }
+        histogram[value] += run as u32;
+    }
+    if index == REGISTERS {
+        Some(histogram)
+    } else {
+        None
+    }
+}
+
+/// The estimator of Otmar Ertl's "New cardinality estimation algorithms
+/// for HyperLogLog sketches", which Redis uses since 5.0.
+fn estimate(histogram: &[u32; 64]) -> u64 {
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[Q + 1] as f64) / m);
+    for count in histogram[1..=Q].iter().rev() {
+        z += *count as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    let alpha = 0.5 / 2f64.ln();
+    (alpha * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! An index of the keys in a dump and the digests of their values, for
+//! exporting only what changed since.
+//!
+//! The index is much smaller than the dump, as it only keeps the key names
+//! and a SHA-256 digest per key, computed like the `digest` format does.
+//! `Index::delta` wraps a formatter so it only sees the keys of a newer dump
+//! that were added or changed since the indexed one:
+//!
+//! ```rust,no_run
+//! use rdb::index::Index;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! let index = Index::read_from(BufReader::new(File::open("dump.idx").unwrap())).unwrap();
+//! let file = BufReader::new(File::open("new.rdb").unwrap());
+//! let delta = index.delta(rdb::formatter::Protocol::new());
+//! rdb::parse(file, delta, rdb::filter::Simple::new()).unwrap();
+//! ```
+//!
+//! Keys deleted since are not passed on, `Delta::removed` lists them once
+//! the newer dump was parsed, leaving out those the parser's filter would
+//! have skipped. Only keys of the types the `digest` format covers are
+//! indexed and can be removed; a key that became a module value keeps its
+//! old entry, but is not reported as removed either.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use crate::constants::encoding_type;
+use crate::event::Recorder;
+use crate::filter::Filter;
+use crate::formatter::digest::KeyDigest;
+use crate::formatter::{Digest, Formatter};
+use crate::helper::read_exact;
+use crate::types::{
+    EncodingType, FilterInfo, RdbError, RdbResult, StreamConsumer, StreamGroup, StreamId,
+    StreamMetadata, StreamPendingEntry,
+};
+
+/// Identifies index files and their format version.
+const MAGIC: &[u8] = b"RDBIDX01";
+
+/// What the index knows about a key.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    typ: String,
+    sha256: Vec<u8>,
+    expiry: Option<u64>,
+}
+
+impl Entry {
+    fn from_digest(digest: &KeyDigest) -> Entry {
+        Entry {
+            typ: digest.typ.to_owned(),
+            sha256: digest.sha256.to_vec(),
+            expiry: digest.expiry,
+        }
+    }
+}
+
+/// A key and the database it is in.
+type KeyId = (u32, Vec<u8>);
+
+/// An encoding of each type named by the digest, for `Filter::matches_type`.
+fn encoding_of(typ: &str) -> Option<u8> {
+    match typ {
+        "string" => Some(encoding_type::STRING),
+        "list" => Some(encoding_type::LIST),
+        "set" => Some(encoding_type::SET),
+        "sortedset" => Some(encoding_type::ZSET),
+        "hash" => Some(encoding_type::HASH),
+        "stream" => Some(encoding_type::STREAM_LISTPACKS),
+        _ => None,
+    }
+}
+
+/// The cluster hash slot of a key: the CRC16 of the key, or of its hash
+/// tag if it has one, modulo 16384.
+fn key_slot(key: &[u8]) -> u32 {
+    let tagged = key
+        .iter()
+        .position(|&c| c == b'{')
+        .and_then(|start| {
+            let rest = &key[start + 1..];
+            rest.iter().position(|&c| c == b'}').map(|end| &rest[..end])
+        })
+        .filter(|tag| !tag.is_empty())
+        .unwrap_or(key);
+
+    let crc = tagged.iter().fold(0u16, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    });
+    u32::from(crc) % 16384
+}
+
+/// The keys of a dump and the digests of their values.
+#[derive(Debug, Default)]
+pub struct Index {
+    entries: HashMap<KeyId, Entry>,
+}
+
+impl Index {
+    /// Builds the index of a dump.
+    pub fn build<R: Read, L: Filter>(input: R, filter: L) -> RdbResult<Index> {
+        let entries = Rc::new(RefCell::new(HashMap::new()));
+        let sink = entries.clone();
+        let digest = Digest::with_callback(move |digest| {
+            let entry = Entry::from_digest(&digest);
+            sink.borrow_mut()
+                .insert((digest.db, digest.key.to_vec()), entry);
+            Ok(())
+        });
+        crate::parse(input, digest, filter)?;
+
+        let entries = entries.replace(HashMap::new());
+        Ok(Index { entries })
+    }
+
+    /// The number of keys in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes the index in its binary format.
+    pub fn write_to<W: Write>(&self, mut out: W) -> RdbResult<()> {
+        out.write_all(MAGIC)?;
+        out.write_u64::<LittleEndian>(self.entries.len() as u64)?;
+        for ((db, key), entry) in &self.entries {
+            out.write_u32::<LittleEndian>(*db)?;
+            for part in &[key.as_slice(), entry.typ.as_bytes(), &entry.sha256] {
+                out.write_u64::<LittleEndian>(part.len() as u64)?;
+                out.write_all(part)?;
+            }
+            match entry.expiry {
+                Some(expiry) => {
+                    out.write_u8(1)?;
+                    out.write_u64::<LittleEndian>(expiry)?;
+                }
+                None => out.write_u8(0)?,
+            }
+        }
+        out.flush()?;
+
+        Ok(())
+    }
+
+    /// Reads an index written by `write_to`.
+    pub fn read_from<R: Read>(mut input: R) -> RdbResult<Index> {
+        let magic = read_exact(&mut input, MAGIC.len())?;
+        if magic != MAGIC {
+            return Err(RdbError::Other("Not an rdb index file".into()));
+        }
+
+        let count = input.read_u64::<LittleEndian>()?;
+        let mut entries = HashMap::new();
+        for _ in 0..count {
+            let db = input.read_u32::<LittleEndian>()?;
+            let mut parts = Vec::with_capacity(3);
+            for _ in 0..3 {
+                let length = input.read_u64::<LittleEndian>()?;
+                parts.push(read_exact(&mut input, length as usize)?);
+            }
+            let expiry = match input.read_u8()? {
+                0 => None,
+                _ => Some(input.read_u64::<LittleEndian>()?),
+            };
+
+            let sha256 = parts.pop().unwrap();
+            let typ = String::from_utf8(parts.pop().unwrap())
+                .map_err(|_| RdbError::Other("Invalid type name in index".into()))?;
+            let key = parts.pop().unwrap();
+            entries.insert(
+                (db, key),
+                Entry {
+                    typ,
+                    sha256,
+                    expiry,
+                },
+            );
+        }
+
+        Ok(Index { entries })
+    }
+
+    /// Wraps `formatter` so it only sees keys that are not in the index or
+    /// whose type, value or expiry changed.
+    pub fn delta<F: Formatter>(&self, formatter: F) -> Delta<'_, F> {
+        let last = Rc::new(RefCell::new(None));
+        let sink = last.clone();
+        let digest = Digest::with_callback(move |digest| {
+            *sink.borrow_mut() = Some((
+                (digest.db, digest.key.to_vec()),
+                Entry::from_digest(&digest),
+            ));
+            Ok(())
+        });
+
+        Delta {
+            index: self,
+            formatter,
+            digest,
+            last,
+            key: Recorder::buffered(),
+            db: 0,
+            seen: HashSet::new(),
+        }
+    }
+}
+
+/// A formatter passing on only the keys that changed since an `Index`.
+///
+/// The callbacks of each key are held back until its digest is known.
+pub struct Delta<'a, F: Formatter> {
+    index: &'a Index,
+    formatter: F,
+    digest: Digest,
+    last: Rc<RefCell<Option<(KeyId, Entry)>>>,
+    key: Recorder,
+    db: u32,
+    seen: HashSet<KeyId>,
+}
+
+impl<'a, F: Formatter> Delta<'a, F> {
+    pub fn into_inner(self) -> F {
+        self.formatter
+    }
+
+    /// The keys of the index that were not seen, sorted by database and
+    /// name.
+    ///
+    /// Only complete once the whole dump was parsed. `filter` should be the
+    /// parser's filter: keys it rejects were never seen, but are not
+    /// removed. Slots are checked against the hash slot of each key.
+    pub fn removed<L: Filter>(&self, filter: L) -> Vec<(u32, Vec<u8>)> {
+        let mut removed: Vec<_> = self
+            .index
+            .entries
+            .iter()
+            .filter(|(id, entry)| {
+                let (db, key) = id;
+                !self.seen.contains(*id)
+                    && filter.matches_db(*db)
+                    && filter.matches_key(key)
+                    && filter.matches_slot(key_slot(key))
+                    && encoding_of(&entry.typ).is_some_and(|enc| filter.matches_type(enc))
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        removed.sort();
+        removed
+    }
+
+    /// Replays the held back key if it changed, once its digest is known.
+    fn end_key(&mut self) -> RdbResult<()> {
+        let events = self.key.take();
+        let (id, entry) = match self.last.borrow_mut().take() {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+
+        if self.index.entries.get(&id) != Some(&entry) {
+            for event in events {
+                event.replay(&mut self.formatter)?;
+            }
+        }
+        self.seen.insert(id);
+        Ok(())
+    }
+}
+
+impl<'a, F: Formatter> Formatter for Delta<'a, F> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.formatter.end_rdb()
+    }
+
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.db = db_index;
+        self.digest.start_database(db_index)?;
+        self.formatter.start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.formatter.end_database(db_index)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        self.key.set(key, value, expiry)?;
+        self.digest.set(key, value, expiry)?;
+        self.end_key()
+    }
+
+    // Module values have no digest, so they are always passed on.
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        self.seen.insert((self.db, key.to_vec()));
+        self.formatter.module(key, module_name)
+    }
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.seen.insert((self.db, key.to_vec()));
+        self.formatter.probabilistic_filter(key, filter, expiry)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key.start_hash(key, length, expiry, info)?;
+        self.digest.start_hash(key, length, expiry, info)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key.end_hash(key)?;
+        self.digest.end_hash(key)?;
+        self.end_key()
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.key.hash_element(key, field, value)?;
+        self.digest.hash_element(key, field, value)
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        self.key.hash_element_ex(key, field, value, expiry)?;
+        self.digest.hash_element_ex(key, field, value, expiry)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key.start_set(key, cardinality, expiry, info)?;
+        self.digest.start_set(key, cardinality, expiry, info)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key.end_set(key)?;
+        self.digest.end_set(key)?;
+        self.end_key()
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        self.key.set_element(key, member)?;
+        self.digest.set_element(key, member)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key.start_list(key, length, expiry, info)?;
+        self.digest.start_list(key, length, expiry, info)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key.end_list(key)?;
+        self.digest.end_list(key)?;
+        self.end_key()
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        self.key.list_element(key, value)?;
+        self.digest.list_element(key, value)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key.start_sorted_set(key, length, expiry, info)?;
+        self.digest.start_sorted_set(key, length, expiry, info)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key.end_sorted_set(key)?;
+        self.digest.end_sorted_set(key)?;
+        self.end_key()
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        self.key.sorted_set_element(key, score, member)?;
+        self.digest.sorted_set_element(key, score, member)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        self.key.start_stream(key, length, expiry, info)?;
+        self.digest.start_stream(key, length, expiry, info)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        self.key.end_stream(key)?;
+        self.digest.end_stream(key)?;
+        self.end_key()
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        self.key.stream_metadata(key, metadata)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        self.key.stream_entry(key, id, fields)?;
+        self.digest.stream_entry(key, id, fields)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        self.key.stream_group(key, group)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        self.key.stream_consumer(key, group, consumer)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        self.key.stream_pending_entry(key, group, entry)
+    }
+}
@@ -30,21 +30,43 @@
 //! rdb::parse(reader, rdb::formatter::JSON::new(), rdb::filter::Simple::new());
 //! ```
 //!
+//! Files spread across many databases can be decoded on several threads
+//! with `parallel::parse`, which takes the path of the file instead of a reader:
+//!
+//! ```rust,no_run
+//! rdb::parallel::parse("dump.rdb", rdb::formatter::JSON::new(), rdb::filter::Simple::new(), 4);
+//! ```
+//!
+//! `parallel::ParallelParser` takes the options of `RdbParser`, such as
+//! strict mode, and collects the same warnings and summary.
+//!
 //! # Formatter
 //!
-//! rdb-rs brings 4 pre-defined formatters, which can be used:
+//! rdb-rs brings several pre-defined formatters in `formatter`, among them:
 //!
-//! * `PlainFormatter`: Just plain output for testing
-//! * `JSONFormatter`: JSON-encoded output
-//! * `NilFormatter`: Surpresses all output
-//! * `ProtocolFormatter`: Formats the data in [RESP](http://redis.io/topics/protocol),
-//! the Redis Serialization Protocol
+//! * `Plain`: Just plain output for testing
+//! * `JSON`: JSON-encoded output
+//! * `Nil`: Surpresses all output
+//! * `Protocol`: Formats the data in [RESP](http://redis.io/topics/protocol),
+//!   the Redis Serialization Protocol
 //!
-//! These formatters adhere to the `RdbParseFormatter` trait
+//! These formatters adhere to the `Formatter` trait
 //! and supply a method for each possible datatype or opcode.
 //! Its up to the formatter to correctly handle all provided data such as
 //! lists, sets, hashes, expires and metadata.
 //!
+//! # Stable API
+//!
+//! `parse`, `Parser` and the other items at the crate root, and the
+//! `formatter`, `filter`, `value`, `codec`, `events`, `feed`, `visitor`,
+//! `module`, `parallel`, `envelope`, `extsort`, `index` and `capabilities`
+//! modules are the stable API, checked for semver compatibility with
+//! `make semver-check`. Everything else is private to the crate, and the
+//! analyses behind the subcommands of the `rdb` binary, like `diff`,
+//! `split` and `restore`, are part of the binary. The names used before
+//! 0.3, like `RdbParseFormatter` and `JSONFormatter`, still work but are
+//! deprecated.
+//!
 //! # Command-line
 //!
 //! rdb-rs brings a Command Line application as well.
@@ -74,28 +96,86 @@
 
 use std::io::Read;
 
+pub use crate::de::{from_reader, from_reader_with_filter, Deserializer};
+pub use crate::decode::{decode_value, dump_payload, parse_dump_payload};
+pub use crate::header::{read_header, Header, Version};
+pub use crate::load::{load, load_with_filter, Database, Entry};
+pub use crate::parser::{RdbParser, RdbParserOptions};
+pub use crate::types::{DatabaseSize, ParseSummary, RdbError, RdbOk, RdbResult, RdbStats, Warning};
+
+/// The parser, under the name of the stable API.
+pub type Parser<R, F, L> = RdbParser<R, F, L>;
+
+// Value types were exported from the crate root before `value` existed.
 #[doc(hidden)]
-pub use crate::types::{
-    /* error and result types */
-    RdbError, RdbOk, RdbResult, Type, ZiplistEntry,
+pub use crate::value::{
+    BlobStats, FilterInfo, FilterKind, KeyUsage, StreamConsumer, StreamGroup, StreamId,
+    StreamMetadata, StreamPendingEntry, Type, ValueLength, ZiplistEntry,
 };
 
-pub use crate::parser::RdbParser;
-
+use crate::envelope::KeyUnwrapper;
 use crate::filter::Filter;
-use crate::formatter::Formatter;
+use crate::formatter::{Formatter, Materialize, ValueFormatter, WithContext};
 
-#[macro_use]
-mod macros;
 mod constants;
+mod de;
+mod decode;
+mod event;
+mod geo;
+mod header;
 mod helper;
+mod hyperloglog;
+mod listpack;
+mod load;
+mod parser;
+mod sink;
+mod types;
+mod ziplist;
 
+pub mod capabilities;
+pub mod codec;
+pub mod envelope;
+pub mod events;
+pub mod extsort;
+pub mod feed;
 pub mod filter;
 pub mod formatter;
-pub mod parser;
-pub mod types;
+pub mod index;
+pub mod module;
+pub mod parallel;
+pub mod value;
+pub mod visitor;
 
-pub fn parse<R: Read, F: Formatter, T: Filter>(input: R, formatter: F, filter: T) -> RdbOk {
+/// Parses `input`, passing everything matching `filter` to `formatter`, and
+/// returns what was read.
+pub fn parse<R: Read, F: Formatter, T: Filter>(
+    input: R,
+    formatter: F,
+    filter: T,
+) -> RdbResult<RdbStats> {
     let mut parser = RdbParser::new(input, formatter, filter);
-    parser.parse()
+    parser.parse()?;
+    Ok(parser.stats().clone())
+}
+
+/// Like `parse`, but passes each value to `formatter` in one call, with all
+/// of its elements, see `formatter::Materialize`.
+pub fn parse_values<R: Read, F: ValueFormatter, T: Filter>(
+    input: R,
+    formatter: F,
+    filter: T,
+) -> RdbResult<RdbStats> {
+    parse(input, WithContext::new(Materialize::new(formatter)), filter)
+}
+
+/// Like `parse`, but passes the input through `unwrapper` first, e.g. to
+/// decrypt it.
+pub fn parse_unwrapped<R: Read + 'static, U: KeyUnwrapper, F: Formatter, T: Filter>(
+    input: R,
+    unwrapper: U,
+    formatter: F,
+    filter: T,
+) -> RdbResult<RdbStats> {
+    let input = unwrapper.unwrap_key(Box::new(input))?;
+    parse(input, formatter, filter)
 }
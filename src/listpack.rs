@@ -0,0 +1,219 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::Cursor;
+use std::str;
+
+use crate::helper::read_bounded;
+use crate::types::{RdbError, RdbResult, ZiplistEntry};
+
+const END: u8 = 0xFF;
+/// Number of elements stored when the listpack holds too many to count in the header.
+const UNKNOWN_LENGTH: u16 = u16::MAX;
+
+#[inline]
+fn other_error(desc: impl Into<String>) -> RdbError {
+    RdbError::Other(desc.into())
+}
+
+/// Reads a single entry from a listpack, or `None` at its end byte.
+pub(crate) fn read_entry(listpack: &mut Cursor<Vec<u8>>) -> RdbResult<Option<ZiplistEntry>> {
+    let enc = listpack.read_u8()?;
+
+    let (entry, size) = if enc & 0x80 == 0 {
+        (ZiplistEntry::Number((enc & 0x7F) as i64), 1)
+    } else if enc & 0xC0 == 0x80 {
+        let len = (enc & 0x3F) as usize;
+        (
+            ZiplistEntry::String(read_bounded(listpack, len as u64, "listpack")?),
+            1 + len,
+        )
+    } else if enc & 0xE0 == 0xC0 {
+        let value = (((enc & 0x1F) as u16) << 8) | listpack.read_u8()? as u16;
+        // Sign extend from 13 bits.
+        let value = ((value << 3) as i16 >> 3) as i64;
+        (ZiplistEntry::Number(value), 2)
+    } else if enc & 0xF0 == 0xE0 {
+        let len = (((enc & 0x0F) as usize) << 8) | listpack.read_u8()? as usize;
+        (
+            ZiplistEntry::String(read_bounded(listpack, len as u64, "listpack")?),
+            2 + len,
+        )
+    } else {
+        match enc {
+            0xF0 => {
+                let len = listpack.read_u32::<LittleEndian>()? as usize;
+                (
+                    ZiplistEntry::String(read_bounded(listpack, len as u64, "listpack")?),
+                    5 + len,
+                )
+            }
+            0xF1 => (
+                ZiplistEntry::Number(listpack.read_i16::<LittleEndian>()? as i64),
+                3,
+            ),
+            0xF2 => (
+                ZiplistEntry::Number(listpack.read_i24::<LittleEndian>()? as i64),
+                4,
+            ),
+            0xF3 => (
+                ZiplistEntry::Number(listpack.read_i32::<LittleEndian>()? as i64),
+                5,
+            ),
+            0xF4 => (
+                ZiplistEntry::Number(listpack.read_i64::<LittleEndian>()?),
+                9,
+            ),
+            END => return Ok(None),
+            _ => return Err(other_error(format!("Unknown listpack encoding: {}", enc))),
+        }
+    };
+
+    // Every entry ends with its own length, for walking the listpack backwards.
+    let backlen = match size {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    };
+    read_bounded(listpack, backlen, "listpack")?;
+
+    Ok(Some(entry))
+}
+
+/// A decoded listpack blob, read entry by entry.
+///
+/// Listpacks replace ziplists from Redis 7.0 on. Unlike ziplist entries,
+/// each entry stores its own length at its end instead of the length of the
+/// previous one, and the header has no offset of the last entry.
+pub(crate) struct Listpack {
+    reader: Cursor<Vec<u8>>,
+    total_bytes: u32,
+    num_elements: u16,
+    entries: u32,
+    finished: bool,
+}
+
+impl Listpack {
+    pub(crate) fn new(listpack: Vec<u8>) -> RdbResult<Listpack> {
+        let mut reader = Cursor::new(listpack);
+        let total_bytes = reader.read_u32::<LittleEndian>()?;
+        let num_elements = reader.read_u16::<LittleEndian>()?;
+
+        Ok(Listpack {
+            reader,
+            total_bytes,
+            num_elements,
+            entries: 0,
+            finished: false,
+        })
+    }
+
+    /// The number of bytes not read yet.
+    pub(crate) fn remaining(&self) -> u64 {
+        (self.reader.get_ref().len() as u64).saturating_sub(self.reader.position())
+    }
+
+    /// The size of the serialized listpack in bytes.
+    pub(crate) fn raw_length(&self) -> u64 {
+        self.reader.get_ref().len() as u64
+    }
+
+    /// The number of entries, as stored in the header.
+    pub(crate) fn header_length(&self) -> u32 {
+        self.num_elements as u32
+    }
+
+    /// The number of entries, counted if there are too many for the header.
+    ///
+    /// Must be called before reading entries.
+    pub(crate) fn count(&self) -> RdbResult<u32> {
+        if self.num_elements != UNKNOWN_LENGTH {
+            return Ok(self.num_elements as u32);
+        }
+
+        let mut listpack = Listpack::new(self.reader.get_ref().clone())?;
+        while listpack.next_entry()?.is_some() {}
+        Ok(listpack.entries)
+    }
+
+    /// Returns the next entry or `None` once the end byte is reached.
+    pub(crate) fn next_entry(&mut self) -> RdbResult<Option<ZiplistEntry>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        match read_entry(&mut self.reader)? {
+            Some(entry) => {
+                self.entries += 1;
+                Ok(Some(entry))
+            }
+            None => {
+                self.finished = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Like `next_entry`, but formats numbers as strings.
+    pub(crate) fn next_string(&mut self) -> RdbResult<Option<Vec<u8>>> {
+        Ok(self.next_entry()?.map(|entry| match entry {
+            ZiplistEntry::String(val) => val,
+            ZiplistEntry::Number(val) => val.to_string().into_bytes(),
+        }))
+    }
+
+    /// Reads the next entry as a string, failing at the end of the listpack.
+    pub(crate) fn expect_string(&mut self) -> RdbResult<Vec<u8>> {
+        self.next_string()?
+            .ok_or_else(|| other_error("Unexpected end of listpack"))
+    }
+
+    /// Reads the next entry as a number, failing at the end of the listpack.
+    pub(crate) fn expect_number(&mut self) -> RdbResult<i64> {
+        match self.next_entry()? {
+            Some(ZiplistEntry::Number(value)) => Ok(value),
+            Some(ZiplistEntry::String(value)) => str::from_utf8(&value)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| other_error("Expected a number in listpack")),
+            None => Err(other_error("Unexpected end of listpack")),
+        }
+    }
+
+    /// Checks the redundant header fields against the entries read so far.
+    ///
+    /// Must only be called after all entries were read.
+    pub(crate) fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let raw_length = self.raw_length();
+        if self.total_bytes as u64 != raw_length {
+            problems.push(format!(
+                "listpack total bytes is {}, but the listpack is {} bytes long",
+                self.total_bytes, raw_length
+            ));
+        }
+
+        let counted_matches = if self.num_elements == UNKNOWN_LENGTH {
+            self.entries >= UNKNOWN_LENGTH as u32
+        } else {
+            self.entries == self.num_elements as u32
+        };
+        if !counted_matches {
+            problems.push(format!(
+                "listpack number of elements is {}, but it contains {} entries",
+                self.num_elements, self.entries
+            ));
+        }
+
+        let trailing = raw_length.saturating_sub(self.reader.position());
+        if trailing > 0 {
+            problems.push(format!(
+                "listpack has {} trailing bytes after the end byte",
+                trailing
+            ));
+        }
+
+        problems
+    }
+}
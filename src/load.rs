@@ -0,0 +1,91 @@
+//! Loading a whole dump into memory.
+//!
+//! `rdb::load` returns every database of a dump with its keys, for callers
+//! that just want the data:
+//!
+//! ```rust,no_run
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! # fn main() -> rdb::RdbResult<()> {
+//! let file = BufReader::new(File::open("dump.rdb")?);
+//! for database in rdb::load(file)? {
+//!     for (key, entry) in &database.keys {
+//!         println!("{}: {:?} {:?}", database.index, key, entry.value);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use serde::{Deserialize, Serialize};
+
+use crate::filter::{Filter, Simple};
+use crate::formatter::{KeyContext, ValueFormatter};
+use crate::types::{EncodingType, RdbResult, Value};
+
+/// A database of a dump.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Database {
+    pub index: u32,
+    pub keys: HashMap<Vec<u8>, Entry>,
+}
+
+/// The value of a key, with its metadata.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub value: Value,
+    /// Unix time in milliseconds the key expires at.
+    pub expiry: Option<u64>,
+    pub encoding: Option<EncodingType>,
+}
+
+/// Reads all databases of the dump in `input`, in the order of the dump.
+///
+/// Keys without a value, see `formatter::Materialize`, are left out.
+pub fn load<R: Read>(input: R) -> RdbResult<Vec<Database>> {
+    load_with_filter(input, Simple::new())
+}
+
+/// Like `load`, but only with the databases and keys matching `filter`.
+pub fn load_with_filter<R: Read, L: Filter>(input: R, filter: L) -> RdbResult<Vec<Database>> {
+    let mut databases = Load(Vec::new());
+    crate::parse_values(input, &mut databases, filter)?;
+    Ok(databases.0)
+}
+
+struct Load(Vec<Database>);
+
+impl Load {
+    fn database(&mut self, index: u32) -> &mut Database {
+        if self.0.last().map(|database| database.index) != Some(index) {
+            self.0.push(Database {
+                index,
+                keys: HashMap::new(),
+            });
+        }
+        self.0.last_mut().unwrap()
+    }
+}
+
+impl ValueFormatter for &mut Load {
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.database(db_index);
+        Ok(())
+    }
+
+    fn value(&mut self, context: &KeyContext, value: Value) -> RdbResult<()> {
+        let entry = Entry {
+            value,
+            expiry: context.expiry,
+            encoding: context.encoding,
+        };
+        self.database(context.db)
+            .keys
+            .insert(context.key.clone(), entry);
+        Ok(())
+    }
+}
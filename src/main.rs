@@ -1,27 +1,1938 @@
-use getopts::Options;
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::cli::anomaly::Anomalies;
+use crate::cli::diff::Diff;
+use crate::cli::expiry::Expiries;
+use crate::cli::health::{self, Condition, Finding};
+use crate::cli::lag::Lag;
+use crate::cli::lineage;
+use crate::cli::manifest::Manifest;
+use crate::cli::memory::{Allocator, Memory, MemoryProfile};
+use crate::cli::quota::Limits;
+use crate::cli::restore::Restore;
+use crate::cli::split::Split;
+use chrono::Utc;
+use getopts::{Matches, Options};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use rdb::capabilities::Capabilities;
+use rdb::envelope::{AesGcm, KeyEncoding, KeyUnwrapper};
+use rdb::filter::Simple;
+use rdb::formatter::folded::Weight;
+use rdb::formatter::protocol::Flush;
+#[cfg(feature = "parquet")]
+use rdb::formatter::Parquet;
+use rdb::formatter::{
+    time, Crlf, Digest, Dot, FlushPolicy, Folded, Formatter, JsonLines, Prometheus, Protocol,
+    Sorted, Statistics, Table, Tee, Template, TimeFormat, Zone,
+};
+use rdb::index::Index;
+use rdb::parallel::ParallelParser;
+use rdb::{Header, RdbError, RdbOk, RdbParser, RdbResult, Warning};
 use regex::bytes::Regex;
 use std::env;
-use std::fs::File;
-use std::io::{BufReader, Write};
-use std::path::Path;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
 
-fn print_usage(program: &str, opts: Options) {
-    let brief = format!("Usage: {} [options] dump.rdb", program);
-    print!("{}", opts.usage(&brief));
+mod cli;
+
+/// Exit status of `diff` if the dumps differ and of `assert` if an
+/// expectation was not met.
+const MISMATCH_STATUS: i32 = 1;
+/// Exit status for invalid arguments and files that can't be opened.
+const USAGE_STATUS: i32 = 2;
+/// Exit status of `sync` if the target rejected commands.
+const REJECTED_STATUS: i32 = 6;
+/// Exit status of a process killed by SIGPIPE (128 + 13).
+const BROKEN_PIPE_STATUS: i32 = 141;
+
+/// Like `print!`, but returns the error of writing to stdout instead of
+/// panicking, so a reader closing the pipe early ends the program quietly.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        write!(io::stdout().lock(), $($arg)*)
+    };
+}
+
+/// Like `println!`, see `out!`.
+macro_rules! outln {
+    () => {
+        writeln!(io::stdout().lock())
+    };
+    ($($arg:tt)*) => {
+        writeln!(io::stdout().lock(), $($arg)*)
+    };
+}
+
+fn add_fail_on_option(opts: &mut Options, default: &str) {
+    opts.optopt(
+        "",
+        "fail-on",
+        &format!(
+            "Comma-separated conditions that fail the run: warnings, corrupt, empty, quirks (default: {})",
+            default
+        ),
+        "CONDITIONS",
+    );
+}
+
+fn fail_on(matches: &Matches, default: &str) -> Result<Vec<Condition>, String> {
+    health::conditions(
+        &matches
+            .opt_str("fail-on")
+            .unwrap_or_else(|| default.to_owned()),
+    )
+}
+
+/// Parses a file in strict mode and returns the problems found, each
+/// message prefixed with the path.
+fn inspect(path: &Path, lenient: bool, tolerant: bool) -> Result<Vec<Finding>, io::Error> {
+    let file = File::open(path)?;
+    let mut findings = health::inspect(BufReader::new(file), lenient, tolerant);
+    for finding in &mut findings {
+        finding.message = format!("{}: {}", path.display(), finding.message);
+    }
+    Ok(findings)
+}
+
+enum Input<R: Read> {
+    Sequential(R),
+    /// Read sequentially, only measuring the lengths of values, skipping
+    /// module values their decoder rejects in lenient mode and reporting
+    /// HyperLogLogs and bitmaps instead of their strings, and printing what
+    /// was read in the end if `summary` is set.
+    Options {
+        reader: R,
+        lengths_only: bool,
+        lenient: bool,
+        hyperloglogs: bool,
+        bitmap_keys: Option<Regex>,
+        summary: bool,
+    },
+    Parallel {
+        path: PathBuf,
+        jobs: usize,
+        lenient: bool,
+        hyperloglogs: bool,
+        bitmap_keys: Option<Regex>,
+        progress_bar: ProgressBar,
+    },
+}
+
+fn print_warnings(warnings: &[Warning]) {
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+}
+
+fn parse<R: Read, F: Formatter>(input: Input<R>, formatter: F, filter: Simple) -> RdbOk {
+    match input {
+        Input::Sequential(reader) => rdb::parse(reader, formatter, filter).map(|_| ()),
+        Input::Options {
+            reader,
+            lengths_only,
+            lenient,
+            hyperloglogs,
+            bitmap_keys,
+            summary,
+        } => {
+            let mut parser = RdbParser::new(reader, formatter, filter);
+            parser.set_lengths_only(lengths_only);
+            parser.set_lenient(lenient);
+            parser.set_hyperloglogs(hyperloglogs);
+            if let Some(keys) = bitmap_keys {
+                parser.set_bitmap_keys(keys);
+            }
+            let res = parser.parse();
+            print_warnings(parser.warnings());
+            if summary {
+                eprintln!("{}", parser.stats());
+            }
+            res
+        }
+        Input::Parallel {
+            path,
+            jobs,
+            lenient,
+            hyperloglogs,
+            bitmap_keys,
+            progress_bar,
+        } => {
+            let progress = |bytes| progress_bar.inc(bytes);
+            let mut parser = ParallelParser::new(path, formatter, filter);
+            parser.set_jobs(jobs);
+            parser.set_lenient(lenient);
+            parser.set_hyperloglogs(hyperloglogs);
+            if let Some(keys) = bitmap_keys {
+                parser.set_bitmap_keys(keys);
+            }
+            parser.set_progress(&progress);
+            let res = parser.parse();
+            print_warnings(parser.warnings());
+            res
+        }
+    }
+}
+
+/// Parses into `formatter`, passing it the keys in canonical order if
+/// `sorted` holds the memory budget of the sort.
+fn parse_in_order<R: Read, F: Formatter>(
+    input: Input<R>,
+    formatter: F,
+    filter: Simple,
+    sorted: Option<usize>,
+) -> RdbOk {
+    match sorted {
+        Some(budget) => {
+            let mut sorted = Sorted::new(formatter);
+            sorted.set_memory_budget(budget);
+            parse(input, sorted, filter)
+        }
+        None => parse(input, formatter, filter),
+    }
+}
+
+/// Whether `stream` is a terminal rendering ANSI colors. Consoles without
+/// ANSI support, like older Windows ones, don't set `TERM`, while Windows
+/// Terminal sets `WT_SESSION`.
+fn ansi(stream: impl IsTerminal) -> bool {
+    if !stream.is_terminal() || env::var_os("NO_COLOR").is_some() {
+        return false;
+    }
+    match env::var("TERM") {
+        Ok(term) => term != "dumb",
+        Err(_) => env::var_os("WT_SESSION").is_some(),
+    }
+}
+
+/// A progress bar for reading `length` bytes.
+fn progress_bar(length: u64) -> ProgressBar {
+    let template = if ansi(io::stderr()) {
+        "[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+    } else {
+        "[{elapsed_precise}] {bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+    };
+    let progress_bar = ProgressBar::new(length);
+    progress_bar.set_style(ProgressStyle::default_bar().template(template));
+    progress_bar
+}
+
+fn print_usage(program: &str, opts: Options) -> io::Result<()> {
+    let brief = format!(
+        "Usage: {0} [options] dump.rdb\n       {0} check [options] dump.rdb...\n       {0} info [options] dump.rdb...\n       {0} lineage [options] dump.rdb...\n       {0} assert --manifest FILE dump.rdb...\n       {0} quota --limits FILE dump.rdb...\n       {0} lag [options] dump.rdb...\n       {0} expiries [options] dump.rdb...\n       {0} memory [options] dump.rdb...\n       {0} anomalies [options] dump.rdb...\n       {0} diff [options] left.rdb right.rdb\n       {0} index --output FILE dump.rdb\n       {0} delta --since FILE [options] dump.rdb\n       {0} sync --target URL [options] old.idx new.rdb\n       {0} split [--by-prefix REGEX] --output TEMPLATE [options] dump.rdb\n       {0} etl --analytics FILE --restore FILE [options] dump.rdb\n       {0} capabilities [--json]",
+        program
+    );
+    out!("{}", opts.usage(&brief))
+}
+
+/// The subcommands of the binary, besides dumping a file.
+const COMMANDS: &[&str] = &[
+    "check",
+    "info",
+    "lineage",
+    "assert",
+    "quota",
+    "lag",
+    "expiries",
+    "memory",
+    "anomalies",
+    "diff",
+    "index",
+    "delta",
+    "sync",
+    "split",
+    "etl",
+    "capabilities",
+];
+
+fn print_command_usage(program: &str, command: &str, opts: Options) -> io::Result<()> {
+    let brief = format!("Usage: {} {} [options] dump.rdb...", program, command);
+    out!("{}", opts.usage(&brief))
+}
+
+/// Returns once the usage was printed for `--help`, or exits with
+/// `USAGE_STATUS` if it was printed for missing operands.
+fn help_or_usage_error(matches: &Matches) -> io::Result<()> {
+    if !matches.opt_present("h") {
+        process::exit(USAGE_STATUS);
+    }
+    Ok(())
+}
+
+/// Arguments that are not valid Unicode, which getopts can't parse. It gets
+/// `"\0<index>"` placeholders instead, which `path_arg` resolves again, so
+/// file names are used exactly as given.
+static RAW_ARGS: OnceLock<Vec<OsString>> = OnceLock::new();
+
+/// The path named by a command line argument.
+fn path_arg<S: AsRef<str>>(arg: S) -> PathBuf {
+    let arg = arg.as_ref();
+    let raw = arg
+        .strip_prefix('\0')
+        .and_then(|index| index.parse::<usize>().ok())
+        .and_then(|index| RAW_ARGS.get()?.get(index));
+    match raw {
+        Some(raw) => PathBuf::from(raw),
+        None => PathBuf::from(arg),
+    }
+}
+
+pub fn main() {
+    let mut args = Vec::new();
+    let mut raw_args = Vec::new();
+    for arg in env::args_os() {
+        match arg.into_string() {
+            Ok(arg) => args.push(arg),
+            Err(arg) => {
+                args.push(format!("\0{}", raw_args.len()));
+                raw_args.push(arg);
+            }
+        }
+    }
+    RAW_ARGS.set(raw_args).unwrap();
+    let program = args.remove(0);
+
+    let res = match args.first().map(|arg| &arg[..]) {
+        Some("check") => check(&program, &args[1..]),
+        Some("info") => info(&program, &args[1..]),
+        Some("lineage") => lineage(&program, &args[1..]),
+        Some("assert") => assert(&program, &args[1..]),
+        Some("quota") => quota(&program, &args[1..]),
+        Some("lag") => lag(&program, &args[1..]),
+        Some("expiries") => expiries(&program, &args[1..]),
+        Some("memory") => memory(&program, &args[1..]),
+        Some("anomalies") => anomalies(&program, &args[1..]),
+        Some("diff") => diff(&program, &args[1..]),
+        Some("index") => index(&program, &args[1..]),
+        Some("delta") => delta(&program, &args[1..]),
+        Some("sync") => sync(&program, &args[1..]),
+        Some("split") => split(&program, &args[1..]),
+        Some("etl") => etl(&program, &args[1..]),
+        Some("capabilities") => capabilities(&program, &args[1..]),
+        _ => dump(&program, &args),
+    };
+
+    match res {
+        Ok(()) => {}
+        Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {
+            process::exit(BROKEN_PIPE_STATUS)
+        }
+        Err(err) => {
+            eprintln!("Writing the output failed: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// The dumps given to a subcommand that couldn't be read, to exit with the
+/// status of the most severe failure once all of them were tried.
+#[derive(Default)]
+struct Failures {
+    unreadable: bool,
+    corrupt: bool,
+}
+
+impl Failures {
+    /// Opens the dump at `path` and passes it to `parse`, returns whether
+    /// both succeeded and reports on stderr why not.
+    fn parse<T, F>(&mut self, path: &Path, parse: F) -> bool
+    where
+        F: FnOnce(BufReader<File>) -> RdbResult<T>,
+    {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                self.unreadable = true;
+                return false;
+            }
+        };
+        if let Err(err) = parse(BufReader::new(file)) {
+            eprintln!("{}: parsing failed: {}", path.display(), err);
+            self.corrupt = true;
+            return false;
+        }
+        true
+    }
+
+    /// Exits with `USAGE_STATUS` if a dump couldn't be opened, or with the
+    /// status of `Condition::Corrupt` if one couldn't be parsed.
+    fn exit(&self) {
+        if self.unreadable {
+            process::exit(USAGE_STATUS);
+        }
+        if self.corrupt {
+            process::exit(Condition::Corrupt.status());
+        }
+    }
+}
+
+/// Parses the given files in strict mode and lists all problems found.
+fn check(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    add_fail_on_option(&mut opts, "warnings,corrupt");
+    opts.optflag(
+        "",
+        "lenient",
+        "Skip module values their decoder rejects, reporting them as warnings",
+    );
+    opts.optflag(
+        "",
+        "tolerant",
+        "Accept AUX fields of managed services and bytes after the checksum, reporting them without failing",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "check", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "check", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let conditions = match fail_on(&matches, "warnings,corrupt") {
+        Ok(conditions) => conditions,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_command_usage(program, "check", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut unreadable = false;
+    let mut worst = None;
+    for path in matches.free.iter().map(path_arg) {
+        let lenient = matches.opt_present("lenient");
+        let findings = match inspect(&path, lenient, matches.opt_present("tolerant")) {
+            Ok(findings) => findings,
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                unreadable = true;
+                continue;
+            }
+        };
+
+        for finding in findings {
+            let failing = conditions.contains(&finding.condition);
+            // An empty dump is only a problem if asked for.
+            if failing || finding.condition != Condition::Empty {
+                outln!("{}", finding.message)?;
+            }
+            if failing {
+                worst = worst.into_iter().chain(Some(finding.condition)).min();
+            }
+        }
+    }
+
+    if unreadable {
+        process::exit(USAGE_STATUS);
+    }
+    if let Some(condition) = worst {
+        process::exit(condition.status());
+    }
+
+    Ok(())
+}
+
+/// Prints the metadata stored in the AUX fields of the given files.
+fn info(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    add_time_options(&mut opts);
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "info", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "info", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let time_format = match time_format(&matches) {
+        Ok(time_format) => time_format,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_command_usage(program, "info", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failed = false;
+    for (i, path) in matches.free.iter().map(path_arg).enumerate() {
+        let header = File::open(&path)
+            .map_err(RdbError::from)
+            .and_then(|file| rdb::read_header(BufReader::new(file)));
+        let header = match header {
+            Ok(header) => header,
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                failed = true;
+                continue;
+            }
+        };
+
+        if i > 0 {
+            outln!()?;
+        }
+        print_header(&path, &header, &time_format)?;
+    }
+
+    if failed {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn print_header(path: &Path, header: &Header, time_format: &TimeFormat) -> io::Result<()> {
+    outln!("{}", path.display())?;
+    outln!("  rdb-version:    {}", header.rdb_version)?;
+    if let Some(version) = header.redis_version {
+        outln!("  redis-ver:      {}", version)?;
+    }
+    if let Some(bits) = header.redis_bits {
+        outln!("  redis-bits:     {}", bits)?;
+    }
+    if let Some(ctime) = header.ctime {
+        outln!("  ctime:          {}", time_format.render(ctime))?;
+    }
+    if let Some(used_mem) = header.used_mem {
+        outln!("  used-mem:       {}", HumanBytes(used_mem))?;
+    }
+    if let Some(db) = header.repl_stream_db {
+        outln!("  repl-stream-db: {}", db)?;
+    }
+    if let Some(ref repl_id) = header.repl_id {
+        outln!("  repl-id:        {}", repl_id)?;
+    }
+    if let Some(offset) = header.repl_offset {
+        outln!("  repl-offset:    {}", offset)?;
+    }
+    if let Some(aof_base) = header.aof_base {
+        outln!("  aof-base:       {}", aof_base)?;
+    }
+    for (key, value) in &header.other {
+        outln!(
+            "  {:<15} {}",
+            format!("{}:", String::from_utf8_lossy(key)),
+            String::from_utf8_lossy(value)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Groups the given files by replication ID and orders each group by
+/// replication offset, so the most recent usable backup is listed last.
+fn lineage(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    add_time_options(&mut opts);
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "lineage", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "lineage", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let time_format = match time_format(&matches) {
+        Ok(time_format) => time_format,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_command_usage(program, "lineage", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failed = false;
+    let mut backups = Vec::new();
+    for path in matches.free.iter().map(path_arg) {
+        let header = File::open(&path)
+            .map_err(RdbError::from)
+            .and_then(|file| rdb::read_header(BufReader::new(file)));
+        match header {
+            Ok(header) => backups.push((path, header)),
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                failed = true;
+            }
+        }
+    }
+
+    for (i, history) in lineage::histories(backups).iter().enumerate() {
+        if i > 0 {
+            outln!()?;
+        }
+        match &history.repl_id {
+            Some(repl_id) => outln!("repl-id {}", repl_id)?,
+            None => outln!("no repl-id")?,
+        }
+
+        for (j, (path, header)) in history.backups.iter().enumerate() {
+            let offset = header
+                .repl_offset
+                .map_or_else(|| "-".to_owned(), |offset| offset.to_string());
+            let ctime = header
+                .ctime
+                .map_or_else(|| "-".to_owned(), |ctime| time_format.render(ctime));
+            let latest = if j + 1 == history.backups.len() {
+                "  (latest)"
+            } else {
+                ""
+            };
+            outln!(
+                "  {:>14}  {:<23}  {}{}",
+                offset,
+                ctime,
+                path.display(),
+                latest
+            )?;
+        }
+    }
+
+    if failed {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Verifies that the given files contain the keys declared in a manifest.
+fn assert(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "m",
+        "manifest",
+        "YAML file declaring the expected keys",
+        "FILE",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "assert", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let manifest_path = match matches.opt_str("m") {
+        Some(path) if !matches.opt_present("h") && !matches.free.is_empty() => path_arg(path),
+        _ => {
+            print_command_usage(program, "assert", opts)?;
+            return help_or_usage_error(&matches);
+        }
+    };
+
+    let manifest = File::open(&manifest_path)
+        .map_err(RdbError::from)
+        .and_then(|file| Manifest::from_reader(BufReader::new(file)));
+    let manifest = match manifest {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("{}: {}", manifest_path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failed = false;
+    let mut failures = Failures::default();
+    for path in matches.free.iter().map(path_arg) {
+        let mut checker = manifest.checker();
+        let parsed = failures.parse(&path, |input| {
+            rdb::parse(input, &mut checker, Simple::new())
+        });
+        if !parsed {
+            continue;
+        }
+
+        let violations = checker.violations();
+        for violation in &violations {
+            outln!("{}: {}", path.display(), violation)?;
+        }
+        failed |= !violations.is_empty();
+    }
+
+    failures.exit();
+    if failed {
+        process::exit(MISMATCH_STATUS);
+    }
+
+    Ok(())
+}
+
+/// Reports the usage of each tenant of the given files against its quota.
+fn quota(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "l",
+        "limits",
+        "YAML file mapping key prefixes to key and byte quotas",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "lengths-only",
+        "Skip values, only measuring their lengths. Encoded values count with their encoded size",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "quota", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let limits_path = match matches.opt_str("l") {
+        Some(path) if !matches.opt_present("h") && !matches.free.is_empty() => path_arg(path),
+        _ => {
+            print_command_usage(program, "quota", opts)?;
+            return help_or_usage_error(&matches);
+        }
+    };
+
+    let limits = File::open(&limits_path)
+        .map_err(RdbError::from)
+        .and_then(|file| Limits::from_reader(BufReader::new(file)));
+    let limits = match limits {
+        Ok(limits) => limits,
+        Err(err) => {
+            eprintln!("{}: {}", limits_path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failed = false;
+    let mut failures = Failures::default();
+    for path in matches.free.iter().map(path_arg) {
+        let mut usage = limits.usage();
+        let parsed = failures.parse(&path, |input| {
+            let mut parser = RdbParser::new(input, &mut usage, Simple::new());
+            parser.set_lengths_only(matches.opt_present("lengths-only"));
+            parser.parse()
+        });
+        if !parsed {
+            continue;
+        }
+
+        for tenant in usage.report() {
+            outln!("{}: {}", path.display(), tenant)?;
+            failed |= tenant.exceeded();
+        }
+    }
+
+    failures.exit();
+    if failed {
+        process::exit(MISMATCH_STATUS);
+    }
+
+    Ok(())
+}
+
+/// Reports the backlog of each consumer group of the streams in the given
+/// files.
+fn lag(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "k",
+        "keys",
+        "Streams to report. Can be a regular expression",
+        "KEYS",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "lag", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "lag", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let keys = match matches.opt_str("k").map(|k| Regex::new(&k)).transpose() {
+        Ok(keys) => keys,
+        Err(err) => {
+            outln!("Incorrect regexp: {:?}\n", err)?;
+            print_command_usage(program, "lag", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failures = Failures::default();
+    for path in matches.free.iter().map(path_arg) {
+        let mut filter = Simple::new();
+        filter.add_type(rdb::Type::Stream);
+        if let Some(keys) = &keys {
+            filter.add_keys(keys.clone());
+        }
+
+        let mut lag = Lag::new();
+        let parsed = failures.parse(&path, |input| {
+            RdbParser::new(input, &mut lag, filter).parse()
+        });
+        if !parsed {
+            continue;
+        }
+
+        for group in lag.report() {
+            outln!("{}: {}", path.display(), group)?;
+        }
+    }
+
+    failures.exit();
+
+    Ok(())
+}
+
+/// Prints a histogram of when the keys of the given files expire, marking
+/// the windows in which unusually many expire at once.
+fn expiries(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "w",
+        "window",
+        "Length of the windows in seconds (default: 60)",
+        "SECONDS",
+    );
+    opts.optopt(
+        "",
+        "factor",
+        "How many times the mean a window must hold to be a storm (default: 10)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "top",
+        "Number of prefixes shown per storm (default: 3)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "separator",
+        "Character the prefix of a key ends at (default: ':')",
+        "SEP",
+    );
+    opts.optopt(
+        "",
+        "weight",
+        "What storms are detected by. Valid: count, bytes (default: count)",
+        "WEIGHT",
+    );
+    opts.optflag(
+        "",
+        "lengths-only",
+        "Skip values, only measuring their lengths. Encoded values count with their encoded size",
+    );
+    add_time_options(&mut opts);
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "expiries", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "expiries", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let settings = time_format(&matches).and_then(|time_format| {
+        let window: u64 = match matches.opt_str("window") {
+            Some(window) => window
+                .parse()
+                .map_err(|err| format!("Invalid window: {}", err))?,
+            None => 60,
+        };
+        Ok((time_format, window))
+    });
+    let (time_format, window) = match settings {
+        Ok(settings) => settings,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_command_usage(program, "expiries", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failures = Failures::default();
+    for path in matches.free.iter().map(path_arg) {
+        let mut expiries = match expiries_formatter(&matches, window) {
+            Ok(expiries) => expiries,
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_command_usage(program, "expiries", opts)?;
+                process::exit(USAGE_STATUS);
+            }
+        };
+        let parsed = failures.parse(&path, |input| {
+            let mut parser = RdbParser::new(input, &mut expiries, Simple::new());
+            parser.set_lengths_only(matches.opt_present("lengths-only"));
+            parser.parse()
+        });
+        if !parsed {
+            continue;
+        }
+
+        let report = expiries.report();
+        let bytes = matches.opt_str("weight").as_deref() == Some("bytes");
+        let weight = |keys: u64, size: u64| if bytes { size } else { keys };
+        let max = report
+            .iter()
+            .map(|window| weight(window.keys, window.bytes))
+            .max()
+            .unwrap_or(0);
+        outln!("{}", path.display())?;
+        for window in report {
+            let bar = weight(window.keys, window.bytes) * 40 / max.max(1);
+            out!(
+                "  {}  {:>8} keys  {:>10}  {:<40}",
+                time_format.render_millis(window.start),
+                window.keys,
+                HumanBytes(window.bytes).to_string(),
+                "#".repeat(bar.max(1) as usize)
+            )?;
+            if window.storm {
+                let prefixes: Vec<_> = window
+                    .prefixes
+                    .iter()
+                    .map(|(prefix, weight)| format!("{:?} {}", prefix, weight))
+                    .collect();
+                out!("  storm: {}", prefixes.join(", "))?;
+            }
+            outln!()?;
+        }
+    }
+
+    failures.exit();
+
+    Ok(())
+}
+
+fn expiries_formatter(matches: &Matches, window: u64) -> Result<Expiries, String> {
+    let mut expiries = Expiries::new(window.saturating_mul(1000));
+
+    if let Some(separator) = matches.opt_str("separator") {
+        match separator.as_bytes() {
+            &[separator] => expiries.set_separator(separator),
+            _ => return Err(format!("Invalid separator: {:?}", separator)),
+        }
+    }
+
+    if let Some(factor) = matches.opt_str("factor") {
+        let factor = factor
+            .parse()
+            .map_err(|err| format!("Invalid factor: {}", err))?;
+        expiries.set_factor(factor);
+    }
+
+    if let Some(top) = matches.opt_str("top") {
+        let top = top.parse().map_err(|err| format!("Invalid top: {}", err))?;
+        expiries.set_top(top);
+    }
+
+    match matches.opt_str("weight").as_deref() {
+        None | Some("count") => {}
+        Some("bytes") => expiries.set_weight(Weight::Bytes),
+        Some(weight) => return Err(format!("Unknown weight: {}", weight)),
+    }
+
+    Ok(expiries)
+}
+
+/// Estimates the memory the keys of the given files need per type and
+/// prefix, including the fragmentation from jemalloc's size classes.
+fn memory(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "",
+        "separator",
+        "Character the prefix of a key ends at (default: ':')",
+        "SEP",
+    );
+    opts.optflag(
+        "",
+        "size-classes",
+        "Also list the allocations of each size class",
+    );
+    opts.optopt(
+        "",
+        "redis-version",
+        "Model this Redis version instead of the one the dump was written by, e.g. 3.0",
+        "VERSION",
+    );
+    opts.optopt(
+        "",
+        "bits",
+        "Model a 32 or 64 bit build instead of the one the dump was written by",
+        "BITS",
+    );
+    opts.optopt(
+        "",
+        "allocator",
+        "Allocator Redis was built with. Valid: jemalloc, libc (default: jemalloc)",
+        "ALLOCATOR",
+    );
+    opts.optopt(
+        "",
+        "load-factor",
+        "Average number of elements per hash table bucket (default: 1)",
+        "N",
+    );
+    opts.optflag(
+        "",
+        "lengths-only",
+        "Skip values, only measuring their lengths. Values count as a single allocation",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "memory", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "memory", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let separator = match matches.opt_str("separator").as_deref().map(str::as_bytes) {
+        None => b':',
+        Some(&[separator]) => separator,
+        Some(separator) => {
+            outln!(
+                "Invalid separator: {:?}\n",
+                String::from_utf8_lossy(separator)
+            )?;
+            print_command_usage(program, "memory", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut failures = Failures::default();
+    for path in matches.free.iter().map(path_arg) {
+        let mut memory = match memory_formatter(&matches) {
+            Ok(memory) => memory,
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_command_usage(program, "memory", opts)?;
+                process::exit(USAGE_STATUS);
+            }
+        };
+        memory.set_separator(separator);
+        let parsed = failures.parse(&path, |input| {
+            let mut parser = RdbParser::new(input, &mut memory, Simple::new());
+            parser.set_lengths_only(matches.opt_present("lengths-only"));
+            parser.parse()
+        });
+        if !parsed {
+            continue;
+        }
+
+        for usage in memory.report() {
+            outln!(
+                "{}: {} {:?}: {} keys, {} requested, {} allocated, {:.1}% fragmentation",
+                path.display(),
+                usage.typ.name(),
+                usage.prefix,
+                usage.keys,
+                HumanBytes(usage.requested),
+                HumanBytes(usage.allocated),
+                usage.fragmentation() * 100.0
+            )?;
+        }
+
+        if matches.opt_present("size-classes") {
+            for class in memory.size_classes() {
+                outln!(
+                    "{}: size class {}: {} allocations, {} requested",
+                    path.display(),
+                    class.size,
+                    class.allocations,
+                    HumanBytes(class.requested)
+                )?;
+            }
+        }
+    }
+
+    failures.exit();
+
+    Ok(())
+}
+
+fn memory_formatter(matches: &Matches) -> Result<Memory, String> {
+    let mut memory = Memory::new();
+
+    let version = matches.opt_str("redis-version");
+    let bits = matches.opt_str("bits");
+    if version.is_some() || bits.is_some() {
+        let version = version.as_deref().unwrap_or("7.0");
+        let mut profile = MemoryProfile::redis(version)
+            .ok_or_else(|| format!("Unsupported Redis version: {}", version))?;
+        profile.pointer_size = match bits.as_deref() {
+            None | Some("64") => 8,
+            Some("32") => 4,
+            Some(bits) => return Err(format!("Invalid bits: {}", bits)),
+        };
+        memory.set_profile(profile);
+    }
+
+    match matches.opt_str("allocator").as_deref() {
+        None | Some("jemalloc") => {}
+        Some("libc") => memory.set_allocator(Allocator::Libc),
+        Some(allocator) => return Err(format!("Unknown allocator: {}", allocator)),
+    }
+
+    if let Some(load_factor) = matches.opt_str("load-factor") {
+        let load_factor: f64 = load_factor
+            .parse()
+            .map_err(|err| format!("Invalid load factor: {}", err))?;
+        if load_factor <= 0.0 {
+            return Err(format!("Invalid load factor: {}", load_factor));
+        }
+        memory.set_load_factor(load_factor);
+    }
+
+    Ok(memory)
+}
+
+/// Flags suspicious key patterns in the given files: unbounded key names,
+/// cache keys without an expiry and keys holding much of the memory.
+fn anomalies(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "",
+        "separator",
+        "Character the segments of a key end at (default: ':')",
+        "SEP",
+    );
+    opts.optopt(
+        "",
+        "min-keys",
+        "Number of keys a pattern with UUIDs, timestamps or hashes needs to be flagged (default: 10000)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "max-share",
+        "Percentage of the estimated memory a single key may hold (default: 10)",
+        "PERCENT",
+    );
+    opts.optmulti(
+        "",
+        "cache-prefix",
+        "Prefix of keys that should expire. Can be given multiple times (default: cache, session, sessions, sess, tmp, temp)",
+        "PREFIX",
+    );
+    opts.optflag(
+        "",
+        "lengths-only",
+        "Skip values, only measuring their lengths. Values count as a single allocation",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "anomalies", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_command_usage(program, "anomalies", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let mut failed = false;
+    let mut failures = Failures::default();
+    for path in matches.free.iter().map(path_arg) {
+        let mut anomalies = match anomalies_formatter(&matches) {
+            Ok(anomalies) => anomalies,
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_command_usage(program, "anomalies", opts)?;
+                process::exit(USAGE_STATUS);
+            }
+        };
+        let parsed = failures.parse(&path, |input| {
+            let mut parser = RdbParser::new(input, &mut anomalies, Simple::new());
+            parser.set_lengths_only(matches.opt_present("lengths-only"));
+            parser.parse()
+        });
+        if !parsed {
+            continue;
+        }
+
+        for anomaly in anomalies.report() {
+            outln!("{}: {}", path.display(), anomaly)?;
+            failed = true;
+        }
+    }
+
+    failures.exit();
+    if failed {
+        process::exit(MISMATCH_STATUS);
+    }
+
+    Ok(())
+}
+
+fn anomalies_formatter(matches: &Matches) -> Result<Anomalies, String> {
+    let mut anomalies = Anomalies::new();
+
+    match matches.opt_str("separator").as_deref().map(str::as_bytes) {
+        None => {}
+        Some(&[separator]) => anomalies.set_separator(separator),
+        Some(separator) => {
+            return Err(format!(
+                "Invalid separator: {:?}",
+                String::from_utf8_lossy(separator)
+            ))
+        }
+    }
+
+    if let Some(min_keys) = matches.opt_str("min-keys") {
+        let min_keys = min_keys
+            .parse()
+            .map_err(|err| format!("Invalid number of keys: {}", err))?;
+        anomalies.set_min_keys(min_keys);
+    }
+
+    if let Some(max_share) = matches.opt_str("max-share") {
+        let max_share: f64 = max_share
+            .parse()
+            .map_err(|err| format!("Invalid percentage: {}", err))?;
+        if !(0.0..=100.0).contains(&max_share) {
+            return Err(format!("Invalid percentage: {}", max_share));
+        }
+        anomalies.set_max_share(max_share / 100.0);
+    }
+
+    let prefixes = matches.opt_strs("cache-prefix");
+    if !prefixes.is_empty() {
+        anomalies.set_cache_prefixes(prefixes.into_iter().map(String::into_bytes).collect());
+    }
+
+    Ok(anomalies)
+}
+
+/// The formats `etl` can write the analytics file in.
+const ANALYTICS_FORMATS: &[&str] = &[
+    "jsonl",
+    #[cfg(feature = "parquet")]
+    "parquet",
+];
+
+/// Writes the keys of a file selected by the filter options both as JSON
+/// Lines or Parquet, for analytics, and as Redis protocol, to restore them,
+/// in a single pass.
+fn etl(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    add_filter_options(&mut opts);
+    opts.optopt(
+        "",
+        "analytics",
+        "File to write the keys to for analytics",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "analytics-format",
+        "Format of the analytics file: jsonl (default) or parquet (experimental)",
+        "FORMAT",
+    );
+    opts.optopt(
+        "",
+        "restore",
+        "File to write the keys to as Redis protocol",
+        "FILE",
+    );
+    opts.optflag(
+        "",
+        "replace",
+        "Restore file: replace existing keys and function libraries instead of merging into them",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "etl", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let (analytics_path, restore_path) =
+        match (matches.opt_str("analytics"), matches.opt_str("restore")) {
+            (Some(analytics), Some(restore))
+                if !matches.opt_present("h") && matches.free.len() == 1 =>
+            {
+                (analytics, restore)
+            }
+            _ => {
+                print_command_usage(program, "etl", opts)?;
+                return help_or_usage_error(&matches);
+            }
+        };
+
+    let filter = match simple_filter(&matches) {
+        Ok(filter) => filter,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_command_usage(program, "etl", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let format = matches
+        .opt_str("analytics-format")
+        .unwrap_or_else(|| "jsonl".to_owned());
+    if !ANALYTICS_FORMATS.contains(&&format[..]) {
+        outln!("Unknown format: {}\n", format)?;
+        print_command_usage(program, "etl", opts)?;
+        process::exit(USAGE_STATUS);
+    }
+
+    let create = |path: PathBuf| match File::create(&path) {
+        Ok(file) => BufWriter::new(file),
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+    let analytics = create(path_arg(analytics_path));
+    let mut restore = Protocol::with_writer(create(path_arg(restore_path)));
+    restore.set_replace(matches.opt_present("replace"));
+
+    let path = path_arg(&matches.free[0]);
+    let mut failures = Failures::default();
+    failures.parse(&path, |input| match &format[..] {
+        #[cfg(feature = "parquet")]
+        "parquet" => rdb::parse(
+            input,
+            Tee::new(Parquet::with_writer(analytics), restore),
+            filter,
+        ),
+        _ => rdb::parse(
+            input,
+            Tee::new(JsonLines::with_writer(analytics), restore),
+            filter,
+        ),
+    });
+
+    failures.exit();
+
+    Ok(())
+}
+
+/// Prints the RDB versions, types, encodings, opcodes, modules and formats
+/// this build supports, so tools can check them before starting a job.
+fn capabilities(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optflag("", "json", "Print the capabilities as a JSON object");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) if !m.opt_present("h") && m.free.is_empty() => m,
+        Ok(m) => {
+            out!(
+                "{}",
+                opts.usage(&format!("Usage: {} capabilities [options]", program))
+            )?;
+            return help_or_usage_error(&m);
+        }
+        Err(e) => {
+            outln!("{}\n", e)?;
+            out!(
+                "{}",
+                opts.usage(&format!("Usage: {} capabilities [options]", program))
+            )?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let capabilities = Capabilities::new();
+    if matches.opt_present("json") {
+        let mut json = serde_json::to_value(&capabilities).expect("capabilities are valid JSON");
+        json["commands"] = serde_json::json!(COMMANDS);
+        outln!("{}", json)?;
+        return Ok(());
+    }
+
+    let codes = |codes: &[rdb::capabilities::Code]| {
+        codes
+            .iter()
+            .map(|code| format!("{} ({})", code.name, code.code))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    outln!("version: {}", capabilities.version)?;
+    outln!(
+        "rdb versions: {}-{}",
+        capabilities.min_rdb_version,
+        capabilities.max_rdb_version
+    )?;
+    outln!("types: {}", capabilities.types.join(", "))?;
+    outln!("encodings: {}", codes(&capabilities.encodings))?;
+    outln!("opcodes: {}", codes(&capabilities.opcodes))?;
+    outln!("modules: {}", capabilities.modules.join(", "))?;
+    outln!("formats: {}", capabilities.formats.join(", "))?;
+    if capabilities.features.is_empty() {
+        outln!("features: none")?;
+    } else {
+        outln!("features: {}", capabilities.features.join(", "))?;
+    }
+    outln!("commands: {}", COMMANDS.join(", "))?;
+
+    Ok(())
+}
+
+/// Lists the keys that were added, removed or changed between two files.
+fn diff(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "",
+        "score-epsilon",
+        "Treat sorted set scores differing by at most EPSILON as equal",
+        "EPSILON",
+    );
+    opts.optopt(
+        "",
+        "ttl-tolerance",
+        "Treat expiries differing by at most MS milliseconds as equal",
+        "MS",
+    );
+    opts.optopt(
+        "",
+        "memory",
+        "Memory to use before spilling to disk, in MiB (default: 256)",
+        "MIB",
+    );
+    add_fail_on_option(&mut opts, "corrupt");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "diff", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() != 2 {
+        print_command_usage(program, "diff", opts)?;
+        return help_or_usage_error(&matches);
+    }
+
+    let mut diff = Diff::new();
+    let options = (|| -> Result<Vec<Condition>, String> {
+        if let Some(epsilon) = matches.opt_str("score-epsilon") {
+            let epsilon = epsilon
+                .parse()
+                .map_err(|err| format!("Invalid score epsilon: {}", err))?;
+            diff.set_score_epsilon(epsilon);
+        }
+        if let Some(tolerance) = matches.opt_str("ttl-tolerance") {
+            let tolerance = tolerance
+                .parse()
+                .map_err(|err| format!("Invalid TTL tolerance: {}", err))?;
+            diff.set_ttl_tolerance(tolerance);
+        }
+        if let Some(memory) = matches.opt_str("memory") {
+            let memory: usize = memory
+                .parse()
+                .map_err(|err| format!("Invalid memory budget: {}", err))?;
+            diff.set_memory_budget(memory * 1024 * 1024);
+        }
+        fail_on(&matches, "corrupt")
+    })();
+    let conditions = match options {
+        Ok(conditions) => conditions,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_command_usage(program, "diff", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    // Corrupt dumps can't be compared, so only the other conditions need
+    // a separate pass.
+    let mut worst = None;
+    if conditions
+        .iter()
+        .any(|condition| *condition != Condition::Corrupt)
+    {
+        for path in matches.free.iter().map(path_arg) {
+            let findings = match inspect(&path, false, false) {
+                Ok(findings) => findings,
+                Err(err) => {
+                    eprintln!("{}: {}", path.display(), err);
+                    process::exit(USAGE_STATUS);
+                }
+            };
+
+            // Keep stdout for the differences.
+            for finding in findings {
+                if conditions.contains(&finding.condition) {
+                    eprintln!("{}", finding.message);
+                    worst = worst.into_iter().chain(Some(finding.condition)).min();
+                }
+            }
+        }
+    }
+
+    let open = |arg: &str| File::open(path_arg(arg)).map(BufReader::new);
+    let (left, right) =
+        match open(&matches.free[0]).and_then(|left| Ok((left, open(&matches.free[1])?))) {
+            Ok(files) => files,
+            Err(err) => {
+                eprintln!("Comparison failed: {}", err);
+                process::exit(USAGE_STATUS);
+            }
+        };
+
+    let mut differences = 0;
+    let res = diff.compare(left, right, Simple::new(), |difference| {
+        differences += 1;
+        writeln!(io::stdout(), "{}", difference)?;
+        Ok(())
+    });
+
+    match res {
+        Ok(_) => {}
+        Err(ref e) if e.is_broken_pipe() => process::exit(BROKEN_PIPE_STATUS),
+        // Spilling to disk failed.
+        Err(e @ RdbError::Formatter { .. }) => {
+            eprintln!("Comparison failed: {}", e);
+            process::exit(USAGE_STATUS);
+        }
+        Err(e) => {
+            eprintln!("Comparison failed: {}", e);
+            process::exit(Condition::Corrupt.status());
+        }
+    }
+
+    if let Some(condition) = worst {
+        process::exit(condition.status());
+    }
+    if differences > 0 {
+        process::exit(MISMATCH_STATUS);
+    }
+
+    Ok(())
+}
+
+/// Writes the index of a file for later delta exports.
+fn index(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt("o", "output", "File to write the index to", "FILE");
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "index", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let output = match matches.opt_str("o") {
+        Some(output) if !matches.opt_present("h") && matches.free.len() == 1 => path_arg(output),
+        _ => {
+            print_command_usage(program, "index", opts)?;
+            return help_or_usage_error(&matches);
+        }
+    };
+
+    let path = path_arg(&matches.free[0]);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+    let index = match Index::build(BufReader::new(file), Simple::new()) {
+        Ok(index) => index,
+        Err(err) => {
+            eprintln!("{}: parsing failed: {}", path.display(), err);
+            process::exit(Condition::Corrupt.status());
+        }
+    };
+
+    let res = File::create(&output)
+        .map_err(RdbError::from)
+        .and_then(|file| index.write_to(io::BufWriter::new(file)));
+    if let Err(err) = res {
+        eprintln!("{}: {}", output.display(), err);
+        process::exit(USAGE_STATUS);
+    }
+
+    Ok(())
+}
+
+/// Exports the keys of a file that were added or changed since an index.
+fn delta(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt("s", "since", "Index of the previous dump", "FILE");
+    opts.optopt(
+        "f",
+        "format",
+        "Format to output. Valid: protocol, jsonl (default: protocol)",
+        "FORMAT",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "delta", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let since = match matches.opt_str("s") {
+        Some(since) if !matches.opt_present("h") && matches.free.len() == 1 => since,
+        _ => {
+            print_command_usage(program, "delta", opts)?;
+            return help_or_usage_error(&matches);
+        }
+    };
+
+    let index = read_index(&path_arg(since));
+
+    let path = path_arg(&matches.free[0]);
+    let file = match File::open(&path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let format = matches.opt_str("f").unwrap_or_else(|| "protocol".into());
+    let res = match &format[..] {
+        "protocol" => rdb::parse(file, index.delta(Protocol::new()), Simple::new()),
+        "jsonl" => rdb::parse(file, index.delta(JsonLines::new()), Simple::new()),
+        _ => {
+            outln!("Unknown format: {}\n", format)?;
+            print_command_usage(program, "delta", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    match res {
+        Ok(_) => {}
+        Err(ref e) if e.is_broken_pipe() => process::exit(BROKEN_PIPE_STATUS),
+        Err(e) => {
+            eprintln!("{}: parsing failed: {}", path.display(), e);
+            process::exit(Condition::Corrupt.status());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads an index written by the `index` subcommand.
+fn read_index(path: &Path) -> Index {
+    let index = File::open(path)
+        .map_err(RdbError::from)
+        .and_then(|file| Index::read_from(BufReader::new(file)));
+    match index {
+        Ok(index) => index,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    }
+}
+
+/// Applies the keys of a file that were added or changed since an index to
+/// a running server.
+fn sync(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "t",
+        "target",
+        "Server to apply the changes to, redis://[[user]:password@]host[:port]",
+        "URL",
+    );
+    opts.optflag(
+        "",
+        "prune",
+        "Delete the keys of the index that are missing from the dump",
+    );
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "sync", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let target = match matches.opt_str("t") {
+        Some(target) if !matches.opt_present("h") && matches.free.len() == 2 => target,
+        _ => {
+            print_command_usage(program, "sync", opts)?;
+            return help_or_usage_error(&matches);
+        }
+    };
+
+    let index = read_index(&path_arg(&matches.free[0]));
+    let path = path_arg(&matches.free[1]);
+    let file = match File::open(&path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let mut restore = match Restore::connect(&target) {
+        Ok(restore) => restore,
+        Err(err) => {
+            eprintln!("{}: {}", target, err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+    let writer = match restore.writer() {
+        Ok(writer) => writer,
+        Err(err) => {
+            eprintln!("{}: {}", target, err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    // Changed keys are deleted first, so they are not merged into the old
+    // values.
+    let mut protocol = Protocol::with_writer(writer);
+    protocol.set_replace(true);
+    let filter = Simple::new();
+    let mut delta = index.delta(protocol);
+    if let Err(e) = rdb::parse(file, &mut delta, &filter) {
+        eprintln!("{}: parsing failed: {}", path.display(), e);
+        process::exit(Condition::Corrupt.status());
+    }
+
+    let removed = if matches.opt_present("prune") {
+        delta.removed(&filter)
+    } else {
+        vec![]
+    };
+    drop(delta);
+
+    let summary = prune(&mut restore, &removed).and_then(|_| restore.finish());
+    let summary = match summary {
+        Ok(summary) => summary,
+        Err(err) => {
+            eprintln!("{}: {}", target, err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    for error in &summary.errors {
+        eprintln!("{}: {}", target, error);
+    }
+    eprintln!(
+        "{} commands applied, {} keys pruned, {} errors",
+        summary.replies,
+        removed.len(),
+        summary.errors.len()
+    );
+    if !summary.errors.is_empty() {
+        process::exit(REJECTED_STATUS);
+    }
+
+    Ok(())
 }
 
-pub fn main() {
-    let mut args = env::args();
-    let program = args.next().unwrap();
-    let mut opts = Options::new();
+/// Deletes keys, given sorted by database.
+fn prune(restore: &mut Restore, keys: &[(u32, Vec<u8>)]) -> RdbOk {
+    let mut current = None;
+    for (db, key) in keys {
+        if current != Some(*db) {
+            let select = db.to_string();
+            restore.command(&[b"SELECT", select.as_bytes()])?;
+            current = Some(*db);
+        }
+        restore.command(&[b"DEL", key])?;
+    }
+
+    Ok(())
+}
 
+/// Writes the keys of a file to one output per key prefix or database.
+fn split(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+    opts.optopt(
+        "",
+        "by-prefix",
+        "Pattern matching the start of key names, its capture groups name the output (default: all keys)",
+        "REGEX",
+    );
+    opts.optopt(
+        "o",
+        "output",
+        "Files to write to, {N} is replaced with capture group N and {db} with the database",
+        "TEMPLATE",
+    );
     opts.optopt(
         "f",
         "format",
-        "Format to output. Valid: json, plain, nil, protocol",
+        "Format to output. Valid: json, jsonl, plain, protocol, digest (default: json)",
         "FORMAT",
     );
+    add_salt_option(&mut opts);
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(e) => {
+            outln!("{}\n", e)?;
+            print_command_usage(program, "split", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let template = match matches.opt_str("o") {
+        Some(template) if !matches.opt_present("h") && matches.free.len() == 1 => template,
+        _ => {
+            print_command_usage(program, "split", opts)?;
+            return help_or_usage_error(&matches);
+        }
+    };
+    let prefix = matches.opt_str("by-prefix").unwrap_or_default();
+
+    let pattern = match Regex::new(&format!("^(?:{})", prefix)) {
+        Ok(pattern) => pattern,
+        Err(err) => {
+            outln!("Incorrect regexp: {:?}\n", err)?;
+            print_command_usage(program, "split", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let path = path_arg(&matches.free[0]);
+    let file = match File::open(&path) {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    let format = matches.opt_str("f").unwrap_or_else(|| "json".into());
+    let res = match &format[..] {
+        "json" => split_into(file, pattern, &template, |out| {
+            rdb::formatter::JSON::with_writer(out)
+        }),
+        "jsonl" => split_into(file, pattern, &template, JsonLines::with_writer),
+        "plain" => split_into(file, pattern, &template, |out| {
+            rdb::formatter::Plain::with_writer(out)
+        }),
+        "protocol" => split_into(file, pattern, &template, Protocol::with_writer),
+        "digest" => match digest_salt(&matches) {
+            Ok(salt) => split_into(file, pattern, &template, move |out| {
+                let mut formatter = Digest::with_writer(out);
+                formatter.set_salt(&salt);
+                formatter
+            }),
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_command_usage(program, "split", opts)?;
+                process::exit(USAGE_STATUS);
+            }
+        },
+        _ => {
+            outln!("Unknown format: {}\n", format)?;
+            print_command_usage(program, "split", opts)?;
+            process::exit(USAGE_STATUS);
+        }
+    };
+
+    match res {
+        Ok(outputs) => eprintln!("{} outputs written", outputs),
+        Err(e) => {
+            eprintln!("{}: splitting failed: {}", path.display(), e);
+            process::exit(Condition::Corrupt.status());
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses a file into a `Split` creating each output with `formatter`,
+/// returning the number of outputs.
+fn split_into<F, C>(
+    input: BufReader<File>,
+    pattern: Regex,
+    template: &str,
+    formatter: C,
+) -> RdbResult<usize>
+where
+    F: Formatter,
+    C: Fn(BufWriter<File>) -> F + 'static,
+{
+    let mut split = Split::new(pattern, template, move |name: &str| {
+        let path = Path::new(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(formatter(BufWriter::new(File::create(path)?)))
+    });
+    rdb::parse(input, &mut split, Simple::new())?;
+    Ok(split.outputs().len())
+}
+
+fn add_filter_options(opts: &mut Options) {
     opts.optopt(
         "k",
         "keys",
@@ -40,108 +1951,805 @@ pub fn main() {
         "Type to show. Can be specified multiple times",
         "TYPE",
     );
+    opts.optmulti(
+        "",
+        "slot",
+        "Cluster hash slot to show, for dumps of Redis 7.2 cluster nodes. Can be specified multiple times",
+        "SLOT",
+    );
+}
+
+fn simple_filter(matches: &Matches) -> Result<Simple, String> {
+    let mut filter = Simple::new();
+
+    for db in &matches.opt_strs("d") {
+        let db = db
+            .parse()
+            .map_err(|_| format!("Invalid database: {}", db))?;
+        filter.add_database(db);
+    }
+
+    for slot in &matches.opt_strs("slot") {
+        let slot = slot
+            .parse()
+            .map_err(|_| format!("Invalid slot: {}", slot))?;
+        filter.add_slot(slot);
+    }
+
+    for t in &matches.opt_strs("t") {
+        let typ = rdb::Type::from_name(t).ok_or_else(|| format!("Unknown type: {}", t))?;
+        filter.add_type(typ);
+    }
+
+    if let Some(k) = matches.opt_str("k") {
+        let re = Regex::new(&k).map_err(|err| format!("Incorrect regexp: {:?}", err))?;
+        filter.add_keys(re);
+    }
+
+    Ok(filter)
+}
+
+fn add_time_options(opts: &mut Options) {
+    opts.optopt(
+        "",
+        "timezone",
+        "Time zone of timestamps. Valid: utc, local (default: utc)",
+        "ZONE",
+    );
+    opts.optopt(
+        "",
+        "time-format",
+        "strftime-like format of timestamps, rfc3339, or remaining for the time left until them (default: '%Y-%m-%d %H:%M:%S %Z')",
+        "FORMAT",
+    );
+}
+
+fn time_format(matches: &Matches) -> Result<TimeFormat, String> {
+    let zone = match matches.opt_str("timezone").as_deref() {
+        None | Some("utc") | Some("UTC") => Zone::Utc,
+        Some("local") => Zone::Local,
+        Some(zone) => return Err(format!("Unknown time zone: {}", zone)),
+    };
+    let pattern = match matches.opt_str("time-format").as_deref() {
+        None => time::DEFAULT_PATTERN.to_owned(),
+        Some("rfc3339") => time::RFC3339_PATTERN.to_owned(),
+        Some("remaining") => return Ok(TimeFormat::remaining(Utc::now())),
+        Some(pattern) => pattern.to_owned(),
+    };
+
+    TimeFormat::new(zone, &pattern).map_err(|err| err.to_string())
+}
+
+/// Reads `--flush-policy`: `key`, `end` or a number of bytes.
+fn flush_policy(matches: &Matches) -> Result<FlushPolicy, String> {
+    match matches.opt_str("flush-policy").as_deref() {
+        None => Ok(FlushPolicy::default()),
+        Some("key") => Ok(FlushPolicy::Key),
+        Some("end") => Ok(FlushPolicy::End),
+        Some(bytes) => match bytes.parse() {
+            Ok(bytes) if bytes > 0 => Ok(FlushPolicy::Bytes(bytes)),
+            _ => Err(format!("Unknown flush policy: {}", bytes)),
+        },
+    }
+}
+
+/// The formats each format-specific option of `dump` works with.
+const FORMAT_OPTIONS: &[(&str, &[&str])] = &[
+    ("separator", &["folded", "prometheus"]),
+    ("depth", &["folded"]),
+    ("weight", &["folded"]),
+    (
+        "crlf",
+        &[
+            "json",
+            "jsonl",
+            "plain",
+            "digest",
+            "folded",
+            "dot",
+            "compression",
+            "stats",
+            "prometheus",
+            "table",
+            "template",
+        ],
+    ),
+    ("color", &["plain"]),
+    (
+        "lengths-only",
+        &["folded", "nil", "compression", "stats", "prometheus"],
+    ),
+    ("flush-policy", &["json", "plain"]),
+    ("references", &["dot"]),
+    ("geo-keys", &["json"]),
+    ("metadata", &["json"]),
+    ("fingerprint", &["digest"]),
+    ("salt-file", &["digest"]),
+    ("width", &["table"]),
+    ("template", &["template"]),
+    ("summary-json", &["stats"]),
+    ("hyperloglogs", &["jsonl"]),
+    ("bitmap-keys", &["jsonl"]),
+    ("dump-payloads", &["protocol"]),
+    ("replace", &["protocol"]),
+    ("flush", &["protocol"]),
+    ("no-select", &["protocol"]),
+    ("batch-elements", &["protocol"]),
+    ("batch-bytes", &["protocol"]),
+    ("stream-batch", &["protocol"]),
+    (
+        "timezone",
+        &["json", "jsonl", "plain", "digest", "table", "template"],
+    ),
+    (
+        "time-format",
+        &["json", "jsonl", "plain", "digest", "table", "template"],
+    ),
+];
+
+/// Rejects format-specific options given with a format that doesn't use
+/// them, instead of ignoring them.
+fn check_format_options(matches: &Matches, format: &str) -> Result<(), String> {
+    for (option, formats) in FORMAT_OPTIONS {
+        if !matches.opt_present(option) || formats.contains(&format) {
+            continue;
+        }
+        let formats = match formats.split_last() {
+            Some((last, [])) => format!("the {} format", last),
+            Some((last, init)) => format!("the {} and {} formats", init.join(", "), last),
+            None => unreachable!(),
+        };
+        return Err(format!("--{} only works with {}", option, formats));
+    }
+    Ok(())
+}
+
+/// Adds the `--salt-file` option of the digest format.
+fn add_salt_option(opts: &mut Options) {
+    opts.optopt(
+        "",
+        "salt-file",
+        "Digest format: key the digests with the contents of FILE (required)",
+        "FILE",
+    );
+}
+
+/// Reads the salt of the digest format. It is required: unkeyed digests of
+/// short values can be reversed by hashing guesses.
+fn digest_salt(matches: &Matches) -> Result<Vec<u8>, String> {
+    let path = matches
+        .opt_str("salt-file")
+        .map(path_arg)
+        .ok_or("The digest format requires --salt-file")?;
+    let salt = fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    if salt.is_empty() {
+        return Err(format!("{}: the salt is empty", path.display()));
+    }
+    Ok(salt)
+}
+
+fn folded_formatter(matches: &Matches, out: Box<dyn Write>) -> Result<Folded, String> {
+    let mut formatter = Folded::with_writer(out);
+
+    if let Some(separator) = matches.opt_str("separator") {
+        match separator.as_bytes() {
+            &[separator] => formatter.set_separator(separator),
+            _ => return Err(format!("Invalid separator: {:?}", separator)),
+        }
+    }
+
+    if let Some(depth) = matches.opt_str("depth") {
+        let depth = depth
+            .parse()
+            .map_err(|err| format!("Invalid depth: {}", err))?;
+        formatter.set_depth(depth);
+    }
+
+    match matches.opt_str("weight").as_deref() {
+        None | Some("bytes") => {}
+        Some("count") => formatter.set_weight(Weight::Count),
+        Some(weight) => return Err(format!("Unknown weight: {}", weight)),
+    }
+
+    Ok(formatter)
+}
+
+fn protocol_formatter(matches: &Matches) -> Result<Protocol, String> {
+    let mut formatter = Protocol::with_writer(io::stdout());
+    let size = |name: &str, what: &str| -> Result<Option<usize>, String> {
+        matches
+            .opt_str(name)
+            .map(|n| n.parse())
+            .transpose()
+            .map_err(|err| format!("Invalid {}: {}", what, err))
+    };
+
+    if let Some(batch) = size("stream-batch", "stream batch size")? {
+        formatter.set_stream_batch(batch);
+    }
+    if let Some(elements) = size("batch-elements", "number of elements")? {
+        formatter.set_batch_elements(elements);
+    }
+    if let Some(bytes) = size("batch-bytes", "number of bytes")? {
+        formatter.set_batch_bytes(bytes);
+    }
+    formatter.set_dump_payloads(matches.opt_present("dump-payloads"));
+    formatter.set_replace(matches.opt_present("replace"));
+    match matches.opt_str("flush").as_deref() {
+        None => {}
+        Some("all") => formatter.set_flush(Flush::All),
+        Some("db") => formatter.set_flush(Flush::Databases),
+        Some(flush) => return Err(format!("Unknown flush: {}", flush)),
+    }
+    formatter.set_select(!matches.opt_present("no-select"));
+
+    Ok(formatter)
+}
+
+fn dump(program: &str, args: &[String]) -> io::Result<()> {
+    let mut opts = Options::new();
+
+    opts.optopt(
+        "f",
+        "format",
+        "Format to output. Valid: json, jsonl, plain, nil, protocol, digest, folded, dot, compression, stats, prometheus, table, template, parquet (experimental)",
+        "FORMAT",
+    );
+    add_filter_options(&mut opts);
+    opts.optopt(
+        "j",
+        "jobs",
+        "Decode databases on N threads in parallel (0: all available cores)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "separator",
+        "Folded and prometheus formats: character keys are split at (default: ':')",
+        "SEP",
+    );
+    opts.optopt(
+        "",
+        "depth",
+        "Folded format: maximum number of key segments per stack",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "weight",
+        "Folded format: what to aggregate. Valid: bytes, count",
+        "WEIGHT",
+    );
+    opts.optflag(
+        "",
+        "crlf",
+        "End lines with CRLF instead of LF in the text formats",
+    );
+    opts.optopt(
+        "",
+        "color",
+        "Plain format: color keys, type markers and expiries (default: auto, on a terminal)",
+        "auto|always|never",
+    );
+    opts.optflag(
+        "",
+        "lengths-only",
+        "Folded, nil, compression, stats and prometheus formats: skip values, only measuring their lengths",
+    );
+    opts.optflag(
+        "",
+        "lenient",
+        "Skip module values of newer encodings, printing a warning for each",
+    );
+    opts.optflag(
+        "",
+        "summary",
+        "Print the keys read and skipped, the databases, the bytes read and the time taken to stderr",
+    );
+    opts.optflag(
+        "",
+        "sorted",
+        "Write keys in byte order and the members of sets, hashes and sorted sets in a fixed order, so dumps of the same data produce the same output",
+    );
+    opts.optopt(
+        "",
+        "memory",
+        "Sorted output: memory to use before spilling keys to disk, in MiB (default: 256)",
+        "MIB",
+    );
+    opts.optopt(
+        "",
+        "flush-policy",
+        "JSON and plain formats: flush the output after every key, only at the end, or every N bytes (default: 65536)",
+        "key|end|N",
+    );
+    opts.optopt(
+        "",
+        "references",
+        "Dot format: pattern matching key names referenced in values",
+        "REGEX",
+    );
+    opts.optopt(
+        "",
+        "geo-keys",
+        "JSON format: write the coordinates of the members of GEO sets whose names match REGEX instead of their scores",
+        "REGEX",
+    );
+    opts.optflag(
+        "",
+        "fingerprint",
+        "Digest format: print a fingerprint per database and of the whole dump instead of a line per key",
+    );
+    opts.optopt(
+        "",
+        "width",
+        "Table format: cut keys and values off after N characters (default: 40)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "template",
+        "Template format: the line written per key, with {db}, {key}, {type}, {expiry} and {value} replaced, e.g. '{db}\\t{key}\\t{value}'",
+        "TEMPLATE",
+    );
+    opts.optflag(
+        "",
+        "summary-json",
+        "Stats format: write the summary as a JSON object instead of a table",
+    );
+    opts.optflag(
+        "",
+        "metadata",
+        "JSON format: nest every value in an object with its type, database, expiry and encoding",
+    );
+    opts.optflag(
+        "",
+        "hyperloglogs",
+        "JSON Lines format: report the encoding and estimated cardinality of HyperLogLogs instead of their registers",
+    );
+    opts.optopt(
+        "",
+        "bitmap-keys",
+        "JSON Lines format: report the length and number of set bits of the strings of keys matching REGEX instead of their contents",
+        "REGEX",
+    );
+    opts.optflag(
+        "",
+        "dump-payloads",
+        "Protocol format: restore each string, hash, set, list and sorted set with a single RESTORE instead of a command per element",
+    );
+    opts.optflag(
+        "",
+        "replace",
+        "Protocol format: replace existing keys and function libraries instead of merging into them",
+    );
+    opts.optopt(
+        "",
+        "flush",
+        "Protocol format: delete all databases (all) or each restored one (db) before restoring",
+        "WHAT",
+    );
+    opts.optflag(
+        "",
+        "no-select",
+        "Protocol format: leave out SELECT, restoring a single database into the one the client selected",
+    );
+    opts.optopt(
+        "",
+        "batch-elements",
+        "Protocol format: send up to N elements of a hash, set, list or sorted set per HSET, SADD, RPUSH or ZADD (0: no limit, default: 1)",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "batch-bytes",
+        "Protocol format: end batches of elements before they exceed N bytes",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "stream-batch",
+        "Protocol format: send the XADD commands of streams in MULTI/EXEC transactions of up to N entries",
+        "N",
+    );
+    opts.optopt(
+        "",
+        "decrypt-key",
+        "Decrypt an AES-GCM encrypted dump with the key in FILE",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "key-encoding",
+        "Encoding of the decryption key: raw, hex (default: raw)",
+        "ENCODING",
+    );
+    add_salt_option(&mut opts);
+    add_time_options(&mut opts);
     opts.optflag("h", "help", "print this help menu");
 
     let matches = match opts.parse(args) {
         Ok(m) => m,
         Err(e) => {
-            println!("{}\n", e);
-            print_usage(&program, opts);
-            return;
+            outln!("{}\n", e)?;
+            print_usage(program, opts)?;
+            return Ok(());
         }
     };
 
     if matches.opt_present("h") {
-        print_usage(&program, opts);
-        return;
+        print_usage(program, opts)?;
+        return Ok(());
     }
 
-    let mut filter = rdb::filter::Simple::new();
+    // Without the options, the formats keep their machine-readable times.
+    let time_format = match time_format(&matches) {
+        Ok(_) if !matches.opt_present("timezone") && !matches.opt_present("time-format") => None,
+        Ok(time_format) => Some(time_format),
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+    };
 
-    for db in &matches.opt_strs("d") {
-        filter.add_database(db.parse().unwrap());
-    }
+    let flush_policy = match flush_policy(&matches) {
+        Ok(flush_policy) => flush_policy,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+    };
 
-    for t in &matches.opt_strs("t") {
-        let typ = match &t[..] {
-            "string" => rdb::Type::String,
-            "list" => rdb::Type::List,
-            "set" => rdb::Type::Set,
-            "sortedset" | "sorted-set" | "sorted_set" => rdb::Type::SortedSet,
-            "hash" => rdb::Type::Hash,
-            _ => {
-                println!("Unknown type: {}\n", t);
-                print_usage(&program, opts);
-                return;
-            }
-        };
-        filter.add_type(typ);
+    let filter = match simple_filter(&matches) {
+        Ok(filter) => filter,
+        Err(err) => {
+            outln!("{}\n", err)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+    };
+
+    if matches.free.is_empty() {
+        print_usage(program, opts)?;
+        return Ok(());
     }
 
-    if let Some(k) = matches.opt_str("k") {
-        let re = match Regex::new(&k) {
-            Ok(re) => re,
-            Err(err) => {
-                println!("Incorrect regexp: {:?}\n", err);
-                print_usage(&program, opts);
-                return;
+    let jobs = match matches.opt_str("j").map(|j| j.parse::<usize>()) {
+        None => None,
+        Some(Ok(jobs)) => Some(jobs),
+        Some(Err(err)) => {
+            outln!("Invalid number of jobs: {}\n", err)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+    };
+
+    let key_encoding = match matches.opt_str("key-encoding") {
+        None => KeyEncoding::Raw,
+        Some(name) => match KeyEncoding::from_name(&name) {
+            Some(encoding) => encoding,
+            None => {
+                outln!("Unknown key encoding: {}\n", name)?;
+                print_usage(program, opts)?;
+                process::exit(USAGE_STATUS);
             }
-        };
-        filter.add_keys(re);
-    }
+        },
+    };
+    let decrypt_key = match matches
+        .opt_str("decrypt-key")
+        .map(|arg| AesGcm::from_key_file(path_arg(arg), key_encoding))
+    {
+        None => None,
+        Some(Ok(key)) => Some(key),
+        Some(Err(err)) => {
+            eprintln!("Invalid decryption key: {}", err);
+            process::exit(USAGE_STATUS);
+        }
+    };
 
-    if matches.free.is_empty() {
-        print_usage(&program, opts);
-        return;
+    let format = matches.opt_str("f").unwrap_or_else(|| "json".into());
+    if let Err(err) = check_format_options(&matches, &format) {
+        outln!("{}\n", err)?;
+        print_usage(program, opts)?;
+        return Ok(());
+    }
+    let lengths_only = matches.opt_present("lengths-only");
+    let lenient = matches.opt_present("lenient");
+    if lenient && lengths_only {
+        outln!("--lenient and --lengths-only can't be combined\n")?;
+        print_usage(program, opts)?;
+        return Ok(());
     }
 
-    let path = matches.free[0].clone();
-    let file = File::open(&Path::new(&*path)).unwrap();
+    let hyperloglogs = matches.opt_present("hyperloglogs");
+    let bitmap_keys = match matches.opt_str("bitmap-keys").map(|re| Regex::new(&re)) {
+        Some(Ok(keys)) => Some(keys),
+        Some(Err(err)) => {
+            outln!("Incorrect regexp: {:?}\n", err)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+        None => None,
+    };
 
-    let file_length = file.metadata().map(|m| m.len()).unwrap_or(0);
+    let summary = matches.opt_present("summary");
+    // Decrypting needs the whole file, so it can't be split up.
+    if jobs.is_some_and(|jobs| jobs != 1) {
+        let sequential_only = ["decrypt-key", "lengths-only", "summary"];
+        if let Some(option) = sequential_only.iter().find(|o| matches.opt_present(o)) {
+            outln!("--jobs can't be combined with --{}\n", option)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+    }
 
-    let progress_bar = ProgressBar::new(file_length);
-    progress_bar.set_style(ProgressStyle::default_bar().template(
-        "[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
-    ));
+    let path = path_arg(&matches.free[0]);
+    let input: Input<Box<dyn Read>> = match jobs {
+        Some(jobs) if jobs != 1 => {
+            let file_length = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            Input::Parallel {
+                path,
+                jobs,
+                lenient,
+                hyperloglogs,
+                bitmap_keys: bitmap_keys.clone(),
+                progress_bar: progress_bar(file_length),
+            }
+        }
+        _ => {
+            let file = File::open(&path).unwrap();
 
-    let reader = progress_bar.wrap_read(file);
+            let file_length = file.metadata().map(|m| m.len()).unwrap_or(0);
+            let reader = progress_bar(file_length).wrap_read(file);
 
-    let reader = BufReader::new(reader);
+            match decrypt_key {
+                Some(key) => match key.unwrap_key(Box::new(reader)) {
+                    Ok(reader) => Input::Sequential(reader),
+                    Err(err) => {
+                        eprintln!("{}: {}", path.display(), err);
+                        process::exit(USAGE_STATUS);
+                    }
+                },
+                None => Input::Sequential(Box::new(BufReader::new(reader))),
+            }
+        }
+    };
+    let input = match input {
+        Input::Sequential(reader)
+            if lengths_only || lenient || hyperloglogs || bitmap_keys.is_some() || summary =>
+        {
+            Input::Options {
+                reader,
+                lengths_only,
+                lenient,
+                hyperloglogs,
+                bitmap_keys,
+                summary,
+            }
+        }
+        input => input,
+    };
 
-    let mut res = Ok(());
+    // Plain, digest and compression write values unchanged, so they end
+    // their lines themselves instead of having every line feed translated.
+    // Parquet is binary.
+    let crlf = matches.opt_present("crlf");
+    let out: Box<dyn Write> =
+        if crlf && !["plain", "digest", "compression", "parquet"].contains(&&format[..]) {
+            Box::new(Crlf::new(io::stdout()))
+        } else {
+            Box::new(io::stdout())
+        };
 
-    if let Some(f) = matches.opt_str("f") {
-        match &f[..] {
-            "json" => {
-                res = rdb::parse(reader, rdb::formatter::JSON::new(), filter);
+    let sorted = match matches
+        .opt_str("memory")
+        .map(|memory| memory.parse::<usize>())
+    {
+        Some(_) if !matches.opt_present("sorted") => {
+            outln!("--memory only works with --sorted\n")?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+        Some(Err(err)) => {
+            outln!("Invalid memory budget: {}\n", err)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+        Some(Ok(memory)) => Some(memory * 1024 * 1024),
+        None if matches.opt_present("sorted") => Some(rdb::extsort::DEFAULT_BUDGET),
+        None => None,
+    };
+    let res = match &format[..] {
+        "json" => {
+            let mut formatter = rdb::formatter::JSON::with_writer(out);
+            if let Some(time_format) = time_format {
+                formatter.set_time_format(time_format);
             }
-            "plain" => {
-                res = rdb::parse(reader, rdb::formatter::Plain::new(), filter);
+            formatter.set_flush_policy(flush_policy);
+            formatter.set_metadata(matches.opt_present("metadata"));
+            match matches.opt_str("geo-keys").map(|re| Regex::new(&re)) {
+                Some(Ok(geo_keys)) => formatter.set_geo_keys(geo_keys),
+                Some(Err(err)) => {
+                    outln!("Incorrect regexp: {:?}\n", err)?;
+                    print_usage(program, opts)?;
+                    return Ok(());
+                }
+                None => {}
             }
-            "nil" => {
-                res = rdb::parse(reader, rdb::formatter::Nil::new(), filter);
+            parse_in_order(input, formatter, filter, sorted)
+        }
+        "jsonl" => {
+            let mut formatter = JsonLines::with_writer(out);
+            if let Some(time_format) = time_format {
+                formatter.set_time_format(time_format);
             }
-            "protocol" => {
-                res = rdb::parse(reader, rdb::formatter::Protocol::new(), filter);
+            parse_in_order(input, formatter, filter, sorted)
+        }
+        "plain" => {
+            let mut formatter = rdb::formatter::Plain::with_writer(out);
+            if let Some(time_format) = time_format {
+                formatter.set_time_format(time_format);
             }
-            _ => {
-                println!("Unknown format: {}\n", f);
-                print_usage(&program, opts);
+            formatter.set_crlf(crlf);
+            formatter.set_flush_policy(flush_policy);
+            match matches.opt_str("color").as_deref() {
+                None | Some("auto") => formatter.set_color(ansi(io::stdout())),
+                Some("always") => formatter.set_color(true),
+                Some("never") => formatter.set_color(false),
+                Some(color) => {
+                    outln!("Unknown color mode: {}\n", color)?;
+                    print_usage(program, opts)?;
+                    return Ok(());
+                }
             }
+            parse_in_order(input, formatter, filter, sorted)
         }
-    } else {
-        res = rdb::parse(reader, rdb::formatter::JSON::new(), filter);
-    }
+        "nil" => parse_in_order(input, rdb::formatter::Nil::new(), filter, sorted),
+        "protocol" => match protocol_formatter(&matches) {
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+            Ok(mut formatter) => {
+                let res = parse_in_order(input, &mut formatter, filter, sorted);
+                let dropped = formatter.dropped_pending_entries();
+                if dropped > 0 {
+                    eprintln!(
+                        "{} pending entries refer to deleted messages and were left out",
+                        dropped
+                    );
+                }
+                res
+            }
+        },
+        "digest" => match digest_salt(&matches) {
+            Ok(salt) => {
+                let mut formatter = Digest::with_writer(out);
+                formatter.set_salt(&salt);
+                if let Some(time_format) = time_format {
+                    formatter.set_time_format(time_format);
+                }
+                formatter.set_crlf(crlf);
+                formatter.set_fingerprint(matches.opt_present("fingerprint"));
+                parse_in_order(input, formatter, filter, sorted)
+            }
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+        },
+        #[cfg(feature = "parquet")]
+        "parquet" => parse_in_order(input, Parquet::with_writer(out), filter, sorted),
+        "compression" => {
+            let mut formatter = rdb::formatter::Compression::with_writer(out);
+            formatter.set_crlf(crlf);
+            parse_in_order(input, formatter, filter, sorted)
+        }
+        "stats" => {
+            let mut formatter = Statistics::with_writer(out);
+            formatter.set_json(matches.opt_present("summary-json"));
+            parse_in_order(input, formatter, filter, sorted)
+        }
+        "table" => match matches.opt_str("width").map(|n| n.parse::<usize>()) {
+            Some(Err(err)) => {
+                outln!("Invalid width: {}\n", err)?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+            width => {
+                let mut formatter = Table::with_writer(out);
+                if let Some(Ok(width)) = width {
+                    formatter.set_width(width);
+                }
+                if let Some(time_format) = time_format {
+                    formatter.set_time_format(time_format);
+                }
+                parse_in_order(input, formatter, filter, sorted)
+            }
+        },
+        "template" => match matches
+            .opt_str("template")
+            .map(|t| Template::with_writer(&t, out))
+        {
+            Some(Ok(mut formatter)) => {
+                if let Some(time_format) = time_format {
+                    formatter.set_time_format(time_format);
+                }
+                parse_in_order(input, formatter, filter, sorted)
+            }
+            Some(Err(err)) => {
+                outln!("{}\n", err)?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+            None => {
+                outln!("The template format requires --template\n")?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+        },
+        "prometheus" => {
+            let mut formatter = Prometheus::with_writer(out);
+            if let Some(separator) = matches.opt_str("separator") {
+                match separator.as_bytes() {
+                    &[separator] => formatter.set_separator(separator),
+                    _ => {
+                        outln!("Invalid separator: {:?}\n", separator)?;
+                        print_usage(program, opts)?;
+                        return Ok(());
+                    }
+                }
+            }
+            parse_in_order(input, formatter, filter, sorted)
+        }
+        "folded" => match folded_formatter(&matches, out) {
+            Ok(formatter) => parse_in_order(input, formatter, filter, sorted),
+            Err(err) => {
+                outln!("{}\n", err)?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+        },
+        "dot" => match matches.opt_str("references").map(|re| Regex::new(&re)) {
+            Some(Ok(references)) => {
+                parse_in_order(input, Dot::with_writer(references, out), filter, sorted)
+            }
+            Some(Err(err)) => {
+                outln!("Incorrect regexp: {:?}\n", err)?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+            None => {
+                outln!("The dot format requires --references\n")?;
+                print_usage(program, opts)?;
+                return Ok(());
+            }
+        },
+        _ => {
+            outln!("Unknown format: {}\n", format)?;
+            print_usage(program, opts)?;
+            return Ok(());
+        }
+    };
 
     match res {
         Ok(()) => {}
+        // The reader of our output is gone, e.g. `head` got all it wanted.
+        // Exit quietly with the status of a process killed by SIGPIPE.
+        Err(ref e) if e.is_broken_pipe() => process::exit(BROKEN_PIPE_STATUS),
         Err(e) => {
-            println!("");
+            // The output might be the reason for the failure, so don't touch it.
             let mut stderr = std::io::stderr();
+            stderr.write_all(b"\n").unwrap();
 
             let out = format!("Parsing failed: {}\n", e);
-            stderr.write(out.as_bytes()).unwrap();
+            stderr.write_all(out.as_bytes()).unwrap();
         }
     }
+
+    Ok(())
 }
@@ -0,0 +1,312 @@
+//! Decoding of values written by Redis modules.
+//!
+//! A module stores its values as a sequence of numbers and strings in
+//! whatever layout it chose, so they can only be decoded by code that knows
+//! this layout. Without such code, module values are skipped and reported
+//! through `Formatter::module`. A `ModuleDecoder` registered with
+//! `RdbParser::register_module_decoder` turns them into regular values
+//! instead:
+//!
+//! ```rust,no_run
+//! use rdb::formatter::Formatter;
+//! use rdb::module::{ModuleDecoder, ModuleReader};
+//! use rdb::RdbOk;
+//!
+//! /// Passes on values holding a single string as Redis strings.
+//! struct Text;
+//!
+//! impl ModuleDecoder for Text {
+//!     fn decode(
+//!         &mut self,
+//!         key: &[u8],
+//!         expiry: Option<u64>,
+//!         values: &mut ModuleReader,
+//!         formatter: &mut dyn Formatter,
+//!     ) -> RdbOk {
+//!         let text = values.expect_string()?;
+//!         formatter.set(key, &text, expiry)
+//!     }
+//! }
+//!
+//! let file = std::fs::File::open("dump.rdb").unwrap();
+//! let mut parser = rdb::RdbParser::new(
+//!     std::io::BufReader::new(file),
+//!     rdb::formatter::JSON::new(),
+//!     rdb::filter::Simple::new(),
+//! );
+//! parser.register_module_decoder("text-type", Text);
+//! parser.parse().unwrap();
+//! ```
+//!
+//! The bloom and cuckoo filters of RedisBloom are decoded out of the box and
+//! passed to `Formatter::probabilistic_filter`.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::constants::module;
+use crate::formatter::Formatter;
+use crate::parser::{read_blob, read_length};
+use crate::types::{FilterInfo, FilterKind, RdbError, RdbOk, RdbResult};
+
+/// A single number or string saved by a module.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleValue {
+    Signed(i64),
+    Unsigned(u64),
+    Float(f32),
+    Double(f64),
+    String(Vec<u8>),
+}
+
+/// Reads the values a module saved for one key, in the order it saved them.
+pub struct ModuleReader<'a> {
+    input: &'a mut dyn Read,
+    encoding_version: u64,
+    finished: bool,
+}
+
+impl<'a> ModuleReader<'a> {
+    /// Creates a reader for the values in `input`, which starts right after
+    /// the module ID, e.g. to test decoders.
+    pub fn new(input: &'a mut dyn Read, encoding_version: u64) -> ModuleReader<'a> {
+        ModuleReader {
+            input,
+            encoding_version,
+            finished: false,
+        }
+    }
+
+    /// The version of its encoding the module saved the value with.
+    pub fn encoding_version(&self) -> u64 {
+        self.encoding_version
+    }
+
+    /// Returns the next value or `None` once the end of the module value is
+    /// reached.
+    pub fn next_value(&mut self) -> RdbResult<Option<ModuleValue>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let value = match read_length(&mut self.input)? {
+            module::OPCODE_EOF => {
+                self.finished = true;
+                return Ok(None);
+            }
+            module::OPCODE_SINT => ModuleValue::Signed(read_length(&mut self.input)? as i64),
+            module::OPCODE_UINT => ModuleValue::Unsigned(read_length(&mut self.input)?),
+            module::OPCODE_FLOAT => ModuleValue::Float(self.input.read_f32::<LittleEndian>()?),
+            module::OPCODE_DOUBLE => ModuleValue::Double(self.input.read_f64::<LittleEndian>()?),
+            module::OPCODE_STRING => ModuleValue::String(read_blob(&mut self.input)?),
+            opcode => {
+                return Err(RdbError::Other(format!(
+                    "Unknown module opcode: {}",
+                    opcode
+                )))
+            }
+        };
+
+        Ok(Some(value))
+    }
+
+    /// Reads the next value as a signed integer, like
+    /// `RedisModule_LoadSigned`.
+    pub fn expect_signed(&mut self) -> RdbResult<i64> {
+        match self.expect("signed integer")? {
+            ModuleValue::Signed(value) => Ok(value),
+            value => Err(unexpected("signed integer", &value)),
+        }
+    }
+
+    /// Reads the next value as an unsigned integer, like
+    /// `RedisModule_LoadUnsigned`.
+    pub fn expect_unsigned(&mut self) -> RdbResult<u64> {
+        match self.expect("unsigned integer")? {
+            ModuleValue::Unsigned(value) => Ok(value),
+            value => Err(unexpected("unsigned integer", &value)),
+        }
+    }
+
+    /// Reads the next value as a float, like `RedisModule_LoadFloat`.
+    pub fn expect_float(&mut self) -> RdbResult<f32> {
+        match self.expect("float")? {
+            ModuleValue::Float(value) => Ok(value),
+            value => Err(unexpected("float", &value)),
+        }
+    }
+
+    /// Reads the next value as a double, like `RedisModule_LoadDouble`.
+    pub fn expect_double(&mut self) -> RdbResult<f64> {
+        match self.expect("double")? {
+            ModuleValue::Double(value) => Ok(value),
+            value => Err(unexpected("double", &value)),
+        }
+    }
+
+    /// Reads the next value as a string, like
+    /// `RedisModule_LoadStringBuffer`.
+    pub fn expect_string(&mut self) -> RdbResult<Vec<u8>> {
+        match self.expect("string")? {
+            ModuleValue::String(value) => Ok(value),
+            value => Err(unexpected("string", &value)),
+        }
+    }
+
+    /// Skips the values the decoder did not read.
+    pub(crate) fn finish(&mut self) -> RdbOk {
+        while self.next_value()?.is_some() {}
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: &str) -> RdbResult<ModuleValue> {
+        self.next_value()?.ok_or_else(|| {
+            RdbError::Other(format!(
+                "Expected a {} in module value, but it ended",
+                expected
+            ))
+        })
+    }
+}
+
+fn unexpected(expected: &str, value: &ModuleValue) -> RdbError {
+    RdbError::Other(format!(
+        "Expected a {} in module value, found {:?}",
+        expected, value
+    ))
+}
+
+/// Decodes the values of one module type, e.g. to pass them to the
+/// formatter as hashes or strings.
+pub trait ModuleDecoder {
+    /// Decodes the value of `key`. Values the decoder does not read are
+    /// skipped.
+    fn decode(
+        &mut self,
+        key: &[u8],
+        expiry: Option<u64>,
+        values: &mut ModuleReader,
+        formatter: &mut dyn Formatter,
+    ) -> RdbOk;
+}
+
+/// The module name of RedisBloom's bloom filters.
+pub const BLOOM_MODULE: &str = "MBbloom--";
+/// The module name of RedisBloom's cuckoo filters.
+pub const CUCKOO_MODULE: &str = "MBbloomCF";
+
+/// The encoding version that added the options of bloom filters.
+const BLOOM_MIN_OPTIONS_VERSION: u64 = 2;
+/// The encoding version that added the growth factor of bloom filters.
+const BLOOM_MIN_GROWTH_VERSION: u64 = 4;
+/// The encoding version that added the bucket size, maximum number of
+/// iterations and expansion of cuckoo filters.
+const CUCKOO_MIN_EXPANSION_VERSION: u64 = 4;
+/// The bucket size of cuckoo filters before it was configurable.
+const CUCKOO_DEFAULT_BUCKET_SIZE: u64 = 2;
+
+/// Decodes the parameters of RedisBloom's scalable bloom filters.
+///
+/// A bloom filter grows by adding filters, each storing its capacity,
+/// error rate, hash functions and bit array.
+pub struct BloomDecoder;
+
+impl ModuleDecoder for BloomDecoder {
+    fn decode(
+        &mut self,
+        key: &[u8],
+        expiry: Option<u64>,
+        values: &mut ModuleReader,
+        formatter: &mut dyn Formatter,
+    ) -> RdbOk {
+        let version = values.encoding_version();
+        let items = values.expect_unsigned()?;
+        let filters = values.expect_unsigned()?;
+        if version >= BLOOM_MIN_OPTIONS_VERSION {
+            values.expect_unsigned()?;
+        }
+        if version >= BLOOM_MIN_GROWTH_VERSION {
+            values.expect_unsigned()?;
+        }
+
+        let mut capacity = 0;
+        let mut error_rate = None;
+        for _ in 0..filters {
+            capacity += values.expect_unsigned()?;
+            let error = values.expect_double()?;
+            error_rate.get_or_insert(error);
+            // Hash functions and bits per entry, then the number of bits
+            // and its next power of two, which the first version computed.
+            values.expect_unsigned()?;
+            values.expect_double()?;
+            if version > 0 {
+                values.expect_unsigned()?;
+                values.expect_unsigned()?;
+            }
+            // The bit array and the items of this filter.
+            values.expect_string()?;
+            values.expect_unsigned()?;
+        }
+
+        let filter = FilterInfo {
+            kind: FilterKind::Bloom,
+            items,
+            capacity,
+            error_rate,
+            filters,
+        };
+        formatter.probabilistic_filter(key, &filter, expiry)
+    }
+}
+
+/// Decodes the parameters of RedisBloom's cuckoo filters.
+pub struct CuckooDecoder;
+
+impl ModuleDecoder for CuckooDecoder {
+    fn decode(
+        &mut self,
+        key: &[u8],
+        expiry: Option<u64>,
+        values: &mut ModuleReader,
+        formatter: &mut dyn Formatter,
+    ) -> RdbOk {
+        let filters = values.expect_unsigned()?;
+        let buckets = values.expect_unsigned()?;
+        let items = values.expect_unsigned()?;
+        let _deletes = values.expect_unsigned()?;
+
+        // Older versions stored filters of equal size, newer ones grow by
+        // the expansion factor and store the buckets of each filter.
+        let capacity = if values.encoding_version() >= CUCKOO_MIN_EXPANSION_VERSION {
+            let bucket_size = values.expect_unsigned()?;
+            let _max_iterations = values.expect_unsigned()?;
+            let _expansion = values.expect_unsigned()?;
+            let mut capacity = 0;
+            for _ in 0..filters {
+                capacity += values.expect_unsigned()? * bucket_size;
+                values.expect_string()?;
+            }
+            capacity
+        } else {
+            buckets * CUCKOO_DEFAULT_BUCKET_SIZE * filters
+        };
+
+        let filter = FilterInfo {
+            kind: FilterKind::Cuckoo,
+            items,
+            capacity,
+            error_rate: None,
+            filters,
+        };
+        formatter.probabilistic_filter(key, &filter, expiry)
+    }
+}
+
+/// The decoders every parser starts with.
+pub(crate) fn builtin_decoders() -> HashMap<String, Box<dyn ModuleDecoder>> {
+    let mut decoders: HashMap<String, Box<dyn ModuleDecoder>> = HashMap::new();
+    decoders.insert(BLOOM_MODULE.to_owned(), Box::new(BloomDecoder));
+    decoders.insert(CUCKOO_MODULE.to_owned(), Box::new(CuckooDecoder));
+    decoders
+}
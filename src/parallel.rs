@@ -0,0 +1,274 @@
+//! Parallel parsing of files spread across several databases.
+//!
+//! A quick pre-scan skips over every value to find the offsets of all
+//! SELECTDB opcodes. The sections between them are then decoded on worker
+//! threads, while the calling thread replays the decoded callbacks into the
+//! formatter strictly in file order.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Mutex;
+use std::thread;
+
+use regex::bytes::Regex;
+
+use crate::event::{Event, Recorder};
+use crate::filter::Filter;
+use crate::formatter::{Formatter, Nil};
+use crate::parser::RdbParser;
+use crate::sink::Sink;
+use crate::types::{ParseSummary, RdbOk, RdbResult, Warning};
+
+/// Offset of the first opcode, right after the magic string and the version.
+const HEADER_LENGTH: u64 = 9;
+
+/// Number of event batches a worker may decode ahead of the formatter.
+const CHANNEL_CAPACITY: usize = 16;
+
+/// Skips every key during the pre-scan.
+struct SkipAll;
+
+impl Filter for SkipAll {
+    fn matches_db(&self, _db: u32) -> bool {
+        false
+    }
+}
+
+struct Section {
+    start: u64,
+    length: u64,
+}
+
+fn scan_sections(path: &Path) -> RdbResult<Vec<Section>> {
+    let file = File::open(path)?;
+    let file_length = file.metadata()?.len();
+
+    let mut parser = RdbParser::new(BufReader::new(file), Nil::new(), SkipAll);
+    let mut offsets = parser.scan_databases()?;
+    offsets.insert(0, HEADER_LENGTH);
+    offsets.push(file_length);
+
+    Ok(offsets
+        .windows(2)
+        .filter(|w| w[1] > w[0])
+        .map(|w| Section {
+            start: w[0],
+            length: w[1] - w[0],
+        })
+        .collect())
+}
+
+/// Reports the number of bytes read through it.
+struct Progress<'a, R> {
+    inner: R,
+    report: Option<&'a (dyn Fn(u64) + Sync)>,
+}
+
+impl<R: Read> Read for Progress<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(report) = self.report {
+            report(read as u64);
+        }
+        Ok(read)
+    }
+}
+
+/// What decoding a section found besides the values.
+type Report = (Vec<Warning>, ParseSummary);
+
+struct Options<'a> {
+    strict: bool,
+    lenient: bool,
+    hyperloglogs: bool,
+    bitmap_keys: Option<Regex>,
+    progress: Option<&'a (dyn Fn(u64) + Sync)>,
+}
+
+fn decode_section<L: Filter>(
+    path: &Path,
+    section: &Section,
+    filter: L,
+    recorder: Recorder,
+    options: &Options,
+) -> RdbResult<Report> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(section.start))?;
+    let input = Progress {
+        inner: BufReader::new(file).take(section.length),
+        report: options.progress,
+    };
+
+    let mut parser = RdbParser::new(input, recorder, filter);
+    parser.set_strict(options.strict);
+    parser.set_lenient(options.lenient);
+    parser.set_hyperloglogs(options.hyperloglogs);
+    if let Some(keys) = &options.bitmap_keys {
+        parser.set_bitmap_keys(keys.clone());
+    }
+    parser.parse_section(section.start)?;
+    let (mut recorder, warnings, summary) = parser.into_parts();
+    recorder.flush()?;
+
+    Ok((warnings, summary))
+}
+
+/// Parses a file on several threads, with the options of `RdbParser`.
+///
+/// The formatter receives exactly the same callbacks in the same order as
+/// with `RdbParser`, and the warnings and the summary are the same too.
+pub struct ParallelParser<'a, P: AsRef<Path>, F: Formatter, L: Filter + Sync> {
+    path: P,
+    formatter: F,
+    filter: L,
+    jobs: usize,
+    options: Options<'a>,
+    warnings: Vec<Warning>,
+    summary: ParseSummary,
+}
+
+impl<'a, P: AsRef<Path>, F: Formatter, L: Filter + Sync> ParallelParser<'a, P, F, L> {
+    pub fn new(path: P, formatter: F, filter: L) -> ParallelParser<'a, P, F, L> {
+        ParallelParser {
+            path,
+            formatter,
+            filter,
+            jobs: 0,
+            options: Options {
+                strict: false,
+                lenient: false,
+                hyperloglogs: false,
+                bitmap_keys: None,
+                progress: None,
+            },
+            warnings: vec![],
+            summary: ParseSummary::default(),
+        }
+    }
+
+    /// Sets the number of threads. Defaults to 0, the available parallelism.
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = jobs;
+    }
+
+    /// See `RdbParser::set_strict`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.options.strict = strict;
+    }
+
+    /// See `RdbParser::set_lenient`.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.options.lenient = lenient;
+    }
+
+    /// See `RdbParser::set_hyperloglogs`.
+    pub fn set_hyperloglogs(&mut self, hyperloglogs: bool) {
+        self.options.hyperloglogs = hyperloglogs;
+    }
+
+    /// See `RdbParser::set_bitmap_keys`.
+    pub fn set_bitmap_keys(&mut self, keys: Regex) {
+        self.options.bitmap_keys = Some(keys);
+    }
+
+    /// Calls `progress` from the worker threads with the number of bytes
+    /// each read decoded, e.g. to drive a progress bar.
+    pub fn set_progress(&mut self, progress: &'a (dyn Fn(u64) + Sync)) {
+        self.options.progress = Some(progress);
+    }
+
+    /// The sizes of the databases parsed, compared to their RESIZEDB opcodes.
+    pub fn summary(&self) -> &ParseSummary {
+        &self.summary
+    }
+
+    /// All problems found.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn parse(&mut self) -> RdbOk {
+        let path = self.path.as_ref();
+        let mut formatter = Sink::new(&mut self.formatter);
+        let sections = scan_sections(path)?;
+
+        let jobs = match self.jobs {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        };
+        let jobs = jobs.min(sections.len()).max(1);
+
+        let mut receivers: Vec<Receiver<RdbResult<Vec<Event>>>> =
+            Vec::with_capacity(sections.len());
+        let mut queue = VecDeque::with_capacity(sections.len());
+        for (i, section) in sections.into_iter().enumerate() {
+            let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+            receivers.push(receiver);
+            queue.push_back((i, section, sender));
+        }
+        let reports: Mutex<Vec<Option<Report>>> = Mutex::new(vec![None; queue.len()]);
+        let queue = Mutex::new(queue);
+        let filter = &self.filter;
+        let options = &self.options;
+
+        let res: RdbOk = thread::scope(|scope| {
+            for _ in 0..jobs {
+                scope.spawn(|| loop {
+                    let job = queue.lock().unwrap().pop_front();
+                    let (i, section, sender) = match job {
+                        Some(job) => job,
+                        None => break,
+                    };
+
+                    let recorder = Recorder::new(sender.clone());
+                    match decode_section(path, &section, filter, recorder, options) {
+                        Ok(report) => reports.lock().unwrap()[i] = Some(report),
+                        // The receiver might be gone already if the formatter failed.
+                        Err(err) => {
+                            let _ = sender.send(Err(err));
+                        }
+                    }
+                });
+            }
+
+            // Dropping the receivers on an early return makes the workers'
+            // sends fail, so they stop decoding instead of filling the channels.
+            formatter.start_rdb()?;
+            for receiver in receivers {
+                for events in receiver {
+                    for event in events? {
+                        event.replay(&mut formatter)?;
+                    }
+                }
+            }
+
+            Ok(())
+        });
+        res?;
+
+        for (warnings, summary) in reports.into_inner().unwrap().into_iter().flatten() {
+            self.warnings.extend(warnings);
+            self.summary.databases.extend(summary.databases);
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses the file at `path`, decoding its databases on up to `jobs` threads.
+///
+/// The formatter receives exactly the same callbacks in the same order as
+/// with `parse`. A `jobs` value of 0 uses the available parallelism.
+pub fn parse<P: AsRef<Path>, F: Formatter, T: Filter + Sync>(
+    path: P,
+    formatter: F,
+    filter: T,
+    jobs: usize,
+) -> RdbOk {
+    let mut parser = ParallelParser::new(path, formatter, filter);
+    parser.set_jobs(jobs);
+    parser.parse()
+}
@@ -1,69 +1,168 @@
-use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
-use std::io::{Cursor, Read};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+use regex::bytes::Regex;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::time::Instant;
 use std::{f64, str};
 
+use crate::codec::{self, read_zipmap_length};
 use crate::filter::Filter;
+use crate::formatter::payload::crc64_update;
 use crate::formatter::Formatter;
+use crate::header::{is_redis_aux_field, Header};
 use crate::helper;
 use crate::helper::read_exact;
+use crate::hyperloglog;
+use crate::listpack::Listpack;
+use crate::module::{self as modules, ModuleDecoder, ModuleReader};
+use crate::sink::Sink;
+use crate::ziplist::Ziplist;
 
 #[doc(hidden)]
-use crate::constants::{constant, encoding, encoding_type, op_code, version};
+use crate::constants::{
+    constant, encoding, encoding_type, module, op_code, quicklist, stream, version,
+};
 
 #[doc(hidden)]
 pub use crate::types::{
-    EncodingType, /* error and result types */
-    RdbError, RdbOk, RdbResult, Type, ZiplistEntry,
+    BlobStats, DatabaseSize, EncodingType, KeyUsage,
+    ParseSummary, /* error and result types */
+    RdbError, RdbOk, RdbResult, RdbStats, StreamConsumer, StreamGroup, StreamId, StreamMetadata,
+    StreamPendingEntry, Type, ValueLength, Warning, ZiplistEntry,
 };
 
 pub struct RdbParser<R: Read, F: Formatter, L: Filter> {
-    input: R,
-    formatter: F,
+    input: Checksummed<R>,
+    formatter: Sink<F>,
     filter: L,
     last_expiretime: Option<u64>,
+    last_usage: Option<KeyUsage>,
+    last_database: u32,
+    /// The hash slot of the following keys, if the dump tells it.
+    last_slot: Option<u32>,
+    options: RdbParserOptions,
+    blob_stats: BlobStats,
+    warnings: Vec<Warning>,
+    ignored: Vec<Warning>,
+    resizedb: Option<(u32, u32)>,
+    header: Header,
+    keys_in_db: u32,
+    expires_in_db: u32,
+    summary: ParseSummary,
+    stats: RdbStats,
+    module_decoders: HashMap<String, Box<dyn ModuleDecoder>>,
 }
 
+/// The length of the CRC64 checksum following the EOF opcode.
+const CHECKSUM_LENGTH: usize = 8;
+
 #[inline]
 fn other_error(desc: impl Into<String>) -> RdbError {
     RdbError::Other(desc.into())
 }
 
-pub fn read_length_with_encoding<R: Read>(input: &mut R) -> RdbResult<(u32, bool)> {
-    let length;
-    let mut is_encoded = false;
-
+/// Reads a length, or the encoding of a special encoded string if the
+/// returned flag is set.
+///
+/// The top two bits of the first byte select a 6 or 14 bit length, an
+/// encoding, or a longer length whose size follows in the rest of the byte:
+/// `0x80` for a 32 bit and `0x81` for a 64 bit big endian length.
+pub fn read_length_with_encoding<R: Read>(input: &mut R) -> RdbResult<(u64, bool)> {
     let enc_type = input.read_u8()?;
 
+    match enc_type {
+        constant::RDB_32BITLEN => return Ok((input.read_u32::<BigEndian>()? as u64, false)),
+        constant::RDB_64BITLEN => return Ok((input.read_u64::<BigEndian>()?, false)),
+        _ => {}
+    }
+
     match (enc_type & 0xC0) >> 6 {
-        constant::RDB_ENCVAL => {
-            is_encoded = true;
-            length = (enc_type & 0x3F) as u32;
-        }
-        constant::RDB_6BITLEN => {
-            length = (enc_type & 0x3F) as u32;
-        }
+        constant::RDB_ENCVAL => Ok(((enc_type & 0x3F) as u64, true)),
+        constant::RDB_6BITLEN => Ok(((enc_type & 0x3F) as u64, false)),
         constant::RDB_14BITLEN => {
             let next_byte = input.read_u8()?;
-            length = (((enc_type & 0x3F) as u32) << 8) | next_byte as u32;
-        }
-        _ => {
-            length = input.read_u32::<BigEndian>()?;
+            Ok(((((enc_type & 0x3F) as u64) << 8) | next_byte as u64, false))
         }
+        _ => Err(other_error(format!(
+            "Unknown length encoding: {}",
+            enc_type
+        ))),
     }
-
-    Ok((length, is_encoded))
 }
 
-pub fn read_length<R: Read>(input: &mut R) -> RdbResult<u32> {
+/// Reads a length, see `read_length_with_encoding`.
+pub fn read_length<R: Read>(input: &mut R) -> RdbResult<u64> {
     let (length, _) = read_length_with_encoding(input)?;
     Ok(length)
 }
 
+/// Reads the number of elements of a value, which the formatters take as
+/// 32 bit numbers.
+fn read_count<R: Read>(input: &mut R) -> RdbResult<u32> {
+    let length = read_length(input)?;
+    u32::try_from(length).map_err(|_| other_error(format!("Too many elements: {}", length)))
+}
+
+/// Whether `code` is a value type this version can read.
+fn is_value_type(code: u8) -> bool {
+    matches!(
+        code,
+        encoding_type::STRING..=encoding_type::MODULE_2
+            | encoding_type::HASH_ZIPMAP..=encoding_type::HASH_LISTPACK_EX
+    )
+}
+
+/// A node of a quicklist 2, holding a single large element or a listpack
+/// of smaller ones.
+enum QuicklistNode {
+    Plain(Vec<u8>),
+    Packed(Listpack),
+}
+
+/// Decodes the name of a module from its ID, which packs 9 characters of 6
+/// bits each above a 10 bit encoding version.
+fn module_name(module_id: u64) -> String {
+    (0..9)
+        .map(|i| module::NAME_CHARSET[((module_id >> (58 - 6 * i)) & 63) as usize] as char)
+        .collect()
+}
+
+/// Reads a stream ID stored as two lengths, as opposed to 16 raw bytes.
+fn read_stream_id_lengths<R: Read>(input: &mut R) -> RdbResult<StreamId> {
+    Ok(StreamId {
+        ms: read_length(input)?,
+        seq: read_length(input)?,
+    })
+}
+
+fn read_stream_id(raw: &[u8]) -> RdbResult<StreamId> {
+    if raw.len() != 16 {
+        return Err(other_error(format!(
+            "Invalid stream ID length: {}",
+            raw.len()
+        )));
+    }
+    let mut raw = Cursor::new(raw);
+    Ok(StreamId {
+        ms: raw.read_u64::<BigEndian>()?,
+        seq: raw.read_u64::<BigEndian>()?,
+    })
+}
+
+/// Fills `buf`, even from an input handing out fewer bytes at a time.
+fn read_exactly<R: Read>(input: &mut R, buf: &mut [u8], what: &str) -> RdbOk {
+    input.read_exact(buf).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => {
+            other_error(format!("Could not read enough bytes for the {}", what))
+        }
+        _ => err.into(),
+    })
+}
+
 pub fn verify_magic<R: Read>(input: &mut R) -> RdbOk {
     let mut magic = [0; 5];
-    if input.read(&mut magic)? != 5 {
-        return Err(other_error("Could not read enough bytes for the magic"));
-    }
+    read_exactly(input, &mut magic, "magic")?;
 
     if magic == constant::RDB_MAGIC.as_bytes() {
         Ok(())
@@ -73,17 +172,33 @@ pub fn verify_magic<R: Read>(input: &mut R) -> RdbOk {
 }
 
 pub fn verify_version<R: Read>(input: &mut R) -> RdbOk {
+    read_version(input).map(|_| ())
+}
+
+/// Reads the 4 digit version number and checks that it is supported.
+pub fn read_version<R: Read>(input: &mut R) -> RdbResult<u32> {
+    let version = read_version_number(input)?;
+    check_version(version)?;
+    Ok(version)
+}
+
+fn read_version_number<R: Read>(input: &mut R) -> RdbResult<u32> {
     let mut version = [0; 4];
-    if input.read(&mut version)? != 4 {
-        return Err(other_error("Could not read enough bytes for the version"));
-    }
+    read_exactly(input, &mut version, "version")?;
 
-    let version = (version[0] - 48) as u32 * 1000
-        + (version[1] - 48) as u32 * 100
-        + (version[2] - 48) as u32 * 10
-        + (version[3] - 48) as u32;
+    if !version.iter().all(u8::is_ascii_digit) {
+        return Err(other_error(format!(
+            "Invalid version: {}",
+            String::from_utf8_lossy(&version)
+        )));
+    }
+    Ok(version
+        .iter()
+        .fold(0, |version, digit| version * 10 + (digit - b'0') as u32))
+}
 
-    let is_ok = version >= version::SUPPORTED_MINIMUM && version <= version::SUPPORTED_MAXIMUM;
+fn check_version(version: u32) -> RdbOk {
+    let is_ok = (version::SUPPORTED_MINIMUM..=version::SUPPORTED_MAXIMUM).contains(&version);
 
     if is_ok {
         Ok(())
@@ -97,166 +212,755 @@ pub fn verify_version<R: Read>(input: &mut R) -> RdbOk {
     }
 }
 
+/// Reads a string, which may be stored as an integer or LZF compressed.
 pub fn read_blob<R: Read>(input: &mut R) -> RdbResult<Vec<u8>> {
+    read_blob_compressed(input, None).map(|(blob, _)| blob)
+}
+
+/// Like `read_blob`, but also returns the compressed length of LZF
+/// compressed blobs, and fails on blobs longer than `limit`.
+fn read_blob_compressed<R: Read>(
+    input: &mut R,
+    limit: Option<u64>,
+) -> RdbResult<(Vec<u8>, Option<u64>)> {
+    match read_raw_blob(input, limit)? {
+        RawBlob::Plain(blob) => Ok((blob, None)),
+        RawBlob::Lzf { data, real_length } => {
+            let blob = decompress(&data, real_length)?;
+            Ok((blob, Some(data.len() as u64)))
+        }
+    }
+}
+
+fn decompress(data: &[u8], real_length: u64) -> RdbResult<Vec<u8>> {
+    lzf::decompress(data, real_length as usize)
+        .map_err(|err| other_error(format!("Decompressing failed: {:?}", err)))
+}
+
+/// Parses a score stored as a string, as ziplists and older sorted sets do.
+fn parse_score(score: &[u8]) -> RdbResult<f64> {
+    str::from_utf8(score)
+        .ok()
+        .and_then(|score| score.parse().ok())
+        .ok_or_else(|| {
+            other_error(format!(
+                "Invalid score: {:?}",
+                String::from_utf8_lossy(score)
+            ))
+        })
+}
+
+/// A blob as stored in the dump.
+enum RawBlob {
+    /// A blob stored as is or as an integer, already turned into bytes.
+    Plain(Vec<u8>),
+    /// The LZF compressed payload of a blob of `real_length` bytes.
+    Lzf { data: Vec<u8>, real_length: u64 },
+}
+
+/// Fails if a blob is longer than `limit`, see
+/// `RdbParserOptions::max_blob_length`.
+fn check_blob_length(length: u64, limit: Option<u64>) -> RdbOk {
+    match limit {
+        Some(limit) if length > limit => Err(other_error(format!(
+            "Blob of {} bytes exceeds the limit of {} bytes",
+            length, limit
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn read_raw_blob<R: Read>(input: &mut R, limit: Option<u64>) -> RdbResult<RawBlob> {
     let (length, is_encoded) = read_length_with_encoding(input)?;
 
     if is_encoded {
         let result = match length {
             encoding::INT8 => helper::int_to_vec(input.read_i8()? as i32),
             encoding::INT16 => helper::int_to_vec(input.read_i16::<LittleEndian>()? as i32),
-            encoding::INT32 => helper::int_to_vec(input.read_i32::<LittleEndian>()? as i32),
+            encoding::INT32 => helper::int_to_vec(input.read_i32::<LittleEndian>()?),
             encoding::LZF => {
                 let compressed_length = read_length(input)?;
                 let real_length = read_length(input)?;
+                check_blob_length(real_length.max(compressed_length), limit)?;
                 let data = read_exact(input, compressed_length as usize)?;
-                lzf::decompress(&data, real_length as usize).unwrap()
+                return Ok(RawBlob::Lzf { data, real_length });
             }
-            _ => panic!("Unknown encoding: {}", length),
+            _ => return Err(other_error(format!("Unknown string encoding: {}", length))),
         };
 
-        Ok(result)
+        Ok(RawBlob::Plain(result))
     } else {
-        read_exact(input, length as usize)
+        check_blob_length(length, limit)?;
+        Ok(RawBlob::Plain(read_exact(input, length as usize)?))
+    }
+}
+
+/// The encodings that store a whole value in one blob.
+#[derive(Debug, Clone, Copy)]
+enum Container {
+    Ziplist,
+    Listpack,
+    Intset,
+    Zipmap,
+}
+
+impl Container {
+    /// The number of bytes up to and including the number of entries.
+    fn header_length(self) -> usize {
+        match self {
+            Container::Ziplist => 10,
+            Container::Listpack => 6,
+            Container::Intset => 8,
+            Container::Zipmap => 1,
+        }
+    }
+
+    /// The number of entries stored in the header, unless there are too many
+    /// to count there.
+    fn header_entries(self, header: &[u8]) -> Option<u64> {
+        if header.len() < self.header_length() {
+            return None;
+        }
+        match self {
+            Container::Ziplist => match LittleEndian::read_u16(&header[8..10]) {
+                u16::MAX => None,
+                entries => Some(entries as u64),
+            },
+            Container::Listpack => match LittleEndian::read_u16(&header[4..6]) {
+                u16::MAX => None,
+                entries => Some(entries as u64),
+            },
+            Container::Intset => Some(LittleEndian::read_u32(&header[4..8]) as u64),
+            Container::Zipmap => match header[0] {
+                zmlen if zmlen < 254 => Some(zmlen as u64),
+                _ => None,
+            },
+        }
+    }
+
+    /// Counts the entries by walking the whole blob.
+    fn count_entries(self, blob: Vec<u8>) -> RdbResult<u64> {
+        let mut entries = 0;
+        match self {
+            Container::Ziplist => {
+                let mut ziplist = Ziplist::new(blob)?;
+                while ziplist.next_entry()?.is_some() {
+                    entries += 1;
+                }
+            }
+            Container::Listpack => {
+                let mut listpack = Listpack::new(blob)?;
+                while listpack.next_entry()?.is_some() {
+                    entries += 1;
+                }
+            }
+            Container::Intset => {
+                return Err(other_error("Truncated intset header"));
+            }
+            Container::Zipmap => {
+                let mut zipmap = Cursor::new(blob);
+                zipmap.read_u8()?;
+                loop {
+                    let next_byte = zipmap.read_u8()?;
+                    if next_byte == 0xFF {
+                        break;
+                    }
+                    // Skip the field, then the value and its free bytes.
+                    let field_length = read_zipmap_length(next_byte, &mut zipmap)?;
+                    zipmap.set_position(zipmap.position() + field_length);
+                    let next_byte = zipmap.read_u8()?;
+                    let value_length = read_zipmap_length(next_byte, &mut zipmap)?;
+                    let free = zipmap.read_u8()? as u64;
+                    zipmap.set_position(zipmap.position() + value_length + free);
+                    entries += 1;
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// The options of a parser, see `RdbParser::with_options`.
+///
+/// ```
+/// let options = rdb::RdbParserOptions {
+///     strict: true,
+///     verify_checksum: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct RdbParserOptions {
+    /// See `RdbParser::set_strict`.
+    pub strict: bool,
+    /// See `RdbParser::set_lenient`.
+    pub lenient: bool,
+    /// See `RdbParser::set_tolerant`.
+    pub tolerant: bool,
+    /// See `RdbParser::set_lengths_only`.
+    pub lengths_only: bool,
+    /// See `RdbParser::set_raw_strings`.
+    pub raw_strings: bool,
+    /// See `RdbParser::set_hyperloglogs`.
+    pub hyperloglogs: bool,
+    /// See `RdbParser::set_bitmap_keys`.
+    pub bitmap_keys: Option<Regex>,
+    /// Compares the CRC64 checksum at the end of the dump with its contents
+    /// and fails if they differ. Dumps written with `rdbchecksum no` store
+    /// 0, which is not checked.
+    pub verify_checksum: bool,
+    /// Skips the keys that expired before this Unix time in milliseconds,
+    /// like a server loading the dump at that time would.
+    pub expired_before: Option<u64>,
+    /// Fails on keys, strings and encoded values longer than this many
+    /// bytes instead of reading them into memory.
+    pub max_blob_length: Option<u64>,
+    /// Decodes the values of the modules in `module::builtin_decoders`
+    /// instead of skipping them, on by default.
+    pub builtin_module_decoders: bool,
+    /// Reads dumps of versions newer than this crate supports, with a
+    /// warning. They still fail on opcodes and value types it doesn't know.
+    pub newer_versions: bool,
+}
+
+impl Default for RdbParserOptions {
+    fn default() -> RdbParserOptions {
+        RdbParserOptions {
+            strict: false,
+            lenient: false,
+            tolerant: false,
+            lengths_only: false,
+            raw_strings: false,
+            hyperloglogs: false,
+            bitmap_keys: None,
+            verify_checksum: false,
+            expired_before: None,
+            max_blob_length: None,
+            builtin_module_decoders: true,
+            newer_versions: false,
+        }
+    }
+}
+
+/// The input of a parser, counting the bytes read and computing their
+/// CRC64 checksum if it is to be verified.
+struct Checksummed<R> {
+    inner: R,
+    crc: Option<u64>,
+    position: u64,
+}
+
+impl<R> Checksummed<R> {
+    fn new(inner: R, verify: bool) -> Checksummed<R> {
+        Checksummed {
+            inner,
+            crc: if verify { Some(0) } else { None },
+            position: 0,
+        }
     }
 }
 
-fn read_ziplist_metadata<T: Read>(input: &mut T) -> RdbResult<(u32, u32, u16)> {
-    let zlbytes = input.read_u32::<LittleEndian>()?;
-    let zltail = input.read_u32::<LittleEndian>()?;
-    let zllen = input.read_u16::<LittleEndian>()?;
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.position += length as u64;
+        if let Some(crc) = &mut self.crc {
+            *crc = crc64_update(*crc, &buf[..length]);
+        }
+        Ok(length)
+    }
+}
 
-    Ok((zlbytes, zltail, zllen))
+impl<R: Seek> Seek for Checksummed<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.position = position;
+        Ok(position)
+    }
 }
 
 impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
     pub fn new(input: R, formatter: F, filter: L) -> RdbParser<R, F, L> {
+        RdbParser::with_options(input, formatter, filter, RdbParserOptions::default())
+    }
+
+    /// Creates a parser with all of its options set at once.
+    pub fn with_options(
+        input: R,
+        formatter: F,
+        filter: L,
+        options: RdbParserOptions,
+    ) -> RdbParser<R, F, L> {
+        let module_decoders = if options.builtin_module_decoders {
+            modules::builtin_decoders()
+        } else {
+            HashMap::new()
+        };
+
         RdbParser {
-            input,
-            formatter,
+            input: Checksummed::new(input, options.verify_checksum),
+            formatter: Sink::new(formatter),
             filter,
             last_expiretime: None,
+            last_usage: None,
+            last_database: 0,
+            last_slot: None,
+            options,
+            blob_stats: BlobStats::default(),
+            warnings: vec![],
+            ignored: vec![],
+            resizedb: None,
+            header: Header::default(),
+            keys_in_db: 0,
+            expires_in_db: 0,
+            summary: ParseSummary::default(),
+            stats: RdbStats::default(),
+            module_decoders,
+        }
+    }
+
+    /// The options the parser was created with, as changed by the setters
+    /// since.
+    pub fn options(&self) -> &RdbParserOptions {
+        &self.options
+    }
+
+    /// Enables validation of redundant metadata such as ziplist headers,
+    /// intset sizes and the key counts announced by RESIZEDB.
+    ///
+    /// Mismatches don't stop the parser, they are collected as warnings.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.options.strict = strict;
+    }
+
+    /// Skips values whose extent is known but that can't be decoded instead
+    /// of failing, so dumps of slightly newer Redis versions stay partially
+    /// readable.
+    ///
+    /// Only module values qualify: their numbers and strings are each
+    /// tagged, so a value a registered `ModuleDecoder` rejects, e.g. of a
+    /// newer encoding version, is skipped up to its end marker, reported
+    /// through `Formatter::module` and as a warning. Parts of the value the
+    /// decoder already passed on are not taken back. Opcodes and value
+    /// types this version doesn't know have no known layout, so they fail
+    /// the parse with `RdbError::UnknownType` in either mode, after
+    /// `Formatter::unknown_type` reported them.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.options.lenient = lenient;
+    }
+
+    /// Accepts the quirks of dumps exported from managed services instead
+    /// of reporting them as warnings in strict mode.
+    ///
+    /// These are AUX fields Redis doesn't write and bytes following the
+    /// checksum. Either way they are skipped, tolerant mode only moves
+    /// them from `warnings` to `ignored`.
+    pub fn set_tolerant(&mut self, tolerant: bool) {
+        self.options.tolerant = tolerant;
+    }
+
+    /// Skips the contents of values and reports each key through
+    /// `Formatter::value_length` instead of the value callbacks.
+    ///
+    /// Strings and the elements of collections are skipped by their length
+    /// without reading them, for scans that only need sizes.
+    pub fn set_lengths_only(&mut self, lengths_only: bool) {
+        self.options.lengths_only = lengths_only;
+    }
+
+    /// Passes strings stored LZF compressed to `Formatter::compressed_set`
+    /// without decompressing them.
+    ///
+    /// Formatters that write the payload back into a dump as is then skip
+    /// a decompression and recompression cycle for each of these strings.
+    pub fn set_raw_strings(&mut self, raw_strings: bool) {
+        self.options.raw_strings = raw_strings;
+    }
+
+    /// Passes strings holding a HyperLogLog to `Formatter::hyperloglog`
+    /// with their estimated cardinality instead of to `set`.
+    ///
+    /// Strings passed raw, see `set_raw_strings`, are not checked.
+    pub fn set_hyperloglogs(&mut self, hyperloglogs: bool) {
+        self.options.hyperloglogs = hyperloglogs;
+    }
+
+    /// Passes the strings of keys matching `keys` to `Formatter::bitmap`
+    /// with their length and number of set bits instead of to `set`.
+    ///
+    /// Strings passed raw, see `set_raw_strings`, are not checked.
+    pub fn set_bitmap_keys(&mut self, keys: Regex) {
+        self.options.bitmap_keys = Some(keys);
+    }
+
+    /// The version and AUX fields read so far.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Decodes the values of the module type `module_name`, e.g.
+    /// `ReJSON-RL`, with `decoder` instead of skipping them.
+    pub fn register_module_decoder<D: ModuleDecoder + 'static>(
+        &mut self,
+        module_name: &str,
+        decoder: D,
+    ) {
+        self.module_decoders
+            .insert(module_name.to_owned(), Box::new(decoder));
+    }
+
+    /// The sizes of the databases parsed so far, compared to their RESIZEDB
+    /// opcodes.
+    pub fn summary(&self) -> &ParseSummary {
+        &self.summary
+    }
+
+    /// All problems found so far.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// The quirks tolerant mode accepted so far.
+    pub fn ignored(&self) -> &[Warning] {
+        &self.ignored
+    }
+
+    /// Reports a quirk of a managed service's dump, see `set_tolerant`.
+    fn quirk(&mut self, message: String) {
+        let quirk = Warning {
+            db: self.last_database,
+            key: None,
+            message,
+        };
+        if self.options.tolerant {
+            self.ignored.push(quirk);
+        } else if self.options.strict {
+            self.warnings.push(quirk);
+        }
+    }
+
+    fn warn(&mut self, key: Option<&[u8]>, message: String) {
+        self.warnings.push(Warning {
+            db: self.last_database,
+            key: key.map(|key| key.to_vec()),
+            message,
+        });
+    }
+
+    fn check_database_size(&mut self) {
+        let size = DatabaseSize {
+            db: self.last_database,
+            announced: self.resizedb.take(),
+            keys: self.keys_in_db,
+            expires: self.expires_in_db,
+        };
+        if let Some((db_size, expires_size)) = size.announced {
+            if self.options.strict && size.mismatched() {
+                let message = format!(
+                    "RESIZEDB announced {} keys ({} with expiry), but the database contains {} ({} with expiry)",
+                    db_size, expires_size, size.keys, size.expires
+                );
+                self.warn(None, message);
+            }
+        }
+        if size.announced.is_some() || size.keys > 0 {
+            self.summary.databases.push(size);
         }
+
+        self.keys_in_db = 0;
+        self.expires_in_db = 0;
+    }
+
+    /// What the parser did so far, see `RdbStats`.
+    pub fn stats(&self) -> &RdbStats {
+        &self.stats
     }
 
     pub fn parse(&mut self) -> RdbOk {
+        let start = Instant::now();
+        let parsed = self.parse_dump();
+        self.stats.bytes_read = self.input.position;
+        self.stats.duration += start.elapsed();
+        parsed
+    }
+
+    fn parse_dump(&mut self) -> RdbOk {
         verify_magic(&mut self.input)?;
-        verify_version(&mut self.input)?;
+        let version = read_version_number(&mut self.input)?;
+        if self.options.newer_versions && version > version::SUPPORTED_MAXIMUM {
+            let message = format!(
+                "version {} is newer than the supported {}",
+                version,
+                version::SUPPORTED_MAXIMUM
+            );
+            self.warn(None, message);
+        } else {
+            check_version(version)?;
+        }
+        self.header = Header::new(version);
 
         self.formatter.start_rdb()?;
 
-        let mut last_database: u32 = 0;
         loop {
             let next_op = self.input.read_u8()?;
 
-            match next_op {
-                op_code::SELECTDB => {
-                    last_database = unwrap_or_panic!(read_length(&mut self.input));
-                    if self.filter.matches_db(last_database) {
-                        self.formatter.start_database(last_database)?;
-                    }
-                }
-                op_code::EOF => {
-                    self.formatter.end_database(last_database)?;
-                    self.formatter.end_rdb()?;
+            if !self.read_op(next_op)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
 
-                    let mut checksum = Vec::new();
-                    let len = self.input.read_to_end(&mut checksum)?;
-                    if len > 0 {
-                        self.formatter.checksum(&checksum)?;
+    /// Parses a section of a file that starts at an opcode boundary, e.g. a
+    /// single database as located by `scan_databases`.
+    ///
+    /// Stops at the end of the input or after the EOF opcode. `start` is
+    /// the offset of the section in the file, for `Formatter::key_offset`.
+    pub(crate) fn parse_section(&mut self, start: u64) -> RdbOk {
+        self.input.position = start;
+        loop {
+            let mut next_op = [0; 1];
+            if self.input.read(&mut next_op)? == 0 {
+                // The database continues in no other section.
+                self.check_database_size();
+                return Ok(());
+            }
+
+            if !self.read_op(next_op[0])? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Parses a single value of type `value_type` without a key, like the
+    /// payload of `DUMP`, which must end with the input.
+    pub(crate) fn parse_value(&mut self, value_type: u8) -> RdbOk {
+        if !is_value_type(value_type) {
+            return self.unknown_type(value_type);
+        }
+        self.read_type(b"", value_type)?;
+        if self.input.read(&mut [0])? != 0 {
+            return Err(other_error("Unexpected bytes after the value"));
+        }
+        Ok(())
+    }
+
+    /// The formatter, the warnings and the summary, once parsing is done.
+    pub(crate) fn into_parts(self) -> (F, Vec<Warning>, ParseSummary) {
+        (self.formatter.into_inner(), self.warnings, self.summary)
+    }
+
+    /// Handles a single opcode (or key) and returns `false` once the EOF opcode was read.
+    fn read_op(&mut self, next_op: u8) -> RdbResult<bool> {
+        match next_op {
+            op_code::SELECTDB => {
+                self.check_database_size();
+                self.last_database = read_count(&mut self.input)?;
+                self.stats.databases.push(self.last_database);
+                self.last_slot = None;
+                if self.filter.matches_db(self.last_database) {
+                    self.formatter.start_database(self.last_database)?;
+                }
+            }
+            op_code::EOF => {
+                self.check_database_size();
+                self.formatter.end_database(self.last_database)?;
+                self.formatter.end_rdb()?;
+
+                // The CRC64 checksum, unless the dump is too old for one.
+                let crc = self.input.crc.take();
+                let mut checksum = Vec::new();
+                self.input.read_to_end(&mut checksum)?;
+                let trailing = checksum.split_off(checksum.len().min(CHECKSUM_LENGTH));
+                if let (Some(crc), CHECKSUM_LENGTH) = (crc, checksum.len()) {
+                    // Dumps written with `rdbchecksum no` store 0.
+                    let stored = LittleEndian::read_u64(&checksum);
+                    if stored != 0 && stored != crc {
+                        return Err(other_error(format!(
+                            "Checksum mismatch: the dump stores {:016x}, but its contents have {:016x}",
+                            stored, crc
+                        )));
                     }
-                    break;
                 }
-                op_code::EXPIRETIME_MS => {
-                    let expiretime_ms = self.input.read_u64::<LittleEndian>()?;
-                    self.last_expiretime = Some(expiretime_ms);
+                if !checksum.is_empty() {
+                    self.formatter.checksum(&checksum)?;
                 }
-                op_code::EXPIRETIME => {
-                    let expiretime = self.input.read_u32::<BigEndian>()?;
-                    self.last_expiretime = Some(expiretime as u64 * 1000);
+                if !trailing.is_empty() {
+                    self.quirk(format!("{} bytes after the checksum", trailing.len()));
                 }
-                op_code::RESIZEDB => {
-                    let db_size = read_length(&mut self.input)?;
-                    let expires_size = read_length(&mut self.input)?;
+                return Ok(false);
+            }
+            op_code::EXPIRETIME_MS => {
+                let expiretime_ms = self.input.read_u64::<LittleEndian>()?;
+                self.last_expiretime = Some(expiretime_ms);
+            }
+            op_code::EXPIRETIME => {
+                let expiretime = self.input.read_u32::<BigEndian>()?;
+                self.last_expiretime = Some(expiretime as u64 * 1000);
+            }
+            op_code::IDLE => {
+                let idle = read_length(&mut self.input)?;
+                self.last_usage = Some(KeyUsage::Idle(idle));
+            }
+            op_code::FREQ => {
+                let frequency = self.input.read_u8()?;
+                self.last_usage = Some(KeyUsage::Frequency(frequency));
+            }
+            op_code::RESIZEDB => {
+                let db_size = read_count(&mut self.input)?;
+                let expires_size = read_count(&mut self.input)?;
 
-                    self.formatter.resizedb(db_size, expires_size)?;
-                }
-                op_code::AUX => {
-                    let auxkey = read_blob(&mut self.input)?;
-                    let auxval = read_blob(&mut self.input)?;
+                self.resizedb = Some((db_size, expires_size));
+                self.formatter.resizedb(db_size, expires_size)?;
+            }
+            op_code::AUX => {
+                let auxkey = self.read_limited_blob()?;
+                let auxval = self.read_limited_blob()?;
 
-                    self.formatter.aux_field(&auxkey, &auxval)?;
+                if !is_redis_aux_field(&auxkey) {
+                    let name = String::from_utf8_lossy(&auxkey);
+                    self.quirk(format!("unknown AUX field {}", name));
+                }
+                self.header.add_aux_field(&auxkey, &auxval);
+                self.formatter.aux_field(&auxkey, &auxval)?;
+            }
+            op_code::SLOT_INFO => {
+                let slot = read_count(&mut self.input)?;
+                let slot_size = read_count(&mut self.input)?;
+                let expires_slot_size = read_count(&mut self.input)?;
+
+                self.last_slot = Some(slot);
+                self.formatter
+                    .slot_info(slot, slot_size, expires_slot_size)?;
+            }
+            op_code::FUNCTION2 => {
+                let library_code = self.read_limited_blob()?;
+                self.formatter.function(&library_code)?;
+            }
+            op_code::MODULE_AUX => {
+                // Data modules store for themselves, not for a key.
+                read_length(&mut self.input)?;
+                self.skip_module_fields()?;
+            }
+            _ if !is_value_type(next_op) => self.unknown_type(next_op)?,
+            _ => {
+                self.keys_in_db += 1;
+                if self.last_expiretime.is_some() {
+                    self.expires_in_db += 1;
                 }
-                _ => {
-                    if self.filter.matches_db(last_database) {
-                        let key = read_blob(&mut self.input)?;
 
-                        if self.filter.matches_type(next_op) && self.filter.matches_key(&key) {
-                            self.read_type(&key, next_op)?;
+                let matches_slot = match self.last_slot {
+                    Some(slot) => self.filter.matches_slot(slot),
+                    None => true,
+                };
+                // `visitor::visit` takes the type from the call to
+                // `matches_type`.
+                let offset = self.input.position - 1;
+                let expired = match (self.last_expiretime, self.options.expired_before) {
+                    (Some(expiry), Some(now)) => expiry < now,
+                    _ => false,
+                };
+                if self.filter.matches_db(self.last_database) && matches_slot && !expired {
+                    let key = self.read_limited_blob()?;
+
+                    if !self.filter.matches_type(next_op) || !self.filter.matches_key(&key) {
+                        self.skip_object(next_op)?;
+                        self.stats.keys_skipped += 1;
+                    } else {
+                        if let Some(usage) = self.last_usage {
+                            self.formatter.key_usage(&key, usage)?;
+                        }
+                        self.blob_stats = BlobStats::default();
+                        if self.options.lengths_only {
+                            self.read_value_length(&key, next_op)?;
                         } else {
-                            self.skip_object(next_op)?;
+                            self.read_type(&key, next_op)?;
                         }
-                    } else {
-                        self.skip_key_and_object(next_op)?;
+                        let blob_stats = self.blob_stats;
+                        self.formatter.blob_stats(&key, &blob_stats)?;
+                        let size = self.input.position - offset;
+                        self.formatter.key_offset(&key, offset, size)?;
+                        self.stats.keys_read += 1;
+                        *self
+                            .stats
+                            .types
+                            .entry(Type::from_encoding(next_op))
+                            .or_insert(0) += 1;
                     }
-
-                    self.last_expiretime = None;
+                } else {
+                    self.skip_key_and_object(next_op)?;
+                    self.stats.keys_skipped += 1;
                 }
+
+                self.last_expiretime = None;
+                self.last_usage = None;
             }
         }
 
-        Ok(())
+        Ok(true)
+    }
+
+    /// Reports an unknown opcode or value type and fails, as there is no
+    /// telling where its payload ends.
+    fn unknown_type(&mut self, code: u8) -> RdbOk {
+        self.formatter.unknown_type(code)?;
+        Err(RdbError::UnknownType { code })
     }
 
+    /// Reads a list, or a set if `typ` is `Type::Set`, stored as a linked
+    /// list of blobs.
     fn read_linked_list(&mut self, key: &[u8], typ: Type) -> RdbOk {
-        let mut len = read_length(&mut self.input)?;
+        let mut len = read_count(&mut self.input)?;
 
         match typ {
-            Type::List => {
-                self.formatter.start_list(
+            Type::Set => {
+                self.formatter.start_set(
                     key,
                     len,
                     self.last_expiretime,
-                    EncodingType::LinkedList,
+                    EncodingType::Hashtable,
                 )?;
             }
-            Type::Set => {
-                self.formatter.start_set(
+            _ => {
+                self.formatter.start_list(
                     key,
                     len,
                     self.last_expiretime,
                     EncodingType::LinkedList,
                 )?;
             }
-            _ => panic!("Unknown encoding type for linked list"),
         }
 
         while len > 0 {
-            let blob = read_blob(&mut self.input)?;
-            self.formatter.list_element(key, &blob)?;
+            let blob = self.read_value_blob()?;
+            match typ {
+                Type::Set => self.formatter.set_element(key, &blob)?,
+                _ => self.formatter.list_element(key, &blob)?,
+            }
             len -= 1;
         }
 
         match typ {
-            Type::List => self.formatter.end_list(key)?,
             Type::Set => self.formatter.end_set(key)?,
-            _ => panic!("Unknown encoding type for linked list"),
+            _ => self.formatter.end_list(key)?,
         }
 
         Ok(())
     }
 
     fn read_sorted_set_type_2(&mut self, key: &[u8]) -> RdbOk {
-        let mut set_items = unwrap_or_panic!(read_length(&mut self.input));
+        let mut set_items = read_count(&mut self.input)?;
 
         self.formatter.start_sorted_set(
             key,
             set_items,
             self.last_expiretime,
-            EncodingType::Hashtable,
+            EncodingType::Skiplist,
         )?;
 
         while set_items > 0 {
-            let val = read_blob(&mut self.input)?;
+            let val = self.read_value_blob()?;
 
             let score = self.input.read_f64::<LittleEndian>()?;
 
@@ -271,27 +975,25 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
     }
 
     fn read_sorted_set(&mut self, key: &[u8]) -> RdbOk {
-        let mut set_items = unwrap_or_panic!(read_length(&mut self.input));
+        let mut set_items = read_count(&mut self.input)?;
 
         self.formatter.start_sorted_set(
             key,
             set_items,
             self.last_expiretime,
-            EncodingType::Hashtable,
+            EncodingType::Skiplist,
         )?;
 
         while set_items > 0 {
-            let val = read_blob(&mut self.input)?;
+            let val = self.read_value_blob()?;
             let score_length = self.input.read_u8()?;
             let score = match score_length {
                 253 => f64::NAN,
                 254 => f64::INFINITY,
                 255 => f64::NEG_INFINITY,
                 _ => {
-                    let tmp = read_exact(&mut self.input, score_length as usize)?;
-                    unsafe { str::from_utf8_unchecked(&tmp) }
-                        .parse::<f64>()
-                        .unwrap()
+                    let score = read_exact(&mut self.input, score_length as usize)?;
+                    parse_score(&score)?
                 }
             };
 
@@ -306,7 +1008,7 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
     }
 
     fn read_hash(&mut self, key: &[u8]) -> RdbOk {
-        let mut hash_items = read_length(&mut self.input)?;
+        let mut hash_items = read_count(&mut self.input)?;
 
         self.formatter.start_hash(
             key,
@@ -316,8 +1018,8 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
         )?;
 
         while hash_items > 0 {
-            let field = read_blob(&mut self.input)?;
-            let val = read_blob(&mut self.input)?;
+            let field = self.read_value_blob()?;
+            let val = self.read_value_blob()?;
 
             self.formatter.hash_element(key, &field, &val)?;
 
@@ -329,107 +1031,86 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
         Ok(())
     }
 
-    fn read_ziplist_entry<T: Read>(&mut self, ziplist: &mut T) -> RdbResult<ZiplistEntry> {
-        // 1. 1 or 5 bytes length of previous entry
-        let byte = ziplist.read_u8()?;
-        if byte == 254 {
-            let mut bytes = [0; 4];
-            if ziplist.read(&mut bytes)? != 4 {
-                return Err(other_error(
-                    "Could not read 4 bytes to skip after ziplist length",
-                ));
-            }
+    /// Reads a hash with field expiries, stored as a hash table.
+    ///
+    /// Each field is preceded by its expiry, 0 if it has none. Since Redis
+    /// 7.4.0 the smallest expiry comes first and the others are stored
+    /// relative to it, plus one.
+    fn read_hash_metadata(&mut self, key: &[u8], value_type: u8) -> RdbOk {
+        let min_expiry = self.read_min_field_expiry(value_type)?;
+        let mut hash_items = read_count(&mut self.input)?;
+
+        self.formatter.start_hash(
+            key,
+            hash_items,
+            self.last_expiretime,
+            EncodingType::Hashtable,
+        )?;
+
+        while hash_items > 0 {
+            let expiry = match (read_length(&mut self.input)?, min_expiry) {
+                (0, _) => None,
+                (expiry, Some(min_expiry)) => Some(expiry + min_expiry - 1),
+                (expiry, None) => Some(expiry),
+            };
+            let field = self.read_value_blob()?;
+            let val = self.read_value_blob()?;
+
+            self.hash_field(key, &field, &val, expiry)?;
+
+            hash_items -= 1;
         }
 
-        let length: u64;
-        let number_value: i64;
+        self.formatter.end_hash(key)?;
 
-        // 2. Read flag or number value
-        let flag = ziplist.read_u8()?;
+        Ok(())
+    }
 
-        match (flag & 0xC0) >> 6 {
-            0 => length = (flag & 0x3F) as u64,
-            1 => {
-                let next_byte = ziplist.read_u8()?;
-                length = (((flag & 0x3F) as u64) << 8) | next_byte as u64;
-            }
-            2 => {
-                length = ziplist.read_u32::<BigEndian>()? as u64;
+    /// Reads the smallest field expiry stored in front of the hashes with
+    /// field expiries since Redis 7.4.0.
+    fn read_min_field_expiry(&mut self, value_type: u8) -> RdbResult<Option<u64>> {
+        match value_type {
+            encoding_type::HASH_METADATA | encoding_type::HASH_LISTPACK_EX => {
+                Ok(Some(self.input.read_u64::<LittleEndian>()?))
             }
-            _ => {
-                match (flag & 0xF0) >> 4 {
-                    0xC => number_value = ziplist.read_i16::<LittleEndian>()? as i64,
-                    0xD => number_value = ziplist.read_i32::<LittleEndian>()? as i64,
-                    0xE => number_value = ziplist.read_i64::<LittleEndian>()? as i64,
-                    0xF => match flag & 0xF {
-                        0 => {
-                            let mut bytes = [0; 3];
-                            if ziplist.read(&mut bytes)? != 3 {
-                                return Err(other_error(
-                                    "Could not read enough bytes for 24bit number",
-                                ));
-                            }
-
-                            let number: i32 = (((bytes[2] as i32) << 24)
-                                ^ ((bytes[1] as i32) << 16)
-                                ^ ((bytes[0] as i32) << 8)
-                                ^ 48)
-                                >> 8;
-
-                            number_value = number as i64;
-                        }
-                        0xE => {
-                            number_value = ziplist.read_i8()? as i64;
-                        }
-                        _ => {
-                            number_value = (flag & 0xF) as i64 - 1;
-                        }
-                    },
-                    _ => {
-                        panic!("Flag not handled: {}", flag);
-                    }
-                }
+            _ => Ok(None),
+        }
+    }
 
-                return Ok(ZiplistEntry::Number(number_value));
-            }
+    fn hash_field(&mut self, key: &[u8], field: &[u8], value: &[u8], expiry: Option<u64>) -> RdbOk {
+        match expiry {
+            Some(expiry) => self.formatter.hash_element_ex(key, field, value, expiry),
+            None => self.formatter.hash_element(key, field, value),
         }
+    }
 
-        // 3. Read value
-        let rawval = read_exact(ziplist, length as usize)?;
-        Ok(ZiplistEntry::String(rawval))
+    fn read_ziplist(&mut self) -> RdbResult<Ziplist> {
+        let ziplist = self.read_value_blob()?;
+        Ziplist::new(ziplist)
     }
 
-    fn read_ziplist_entry_string<T: Read>(&mut self, reader: &mut T) -> RdbResult<Vec<u8>> {
-        let entry = self.read_ziplist_entry(reader)?;
-        match entry {
-            ZiplistEntry::String(val) => Ok(val),
-            ZiplistEntry::Number(val) => Ok(val.to_string().into_bytes()),
+    fn validate_ziplist(&mut self, key: &[u8], ziplist: &Ziplist) {
+        if self.options.strict {
+            for problem in ziplist.validate() {
+                self.warn(Some(key), problem);
+            }
         }
     }
 
     fn read_list_ziplist(&mut self, key: &[u8]) -> RdbOk {
-        let ziplist = read_blob(&mut self.input)?;
-        let raw_length = ziplist.len() as u64;
-
-        let mut reader = Cursor::new(ziplist);
-        let (_zlbytes, _zltail, zllen) = read_ziplist_metadata(&mut reader)?;
+        let mut ziplist = self.read_ziplist()?;
 
         self.formatter.start_list(
             key,
-            zllen as u32,
+            ziplist.header_length(),
             self.last_expiretime,
-            EncodingType::Ziplist(raw_length),
+            EncodingType::Ziplist(ziplist.raw_length()),
         )?;
 
-        for _ in 0..zllen {
-            let entry = self.read_ziplist_entry_string(&mut reader)?;
+        while let Some(entry) = ziplist.next_string()? {
             self.formatter.list_element(key, &entry)?;
         }
-
-        let last_byte = reader.read_u8()?;
-        if last_byte != 0xFF {
-            return Err(other_error("Invalid end byte of ziplist"));
-        }
+        self.validate_ziplist(key, &ziplist);
 
         self.formatter.end_list(key)?;
 
@@ -437,32 +1118,22 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
     }
 
     fn read_hash_ziplist(&mut self, key: &[u8]) -> RdbOk {
-        let ziplist = read_blob(&mut self.input)?;
-        let raw_length = ziplist.len() as u64;
-
-        let mut reader = Cursor::new(ziplist);
-        let (_zlbytes, _zltail, zllen) = read_ziplist_metadata(&mut reader)?;
-
-        assert!(zllen % 2 == 0);
-        let zllen = zllen / 2;
+        let mut ziplist = self.read_ziplist()?;
 
         self.formatter.start_hash(
             key,
-            zllen as u32,
+            ziplist.header_length() / 2,
             self.last_expiretime,
-            EncodingType::Ziplist(raw_length),
+            EncodingType::Ziplist(ziplist.raw_length()),
         )?;
 
-        for _ in 0..zllen {
-            let field = self.read_ziplist_entry_string(&mut reader)?;
-            let value = self.read_ziplist_entry_string(&mut reader)?;
+        while let Some(field) = ziplist.next_string()? {
+            let value = ziplist
+                .next_string()?
+                .ok_or_else(|| other_error("Odd number of entries in hash ziplist"))?;
             self.formatter.hash_element(key, &field, &value)?;
         }
-
-        let last_byte = reader.read_u8()?;
-        if last_byte != 0xFF {
-            return Err(other_error("Invalid end byte of ziplist"));
-        }
+        self.validate_ziplist(key, &ziplist);
 
         self.formatter.end_hash(key)?;
 
@@ -470,155 +1141,265 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
     }
 
     fn read_sortedset_ziplist(&mut self, key: &[u8]) -> RdbOk {
-        let ziplist = read_blob(&mut self.input)?;
-        let raw_length = ziplist.len() as u64;
-
-        let mut reader = Cursor::new(ziplist);
-        let (_zlbytes, _zltail, zllen) = read_ziplist_metadata(&mut reader)?;
+        let mut ziplist = self.read_ziplist()?;
 
         self.formatter.start_sorted_set(
             key,
-            zllen as u32,
+            ziplist.header_length() / 2,
             self.last_expiretime,
-            EncodingType::Ziplist(raw_length),
+            EncodingType::Ziplist(ziplist.raw_length()),
         )?;
 
-        assert!(zllen % 2 == 0);
-        let zllen = zllen / 2;
-
-        for _ in 0..zllen {
-            let entry = self.read_ziplist_entry_string(&mut reader)?;
-            let score = self.read_ziplist_entry_string(&mut reader)?;
-            let score = str::from_utf8(&score).unwrap().parse::<f64>().unwrap();
+        while let Some(entry) = ziplist.next_string()? {
+            let score = ziplist
+                .next_string()?
+                .ok_or_else(|| other_error("Odd number of entries in sorted set ziplist"))?;
+            let score = parse_score(&score)?;
             self.formatter.sorted_set_element(key, score, &entry)?;
         }
-
-        let last_byte = reader.read_u8()?;
-        if last_byte != 0xFF {
-            return Err(other_error("Invalid end byte of ziplist"));
-        }
+        self.validate_ziplist(key, &ziplist);
 
         self.formatter.end_sorted_set(key)?;
 
         Ok(())
     }
 
-    fn read_quicklist_ziplist(&mut self, key: &[u8]) -> RdbOk {
-        let ziplist = read_blob(&mut self.input)?;
-
-        let mut reader = Cursor::new(ziplist);
-        let (_zlbytes, _zltail, zllen) = read_ziplist_metadata(&mut reader)?;
-
-        for _ in 0..zllen {
-            let entry = self.read_ziplist_entry_string(&mut reader)?;
-            self.formatter.list_element(key, &entry)?;
-        }
-
-        let last_byte = reader.read_u8()?;
-        if last_byte != 0xFF {
-            return Err(other_error("Invalid end byte of ziplist (quicklist)"));
-        }
-
-        Ok(())
+    fn read_listpack(&mut self) -> RdbResult<Listpack> {
+        let listpack = self.read_value_blob()?;
+        Listpack::new(listpack)
     }
 
-    fn read_zipmap_entry<T: Read>(&mut self, next_byte: u8, zipmap: &mut T) -> RdbResult<Vec<u8>> {
-        let elem_len;
-        match next_byte {
-            253 => elem_len = zipmap.read_u32::<LittleEndian>().unwrap(),
-            254 | 255 => panic!("Invalid length value in zipmap: {}", next_byte),
-            _ => elem_len = next_byte as u32,
+    fn validate_listpack(&mut self, key: &[u8], listpack: &Listpack) {
+        if self.options.strict {
+            for problem in listpack.validate() {
+                self.warn(Some(key), problem);
+            }
         }
-
-        read_exact(zipmap, elem_len as usize)
     }
 
-    fn read_hash_zipmap(&mut self, key: &[u8]) -> RdbOk {
-        let zipmap = read_blob(&mut self.input)?;
-        let raw_length = zipmap.len() as u64;
-
-        let mut reader = Cursor::new(zipmap);
-
-        let zmlen = reader.read_u8()?;
-
-        let mut length: i32;
-        let size;
-        if zmlen <= 254 {
-            length = zmlen as i32;
-            size = zmlen
-        } else {
-            length = -1;
-            size = 0;
-        }
+    fn read_hash_listpack(&mut self, key: &[u8]) -> RdbOk {
+        let mut listpack = self.read_listpack()?;
 
         self.formatter.start_hash(
             key,
-            size as u32,
+            listpack.header_length() / 2,
             self.last_expiretime,
-            EncodingType::Zipmap(raw_length),
+            EncodingType::Listpack(listpack.raw_length()),
         )?;
 
-        loop {
-            let next_byte = reader.read_u8()?;
+        while let Some(field) = listpack.next_string()? {
+            let value = listpack
+                .next_string()?
+                .ok_or_else(|| other_error("Odd number of entries in hash listpack"))?;
+            self.formatter.hash_element(key, &field, &value)?;
+        }
+        self.validate_listpack(key, &listpack);
 
-            if next_byte == 0xFF {
-                break; // End of list.
-            }
+        self.formatter.end_hash(key)?;
 
-            let field = self.read_zipmap_entry(next_byte, &mut reader)?;
+        Ok(())
+    }
 
-            let next_byte = reader.read_u8()?;
-            let _free = reader.read_u8()?;
-            let value = self.read_zipmap_entry(next_byte, &mut reader)?;
+    /// Reads a listpack of fields, values and absolute expiries, 0 for
+    /// fields without one.
+    fn read_hash_listpack_ex(&mut self, key: &[u8], value_type: u8) -> RdbOk {
+        self.read_min_field_expiry(value_type)?;
+        let mut listpack = self.read_listpack()?;
 
-            self.formatter.hash_element(key, &field, &value)?;
+        self.formatter.start_hash(
+            key,
+            listpack.header_length() / 3,
+            self.last_expiretime,
+            EncodingType::Listpack(listpack.raw_length()),
+        )?;
 
-            if length > 0 {
-                length -= 1;
-            }
+        while let Some(field) = listpack.next_string()? {
+            let value = listpack
+                .next_string()?
+                .ok_or_else(|| other_error("Missing value in hash listpack"))?;
+            let expiry = match listpack.expect_number()? {
+                0 => None,
+                expiry => Some(expiry as u64),
+            };
+            self.hash_field(key, &field, &value, expiry)?;
+        }
+        self.validate_listpack(key, &listpack);
+
+        self.formatter.end_hash(key)?;
+
+        Ok(())
+    }
+
+    fn read_sortedset_listpack(&mut self, key: &[u8]) -> RdbOk {
+        let mut listpack = self.read_listpack()?;
+
+        self.formatter.start_sorted_set(
+            key,
+            listpack.header_length() / 2,
+            self.last_expiretime,
+            EncodingType::Listpack(listpack.raw_length()),
+        )?;
+
+        while let Some(entry) = listpack.next_string()? {
+            let score = listpack
+                .next_string()?
+                .ok_or_else(|| other_error("Odd number of entries in sorted set listpack"))?;
+            let score = parse_score(&score)?;
+            self.formatter.sorted_set_element(key, score, &entry)?;
+        }
+        self.validate_listpack(key, &listpack);
+
+        self.formatter.end_sorted_set(key)?;
+
+        Ok(())
+    }
+
+    fn read_set_listpack(&mut self, key: &[u8]) -> RdbOk {
+        let mut listpack = self.read_listpack()?;
+
+        self.formatter.start_set(
+            key,
+            listpack.header_length(),
+            self.last_expiretime,
+            EncodingType::Listpack(listpack.raw_length()),
+        )?;
+
+        while let Some(member) = listpack.next_string()? {
+            self.formatter.set_element(key, &member)?;
+        }
+        self.validate_listpack(key, &listpack);
+
+        self.formatter.end_set(key)?;
 
-            if length == 0 {
-                let last_byte = reader.read_u8()?;
+        Ok(())
+    }
 
-                if last_byte != 0xFF {
-                    return Err(other_error("Invalid end byte of zipmap"));
+    /// Reads a quicklist as stored from Redis 7.0 on.
+    ///
+    /// Each node is either a listpack or, for elements too large to pack, a
+    /// single plain element.
+    fn read_quicklist_2(&mut self, key: &[u8]) -> RdbOk {
+        let len = read_length(&mut self.input)?;
+
+        // The nodes are read first, as the list is announced with the
+        // number of elements of all of them.
+        let mut nodes = vec![];
+        let mut length = 0u32;
+        let mut raw_length = 0;
+        for _ in 0..len {
+            let node = match read_length(&mut self.input)? {
+                quicklist::CONTAINER_PLAIN => QuicklistNode::Plain(self.read_value_blob()?),
+                quicklist::CONTAINER_PACKED => QuicklistNode::Packed(self.read_listpack()?),
+                container => {
+                    return Err(other_error(format!(
+                        "Unknown quicklist container: {}",
+                        container
+                    )))
+                }
+            };
+            match node {
+                QuicklistNode::Plain(ref element) => {
+                    length = length.saturating_add(1);
+                    raw_length += element.len() as u64;
+                }
+                QuicklistNode::Packed(ref listpack) => {
+                    length = length.saturating_add(listpack.count()?);
+                    raw_length += listpack.raw_length();
+                }
+            }
+            nodes.push(node);
+        }
+
+        self.formatter.start_list(
+            key,
+            length,
+            self.last_expiretime,
+            EncodingType::Quicklist(raw_length),
+        )?;
+        for node in nodes {
+            match node {
+                QuicklistNode::Plain(element) => self.formatter.list_element(key, &element)?,
+                QuicklistNode::Packed(mut listpack) => {
+                    while let Some(element) = listpack.next_string()? {
+                        self.formatter.list_element(key, &element)?;
+                    }
+                    self.validate_listpack(key, &listpack);
                 }
-                break;
             }
         }
+        self.formatter.end_list(key)?;
 
+        Ok(())
+    }
+
+    fn read_hash_zipmap(&mut self, key: &[u8]) -> RdbOk {
+        let zipmap = self.read_value_blob()?;
+        let raw_length = zipmap.len() as u64;
+        let entries = codec::decode_zipmap(&zipmap)?;
+
+        // A zmlen of 254 means the entries have to be counted.
+        let zmlen = zipmap[0];
+        let size = if zmlen < 254 { zmlen } else { 0 };
+        if self.options.strict && zmlen < 254 && entries.len() != usize::from(zmlen) {
+            self.warn(
+                Some(key),
+                format!(
+                    "zipmap zmlen is {}, but it contains {} entries",
+                    zmlen,
+                    entries.len()
+                ),
+            );
+        }
+
+        self.formatter.start_hash(
+            key,
+            size as u32,
+            self.last_expiretime,
+            EncodingType::Zipmap(raw_length),
+        )?;
+        for (field, value) in entries {
+            self.formatter.hash_element(key, &field, &value)?;
+        }
         self.formatter.end_hash(key)?;
 
         Ok(())
     }
 
     fn read_set_intset(&mut self, key: &[u8]) -> RdbOk {
-        let intset = read_blob(&mut self.input)?;
+        let intset = self.read_value_blob()?;
         let raw_length = intset.len() as u64;
-
-        let mut reader = Cursor::new(intset);
-        let byte_size = reader.read_u32::<LittleEndian>()?;
-        let intset_length = reader.read_u32::<LittleEndian>()?;
+        let values = codec::decode_intset(&intset)?;
+
+        if self.options.strict {
+            let byte_size = LittleEndian::read_u32(&intset[..4]) as u64;
+            let expected_length = 8 + values.len() as u64 * byte_size;
+            if raw_length != expected_length {
+                self.warn(
+                    Some(key),
+                    format!(
+                        "intset of {} {}-byte integers should be {} bytes long, but is {}",
+                        values.len(),
+                        byte_size,
+                        expected_length,
+                        raw_length
+                    ),
+                );
+            }
+            for _ in values.windows(2).filter(|pair| pair[0] >= pair[1]) {
+                self.warn(Some(key), "intset is not sorted in ascending order".into());
+            }
+        }
 
         self.formatter.start_set(
             key,
-            intset_length,
+            values.len() as u32,
             self.last_expiretime,
             EncodingType::Intset(raw_length),
         )?;
-
-        for _ in 0..intset_length {
-            let val = match byte_size {
-                2 => reader.read_i16::<LittleEndian>()? as i64,
-                4 => reader.read_i32::<LittleEndian>()? as i64,
-                8 => reader.read_i64::<LittleEndian>()?,
-                _ => panic!("unhandled byte size in intset: {}", byte_size),
-            };
-
+        for value in values {
             self.formatter
-                .set_element(key, val.to_string().as_bytes())?;
+                .set_element(key, value.to_string().as_bytes())?;
         }
-
         self.formatter.end_set(key)?;
 
         Ok(())
@@ -627,22 +1408,263 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
     fn read_quicklist(&mut self, key: &[u8]) -> RdbOk {
         let len = read_length(&mut self.input)?;
 
-        self.formatter
-            .start_set(key, 0, self.last_expiretime, EncodingType::Quicklist)?;
+        // The nodes are read first, as the list is announced with the
+        // number of elements of all of them.
+        let mut ziplists = vec![];
+        let mut length = 0u32;
+        let mut raw_length = 0;
         for _ in 0..len {
-            self.read_quicklist_ziplist(key)?;
+            let ziplist = self.read_ziplist()?;
+            length = length.saturating_add(ziplist.count()?);
+            raw_length += ziplist.raw_length();
+            ziplists.push(ziplist);
+        }
+
+        self.formatter.start_list(
+            key,
+            length,
+            self.last_expiretime,
+            EncodingType::Quicklist(raw_length),
+        )?;
+        for mut ziplist in ziplists {
+            while let Some(entry) = ziplist.next_string()? {
+                self.formatter.list_element(key, &entry)?;
+            }
+            self.validate_ziplist(key, &ziplist);
+        }
+        self.formatter.end_list(key)?;
+
+        Ok(())
+    }
+
+    /// Reads the length and metadata stored after a stream's entries.
+    ///
+    /// Stream encoding v2 adds the first ID, the largest deleted ID and the
+    /// number of entries ever added.
+    fn read_stream_metadata(&mut self, value_type: u8) -> RdbResult<(u32, StreamMetadata)> {
+        let length = read_count(&mut self.input)?;
+        let mut metadata = StreamMetadata {
+            last_id: read_stream_id_lengths(&mut self.input)?,
+            ..StreamMetadata::default()
+        };
+
+        if value_type != encoding_type::STREAM_LISTPACKS {
+            metadata.first_id = Some(read_stream_id_lengths(&mut self.input)?);
+            metadata.max_deleted_entry_id = Some(read_stream_id_lengths(&mut self.input)?);
+            metadata.entries_added = Some(read_length(&mut self.input)?);
+        }
+
+        Ok((length, metadata))
+    }
+
+    fn read_stream(&mut self, key: &[u8], value_type: u8) -> RdbOk {
+        let nodes = read_length(&mut self.input)?;
+        // Not preallocated, as the number of nodes isn't checked yet.
+        let mut listpacks = vec![];
+        for _ in 0..nodes {
+            let master_id = read_stream_id(&self.read_value_blob()?)?;
+            let listpack = self.read_value_blob()?;
+            listpacks.push((master_id, listpack));
+        }
+
+        let (length, metadata) = self.read_stream_metadata(value_type)?;
+
+        self.formatter.start_stream(
+            key,
+            length,
+            self.last_expiretime,
+            EncodingType::StreamListpacks,
+        )?;
+        self.formatter.stream_metadata(key, &metadata)?;
+        for (master_id, listpack) in listpacks {
+            self.read_stream_listpack(key, master_id, listpack)?;
+        }
+        self.read_stream_groups(key, value_type)?;
+        self.formatter.end_stream(key)?;
+
+        Ok(())
+    }
+
+    /// Decodes the entries of one stream node.
+    ///
+    /// The first entry is the master entry with the field names most
+    /// entries share. Entries flagged with `FLAG_SAMEFIELDS` only store
+    /// their values, and IDs are stored relative to the node's master ID.
+    fn read_stream_listpack(
+        &mut self,
+        key: &[u8],
+        master_id: StreamId,
+        listpack: Vec<u8>,
+    ) -> RdbOk {
+        let mut listpack = Listpack::new(listpack)?;
+
+        let _count = listpack.expect_number()?;
+        let _deleted = listpack.expect_number()?;
+        let num_master_fields = listpack.expect_number()?;
+        // Each field takes at least two bytes of the listpack.
+        let mut master_fields =
+            Vec::with_capacity((num_master_fields as u64).min(listpack.remaining() / 2) as usize);
+        for _ in 0..num_master_fields {
+            master_fields.push(listpack.expect_string()?);
+        }
+        // The master entry's terminator.
+        listpack.expect_number()?;
+
+        loop {
+            let flags = match listpack.next_entry()? {
+                None => break,
+                Some(ZiplistEntry::Number(flags)) => flags,
+                Some(ZiplistEntry::String(_)) => {
+                    return Err(other_error("Expected stream entry flags in listpack"))
+                }
+            };
+            let id = StreamId {
+                ms: master_id.ms.wrapping_add(listpack.expect_number()? as u64),
+                seq: master_id.seq.wrapping_add(listpack.expect_number()? as u64),
+            };
+
+            let mut fields = Vec::with_capacity(master_fields.len());
+            if flags & stream::FLAG_SAMEFIELDS != 0 {
+                for field in &master_fields {
+                    fields.push((field.clone(), listpack.expect_string()?));
+                }
+            } else {
+                let num_fields = listpack.expect_number()?;
+                for _ in 0..num_fields {
+                    let field = listpack.expect_string()?;
+                    let value = listpack.expect_string()?;
+                    fields.push((field, value));
+                }
+            }
+            // The number of elements of this entry.
+            listpack.expect_number()?;
+
+            if flags & stream::FLAG_DELETED == 0 {
+                self.formatter.stream_entry(key, id, &fields)?;
+            }
+        }
+        self.validate_listpack(key, &listpack);
+
+        Ok(())
+    }
+
+    /// Reads the consumer groups stored after a stream's entries.
+    ///
+    /// Each group stores its pending entries first, and then its consumers
+    /// with the IDs of the pending entries they own. Pending entries are
+    /// reported after all consumers of the group, so their consumer is known.
+    fn read_stream_groups(&mut self, key: &[u8], value_type: u8) -> RdbResult<()> {
+        let groups = read_length(&mut self.input)?;
+        for _ in 0..groups {
+            let name = self.read_value_blob()?;
+            let last_id = read_stream_id_lengths(&mut self.input)?;
+            let entries_read = if value_type != encoding_type::STREAM_LISTPACKS {
+                Some(read_length(&mut self.input)?)
+            } else {
+                None
+            };
+            let group = StreamGroup {
+                name,
+                last_id,
+                entries_read,
+            };
+            self.formatter.stream_group(key, &group)?;
+
+            let pending = read_length(&mut self.input)?;
+            let mut entries = vec![];
+            for _ in 0..pending {
+                let id = read_stream_id(&read_exact(&mut self.input, 16)?)?;
+                let delivery_time = self.input.read_u64::<LittleEndian>()?;
+                let delivery_count = read_length(&mut self.input)?;
+                entries.push(StreamPendingEntry {
+                    id,
+                    consumer: vec![],
+                    delivery_time,
+                    delivery_count,
+                });
+            }
+
+            let consumers = read_length(&mut self.input)?;
+            let mut owners = HashMap::new();
+            for _ in 0..consumers {
+                let name = self.read_value_blob()?;
+                let seen_time = self.input.read_u64::<LittleEndian>()?;
+                let active_time = if value_type == encoding_type::STREAM_LISTPACKS_3 {
+                    Some(self.input.read_u64::<LittleEndian>()?)
+                } else {
+                    None
+                };
+                let pending = read_length(&mut self.input)?;
+                for _ in 0..pending {
+                    let id = read_stream_id(&read_exact(&mut self.input, 16)?)?;
+                    owners.insert(id, name.clone());
+                }
+
+                let consumer = StreamConsumer {
+                    name,
+                    seen_time,
+                    active_time,
+                };
+                self.formatter
+                    .stream_consumer(key, &group.name, &consumer)?;
+            }
+
+            for mut entry in entries {
+                entry.consumer = owners.remove(&entry.id).ok_or_else(|| {
+                    other_error(format!("Pending stream entry {} has no consumer", entry.id))
+                })?;
+                self.formatter
+                    .stream_pending_entry(key, &group.name, &entry)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Skips the consumer groups stored after a stream's entries.
+    ///
+    /// Encoding v2 adds the number of entries read by each group and v3 the
+    /// time each consumer was last active.
+    fn skip_stream_groups(&mut self, value_type: u8) -> RdbResult<()> {
+        let groups = read_length(&mut self.input)?;
+        for _ in 0..groups {
+            self.skip_blob()?;
+            read_stream_id_lengths(&mut self.input)?;
+            if value_type != encoding_type::STREAM_LISTPACKS {
+                read_length(&mut self.input)?;
+            }
+
+            // Pending entries: ID, delivery time and delivery count.
+            let pending = read_length(&mut self.input)?;
+            for _ in 0..pending {
+                self.skip(16 + 8)?;
+                read_length(&mut self.input)?;
+            }
+
+            // Consumers: name, seen (and active) time and the IDs of their
+            // pending entries.
+            let consumers = read_length(&mut self.input)?;
+            for _ in 0..consumers {
+                self.skip_blob()?;
+                self.skip(8)?;
+                if value_type == encoding_type::STREAM_LISTPACKS_3 {
+                    self.skip(8)?;
+                }
+                let pending = usize::try_from(read_length(&mut self.input)?)
+                    .ok()
+                    .and_then(|pending| pending.checked_mul(16))
+                    .ok_or_else(|| other_error("Too many pending entries"))?;
+                self.skip(pending)?;
+            }
         }
-        self.formatter.end_set(key)?;
 
         Ok(())
     }
 
     fn read_type(&mut self, key: &[u8], value_type: u8) -> RdbOk {
         match value_type {
-            encoding_type::STRING => {
-                let val = read_blob(&mut self.input)?;
-                self.formatter.set(key, &val, self.last_expiretime)?;
-            }
+            encoding_type::STRING if self.options.raw_strings => self.read_raw_string(key)?,
+            encoding_type::STRING => self.read_string(key)?,
             encoding_type::LIST => self.read_linked_list(key, Type::List)?,
             encoding_type::SET => self.read_linked_list(key, Type::Set)?,
             encoding_type::ZSET => self.read_sorted_set(key)?,
@@ -654,42 +1676,391 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
             encoding_type::ZSET_ZIPLIST => self.read_sortedset_ziplist(key)?,
             encoding_type::HASH_ZIPLIST => self.read_hash_ziplist(key)?,
             encoding_type::LIST_QUICKLIST => self.read_quicklist(key)?,
-            _ => panic!("Value Type not implemented: {}", value_type),
+            encoding_type::HASH_LISTPACK => self.read_hash_listpack(key)?,
+            encoding_type::HASH_METADATA_PRE_GA | encoding_type::HASH_METADATA => {
+                self.read_hash_metadata(key, value_type)?
+            }
+            encoding_type::HASH_LISTPACK_EX_PRE_GA | encoding_type::HASH_LISTPACK_EX => {
+                self.read_hash_listpack_ex(key, value_type)?
+            }
+            encoding_type::ZSET_LISTPACK => self.read_sortedset_listpack(key)?,
+            encoding_type::LIST_QUICKLIST_2 => self.read_quicklist_2(key)?,
+            encoding_type::SET_LISTPACK => self.read_set_listpack(key)?,
+            encoding_type::STREAM_LISTPACKS
+            | encoding_type::STREAM_LISTPACKS_2
+            | encoding_type::STREAM_LISTPACKS_3 => self.read_stream(key, value_type)?,
+            encoding_type::MODULE | encoding_type::MODULE_2 => {
+                self.read_module_value(key, value_type)?
+            }
+            _ => return Err(RdbError::UnknownType { code: value_type }),
         };
 
         Ok(())
     }
 
+    fn read_string(&mut self, key: &[u8]) -> RdbOk {
+        let val = self.read_value_blob()?;
+        let expiry = self.last_expiretime;
+        if self
+            .options
+            .bitmap_keys
+            .as_ref()
+            .is_some_and(|re| re.is_match(key))
+        {
+            let popcount = val.iter().map(|byte| u64::from(byte.count_ones())).sum();
+            return self
+                .formatter
+                .bitmap(key, val.len() as u64, popcount, expiry);
+        }
+        let hll = if self.options.hyperloglogs {
+            hyperloglog::decode(&val)
+        } else {
+            None
+        };
+        match hll {
+            Some((encoding, cardinality)) => {
+                self.formatter
+                    .hyperloglog(key, encoding, cardinality, expiry)
+            }
+            None => self.formatter.set(key, &val, expiry),
+        }
+    }
+
     fn skip(&mut self, skip_bytes: usize) -> RdbResult<()> {
-        let mut buf = vec![0; skip_bytes];
-        self.input.read_exact(&mut buf)?;
+        let skipped = io::copy(
+            &mut (&mut self.input).take(skip_bytes as u64),
+            &mut io::sink(),
+        )?;
+        if skipped != skip_bytes as u64 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
 
         Ok(())
     }
 
     fn skip_blob(&mut self) -> RdbResult<()> {
-        let (len, is_encoded) = unwrap_or_panic!(read_length_with_encoding(&mut self.input));
-        let skip_bytes;
-
-        if is_encoded {
-            skip_bytes = match len {
+        let (len, is_encoded) = read_length_with_encoding(&mut self.input)?;
+        let skip_bytes = if is_encoded {
+            match len {
                 encoding::INT8 => 1,
                 encoding::INT16 => 2,
                 encoding::INT32 => 4,
                 encoding::LZF => {
-                    let compressed_length = unwrap_or_panic!(read_length(&mut self.input));
-                    let _real_length = unwrap_or_panic!(read_length(&mut self.input));
+                    let compressed_length = read_length(&mut self.input)?;
+                    let _real_length = read_length(&mut self.input)?;
                     compressed_length
                 }
-                _ => panic!("Unknown encoding: {}", len),
+                _ => return Err(other_error(format!("Unknown string encoding: {}", len))),
             }
         } else {
-            skip_bytes = len;
-        }
+            len
+        };
 
         self.skip(skip_bytes as usize)
     }
 
+    /// Reads a blob of the current value and adds it to its `BlobStats`.
+    /// Reads a blob that is not part of a value, like a key.
+    fn read_limited_blob(&mut self) -> RdbResult<Vec<u8>> {
+        read_blob_compressed(&mut self.input, self.options.max_blob_length).map(|(blob, _)| blob)
+    }
+
+    fn read_value_blob(&mut self) -> RdbResult<Vec<u8>> {
+        let (blob, compressed_length) =
+            read_blob_compressed(&mut self.input, self.options.max_blob_length)?;
+        self.blob_stats.add(blob.len() as u64, compressed_length);
+        Ok(blob)
+    }
+
+    fn read_raw_string(&mut self, key: &[u8]) -> RdbOk {
+        match read_raw_blob(&mut self.input, self.options.max_blob_length)? {
+            RawBlob::Plain(val) => {
+                self.blob_stats.add(val.len() as u64, None);
+                self.formatter.set(key, &val, self.last_expiretime)
+            }
+            RawBlob::Lzf { data, real_length } => {
+                self.blob_stats.add(real_length, Some(data.len() as u64));
+                self.formatter
+                    .compressed_set(key, &data, real_length, self.last_expiretime)
+            }
+        }
+    }
+
+    fn read_module_id(&mut self, value_type: u8) -> RdbResult<u64> {
+        if value_type == encoding_type::MODULE {
+            return Err(other_error(
+                "Module values from pre-release versions of Redis 4.0 can't be read",
+            ));
+        }
+
+        read_length(&mut self.input)
+    }
+
+    /// Decodes a module value with the decoder registered for its module,
+    /// or skips it.
+    fn read_module_value(&mut self, key: &[u8], value_type: u8) -> RdbOk {
+        let module_id = self.read_module_id(value_type)?;
+        let module_name = module_name(module_id);
+
+        match self.module_decoders.get_mut(&module_name) {
+            Some(decoder) => {
+                let mut values = ModuleReader::new(&mut self.input, module_id & 1023);
+                match decoder.decode(key, self.last_expiretime, &mut values, &mut self.formatter) {
+                    Err(RdbError::Other(message)) if self.options.lenient => {
+                        values.finish()?;
+                        self.warn(
+                            Some(key),
+                            format!("skipped value of module {}: {}", module_name, message),
+                        );
+                        self.formatter.module(key, &module_name)
+                    }
+                    res => {
+                        res?;
+                        values.finish()
+                    }
+                }
+            }
+            None => {
+                self.skip_module_fields()?;
+                self.formatter.module(key, &module_name)
+            }
+        }
+    }
+
+    /// Skips a module value and returns the bytes of the numbers and
+    /// strings stored.
+    fn skip_module_value(&mut self, value_type: u8) -> RdbResult<u64> {
+        self.read_module_id(value_type)?;
+        self.skip_module_fields()
+    }
+
+    /// Walks the opcodes of a module value up to its end marker, as the
+    /// value can't be decoded without the module that wrote it.
+    fn skip_module_fields(&mut self) -> RdbResult<u64> {
+        let mut bytes = 0;
+        loop {
+            match read_length(&mut self.input)? {
+                module::OPCODE_EOF => break,
+                module::OPCODE_SINT | module::OPCODE_UINT => {
+                    read_length(&mut self.input)?;
+                    bytes += 8;
+                }
+                module::OPCODE_FLOAT => {
+                    self.skip(4)?;
+                    bytes += 4;
+                }
+                module::OPCODE_DOUBLE => {
+                    self.skip(8)?;
+                    bytes += 8;
+                }
+                module::OPCODE_STRING => bytes += self.skip_blob_length()?,
+                opcode => return Err(other_error(format!("Unknown module opcode: {}", opcode))),
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /// Skips a blob and returns the length `read_blob` would have returned.
+    fn skip_blob_length(&mut self) -> RdbResult<u64> {
+        let (len, is_encoded) = read_length_with_encoding(&mut self.input)?;
+        if !is_encoded {
+            self.skip(len as usize)?;
+            self.blob_stats.add(len, None);
+            return Ok(len);
+        }
+
+        let number = match len {
+            encoding::INT8 => self.input.read_i8()? as i32,
+            encoding::INT16 => self.input.read_i16::<LittleEndian>()? as i32,
+            encoding::INT32 => self.input.read_i32::<LittleEndian>()?,
+            encoding::LZF => {
+                let compressed_length = read_length(&mut self.input)?;
+                let real_length = read_length(&mut self.input)?;
+                self.skip(compressed_length as usize)?;
+                self.blob_stats.add(real_length, Some(compressed_length));
+                return Ok(real_length);
+            }
+            _ => return Err(other_error(format!("Unknown encoding: {}", len))),
+        };
+
+        let length = number.to_string().len() as u64;
+        self.blob_stats.add(length, None);
+        Ok(length)
+    }
+
+    /// Counts the entries of a blob encoded value and returns them with
+    /// the size of the blob.
+    ///
+    /// Uncompressed blobs are skipped after their header, unless it doesn't
+    /// hold the number of entries.
+    fn measure_container(&mut self, container: Container) -> RdbResult<(u64, u64)> {
+        let (len, is_encoded) = read_length_with_encoding(&mut self.input)?;
+        let blob = match (len, is_encoded) {
+            (encoding::LZF, true) => {
+                let compressed_length = read_length(&mut self.input)?;
+                let real_length = read_length(&mut self.input)?;
+                let data = read_exact(&mut self.input, compressed_length as usize)?;
+                self.blob_stats.add(real_length, Some(compressed_length));
+                decompress(&data, real_length)?
+            }
+            (_, true) => return Err(other_error(format!("Unexpected encoding: {}", len))),
+            (len, false) => {
+                self.blob_stats.add(len, None);
+                let header =
+                    read_exact(&mut self.input, container.header_length().min(len as usize))?;
+                if let Some(entries) = container.header_entries(&header) {
+                    self.skip(len as usize - header.len())?;
+                    return Ok((entries, len));
+                }
+                let mut blob = header;
+                blob.extend(read_exact(&mut self.input, len as usize - blob.len())?);
+                blob
+            }
+        };
+
+        let length = blob.len() as u64;
+        let entries = match container.header_entries(&blob) {
+            Some(entries) => entries,
+            None => container.count_entries(blob)?,
+        };
+        Ok((entries, length))
+    }
+
+    /// Measures a value without decoding it, for lengths-only mode.
+    fn read_value_length(&mut self, key: &[u8], value_type: u8) -> RdbOk {
+        let score = std::mem::size_of::<f64>() as u64;
+        let (elements, bytes) = match value_type {
+            encoding_type::STRING => (1, self.skip_blob_length()?),
+            encoding_type::MODULE | encoding_type::MODULE_2 => {
+                (1, self.skip_module_value(value_type)?)
+            }
+            encoding_type::LIST | encoding_type::SET => {
+                let length = read_length(&mut self.input)?;
+                let mut bytes = 0;
+                for _ in 0..length {
+                    bytes += self.skip_blob_length()?;
+                }
+                (length, bytes)
+            }
+            encoding_type::HASH => {
+                let length = read_length(&mut self.input)?;
+                let fields = length
+                    .checked_mul(2)
+                    .ok_or_else(|| other_error("Too many elements"))?;
+                let mut bytes = 0;
+                for _ in 0..fields {
+                    bytes += self.skip_blob_length()?;
+                }
+                (length, bytes)
+            }
+            encoding_type::ZSET => {
+                let length = read_length(&mut self.input)?;
+                let mut bytes = 0;
+                for _ in 0..length {
+                    bytes += self.skip_blob_length()? + score;
+                    // Scores are strings, except for NaN and infinities.
+                    let score_length = self.input.read_u8()?;
+                    if score_length < 253 {
+                        self.skip(score_length as usize)?;
+                    }
+                }
+                (length, bytes)
+            }
+            encoding_type::ZSET_2 => {
+                let length = read_length(&mut self.input)?;
+                let mut bytes = 0;
+                for _ in 0..length {
+                    bytes += self.skip_blob_length()? + score;
+                    self.skip(8)?;
+                }
+                (length, bytes)
+            }
+            encoding_type::HASH_ZIPMAP => self.measure_container(Container::Zipmap)?,
+            encoding_type::LIST_ZIPLIST => self.measure_container(Container::Ziplist)?,
+            encoding_type::SET_INTSET => self.measure_container(Container::Intset)?,
+            encoding_type::SET_LISTPACK => self.measure_container(Container::Listpack)?,
+            encoding_type::ZSET_ZIPLIST | encoding_type::HASH_ZIPLIST => {
+                let (entries, bytes) = self.measure_container(Container::Ziplist)?;
+                (entries / 2, bytes)
+            }
+            encoding_type::ZSET_LISTPACK | encoding_type::HASH_LISTPACK => {
+                let (entries, bytes) = self.measure_container(Container::Listpack)?;
+                (entries / 2, bytes)
+            }
+            encoding_type::HASH_METADATA_PRE_GA | encoding_type::HASH_METADATA => {
+                self.read_min_field_expiry(value_type)?;
+                let length = read_length(&mut self.input)?;
+                let mut bytes = 0;
+                for _ in 0..length {
+                    read_length(&mut self.input)?;
+                    bytes += self.skip_blob_length()? + self.skip_blob_length()?;
+                }
+                (length, bytes)
+            }
+            encoding_type::HASH_LISTPACK_EX_PRE_GA | encoding_type::HASH_LISTPACK_EX => {
+                self.read_min_field_expiry(value_type)?;
+                let (entries, bytes) = self.measure_container(Container::Listpack)?;
+                (entries / 3, bytes)
+            }
+            encoding_type::LIST_QUICKLIST => {
+                let nodes = read_length(&mut self.input)?;
+                let (mut elements, mut bytes) = (0, 0);
+                for _ in 0..nodes {
+                    let (entries, size) = self.measure_container(Container::Ziplist)?;
+                    elements += entries;
+                    bytes += size;
+                }
+                (elements, bytes)
+            }
+            encoding_type::LIST_QUICKLIST_2 => {
+                let nodes = read_length(&mut self.input)?;
+                let (mut elements, mut bytes) = (0, 0);
+                for _ in 0..nodes {
+                    let (entries, size) = match read_length(&mut self.input)? {
+                        quicklist::CONTAINER_PLAIN => (1, self.skip_blob_length()?),
+                        quicklist::CONTAINER_PACKED => {
+                            self.measure_container(Container::Listpack)?
+                        }
+                        container => {
+                            return Err(other_error(format!(
+                                "Unknown quicklist container: {}",
+                                container
+                            )))
+                        }
+                    };
+                    elements += entries;
+                    bytes += size;
+                }
+                (elements, bytes)
+            }
+            encoding_type::STREAM_LISTPACKS
+            | encoding_type::STREAM_LISTPACKS_2
+            | encoding_type::STREAM_LISTPACKS_3 => {
+                let nodes = read_length(&mut self.input)?;
+                let mut bytes = 0;
+                for _ in 0..nodes {
+                    self.skip_blob()?;
+                    bytes += self.skip_blob_length()?;
+                }
+                let (length, _) = self.read_stream_metadata(value_type)?;
+                self.skip_stream_groups(value_type)?;
+                (length as u64, bytes)
+            }
+            _ => return Err(RdbError::UnknownType { code: value_type }),
+        };
+
+        let length = ValueLength {
+            typ: Type::from_encoding(value_type),
+            elements,
+            bytes,
+        };
+        self.formatter
+            .value_length(key, &length, self.last_expiretime)?;
+
+        Ok(())
+    }
+
     fn skip_object(&mut self, enc_type: u8) -> RdbResult<()> {
         let blobs_to_skip = match enc_type {
             encoding_type::STRING
@@ -697,12 +2068,24 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
             | encoding_type::LIST_ZIPLIST
             | encoding_type::SET_INTSET
             | encoding_type::ZSET_ZIPLIST
-            | encoding_type::HASH_ZIPLIST => 1,
+            | encoding_type::HASH_ZIPLIST
+            | encoding_type::HASH_LISTPACK
+            | encoding_type::ZSET_LISTPACK
+            | encoding_type::SET_LISTPACK => 1,
             encoding_type::LIST | encoding_type::SET | encoding_type::LIST_QUICKLIST => {
-                unwrap_or_panic!(read_length(&mut self.input))
+                read_length(&mut self.input)?
             }
-            encoding_type::ZSET | encoding_type::HASH => {
-                unwrap_or_panic!(read_length(&mut self.input)) * 2
+            encoding_type::ZSET | encoding_type::HASH => read_length(&mut self.input)?
+                .checked_mul(2)
+                .ok_or_else(|| other_error("Too many elements"))?,
+            encoding_type::LIST_QUICKLIST_2 => {
+                let length = read_length(&mut self.input)?;
+                for _ in 0..length {
+                    read_length(&mut self.input)?;
+                    self.skip_blob()?;
+                }
+
+                0
             }
             encoding_type::ZSET_2 => {
                 let length = read_length(&mut self.input)?;
@@ -713,7 +2096,42 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
 
                 0
             }
-            _ => panic!("Unknown encoding type: {}", enc_type),
+            encoding_type::HASH_METADATA_PRE_GA | encoding_type::HASH_METADATA => {
+                self.read_min_field_expiry(enc_type)?;
+                let length = read_length(&mut self.input)?;
+                for _ in 0..length {
+                    read_length(&mut self.input)?;
+                    self.skip_blob()?;
+                    self.skip_blob()?;
+                }
+
+                0
+            }
+            encoding_type::HASH_LISTPACK_EX_PRE_GA | encoding_type::HASH_LISTPACK_EX => {
+                self.read_min_field_expiry(enc_type)?;
+
+                1
+            }
+            encoding_type::STREAM_LISTPACKS
+            | encoding_type::STREAM_LISTPACKS_2
+            | encoding_type::STREAM_LISTPACKS_3 => {
+                let blobs = read_length(&mut self.input)?
+                    .checked_mul(2)
+                    .ok_or_else(|| other_error("Too many stream nodes"))?;
+                for _ in 0..blobs {
+                    self.skip_blob()?;
+                }
+                self.read_stream_metadata(enc_type)?;
+                self.skip_stream_groups(enc_type)?;
+
+                0
+            }
+            encoding_type::MODULE | encoding_type::MODULE_2 => {
+                self.skip_module_value(enc_type)?;
+
+                0
+            }
+            _ => return Err(RdbError::UnknownType { code: enc_type }),
         };
 
         for _ in 0..blobs_to_skip {
@@ -729,3 +2147,29 @@ impl<R: Read, F: Formatter, L: Filter> RdbParser<R, F, L> {
         Ok(())
     }
 }
+
+impl<R: Read + Seek, F: Formatter, L: Filter> RdbParser<R, F, L> {
+    /// Walks the whole file and returns the offset of every SELECTDB opcode.
+    ///
+    /// Keys are not decoded, so this is only cheap if the filter rejects
+    /// all databases and thereby forces every value to be skipped.
+    pub(crate) fn scan_databases(&mut self) -> RdbResult<Vec<u64>> {
+        verify_magic(&mut self.input)?;
+        verify_version(&mut self.input)?;
+
+        let mut offsets = vec![];
+        loop {
+            let next_op = self.input.read_u8()?;
+
+            if next_op == op_code::SELECTDB {
+                offsets.push(self.input.stream_position()? - 1);
+            }
+
+            if !self.read_op(next_op)? {
+                break;
+            }
+        }
+
+        Ok(offsets)
+    }
+}
@@ -0,0 +1,300 @@
+use crate::formatter::Formatter;
+use crate::types::{
+    BlobStats, EncodingType, FilterInfo, HllEncoding, KeyUsage, RdbError, RdbResult,
+    StreamConsumer, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, ValueLength,
+};
+
+/// Wraps the user's formatter and tags every error it returns with the
+/// database and key being formatted.
+///
+/// This tells a failing sink (a closed pipe, a full disk) apart from a
+/// broken input, and the `?` on each callback makes the parser stop right
+/// away instead of decoding the rest of the file.
+pub(crate) struct Sink<F> {
+    formatter: F,
+    db: u32,
+}
+
+impl<F: Formatter> Sink<F> {
+    pub(crate) fn new(formatter: F) -> Sink<F> {
+        Sink { formatter, db: 0 }
+    }
+
+    pub(crate) fn into_inner(self) -> F {
+        self.formatter
+    }
+
+    fn wrap<T>(&self, key: Option<&[u8]>, res: RdbResult<T>) -> RdbResult<T> {
+        res.map_err(|err| match err {
+            RdbError::Formatter { .. } => err,
+            err => RdbError::Formatter {
+                db: self.db,
+                key: key.map(|key| key.to_vec()),
+                source: Box::new(err),
+            },
+        })
+    }
+}
+
+impl<F: Formatter> Formatter for Sink<F> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        let res = self.formatter.start_rdb();
+        self.wrap(None, res)
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        let res = self.formatter.end_rdb();
+        self.wrap(None, res)
+    }
+    fn checksum(&mut self, checksum: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.checksum(checksum);
+        self.wrap(None, res)
+    }
+
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.db = db_index;
+        let res = self.formatter.start_database(db_index);
+        self.wrap(None, res)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        let res = self.formatter.end_database(db_index);
+        self.wrap(None, res)
+    }
+
+    fn resizedb(&mut self, db_size: u32, expires_size: u32) -> RdbResult<()> {
+        let res = self.formatter.resizedb(db_size, expires_size);
+        self.wrap(None, res)
+    }
+    fn slot_info(&mut self, slot: u32, slot_size: u32, expires_slot_size: u32) -> RdbResult<()> {
+        let res = self.formatter.slot_info(slot, slot_size, expires_slot_size);
+        self.wrap(None, res)
+    }
+    fn aux_field(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.aux_field(key, value);
+        self.wrap(None, res)
+    }
+    fn function(&mut self, library_code: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.function(library_code);
+        self.wrap(None, res)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8], expiry: Option<u64>) -> RdbResult<()> {
+        let res = self.formatter.set(key, value, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn compressed_set(
+        &mut self,
+        key: &[u8],
+        compressed: &[u8],
+        real_length: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let res = self
+            .formatter
+            .compressed_set(key, compressed, real_length, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn module(&mut self, key: &[u8], module_name: &str) -> RdbResult<()> {
+        let res = self.formatter.module(key, module_name);
+        self.wrap(Some(key), res)
+    }
+
+    fn unknown_type(&mut self, code: u8) -> RdbResult<()> {
+        let res = self.formatter.unknown_type(code);
+        self.wrap(None, res)
+    }
+
+    fn probabilistic_filter(
+        &mut self,
+        key: &[u8],
+        filter: &FilterInfo,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let res = self.formatter.probabilistic_filter(key, filter, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn hyperloglog(
+        &mut self,
+        key: &[u8],
+        encoding: HllEncoding,
+        cardinality: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let res = self
+            .formatter
+            .hyperloglog(key, encoding, cardinality, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn bitmap(
+        &mut self,
+        key: &[u8],
+        length: u64,
+        popcount: u64,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let res = self.formatter.bitmap(key, length, popcount, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        let res = self.formatter.value_length(key, length, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn blob_stats(&mut self, key: &[u8], stats: &BlobStats) -> RdbResult<()> {
+        let res = self.formatter.blob_stats(key, stats);
+        self.wrap(Some(key), res)
+    }
+    fn key_offset(&mut self, key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        let res = self.formatter.key_offset(key, offset, size);
+        self.wrap(Some(key), res)
+    }
+    fn key_usage(&mut self, key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        let res = self.formatter.key_usage(key, usage);
+        self.wrap(Some(key), res)
+    }
+
+    fn start_hash(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        let res = self.formatter.start_hash(key, length, expiry, info);
+        self.wrap(Some(key), res)
+    }
+    fn end_hash(&mut self, key: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.end_hash(key);
+        self.wrap(Some(key), res)
+    }
+    fn hash_element(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.hash_element(key, field, value);
+        self.wrap(Some(key), res)
+    }
+    fn hash_element_ex(
+        &mut self,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+        expiry: u64,
+    ) -> RdbResult<()> {
+        let res = self.formatter.hash_element_ex(key, field, value, expiry);
+        self.wrap(Some(key), res)
+    }
+
+    fn start_set(
+        &mut self,
+        key: &[u8],
+        cardinality: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        let res = self.formatter.start_set(key, cardinality, expiry, info);
+        self.wrap(Some(key), res)
+    }
+    fn end_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.end_set(key);
+        self.wrap(Some(key), res)
+    }
+    fn set_element(&mut self, key: &[u8], member: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.set_element(key, member);
+        self.wrap(Some(key), res)
+    }
+
+    fn start_list(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        let res = self.formatter.start_list(key, length, expiry, info);
+        self.wrap(Some(key), res)
+    }
+    fn end_list(&mut self, key: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.end_list(key);
+        self.wrap(Some(key), res)
+    }
+    fn list_element(&mut self, key: &[u8], value: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.list_element(key, value);
+        self.wrap(Some(key), res)
+    }
+
+    fn start_sorted_set(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        let res = self.formatter.start_sorted_set(key, length, expiry, info);
+        self.wrap(Some(key), res)
+    }
+    fn end_sorted_set(&mut self, key: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.end_sorted_set(key);
+        self.wrap(Some(key), res)
+    }
+    fn sorted_set_element(&mut self, key: &[u8], score: f64, member: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.sorted_set_element(key, score, member);
+        self.wrap(Some(key), res)
+    }
+
+    fn start_stream(
+        &mut self,
+        key: &[u8],
+        length: u32,
+        expiry: Option<u64>,
+        info: EncodingType,
+    ) -> RdbResult<()> {
+        let res = self.formatter.start_stream(key, length, expiry, info);
+        self.wrap(Some(key), res)
+    }
+    fn end_stream(&mut self, key: &[u8]) -> RdbResult<()> {
+        let res = self.formatter.end_stream(key);
+        self.wrap(Some(key), res)
+    }
+    fn stream_metadata(&mut self, key: &[u8], metadata: &StreamMetadata) -> RdbResult<()> {
+        let res = self.formatter.stream_metadata(key, metadata);
+        self.wrap(Some(key), res)
+    }
+    fn stream_entry(
+        &mut self,
+        key: &[u8],
+        id: StreamId,
+        fields: &[(Vec<u8>, Vec<u8>)],
+    ) -> RdbResult<()> {
+        let res = self.formatter.stream_entry(key, id, fields);
+        self.wrap(Some(key), res)
+    }
+    fn stream_group(&mut self, key: &[u8], group: &StreamGroup) -> RdbResult<()> {
+        let res = self.formatter.stream_group(key, group);
+        self.wrap(Some(key), res)
+    }
+    fn stream_consumer(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        consumer: &StreamConsumer,
+    ) -> RdbResult<()> {
+        let res = self.formatter.stream_consumer(key, group, consumer);
+        self.wrap(Some(key), res)
+    }
+    fn stream_pending_entry(
+        &mut self,
+        key: &[u8],
+        group: &[u8],
+        entry: &StreamPendingEntry,
+    ) -> RdbResult<()> {
+        let res = self.formatter.stream_pending_entry(key, group, entry);
+        self.wrap(Some(key), res)
+    }
+}
@@ -1,4 +1,8 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::io::Error as IoError;
+use std::time::Duration;
 use thiserror::Error;
 
 use crate::constants::encoding_type;
@@ -18,41 +22,426 @@ pub enum RdbError {
 
     #[error("{0}")]
     Other(String),
+
+    /// A length stored inside a ziplist, listpack, intset or zipmap points
+    /// past the end of the blob holding it.
+    #[error("{container} entry of {length} bytes exceeds the {available} bytes left in the blob")]
+    Overrun {
+        container: &'static str,
+        length: u64,
+        available: u64,
+    },
+
+    /// An opcode or value type this version doesn't know. Its layout, and
+    /// so where the next entry starts, is unknown.
+    #[error("Unknown opcode or value type: {code}")]
+    UnknownType { code: u8 },
+
+    /// The formatter failed, e.g. because its output was closed.
+    #[error("Formatter failed at {}: {source}", position(*.db, .key))]
+    Formatter {
+        db: u32,
+        key: Option<Vec<u8>>,
+        source: Box<RdbError>,
+    },
+}
+
+impl RdbError {
+    /// Whether the error was caused by writing to a closed pipe.
+    pub fn is_broken_pipe(&self) -> bool {
+        match self {
+            RdbError::Io(err) => err.kind() == std::io::ErrorKind::BrokenPipe,
+            RdbError::Formatter { source, .. } => source.is_broken_pipe(),
+            RdbError::Other(_) | RdbError::Overrun { .. } | RdbError::UnknownType { .. } => false,
+        }
+    }
+}
+
+fn position(db: u32, key: &Option<Vec<u8>>) -> String {
+    match key {
+        Some(key) => format!("db {}, key {}", db, String::from_utf8_lossy(key)),
+        None => format!("db {}", db),
+    }
 }
 
 pub type RdbOk = RdbResult<()>;
 
-#[derive(Debug, PartialEq)]
+/// A problem found while parsing that did not prevent decoding the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub db: u32,
+    pub key: Option<Vec<u8>>,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(
+                f,
+                "db {}, key {}: {}",
+                self.db,
+                String::from_utf8_lossy(key),
+                self.message
+            ),
+            None => write!(f, "db {}: {}", self.db, self.message),
+        }
+    }
+}
+
+/// The encodings of HyperLogLogs, see `hyperloglog::decode`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HllEncoding {
+    /// Every register stored in 6 bits.
+    Dense,
+    /// Runs of registers of the same value, for small cardinalities.
+    Sparse,
+}
+
+/// The kinds of probabilistic filters of the RedisBloom module.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FilterKind {
+    Bloom,
+    Cuckoo,
+}
+
+/// The parameters of a RedisBloom filter, as stored in its module value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterInfo {
+    pub kind: FilterKind,
+    /// The number of items added, minus the deleted ones for cuckoo filters.
+    pub items: u64,
+    /// The number of items the filter holds before it has to grow.
+    pub capacity: u64,
+    /// The false positive rate, only stored for bloom filters.
+    pub error_rate: Option<f64>,
+    /// The number of filters it grew to, starting at 1.
+    pub filters: u64,
+}
+
+/// The number of keys found in a database, next to the sizes its RESIZEDB
+/// opcode announced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DatabaseSize {
+    pub db: u32,
+    /// The number of keys and of keys with expiry announced, if the
+    /// database has a RESIZEDB opcode.
+    pub announced: Option<(u32, u32)>,
+    pub keys: u32,
+    pub expires: u32,
+}
+
+impl DatabaseSize {
+    /// Whether RESIZEDB announced other sizes than were found, a cheap
+    /// sign of a truncated or corrupt database.
+    pub fn mismatched(&self) -> bool {
+        self.announced
+            .is_some_and(|announced| announced != (self.keys, self.expires))
+    }
+}
+
+/// What the parser found in a file besides the values.
+#[derive(Debug, Clone, Default)]
+pub struct ParseSummary {
+    /// The databases parsed so far, in the order of the file.
+    pub databases: Vec<DatabaseSize>,
+}
+
+impl ParseSummary {
+    /// The databases whose sizes don't match their RESIZEDB opcode.
+    pub fn mismatched_databases(&self) -> impl Iterator<Item = &DatabaseSize> {
+        self.databases.iter().filter(|size| size.mismatched())
+    }
+}
+
+/// What happened during a parse, as returned by `rdb::parse`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RdbStats {
+    /// Keys passed on to the formatter.
+    pub keys_read: u64,
+    /// Keys skipped over because of the filter or their expiry.
+    pub keys_skipped: u64,
+    pub bytes_read: u64,
+    /// The number of keys read of each type.
+    pub types: BTreeMap<Type, u64>,
+    /// The databases selected in the dump, in its order.
+    pub databases: Vec<u32>,
+    pub duration: Duration,
+}
+
+impl fmt::Display for RdbStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "keys: {} read, {} skipped",
+            self.keys_read, self.keys_skipped
+        )?;
+        for (value_type, count) in &self.types {
+            writeln!(f, "  {}: {}", value_type.name(), count)?;
+        }
+        let databases: Vec<String> = self.databases.iter().map(u32::to_string).collect();
+        writeln!(f, "databases: {}", databases.join(", "))?;
+        writeln!(f, "bytes: {}", self.bytes_read)?;
+        write!(f, "duration: {:.3}s", self.duration.as_secs_f64())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Type {
     String,
     List,
     Set,
     SortedSet,
     Hash,
+    Stream,
+    Module,
 }
 
 impl Type {
     pub fn from_encoding(enc_type: u8) -> Type {
         match enc_type {
             encoding_type::STRING => Type::String,
-            encoding_type::HASH | encoding_type::HASH_ZIPMAP | encoding_type::HASH_ZIPLIST => {
-                Type::Hash
+            encoding_type::HASH
+            | encoding_type::HASH_ZIPMAP
+            | encoding_type::HASH_ZIPLIST
+            | encoding_type::HASH_LISTPACK
+            | encoding_type::HASH_METADATA_PRE_GA
+            | encoding_type::HASH_LISTPACK_EX_PRE_GA
+            | encoding_type::HASH_METADATA
+            | encoding_type::HASH_LISTPACK_EX => Type::Hash,
+            encoding_type::LIST
+            | encoding_type::LIST_ZIPLIST
+            | encoding_type::LIST_QUICKLIST
+            | encoding_type::LIST_QUICKLIST_2 => Type::List,
+            encoding_type::SET | encoding_type::SET_INTSET | encoding_type::SET_LISTPACK => {
+                Type::Set
             }
-            encoding_type::LIST | encoding_type::LIST_ZIPLIST => Type::List,
-            encoding_type::SET | encoding_type::SET_INTSET => Type::Set,
-            encoding_type::ZSET | encoding_type::ZSET_ZIPLIST => Type::SortedSet,
+            encoding_type::ZSET
+            | encoding_type::ZSET_2
+            | encoding_type::ZSET_ZIPLIST
+            | encoding_type::ZSET_LISTPACK => Type::SortedSet,
+            encoding_type::STREAM_LISTPACKS
+            | encoding_type::STREAM_LISTPACKS_2
+            | encoding_type::STREAM_LISTPACKS_3 => Type::Stream,
+            encoding_type::MODULE | encoding_type::MODULE_2 => Type::Module,
             _ => panic!("Unknown encoding type: {}", enc_type),
         }
     }
+
+    /// Parses the type names accepted on the command line, e.g. `sortedset`.
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "string" => Some(Type::String),
+            "list" => Some(Type::List),
+            "set" => Some(Type::Set),
+            "sortedset" | "sorted-set" | "sorted_set" => Some(Type::SortedSet),
+            "hash" => Some(Type::Hash),
+            "stream" => Some(Type::Stream),
+            "module" => Some(Type::Module),
+            _ => None,
+        }
+    }
+
+    /// The name of the type, as accepted by `from_name`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::String => "string",
+            Type::List => "list",
+            Type::Set => "set",
+            Type::SortedSet => "sortedset",
+            Type::Hash => "hash",
+            Type::Stream => "stream",
+            Type::Module => "module",
+        }
+    }
+}
+
+/// The size of a value, as measured in lengths-only mode.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ValueLength {
+    pub typ: Type,
+    /// The number of elements, field/value pairs or scored members, 1 for
+    /// strings and module values.
+    pub elements: u64,
+    /// The bytes of all elements and values, including 8 bytes per score.
+    /// For values encoded as a single ziplist, listpack, intset or zipmap,
+    /// and for streams, the size of the encoded data instead.
+    pub bytes: u64,
+}
+
+/// How recently or often a key was used, as stored by servers evicting keys
+/// with an LRU or LFU `maxmemory-policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KeyUsage {
+    /// Seconds since the key was last used.
+    Idle(u64),
+    /// The logarithmic access counter of LFU eviction.
+    Frequency(u8),
+}
+
+/// The lengths of the blobs a value is stored in, to tell how much LZF
+/// compression saves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlobStats {
+    pub blobs: u64,
+    pub compressed_blobs: u64,
+    /// The length of all blobs once decompressed.
+    pub real_bytes: u64,
+    /// The length of all blobs as stored, with compressed blobs counting
+    /// with their compressed length.
+    pub stored_bytes: u64,
+}
+
+impl BlobStats {
+    /// Adds a blob, given its compressed length if it was compressed.
+    pub fn add(&mut self, real_length: u64, compressed_length: Option<u64>) {
+        self.blobs += 1;
+        self.real_bytes += real_length;
+        match compressed_length {
+            Some(length) => {
+                self.compressed_blobs += 1;
+                self.stored_bytes += length;
+            }
+            None => self.stored_bytes += real_length,
+        }
+    }
+
+    /// Adds up the blobs of two values.
+    pub fn merge(&mut self, other: &BlobStats) {
+        self.blobs += other.blobs;
+        self.compressed_blobs += other.compressed_blobs;
+        self.real_bytes += other.real_bytes;
+        self.stored_bytes += other.stored_bytes;
+    }
+
+    /// How many times larger the data is than what is stored, 1 if nothing
+    /// was stored.
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            1.0
+        } else {
+            self.real_bytes as f64 / self.stored_bytes as f64
+        }
+    }
 }
 
+/// How a value is encoded in the dump, passed to the `start_*` callbacks.
+///
+/// The number of the compact encodings is the size of their serialized
+/// blobs in bytes, summed over all nodes for quicklists.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum EncodingType {
     String,
+    /// Lists of Redis before 3.2, stored element by element.
     LinkedList,
+    /// Sets and hashes stored element by element.
     Hashtable,
+    /// Sorted sets stored element by element.
     Skiplist,
     Intset(u64),
     Ziplist(u64),
     Zipmap(u64),
-    Quicklist,
+    Listpack(u64),
+    Quicklist(u64),
+    StreamListpacks,
+}
+
+impl EncodingType {
+    /// The name `OBJECT ENCODING` uses for the encoding, e.g. `listpack`.
+    pub fn name(self) -> &'static str {
+        match self {
+            EncodingType::String => "string",
+            EncodingType::LinkedList => "linkedlist",
+            EncodingType::Hashtable => "hashtable",
+            EncodingType::Skiplist => "skiplist",
+            EncodingType::Intset(_) => "intset",
+            EncodingType::Ziplist(_) => "ziplist",
+            EncodingType::Zipmap(_) => "zipmap",
+            EncodingType::Listpack(_) => "listpack",
+            EncodingType::Quicklist(_) => "quicklist",
+            EncodingType::StreamListpacks => "stream",
+        }
+    }
+}
+
+/// The ID of a stream entry, `<milliseconds>-<sequence number>`.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// A stream entry with its fields and their values.
+pub type StreamEntry = (StreamId, Vec<(Vec<u8>, Vec<u8>)>);
+
+/// A whole value with all of its elements, as `Materialize` passes it on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    String(Vec<u8>),
+    List(Vec<Vec<u8>>),
+    Set(Vec<Vec<u8>>),
+    /// Members with their scores.
+    SortedSet(Vec<(Vec<u8>, f64)>),
+    /// Fields with their values and, from Redis 7.4 on, their expiries.
+    Hash(Vec<(Vec<u8>, Vec<u8>, Option<u64>)>),
+    /// Entries with their fields, without the consumer groups.
+    Stream(Vec<StreamEntry>),
+    /// A value of a Redis module, given by the name of the module.
+    Module(String),
+}
+
+/// What a stream stores besides its entries.
+///
+/// The fields that are `None` are only stored from stream encoding v2
+/// (Redis 7.0) on.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamMetadata {
+    /// The largest ID ever added, even if that entry was deleted since.
+    pub last_id: StreamId,
+    pub first_id: Option<StreamId>,
+    pub max_deleted_entry_id: Option<StreamId>,
+    /// The number of entries ever added, including deleted ones.
+    pub entries_added: Option<u64>,
+}
+
+/// A consumer group of a stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamGroup {
+    pub name: Vec<u8>,
+    /// The ID of the last entry delivered to the group.
+    pub last_id: StreamId,
+    /// The number of entries the group read, only stored from stream
+    /// encoding v2 on.
+    pub entries_read: Option<u64>,
+}
+
+/// A consumer of a stream's consumer group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamConsumer {
+    pub name: Vec<u8>,
+    /// Unix timestamp in milliseconds of the consumer's last attempted
+    /// interaction.
+    pub seen_time: u64,
+    /// Unix timestamp in milliseconds of the consumer's last successful
+    /// interaction, only stored from stream encoding v3 on.
+    pub active_time: Option<u64>,
+}
+
+/// An entry delivered to a consumer but not acknowledged yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamPendingEntry {
+    pub id: StreamId,
+    /// The consumer the entry was delivered to.
+    pub consumer: Vec<u8>,
+    /// Unix timestamp in milliseconds of the last delivery.
+    pub delivery_time: u64,
+    pub delivery_count: u64,
 }
@@ -0,0 +1,7 @@
+//! The values the parser passes to formatters, besides plain byte strings.
+
+pub use crate::types::{
+    BlobStats, EncodingType, FilterInfo, FilterKind, HllEncoding, KeyUsage, StreamConsumer,
+    StreamEntry, StreamGroup, StreamId, StreamMetadata, StreamPendingEntry, Type, Value,
+    ValueLength, ZiplistEntry,
+};
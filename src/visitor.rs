@@ -0,0 +1,228 @@
+//! Key metadata for analysis passes.
+//!
+//! A `Visitor` gets one call per key with its type, size, expiry, encoding
+//! and where it is stored in the dump, but never the elements themselves.
+//! `visit` parses in lengths-only mode, so values are skipped over instead
+//! of decoded:
+//!
+//! ```rust,no_run
+//! use rdb::visitor::{KeyInfo, Visitor};
+//! use rdb::RdbResult;
+//! use std::fs::File;
+//! use std::io::BufReader;
+//!
+//! struct Largest(u64);
+//!
+//! impl Visitor for Largest {
+//!     fn key(&mut self, info: &KeyInfo) -> RdbResult<()> {
+//!         self.0 = self.0.max(info.size);
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let file = BufReader::new(File::open("dump.rdb").unwrap());
+//! let mut largest = Largest(0);
+//! rdb::visitor::visit(file, &mut largest, rdb::filter::Simple::new()).unwrap();
+//! ```
+
+use std::cell::Cell;
+use std::io::Read;
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::encoding_type;
+use crate::filter::Filter;
+use crate::formatter::Formatter;
+use crate::parser::RdbParser;
+use crate::types::{EncodingType, KeyUsage, RdbOk, RdbResult, ValueLength};
+
+/// What a `Visitor` learns about a key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyInfo {
+    pub db: u32,
+    pub key: Vec<u8>,
+    /// The type, number of elements and bytes of the value.
+    pub length: ValueLength,
+    pub expiry: Option<u64>,
+    /// How the value is stored, `None` for module values.
+    pub encoding: Option<EncodingType>,
+    pub usage: Option<KeyUsage>,
+    /// The offset of the key's type byte in the dump.
+    pub offset: u64,
+    /// The bytes from the type byte to the end of the value.
+    pub size: u64,
+}
+
+/// An analysis pass getting the metadata of each key, see `visit`.
+#[allow(unused_variables)]
+pub trait Visitor {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        Ok(())
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        Ok(())
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        Ok(())
+    }
+
+    fn key(&mut self, info: &KeyInfo) -> RdbResult<()>;
+}
+
+impl<V: Visitor + ?Sized> Visitor for &mut V {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        (**self).start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        (**self).end_rdb()
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        (**self).start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        (**self).end_database(db_index)
+    }
+    fn key(&mut self, info: &KeyInfo) -> RdbResult<()> {
+        (**self).key(info)
+    }
+}
+
+/// Parses `input`, passing the metadata of every key matching `filter` to
+/// `visitor`.
+pub fn visit<R: Read, V: Visitor, L: Filter>(input: R, visitor: V, filter: L) -> RdbOk {
+    let value_type = Rc::new(Cell::new(0));
+    let filter = Tracked {
+        filter,
+        value_type: value_type.clone(),
+    };
+    let visit = Visit {
+        visitor,
+        value_type,
+        db: 0,
+        usage: None,
+        pending: None,
+    };
+
+    let mut parser = RdbParser::new(input, visit, filter);
+    parser.set_lengths_only(true);
+    parser.parse()
+}
+
+/// Passes on to the user's filter, noting the type byte of each key.
+///
+/// The parser asks for the type of every key after reading the key.
+struct Tracked<L> {
+    filter: L,
+    value_type: Rc<Cell<u8>>,
+}
+
+impl<L: Filter> Filter for Tracked<L> {
+    fn matches_db(&self, db: u32) -> bool {
+        self.filter.matches_db(db)
+    }
+    fn matches_type(&self, enc_type: u8) -> bool {
+        self.value_type.set(enc_type);
+        self.filter.matches_type(enc_type)
+    }
+    fn matches_key(&self, key: &[u8]) -> bool {
+        self.filter.matches_key(key)
+    }
+    fn matches_slot(&self, slot: u32) -> bool {
+        self.filter.matches_slot(slot)
+    }
+}
+
+struct Visit<V> {
+    visitor: V,
+    value_type: Rc<Cell<u8>>,
+    db: u32,
+    usage: Option<KeyUsage>,
+    /// The key measured last, passed on once its offset follows.
+    pending: Option<KeyInfo>,
+}
+
+/// The encoding of a value of type `value_type`, taking `bytes` when
+/// stored in a single blob.
+fn encoding(value_type: u8, bytes: u64) -> Option<EncodingType> {
+    Some(match value_type {
+        encoding_type::STRING => EncodingType::String,
+        encoding_type::LIST => EncodingType::LinkedList,
+        encoding_type::SET
+        | encoding_type::HASH
+        | encoding_type::HASH_METADATA_PRE_GA
+        | encoding_type::HASH_METADATA => EncodingType::Hashtable,
+        encoding_type::ZSET | encoding_type::ZSET_2 => EncodingType::Skiplist,
+        encoding_type::HASH_ZIPMAP => EncodingType::Zipmap(bytes),
+        encoding_type::LIST_ZIPLIST | encoding_type::ZSET_ZIPLIST | encoding_type::HASH_ZIPLIST => {
+            EncodingType::Ziplist(bytes)
+        }
+        encoding_type::SET_INTSET => EncodingType::Intset(bytes),
+        encoding_type::SET_LISTPACK
+        | encoding_type::ZSET_LISTPACK
+        | encoding_type::HASH_LISTPACK
+        | encoding_type::HASH_LISTPACK_EX_PRE_GA
+        | encoding_type::HASH_LISTPACK_EX => EncodingType::Listpack(bytes),
+        encoding_type::LIST_QUICKLIST | encoding_type::LIST_QUICKLIST_2 => {
+            EncodingType::Quicklist(bytes)
+        }
+        encoding_type::STREAM_LISTPACKS
+        | encoding_type::STREAM_LISTPACKS_2
+        | encoding_type::STREAM_LISTPACKS_3 => EncodingType::StreamListpacks,
+        _ => return None,
+    })
+}
+
+impl<V: Visitor> Formatter for Visit<V> {
+    fn start_rdb(&mut self) -> RdbResult<()> {
+        self.visitor.start_rdb()
+    }
+    fn end_rdb(&mut self) -> RdbResult<()> {
+        self.visitor.end_rdb()
+    }
+    fn start_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.db = db_index;
+        self.visitor.start_database(db_index)
+    }
+    fn end_database(&mut self, db_index: u32) -> RdbResult<()> {
+        self.visitor.end_database(db_index)
+    }
+
+    fn key_usage(&mut self, _key: &[u8], usage: KeyUsage) -> RdbResult<()> {
+        self.usage = Some(usage);
+        Ok(())
+    }
+
+    fn value_length(
+        &mut self,
+        key: &[u8],
+        length: &ValueLength,
+        expiry: Option<u64>,
+    ) -> RdbResult<()> {
+        self.pending = Some(KeyInfo {
+            db: self.db,
+            key: key.to_vec(),
+            length: *length,
+            expiry,
+            encoding: encoding(self.value_type.get(), length.bytes),
+            usage: self.usage.take(),
+            offset: 0,
+            size: 0,
+        });
+        Ok(())
+    }
+
+    fn key_offset(&mut self, _key: &[u8], offset: u64, size: u64) -> RdbResult<()> {
+        match self.pending.take() {
+            Some(info) => self.visitor.key(&KeyInfo {
+                offset,
+                size,
+                ..info
+            }),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read};
+
+use crate::helper::read_bounded;
+use crate::types::{RdbError, RdbResult, ZiplistEntry};
+
+/// Size of `zlbytes`, `zltail` and `zllen`.
+const HEADER_SIZE: u64 = 10;
+const END: u8 = 0xFF;
+/// `zllen` value used when the ziplist holds too many entries to count in the header.
+const UNKNOWN_LENGTH: u16 = u16::MAX;
+
+#[inline]
+fn other_error(desc: impl Into<String>) -> RdbError {
+    RdbError::Other(desc.into())
+}
+
+/// Reads a single entry from a ziplist.
+pub(crate) fn read_entry(ziplist: &mut Cursor<Vec<u8>>) -> RdbResult<ZiplistEntry> {
+    // 1. 1 or 5 bytes length of previous entry
+    let byte = ziplist.read_u8()?;
+    if byte == 254 {
+        let mut bytes = [0; 4];
+        if ziplist.read(&mut bytes)? != 4 {
+            return Err(other_error(
+                "Could not read 4 bytes to skip after ziplist length",
+            ));
+        }
+    }
+
+    // 2. Read flag or number value
+    let flag = ziplist.read_u8()?;
+
+    let length = match (flag & 0xC0) >> 6 {
+        0 => (flag & 0x3F) as u64,
+        1 => {
+            let next_byte = ziplist.read_u8()?;
+            (((flag & 0x3F) as u64) << 8) | next_byte as u64
+        }
+        2 => ziplist.read_u32::<BigEndian>()? as u64,
+        _ => {
+            let number_value = match (flag & 0xF0) >> 4 {
+                0xC => ziplist.read_i16::<LittleEndian>()? as i64,
+                0xD => ziplist.read_i32::<LittleEndian>()? as i64,
+                0xE => ziplist.read_i64::<LittleEndian>()?,
+                0xF => match flag & 0xF {
+                    0 => {
+                        let mut bytes = [0; 3];
+                        if ziplist.read(&mut bytes)? != 3 {
+                            return Err(other_error(
+                                "Could not read enough bytes for 24bit number",
+                            ));
+                        }
+
+                        let number: i32 = (((bytes[2] as i32) << 24)
+                            ^ ((bytes[1] as i32) << 16)
+                            ^ ((bytes[0] as i32) << 8)
+                            ^ 48)
+                            >> 8;
+
+                        number as i64
+                    }
+                    0xE => ziplist.read_i8()? as i64,
+                    _ => (flag & 0xF) as i64 - 1,
+                },
+                _ => {
+                    panic!("Flag not handled: {}", flag);
+                }
+            };
+
+            return Ok(ZiplistEntry::Number(number_value));
+        }
+    };
+
+    // 3. Read value
+    let rawval = read_bounded(ziplist, length, "ziplist")?;
+    Ok(ZiplistEntry::String(rawval))
+}
+
+/// A decoded ziplist blob, read entry by entry.
+///
+/// Entries are read until the end byte, so ziplists with more entries than
+/// `zllen` can count are handled as well.
+pub(crate) struct Ziplist {
+    reader: Cursor<Vec<u8>>,
+    zlbytes: u32,
+    zltail: u32,
+    zllen: u16,
+    entries: u32,
+    last_entry: Option<u64>,
+    finished: bool,
+}
+
+impl Ziplist {
+    pub(crate) fn new(ziplist: Vec<u8>) -> RdbResult<Ziplist> {
+        let mut reader = Cursor::new(ziplist);
+        let zlbytes = reader.read_u32::<LittleEndian>()?;
+        let zltail = reader.read_u32::<LittleEndian>()?;
+        let zllen = reader.read_u16::<LittleEndian>()?;
+
+        Ok(Ziplist {
+            reader,
+            zlbytes,
+            zltail,
+            zllen,
+            entries: 0,
+            last_entry: None,
+            finished: false,
+        })
+    }
+
+    /// The size of the serialized ziplist in bytes.
+    pub(crate) fn raw_length(&self) -> u64 {
+        self.reader.get_ref().len() as u64
+    }
+
+    /// The number of entries, as stored in the header.
+    pub(crate) fn header_length(&self) -> u32 {
+        self.zllen as u32
+    }
+
+    /// The number of entries, counted if there are too many for the header.
+    ///
+    /// Must be called before reading entries.
+    pub(crate) fn count(&self) -> RdbResult<u32> {
+        if self.zllen != UNKNOWN_LENGTH {
+            return Ok(self.zllen as u32);
+        }
+
+        let mut ziplist = Ziplist::new(self.reader.get_ref().clone())?;
+        while ziplist.next_entry()?.is_some() {}
+        Ok(ziplist.entries)
+    }
+
+    /// Returns the next entry or `None` once the end byte is reached.
+    pub(crate) fn next_entry(&mut self) -> RdbResult<Option<ZiplistEntry>> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let position = self.reader.position();
+        match self.reader.get_ref().get(position as usize) {
+            None => Err(other_error("Missing end byte of ziplist")),
+            Some(&END) => {
+                self.reader.set_position(position + 1);
+                self.finished = true;
+                Ok(None)
+            }
+            Some(_) => {
+                let entry = read_entry(&mut self.reader)?;
+                self.entries += 1;
+                self.last_entry = Some(position);
+                Ok(Some(entry))
+            }
+        }
+    }
+
+    /// Like `next_entry`, but formats numbers as strings.
+    pub(crate) fn next_string(&mut self) -> RdbResult<Option<Vec<u8>>> {
+        Ok(self.next_entry()?.map(|entry| match entry {
+            ZiplistEntry::String(val) => val,
+            ZiplistEntry::Number(val) => val.to_string().into_bytes(),
+        }))
+    }
+
+    /// Checks the redundant header fields against the entries read so far.
+    ///
+    /// Must only be called after all entries were read.
+    pub(crate) fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let raw_length = self.raw_length();
+        if self.zlbytes as u64 != raw_length {
+            problems.push(format!(
+                "ziplist zlbytes is {}, but the ziplist is {} bytes long",
+                self.zlbytes, raw_length
+            ));
+        }
+
+        let tail = self.last_entry.unwrap_or(HEADER_SIZE);
+        if self.zltail as u64 != tail {
+            problems.push(format!(
+                "ziplist zltail is {}, but the last entry starts at offset {}",
+                self.zltail, tail
+            ));
+        }
+
+        let counted_matches = if self.zllen == UNKNOWN_LENGTH {
+            self.entries >= UNKNOWN_LENGTH as u32
+        } else {
+            self.entries == self.zllen as u32
+        };
+        if !counted_matches {
+            problems.push(format!(
+                "ziplist zllen is {}, but it contains {} entries",
+                self.zllen, self.entries
+            ));
+        }
+
+        let trailing = raw_length.saturating_sub(self.reader.position());
+        if trailing > 0 {
+            problems.push(format!(
+                "ziplist has {} trailing bytes after the end byte",
+                trailing
+            ));
+        }
+
+        problems
+    }
+}
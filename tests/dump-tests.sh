@@ -5,7 +5,7 @@ SCRIPT="$SCRIPTPATH/$(basename "$0")"
 
 
 DUMP_DIRECTORY="${SCRIPTPATH}/dumps"
-FORMATS="json plain nil protocol"
+FORMATS="json plain nil protocol digest folded jsonl compression"
 
 ARG=$1
 
@@ -25,9 +25,14 @@ fi
 failure=0
 for f in $FORMATS; do
   echo "Running $f tests..."
+  OPTIONS=""
+  if [ "$f" = "digest" ]; then
+    # Any non-empty file does as salt.
+    OPTIONS="--salt-file $SCRIPT"
+  fi
   for dump in $(find "$DUMP_DIRECTORY" -type f -name "*.rdb"); do
     echo "  with $dump"
-    $BIN --format $f $dump >/dev/null
+    $BIN --format $f $OPTIONS $dump >/dev/null
 
     if [ $? -ne 0 ]; then
       echo "Failure with '$dump' (Format: $f)"
@@ -35,7 +40,7 @@ for f in $FORMATS; do
     fi
 
     echo "  with $dump, only database 1"
-    $BIN --format $f --databases 1 $dump >/dev/null
+    $BIN --format $f $OPTIONS --databases 1 $dump >/dev/null
 
     if [ $? -ne 0 ]; then
       echo "Failure with '$dump' (Format: $f, only database 1)"